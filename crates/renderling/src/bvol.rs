@@ -0,0 +1,169 @@
+//! Bounding volumes and culling primitives.
+//!
+//! These are used on the GPU to cull [`Renderlet`](crate::stage::Renderlet)s
+//! that are not visible to the camera.
+use crabslab::SlabItem;
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+use crate::camera::Camera;
+
+/// Axis aligned bounding box.
+///
+/// A box with `min == max` is considered "empty" and is never culled.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Default, Clone, Copy, PartialEq, SlabItem)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl From<(Vec3, Vec3)> for Aabb {
+    fn from((a, b): (Vec3, Vec3)) -> Self {
+        Aabb::new(a, b)
+    }
+}
+
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    /// Whether this box has no volume, in which case it is never culled.
+    pub fn is_zero(&self) -> bool {
+        self.min == self.max
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Returns the radius of the sphere that encloses this box.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).length() * 0.5
+    }
+
+    /// Returns the union of this box and another.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if self.is_zero() {
+            *other
+        } else if other.is_zero() {
+            *self
+        } else {
+            Aabb {
+                min: self.min.min(other.min),
+                max: self.max.max(other.max),
+            }
+        }
+    }
+
+    /// Returns whether this box, transformed by the given model matrix, lies
+    /// completely outside the frustum.
+    ///
+    /// The test is conservative, it uses the bounding sphere of the
+    /// transformed box.
+    pub fn is_outside_frustum(&self, frustum: &Frustum, model: Mat4) -> bool {
+        if self.is_zero() {
+            return false;
+        }
+        let center = model.transform_point3(self.center());
+        let scale = model
+            .x_axis
+            .xyz()
+            .length()
+            .max(model.y_axis.xyz().length().max(model.z_axis.xyz().length()));
+        let radius = self.radius() * scale;
+        frustum.is_sphere_outside(center, radius)
+    }
+}
+
+/// The six planes of a camera's view frustum.
+///
+/// Each plane is stored as `(normal, distance)` with the normal pointing
+/// into the frustum.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Default, Clone, Copy, PartialEq, SlabItem)]
+pub struct Frustum {
+    pub left: Vec4,
+    pub right: Vec4,
+    pub bottom: Vec4,
+    pub top: Vec4,
+    pub near: Vec4,
+    pub far: Vec4,
+}
+
+impl Frustum {
+    /// Extract the frustum planes from a view-projection matrix.
+    ///
+    /// Assumes a depth range of `0.0..=1.0`, as used by `wgpu`.
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        fn normalize(plane: Vec4) -> Vec4 {
+            let len = plane.xyz().length();
+            if len == 0.0 {
+                plane
+            } else {
+                plane / len
+            }
+        }
+        let row0 = view_projection.row(0);
+        let row1 = view_projection.row(1);
+        let row2 = view_projection.row(2);
+        let row3 = view_projection.row(3);
+        Frustum {
+            left: normalize(row3 + row0),
+            right: normalize(row3 - row0),
+            bottom: normalize(row3 + row1),
+            top: normalize(row3 - row1),
+            near: normalize(row2),
+            far: normalize(row3 - row2),
+        }
+    }
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self::from_view_projection(camera.projection * camera.view)
+    }
+
+    /// Returns whether the sphere lies completely outside the frustum.
+    pub fn is_sphere_outside(&self, center: Vec3, radius: f32) -> bool {
+        let point = center.extend(1.0);
+        self.left.dot(point) < -radius
+            || self.right.dot(point) < -radius
+            || self.bottom.dot(point) < -radius
+            || self.top.dot(point) < -radius
+            || self.near.dot(point) < -radius
+            || self.far.dot(point) < -radius
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Quat;
+
+    use super::*;
+
+    #[test]
+    fn frustum_culls_sphere_behind_camera() {
+        let camera = Camera::default_perspective(100.0, 100.0);
+        let frustum = Frustum::from_camera(&camera);
+        let aabb = Aabb::new(Vec3::splat(-0.5), Vec3::splat(0.5));
+        // the default perspective camera looks at the origin
+        assert!(!aabb.is_outside_frustum(&frustum, Mat4::IDENTITY));
+        // the camera sits at (0, 12, 20) so this is behind it
+        let behind = Mat4::from_translation(Vec3::new(0.0, 12.0, 30.0));
+        assert!(aabb.is_outside_frustum(&frustum, behind));
+        // off to the side
+        let beside = Mat4::from_translation(Vec3::new(100.0, 0.0, 0.0));
+        assert!(aabb.is_outside_frustum(&frustum, beside));
+        // scaled up enough to intersect the frustum again
+        let big = Mat4::from_scale_rotation_translation(
+            Vec3::splat(200.0),
+            Quat::IDENTITY,
+            Vec3::new(100.0, 0.0, 0.0),
+        );
+        assert!(!aabb.is_outside_frustum(&frustum, big));
+        // empty boxes are never culled
+        assert!(!Aabb::default().is_outside_frustum(&frustum, behind));
+    }
+}
@@ -124,11 +124,14 @@ async fn adapter<'window>(
 async fn device(
     adapter: &wgpu::Adapter,
 ) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    // Indirect drawing is optional, the stage falls back to direct draws
+    // on adapters that don't support it.
+    let indirect_features = adapter.features()
+        & (wgpu::Features::INDIRECT_FIRST_INSTANCE | wgpu::Features::MULTI_DRAW_INDIRECT);
     adapter
         .request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::INDIRECT_FIRST_INSTANCE
-                        | wgpu::Features::MULTI_DRAW_INDIRECT
+                required_features: indirect_features
                         // this one is a funny requirement, it seems it is needed if using storage buffers in
                         // vertex shaders, even if those shaders are read-only
                         | wgpu::Features::VERTEX_WRITABLE_STORAGE, //| wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
@@ -12,7 +12,11 @@ use spirv_std::{
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
-use crate::{camera::Camera, math::IsVector};
+use crate::{
+    camera::Camera,
+    math::IsVector,
+    pbr::{sheen_distribution_charlie, sheen_visibility},
+};
 
 fn radical_inverse_vdc(mut bits: u32) -> f32 {
     bits = (bits << 16u32) | (bits >> 16u32);
@@ -317,10 +321,70 @@ pub fn brdf_lut_convolution_fragment(in_uv: glam::Vec2, out_color: &mut glam::Ve
     *out_color = integrate_brdf(in_uv.x, in_uv.y);
 }
 
+/// Integrates the directional albedo of the "Charlie" sheen BRDF, which is
+/// used to scale down the layers beneath a sheen layer.
+pub fn integrate_sheen(mut n_dot_v: f32, roughness: f32) -> f32 {
+    n_dot_v = n_dot_v.max(f32::EPSILON);
+    let v = Vec3::new(f32::sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    let mut albedo = 0.0f32;
+    for i in 0..SAMPLE_COUNT {
+        // uniformly sample the hemisphere
+        let xi = hammersley(i, SAMPLE_COUNT);
+        let phi = 2.0 * core::f32::consts::PI * xi.x;
+        let cos_theta = 1.0 - xi.y;
+        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+        let l = Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+        let h = (v + l).alt_norm_or_zero();
+
+        let n_dot_l = l.z.max(0.0);
+        let d = sheen_distribution_charlie(h.z.max(0.0), roughness);
+        let vis = sheen_visibility(n_dot_l, n_dot_v, roughness);
+        albedo += d * vis * n_dot_l;
+    }
+
+    // the probability density of each sample is 1 / 2π
+    let albedo = albedo * 2.0 * core::f32::consts::PI / SAMPLE_COUNT as f32;
+    // the approximate visibility term overestimates at grazing angles, but no
+    // more light can be reflected than arrives
+    albedo.min(1.0)
+}
+
+#[cfg(feature = "sheen_lut_convolution_fragment")]
+#[spirv(fragment)]
+/// Fragment shader for creating a sheen LUT.
+///
+/// Uses the same vertex shader as the BRDF LUT.
+pub fn sheen_lut_convolution_fragment(in_uv: glam::Vec2, out_color: &mut f32) {
+    *out_color = integrate_sheen(in_uv.x, in_uv.y);
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn integrate_sheen_sanity() {
+        for n_dot_v in [0.0, 0.25, 0.5, 1.0] {
+            let mut previous = 0.0;
+            for roughness in [0.0, 0.25, 0.5, 0.75, 1.0] {
+                let albedo = integrate_sheen(n_dot_v, roughness);
+                assert!(
+                    (0.0..=1.0).contains(&albedo),
+                    "sheen albedo is {albedo} at {n_dot_v},{roughness}"
+                );
+                if roughness > 0.0 {
+                    // rougher sheen scatters more light
+                    assert!(
+                        albedo >= previous,
+                        "sheen albedo decreased at {n_dot_v},{roughness}"
+                    );
+                }
+                previous = albedo;
+            }
+        }
+    }
+
     #[test]
     fn integrate_brdf_sanity() {
         let points = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
@@ -1,120 +1,65 @@
-//! Render pipelines and layouts for creating cubemaps.
+//! Cubemaps.
+//!
+//! Helpers for rendering into and looking up the six faces of a cubemap.
+use glam::{Mat4, Vec3};
 
-use crate::texture::Texture;
+#[cfg(not(target_arch = "spirv"))]
+mod cpu;
+#[cfg(not(target_arch = "spirv"))]
+pub use cpu::*;
 
-pub fn cubemap_making_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
-    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: Some("cubemap-making bindgroup"),
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Texture {
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                    view_dimension: wgpu::TextureViewDimension::D2,
-                    multisampled: false,
-                },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                count: None,
-            },
-        ],
-    })
+/// The direction each face of a cubemap looks in, along with its "up"
+/// direction, in the order of the layers of a cubemap texture.
+pub const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::NEG_Y),
+    (Vec3::NEG_X, Vec3::NEG_Y),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::Z, Vec3::NEG_Y),
+    (Vec3::NEG_Z, Vec3::NEG_Y),
+];
+
+/// Returns the view matrix of the given face of a cubemap centered at `eye`.
+pub fn cube_face_view(eye: Vec3, face: usize) -> Mat4 {
+    let (forward, up) = CUBE_FACES[face];
+    Mat4::look_at_rh(eye, eye + forward, up)
 }
 
-pub fn cubemap_making_bindgroup(
-    device: &wgpu::Device,
-    label: Option<&str>,
-    buffer: &wgpu::Buffer,
-    // The texture to sample the environment from
-    texture: &Texture,
-) -> wgpu::BindGroup {
-    device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label,
-        layout: &cubemap_making_bindgroup_layout(device),
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(buffer.as_entire_buffer_binding()),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::TextureView(&texture.view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: wgpu::BindingResource::Sampler(&texture.sampler),
-            },
-        ],
-    })
+/// Returns the index of the face of a cubemap that the given direction
+/// points into.
+pub fn cube_face_index(direction: Vec3) -> usize {
+    let abs = direction.abs();
+    if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x >= 0.0 {
+            0
+        } else {
+            1
+        }
+    } else if abs.y >= abs.z {
+        if direction.y >= 0.0 {
+            3
+        } else {
+            2
+        }
+    } else if direction.z >= 0.0 {
+        4
+    } else {
+        5
+    }
 }
 
-pub struct CubemapMakingRenderPipeline(pub wgpu::RenderPipeline);
+#[cfg(test)]
+mod test {
+    use super::*;
 
-impl CubemapMakingRenderPipeline {
-    /// Create the rendering pipeline that creates cubemaps from equirectangular
-    /// images.
-    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
-        log::trace!("creating cubemap-making render pipeline with format '{format:?}'");
-        let vertex_linkage = crate::linkage::skybox_cubemap_vertex::linkage(device);
-        let fragment_linkage = crate::linkage::skybox_equirectangular_fragment::linkage(device);
-        let bg_layout = cubemap_making_bindgroup_layout(device);
-        let pp_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("cubemap-making pipeline layout"),
-            bind_group_layouts: &[&bg_layout],
-            push_constant_ranges: &[],
-        });
-        CubemapMakingRenderPipeline(device.create_render_pipeline(
-            &wgpu::RenderPipelineDescriptor {
-                label: Some("cubemap-making pipeline"),
-                layout: Some(&pp_layout),
-                vertex: wgpu::VertexState {
-                    module: &vertex_linkage.module,
-                    entry_point: vertex_linkage.entry_point,
-                    buffers: &[],
-                    compilation_options: Default::default(),
-                },
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: None,
-                    unclipped_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                    count: 1,
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &fragment_linkage.module,
-                    entry_point: fragment_linkage.entry_point,
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format,
-                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                multiview: None,
-            },
-        ))
+    #[test]
+    fn cube_face_index_matches_face_direction() {
+        for (i, (forward, _)) in CUBE_FACES.iter().enumerate() {
+            assert_eq!(i, cube_face_index(*forward));
+            assert_eq!(i, cube_face_index(*forward * 3.0 + Vec3::splat(0.5)));
+            // the face's direction is in the center of the view
+            let p = cube_face_view(Vec3::ONE, i).transform_point3(Vec3::ONE + *forward);
+            assert!(p.x.abs() < 1.0e-6 && p.y.abs() < 1.0e-6 && p.z < 0.0, "{p}");
+        }
     }
 }
@@ -1,9 +1,73 @@
-use crabslab::SlabItem;
+//! Indirect drawing.
+//!
+//! Types for drawing staged [`Renderlet`](crate::stage::Renderlet)s with
+//! `RenderPass::multi_draw_indirect`, as well as a compute shader that
+//! frustum culls them on the GPU.
+use crabslab::{Array, Id, Slab, SlabItem};
+use glam::UVec3;
+use spirv_std::spirv;
 
-#[derive(Default, Debug, Clone, Copy, PartialEq, SlabItem)]
+use crate::stage::Renderlet;
+
+#[cfg(not(target_arch = "spirv"))]
+mod cpu;
+#[cfg(not(target_arch = "spirv"))]
+pub use cpu::*;
+
+/// Arguments of one indirect draw call.
+///
+/// The layout of this struct matches `wgpu::util::DrawIndirectArgs`, so
+/// a slab of these can be used directly as an indirect buffer.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Default, Clone, Copy, PartialEq, SlabItem)]
 pub struct DrawIndirect {
+    /// The number of vertices (or indices) to draw.
     pub vertex_count: u32,
+    /// The number of instances to draw.
+    ///
+    /// This is `0` for renderlets that should not be drawn.
     pub instance_count: u32,
+    /// Offset of the first vertex.
     pub base_vertex: u32,
+    /// The first instance, which is the [`Id`](crabslab::Id) of the
+    /// [`Renderlet`](crate::stage::Renderlet) being drawn, or the index of
+    /// its first instance slot if it is instanced.
     pub base_instance: u32,
 }
+
+/// The [`Id`] of the [`Array`] of [`DrawIndirect`]s in the indirect draw
+/// slab.
+///
+/// The array of draws is always the first value on the indirect draw slab.
+pub const DRAWS_ARRAY_ID: Id<Array<DrawIndirect>> = Id::new(0);
+
+#[cfg(feature = "compute_frustum_culling")]
+/// Fills in the indirect draw arguments of each staged renderlet, culling
+/// renderlets that lie outside of their camera's frustum.
+///
+/// `slab` is the stage's slab and `args` is the indirect draw slab, which
+/// holds the [`Array`] of [`DrawIndirect`]s at [`DRAWS_ARRAY_ID`]. Each
+/// draw's `base_instance` is the first instance index of the renderlet to
+/// draw, see [`Renderlet::get_first_instance`].
+#[spirv(compute(threads(32)))]
+pub fn compute_frustum_culling(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] args: &mut [u32],
+    #[spirv(global_invocation_id)] global_id: UVec3,
+) {
+    let draws = args.read(DRAWS_ARRAY_ID);
+    let index = global_id.x as usize;
+    if index >= draws.len() {
+        return;
+    }
+    let draw_id = draws.at(index);
+    let mut draw = args.read(draw_id);
+    let (_, renderlet, _) = Renderlet::read_instance(draw.base_instance, slab);
+    draw.vertex_count = renderlet.get_vertex_count();
+    draw.instance_count = if renderlet.visible && !renderlet.is_outside_camera_view(slab) {
+        renderlet.get_instance_count()
+    } else {
+        0
+    };
+    args.write(draw_id, &draw);
+}
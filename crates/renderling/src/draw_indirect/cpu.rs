@@ -0,0 +1,272 @@
+//! CPU side of indirect drawing.
+use std::sync::Arc;
+
+use crabslab::{Array, Slab};
+use snafu::prelude::*;
+
+use crate::{
+    slab::{Gpu, GpuArray, Hybrid, SlabAllocator, SlabAllocatorError},
+    stage::Renderlet,
+};
+
+use super::{DrawIndirect, DRAWS_ARRAY_ID};
+
+#[derive(Debug, Snafu)]
+pub enum IndirectDrawsError {
+    #[snafu(display("Could not read the indirect draws: {source}"))]
+    Read { source: SlabAllocatorError },
+}
+
+/// Returns whether the given features allow drawing with
+/// `RenderPass::multi_draw_indirect`.
+pub fn supports_indirect_draws(features: wgpu::Features) -> bool {
+    features.contains(wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE)
+}
+
+fn frustum_culling_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("frustum culling"),
+        entries: &[storage(0, true), storage(1, false)],
+    })
+}
+
+fn create_frustum_culling_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    let label = Some("frustum culling");
+    let linkage = crate::linkage::compute_frustum_culling::linkage(device);
+    let bindgroup_layout = frustum_culling_bindgroup_layout(device);
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &[&bindgroup_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label,
+        layout: Some(&layout),
+        module: &linkage.module,
+        entry_point: linkage.entry_point,
+        compilation_options: Default::default(),
+    })
+}
+
+/// Compute pass that fills in the indirect draw arguments, culling
+/// renderlets that are outside of their camera's frustum.
+struct FrustumCulling {
+    pipeline: wgpu::ComputePipeline,
+    bindgroup: Option<wgpu::BindGroup>,
+}
+
+/// A GPU buffer of [`DrawIndirect`] arguments, one per staged
+/// [`Renderlet`].
+///
+/// The arguments are kept on their own slab, separate from the stage's
+/// slab, because the buffer must be created with
+/// [`wgpu::BufferUsages::INDIRECT`].
+pub(crate) struct IndirectDraws {
+    pub(crate) slab: SlabAllocator<wgpu::Buffer>,
+    pub(crate) renderlets: Vec<Hybrid<Renderlet>>,
+    draws: Vec<DrawIndirect>,
+    draws_array: Option<GpuArray<DrawIndirect>>,
+    // Always lives at `DRAWS_ARRAY_ID`, pointing to `draws_array`.
+    draws_array_header: Gpu<Array<DrawIndirect>>,
+    frustum_culling: Option<FrustumCulling>,
+}
+
+impl IndirectDraws {
+    pub fn new(renderlets: Vec<Hybrid<Renderlet>>) -> Self {
+        let mut slab = SlabAllocator::default();
+        let draws_array_header = Gpu::new(&mut slab, Array::default());
+        debug_assert_eq!(DRAWS_ARRAY_ID, draws_array_header.id());
+        Self {
+            slab,
+            renderlets,
+            draws: vec![],
+            draws_array: None,
+            draws_array_header,
+            frustum_culling: None,
+        }
+    }
+
+    /// Returns the draw arguments of the given renderlet.
+    pub fn draw_args(hybrid: &Hybrid<Renderlet>) -> DrawIndirect {
+        let rlet = hybrid.get();
+        DrawIndirect {
+            vertex_count: rlet.get_vertex_count(),
+            instance_count: rlet.visible as u32 * rlet.get_instance_count(),
+            base_vertex: 0,
+            base_instance: rlet.get_first_instance(hybrid.id()),
+        }
+    }
+
+    /// The number of draw calls in the indirect buffer.
+    pub fn draw_count(&self) -> u32 {
+        self.draws.len() as u32
+    }
+
+    /// Offset, in bytes, of the first draw call in the indirect buffer.
+    pub fn offset(&self) -> u64 {
+        self.draws_array
+            .as_ref()
+            .map(|array| array.array().starting_index() as u64)
+            .unwrap_or_default()
+            * std::mem::size_of::<u32>() as u64
+    }
+
+    /// Set whether renderlets are frustum culled on the GPU before drawing.
+    pub fn set_has_frustum_culling(&mut self, device: &wgpu::Device, has_culling: bool) {
+        if has_culling {
+            if self.frustum_culling.is_none() {
+                self.frustum_culling = Some(FrustumCulling {
+                    pipeline: create_frustum_culling_pipeline(device),
+                    bindgroup: None,
+                });
+            }
+        } else {
+            self.frustum_culling = None;
+        }
+    }
+
+    /// Recompute the draw arguments from the staged renderlets and
+    /// synchronize them with the GPU.
+    ///
+    /// Returns the indirect buffer, or `None` if there is nothing to draw.
+    pub fn upkeep(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<Arc<wgpu::Buffer>> {
+        let draws = self
+            .renderlets
+            .iter()
+            .map(Self::draw_args)
+            .collect::<Vec<_>>();
+        if draws.is_empty() {
+            self.draws.clear();
+            let _ = self.draws_array.take();
+            self.draws_array_header.set(Array::default());
+            return None;
+        }
+        match self.draws_array.as_ref() {
+            Some(array) if array.len() == draws.len() => {
+                for (i, (new, old)) in draws.iter().zip(self.draws.iter()).enumerate() {
+                    if new != old {
+                        array.set_item(i, new);
+                    }
+                }
+            }
+            _ => {
+                let array = GpuArray::new(&mut self.slab, &draws);
+                self.draws_array_header.set(array.array());
+                self.draws_array = Some(array);
+            }
+        }
+        self.draws = draws;
+        if self
+            .slab
+            .upkeep((
+                device,
+                queue,
+                Some("indirect draws"),
+                wgpu::BufferUsages::INDIRECT,
+            ))
+            .is_some()
+        {
+            if let Some(culling) = self.frustum_culling.as_mut() {
+                let _ = culling.bindgroup.take();
+            }
+        }
+        self.slab.get_buffer()
+    }
+
+    /// Invalidate any bindgroups that reference the stage's slab buffer.
+    pub fn invalidate_slab_bindgroups(&mut self) {
+        if let Some(culling) = self.frustum_culling.as_mut() {
+            let _ = culling.bindgroup.take();
+        }
+    }
+
+    /// Run the frustum culling compute pass, if culling is enabled.
+    pub fn compute_frustum_culling(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        stage_slab_buffer: &wgpu::Buffer,
+    ) {
+        let draw_count = self.draw_count();
+        let may_indirect_buffer = self.slab.get_buffer();
+        let (Some(culling), Some(indirect_buffer)) =
+            (self.frustum_culling.as_mut(), may_indirect_buffer)
+        else {
+            return;
+        };
+        if draw_count == 0 {
+            return;
+        }
+        let bindgroup = culling.bindgroup.get_or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("frustum culling"),
+                layout: &culling.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: stage_slab_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: indirect_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("frustum culling"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&culling.pipeline);
+        compute_pass.set_bind_group(0, bindgroup, &[]);
+        compute_pass.dispatch_workgroups(draw_count.div_ceil(32), 1, 1);
+    }
+
+    /// Read back the number of visible renderlets that were culled by the
+    /// last frustum culling pass.
+    ///
+    /// This is primarily used for stats and debugging.
+    pub async fn read_culled_count(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<u32, IndirectDrawsError> {
+        let Some(array) = self.draws_array.as_ref() else {
+            return Ok(0);
+        };
+        let range = array.array().into_u32_array();
+        let start = range.starting_index();
+        let data = self
+            .slab
+            .read(
+                device,
+                queue,
+                Some("read culled count"),
+                start..start + range.len(),
+            )
+            .await
+            .context(ReadSnafu)?;
+        let gpu_draws = data.read_vec(Array::<DrawIndirect>::new(0, self.draws.len() as u32));
+        let culled = self
+            .draws
+            .iter()
+            .zip(gpu_draws)
+            .filter(|(cpu, gpu)| cpu.instance_count > 0 && gpu.instance_count == 0)
+            .count();
+        Ok(culled as u32)
+    }
+}
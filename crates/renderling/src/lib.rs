@@ -144,13 +144,14 @@
 pub mod atlas;
 pub mod bits;
 pub mod bloom;
+pub mod bvol;
 pub mod camera;
 pub mod color;
 #[cfg(not(target_arch = "spirv"))]
 mod context;
 pub mod convolution;
-#[cfg(not(target_arch = "spirv"))]
 pub mod cubemap;
+pub mod draw_indirect;
 #[cfg(not(target_arch = "spirv"))]
 pub mod ibl;
 #[cfg(not(target_arch = "spirv"))]
@@ -318,6 +319,135 @@ mod test {
         );
     }
 
+    #[test]
+    // This tests that renderlets with single-sided materials have their back
+    // faces culled, using the CW geometry of `cmy_triangle_backface` alongside
+    // the CCW geometry of `cmy_triangle_sanity`.
+    fn cmy_triangle_backface_culled() {
+        let ctx = Context::headless(100, 100);
+        let mut stage = ctx.new_stage().with_background_color(Vec4::splat(1.0));
+        let camera = stage.new_value(Camera::default_ortho2d(100.0, 100.0));
+        let material = stage.new_value(Material {
+            has_lighting: false,
+            double_sided: false,
+            ..Default::default()
+        });
+        let back = stage.new_array({
+            let mut vs = right_tri_vertices();
+            vs.reverse();
+            vs
+        });
+        let back_tri = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: back.array(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&back_tri);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        frame.present();
+        assert!(
+            img.pixels().all(|p| p.0 == [255, 255, 255, 255]),
+            "back faces should be culled"
+        );
+
+        let front = stage.new_array(right_tri_vertices());
+        let front_tri = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: front.array(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&front_tri);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::assert_img_eq("cmy_triangle.png", img);
+    }
+
+    #[test]
+    // This tests that the front faces of mirrored renderlets are clockwise,
+    // by mirroring the CW geometry of `cmy_triangle_backface`, which would
+    // otherwise be culled.
+    fn cmy_triangle_mirrored_front_faces() {
+        let ctx = Context::headless(100, 100);
+        let mut stage = ctx.new_stage().with_background_color(Vec4::splat(1.0));
+        let camera = stage.new_value(Camera::default_ortho2d(100.0, 100.0));
+        let material = stage.new_value(Material {
+            has_lighting: false,
+            double_sided: false,
+            ..Default::default()
+        });
+        let vertices = stage.new_array({
+            let mut vs = right_tri_vertices();
+            vs.reverse();
+            vs
+        });
+        let transform = stage.new_value(Transform {
+            translation: Vec3::new(100.0, 0.0, 0.0),
+            scale: Vec3::new(-1.0, 1.0, 1.0),
+            ..Default::default()
+        });
+        let tri = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: vertices.array(),
+            transform_id: transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&tri);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        assert!(
+            img.pixels().any(|p| p.0 != [255, 255, 255, 255]),
+            "mirrored front faces should not be culled"
+        );
+    }
+
+    #[test]
+    // This tests that MSAA smooths the triangle's hypotenuse, leaving the
+    // rest of the image untouched.
+    fn cmy_triangle_msaa() {
+        let ctx = Context::headless(100, 100);
+        let mut stage = ctx.new_stage().with_background_color(Vec4::splat(1.0));
+        let camera = stage.new_value(Camera::default_ortho2d(100.0, 100.0));
+        let geometry = stage.new_array(right_tri_vertices());
+        let tri = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&tri);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let aliased = frame.read_image().unwrap();
+        frame.present();
+
+        let mut stage = stage.with_msaa(4);
+        assert_eq!(4, stage.get_msaa_sample_count());
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let antialiased = frame.read_image().unwrap();
+        frame.present();
+        img_diff::save("cmy_triangle/msaa.png", antialiased.clone());
+
+        let differing = aliased
+            .pixels()
+            .zip(antialiased.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(differing > 0, "msaa had no effect");
+        // Only pixels along the hypotenuse should be affected.
+        assert!(differing < 400, "msaa changed {differing} pixels");
+    }
+
     #[test]
     // This tests our ability to update the transform of a `Renderlet` after it
     // has already been sent to the GPU.
@@ -527,6 +657,122 @@ mod test {
         img_diff::assert_eq("cmy_cube/visible_before_again.png", img_before, img);
     }
 
+    #[test]
+    // Test that drawing with `multi_draw_indirect` gives the same results as
+    // drawing each renderlet directly.
+    fn cmy_cube_indirect() {
+        let ctx = Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_background_color(Vec4::splat(1.0))
+            .with_indirect_draws(false);
+        assert!(!stage.is_using_indirect_draws());
+        let (projection, view) = camera::default_perspective(100.0, 100.0);
+        let camera = stage.new_value(Camera {
+            projection,
+            view,
+            ..Default::default()
+        });
+        let geometry = stage.new_array(gpu_cube_vertices());
+        let cube_one_transform = stage.new_value(Transform {
+            translation: Vec3::new(-4.5, 0.0, 0.0),
+            scale: Vec3::new(6.0, 6.0, 6.0),
+            rotation: Quat::from_axis_angle(Vec3::Y, -std::f32::consts::FRAC_PI_4),
+        });
+        let mut renderlet = Renderlet {
+            vertices_array: geometry.array(),
+            camera_id: camera.id(),
+            transform_id: cube_one_transform.id(),
+            ..Default::default()
+        };
+        let cube_one = stage.new_value(renderlet);
+        stage.add_renderlet(&cube_one);
+
+        let cube_two_transform = stage.new_value(Transform {
+            translation: Vec3::new(4.5, 0.0, 0.0),
+            scale: Vec3::new(6.0, 6.0, 6.0),
+            rotation: Quat::from_axis_angle(Vec3::Y, std::f32::consts::FRAC_PI_4),
+        });
+        renderlet.transform_id = cube_two_transform.id();
+        let cube_two = stage.new_value(renderlet);
+        stage.add_renderlet(&cube_two);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let direct_img = frame.read_image().unwrap();
+        frame.present();
+
+        stage.set_use_indirect_draws(true);
+        assert!(stage.is_using_indirect_draws());
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let indirect_img = frame.read_image().unwrap();
+        img_diff::assert_eq("cmy_cube/indirect.png", direct_img, indirect_img.clone());
+        frame.present();
+
+        // the visibility toggle should be picked up by the indirect buffer
+        cube_two.modify(|r| r.visible = false);
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::assert_img_eq("cmy_cube/visible_after.png", img);
+        frame.present();
+    }
+
+    #[test]
+    // Test that renderlets outside of the camera's frustum are culled.
+    fn cmy_cube_frustum_culling() {
+        let ctx = Context::headless(100, 100);
+        let mut stage = ctx.new_stage().with_background_color(Vec4::splat(1.0));
+        let (projection, view) = camera::default_perspective(100.0, 100.0);
+        let camera = stage.new_value(Camera {
+            projection,
+            view,
+            ..Default::default()
+        });
+        let geometry = stage.new_array(gpu_cube_vertices());
+        let bounds = bvol::Aabb::new(Vec3::splat(-0.5), Vec3::splat(0.5));
+        let cube_one_transform = stage.new_value(Transform {
+            translation: Vec3::new(-4.5, 0.0, 0.0),
+            scale: Vec3::new(6.0, 6.0, 6.0),
+            rotation: Quat::from_axis_angle(Vec3::Y, -std::f32::consts::FRAC_PI_4),
+        });
+        let mut renderlet = Renderlet {
+            vertices_array: geometry.array(),
+            camera_id: camera.id(),
+            transform_id: cube_one_transform.id(),
+            bounds,
+            ..Default::default()
+        };
+        let cube_one = stage.new_value(renderlet);
+        stage.add_renderlet(&cube_one);
+
+        let cube_two_transform = stage.new_value(Transform {
+            translation: Vec3::new(4.5, 0.0, 0.0),
+            scale: Vec3::new(6.0, 6.0, 6.0),
+            rotation: Quat::from_axis_angle(Vec3::Y, std::f32::consts::FRAC_PI_4),
+        });
+        renderlet.transform_id = cube_two_transform.id();
+        let cube_two = stage.new_value(renderlet);
+        stage.add_renderlet(&cube_two);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::assert_img_eq("cmy_cube/visible_before.png", img);
+        frame.present();
+        assert_eq!(0, stage.read_culled_count().unwrap());
+
+        // move cube two behind the camera, it should be culled
+        cube_two_transform.modify(|t| t.translation = Vec3::new(0.0, 12.0, 40.0));
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::assert_img_eq("cmy_cube/visible_after.png", img);
+        frame.present();
+        assert_eq!(1, stage.read_culled_count().unwrap());
+    }
+
     #[test]
     // Tests the ability to specify indexed vertices, as well as the ability to
     // update a field within a struct stored on the slab by using a `Hybrid`.
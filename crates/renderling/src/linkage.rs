@@ -13,6 +13,12 @@ pub mod bloom_vertex;
 pub mod brdf_lut_convolution_fragment;
 #[cfg(feature = "brdf_lut_convolution_vertex")]
 pub mod brdf_lut_convolution_vertex;
+#[cfg(feature = "compute_frustum_culling")]
+pub mod compute_frustum_culling;
+#[cfg(feature = "compute_light_tiles")]
+pub mod compute_light_tiles;
+#[cfg(feature = "depth_prepass_fragment")]
+pub mod depth_prepass_fragment;
 #[cfg(feature = "generate_mipmap_fragment")]
 pub mod generate_mipmap_fragment;
 #[cfg(feature = "generate_mipmap_vertex")]
@@ -25,6 +31,10 @@ pub mod prefilter_environment_cubemap_vertex;
 pub mod renderlet_fragment;
 #[cfg(feature = "renderlet_vertex")]
 pub mod renderlet_vertex;
+#[cfg(feature = "shadow_mapping_vertex")]
+pub mod shadow_mapping_vertex;
+#[cfg(feature = "sheen_lut_convolution_fragment")]
+pub mod sheen_lut_convolution_fragment;
 #[cfg(feature = "skybox_cubemap_fragment")]
 pub mod skybox_cubemap_fragment;
 #[cfg(feature = "skybox_cubemap_vertex")]
@@ -135,6 +145,24 @@ pub fn atlas_and_skybox_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGro
     let (prefilter, prefilter_sampler) = cubemap_entry(4);
     let (brdf, brdf_sampler) = image2d_entry(6);
     let (environment, environment_sampler) = cubemap_entry(8);
+    let shadow_maps = wgpu::BindGroupLayoutEntry {
+        binding: 10,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2Array,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let shadow_maps_sampler = wgpu::BindGroupLayoutEntry {
+        binding: 11,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    };
+    let (transmission_background, transmission_background_sampler) = image2d_entry(12);
+    let (sheen_lut, sheen_lut_sampler) = image2d_entry(14);
     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("atlas and skybox"),
         entries: &[
@@ -148,6 +176,12 @@ pub fn atlas_and_skybox_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGro
             brdf_sampler,
             environment,
             environment_sampler,
+            shadow_maps,
+            shadow_maps_sampler,
+            transmission_background,
+            transmission_background_sampler,
+            sheen_lut,
+            sheen_lut_sampler,
         ],
     })
 }
@@ -157,6 +191,8 @@ pub fn atlas_and_skybox_bindgroup(
     layout: &wgpu::BindGroupLayout,
     atlas: &crate::atlas::Atlas,
     skybox: &crate::skybox::Skybox,
+    shadow_maps: &crate::texture::Texture,
+    transmission_background: &crate::texture::Texture,
 ) -> wgpu::BindGroup {
     let label = Some("atlas and skybox");
     device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -207,6 +243,30 @@ pub fn atlas_and_skybox_bindgroup(
                 binding: 9,
                 resource: wgpu::BindingResource::Sampler(&skybox.environment_cubemap.sampler),
             },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(&shadow_maps.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::Sampler(&shadow_maps.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::TextureView(&transmission_background.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::Sampler(&transmission_background.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::TextureView(&skybox.sheen_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: wgpu::BindingResource::Sampler(&skybox.sheen_lut.sampler),
+            },
         ],
     })
 }
@@ -324,7 +384,15 @@ mod test {
                 }
             }
             if ext == "spv" {
-                validate_src(&path);
+                // naga's WGSL front end recurses once per level of nesting, and
+                // our structured shaders nest deeply enough to overflow the
+                // default test thread stack.
+                std::thread::Builder::new()
+                    .stack_size(16 * 1024 * 1024)
+                    .spawn(move || validate_src(&path))
+                    .unwrap()
+                    .join()
+                    .unwrap();
             }
         }
     }
@@ -0,0 +1,22 @@
+#![allow(dead_code)]
+//! Automatically generated with `cd shaders && cargo run --release`.
+//!
+//! Provides the shader linkage for
+//! [draw_indirect::compute_frustum_culling](crate::draw_indirect::compute_frustum_culling).
+//!
+//! **source path**:
+//! `crates/renderling/src/linkage/draw_indirect-compute_frustum_culling.spv`
+use super::ShaderLinkage;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+pub const ENTRY_POINT: &str = "draw_indirect::compute_frustum_culling";
+#[cfg(target_arch = "wasm32")]
+pub const ENTRY_POINT: &str = "draw_indirectcompute_frustum_culling";
+pub fn linkage(device: &wgpu::Device) -> ShaderLinkage {
+    ShaderLinkage {
+        module: Arc::new(device.create_shader_module(wgpu::include_spirv!(
+            "draw_indirect-compute_frustum_culling.spv"
+        ))),
+        entry_point: ENTRY_POINT,
+    }
+}
@@ -0,0 +1,22 @@
+#![allow(dead_code)]
+//! Automatically generated with `cd shaders && cargo run --release`.
+//!
+//! Provides the shader linkage for
+//! [pbr::tiling::compute_light_tiles](crate::pbr::tiling::compute_light_tiles).
+//!
+//! **source path**:
+//! `crates/renderling/src/linkage/pbr-tiling-compute_light_tiles.spv`
+use super::ShaderLinkage;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+pub const ENTRY_POINT: &str = "pbr::tiling::compute_light_tiles";
+#[cfg(target_arch = "wasm32")]
+pub const ENTRY_POINT: &str = "pbrtilingcompute_light_tiles";
+pub fn linkage(device: &wgpu::Device) -> ShaderLinkage {
+    ShaderLinkage {
+        module: Arc::new(
+            device.create_shader_module(wgpu::include_spirv!("pbr-tiling-compute_light_tiles.spv")),
+        ),
+        entry_point: ENTRY_POINT,
+    }
+}
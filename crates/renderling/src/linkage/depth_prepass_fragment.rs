@@ -0,0 +1,22 @@
+#![allow(dead_code)]
+//! Automatically generated with `cd shaders && cargo run --release`.
+//!
+//! Provides the shader linkage for
+//! [pbr::tiling::depth_prepass_fragment](crate::pbr::tiling::depth_prepass_fragment).
+//!
+//! **source path**:
+//! `crates/renderling/src/linkage/pbr-tiling-depth_prepass_fragment.spv`
+use super::ShaderLinkage;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+pub const ENTRY_POINT: &str = "pbr::tiling::depth_prepass_fragment";
+#[cfg(target_arch = "wasm32")]
+pub const ENTRY_POINT: &str = "pbrtilingdepth_prepass_fragment";
+pub fn linkage(device: &wgpu::Device) -> ShaderLinkage {
+    ShaderLinkage {
+        module: Arc::new(device.create_shader_module(wgpu::include_spirv!(
+            "pbr-tiling-depth_prepass_fragment.spv"
+        ))),
+        entry_point: ENTRY_POINT,
+    }
+}
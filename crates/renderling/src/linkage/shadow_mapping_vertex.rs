@@ -0,0 +1,23 @@
+#![allow(dead_code)]
+//! Automatically generated with `cd shaders && cargo run --release`.
+//!
+//! Provides the shader linkage for
+//! [pbr::shadow::shadow_mapping_vertex](crate::pbr::shadow::shadow_mapping_vertex).
+//!
+//! **source path**:
+//! `crates/renderling/src/linkage/pbr-shadow-shadow_mapping_vertex.spv`
+use super::ShaderLinkage;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+pub const ENTRY_POINT: &str = "pbr::shadow::shadow_mapping_vertex";
+#[cfg(target_arch = "wasm32")]
+pub const ENTRY_POINT: &str = "pbrshadowshadow_mapping_vertex";
+pub fn linkage(device: &wgpu::Device) -> ShaderLinkage {
+    ShaderLinkage {
+        module: Arc::new(
+            device
+                .create_shader_module(wgpu::include_spirv!("pbr-shadow-shadow_mapping_vertex.spv")),
+        ),
+        entry_point: ENTRY_POINT,
+    }
+}
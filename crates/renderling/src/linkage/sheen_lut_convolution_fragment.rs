@@ -0,0 +1,23 @@
+#![allow(dead_code)]
+//! Automatically generated with `cd shaders && cargo run --release`.
+//!
+//! Provides the shader linkage for
+//! [convolution::sheen_lut_convolution_fragment](crate::convolution::sheen_lut_convolution_fragment).
+//!
+//! **source path**:
+//! `crates/renderling/src/linkage/convolution-sheen_lut_convolution_fragment.
+//! spv`
+use super::ShaderLinkage;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+pub const ENTRY_POINT: &str = "convolution::sheen_lut_convolution_fragment";
+#[cfg(target_arch = "wasm32")]
+pub const ENTRY_POINT: &str = "convolutionsheen_lut_convolution_fragment";
+pub fn linkage(device: &wgpu::Device) -> ShaderLinkage {
+    ShaderLinkage {
+        module: Arc::new(device.create_shader_module(wgpu::include_spirv!(
+            "convolution-sheen_lut_convolution_fragment.spv"
+        ))),
+        entry_point: ENTRY_POINT,
+    }
+}
@@ -8,7 +8,7 @@
 //! Lastly, it provides some constant geometry used in many shaders.
 use core::ops::Mul;
 use spirv_std::{
-    image::{Cubemap, Image2d},
+    image::{Cubemap, Image2d, Image2dArray},
     Sampler,
 };
 
@@ -32,6 +32,21 @@ impl Sample2d for Image2d {
     }
 }
 
+pub trait Sample2dArray {
+    type Sampler: IsSampler;
+
+    /// Sample the texture at `uv.xy` in the layer `uv.z`.
+    fn sample_by_lod(&self, sampler: Self::Sampler, uv: glam::Vec3, lod: f32) -> glam::Vec4;
+}
+
+impl Sample2dArray for Image2dArray {
+    type Sampler = Sampler;
+
+    fn sample_by_lod(&self, sampler: Self::Sampler, uv: glam::Vec3, lod: f32) -> glam::Vec4 {
+        self.sample_by_lod(sampler, uv, lod)
+    }
+}
+
 pub trait SampleCube {
     type Sampler: IsSampler;
 
@@ -495,10 +510,107 @@ pub fn reflect(i: Vec3, n: Vec3) -> Vec3 {
     i - 2.0 * n.dot(i) * n
 }
 
+/// Refracts the incident vector `i` through a surface with normal `n`, where
+/// `eta` is the ratio of the indices of refraction.
+///
+/// Returns zero on total internal reflection.
+pub fn refract(i: Vec3, n: Vec3, eta: f32) -> Vec3 {
+    let n = n.alt_norm_or_zero();
+    let n_dot_i = n.dot(i);
+    let k = 1.0 - eta * eta * (1.0 - n_dot_i * n_dot_i);
+    if k < 0.0 {
+        Vec3::ZERO
+    } else {
+        eta * i - (eta * n_dot_i + k.sqrt()) * n
+    }
+}
+
+/// A dual quaternion, representing a rotation followed by a translation.
+///
+/// Unlike matrices, dual quaternions can be blended without collapsing the
+/// geometry they transform, which makes them useful for skinning.
+///
+/// See [`crate::stage::SkinningMode::DualQuaternion`].
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq)]
+pub struct DualQuat {
+    pub real: Quat,
+    pub dual: Quat,
+}
+
+/// The Hamilton product of two quaternions, which unlike [`Quat`]'s `Mul`
+/// doesn't require them to be normalized.
+fn quat_product(a: Quat, b: Quat) -> Quat {
+    Quat::from_xyzw(
+        a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+        a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+        a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+    )
+}
+
+impl DualQuat {
+    pub const ZERO: Self = DualQuat {
+        real: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+        dual: Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+    };
+
+    pub fn from_rotation_translation(rotation: Quat, translation: Vec3) -> Self {
+        let t = Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0);
+        DualQuat {
+            real: rotation,
+            dual: quat_product(t, rotation) * 0.5,
+        }
+    }
+
+    /// Returns `self` plus `other` scaled by `weight`.
+    ///
+    /// `other` is flipped to the hemisphere of `self` first, so that blending
+    /// takes the shortest path.
+    pub fn add_weighted(self, other: Self, weight: f32) -> Self {
+        let weight = if self.real.dot(other.real) < 0.0 {
+            -weight
+        } else {
+            weight
+        };
+        DualQuat {
+            real: self.real + other.real * weight,
+            dual: self.dual + other.dual * weight,
+        }
+    }
+
+    /// Normalizes `self` and extracts its rotation and translation.
+    ///
+    /// Returns `(Quat::IDENTITY, Vec3::ZERO)` if `self` is zero.
+    pub fn to_rotation_translation(self) -> (Quat, Vec3) {
+        let length = self.real.length();
+        if length == 0.0 {
+            return (Quat::IDENTITY, Vec3::ZERO);
+        }
+        let real = self.real / length;
+        let dual = self.dual / length;
+        let translation = quat_product(dual * 2.0, real.conjugate()).xyz();
+        (real, translation)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn refract_sanity() {
+        let i = Vec3::new(1.0, -1.0, 0.0).normalize();
+        // matching indices of refraction don't bend the ray
+        assert!(refract(i, Vec3::Y, 1.0).abs_diff_eq(i, 1.0e-6));
+        // entering a denser medium bends the ray toward the normal
+        let r = refract(i, Vec3::Y, 1.0 / 1.5);
+        assert!(r.x < i.x && r.x > 0.0);
+        assert!((r.length() - 1.0).abs() < 1.0e-6);
+        // total internal reflection
+        assert_eq!(Vec3::ZERO, refract(i, Vec3::Y, 1.5));
+    }
+
     #[test]
     fn step_sanity() {
         assert_eq!(0.0, step(0.0, -0.33333));
@@ -525,4 +637,29 @@ mod test {
         let nan = 0.0 / 0.0;
         assert_eq!(0.0, signum_or_zero(nan));
     }
+
+    #[test]
+    fn dual_quat_sanity() {
+        let rotation = Quat::from_rotation_y(1.0);
+        let translation = Vec3::new(1.0, 2.0, 3.0);
+        let dq = DualQuat::from_rotation_translation(rotation, translation);
+        let (r, t) = dq.to_rotation_translation();
+        assert!(r.abs_diff_eq(rotation, 1.0e-6));
+        assert!(t.abs_diff_eq(translation, 1.0e-6));
+
+        // halfway between two rotations about a point is the rotation halfway
+        let a = DualQuat::from_rotation_translation(Quat::IDENTITY, Vec3::X);
+        let b = DualQuat::from_rotation_translation(-Quat::from_rotation_z(1.0), Vec3::X);
+        let (r, t) = DualQuat::ZERO
+            .add_weighted(a, 0.5)
+            .add_weighted(b, 0.5)
+            .to_rotation_translation();
+        assert!(r.abs_diff_eq(Quat::from_rotation_z(0.5), 1.0e-6), "{r}");
+        assert!(t.abs_diff_eq(Vec3::X, 1.0e-6), "{t}");
+
+        assert_eq!(
+            (Quat::IDENTITY, Vec3::ZERO),
+            DualQuat::ZERO.to_rotation_translation()
+        );
+    }
 }
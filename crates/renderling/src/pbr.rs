@@ -14,7 +14,7 @@ use spirv_std::num_traits::Float;
 use crate::{
     atlas::AtlasTexture,
     camera::Camera,
-    math::{self, IsSampler, IsVector, Sample2d, SampleCube},
+    math::{self, IsSampler, IsVector, Sample2d, Sample2dArray, SampleCube},
     pbr::light::{DirectionalLight, PointLight, SpotLight},
     println as my_println,
 };
@@ -25,6 +25,305 @@ use debug::DebugMode;
 pub mod light;
 use light::LightStyle;
 
+pub mod shadow;
+pub mod tiling;
+
+/// How the alpha value of a [`Material`]'s albedo is interpreted.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[repr(u32)]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub enum AlphaMode {
+    /// Alpha is ignored and the rendered output is fully opaque.
+    #[default]
+    Opaque,
+
+    /// The rendered output is either fully opaque or fully transparent,
+    /// depending on whether alpha is below [`Material::alpha_cutoff`].
+    Mask,
+
+    /// The rendered output is blended with the background using alpha.
+    ///
+    /// The stage draws renderlets with blended materials after opaque
+    /// renderlets, back-to-front.
+    Blend,
+}
+
+/// An affine transform of a texture's UV coordinates, applied before the
+/// texture is sampled.
+///
+/// See <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_texture_transform>
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct TextureTransform {
+    /// Offset of the UV coordinate origin as a factor of the texture
+    /// dimensions.
+    pub offset: Vec2,
+    /// Rotation of the UVs in radians, counter-clockwise around the origin.
+    pub rotation: f32,
+    /// Scale factor applied to the UV coordinates.
+    pub scale: Vec2,
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        TextureTransform {
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl TextureTransform {
+    /// Returns the given UV coordinates scaled, then rotated, then offset.
+    pub fn transform_uv(&self, uv: Vec2) -> Vec2 {
+        let uv = uv * self.scale;
+        let (sin, cos) = (self.rotation.sin(), self.rotation.cos());
+        Vec2::new(cos * uv.x + sin * uv.y, cos * uv.y - sin * uv.x) + self.offset
+    }
+}
+
+/// A clear, reflective layer on top of a [`Material`], like the lacquer on
+/// car paint.
+///
+/// See <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_clearcoat>
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct Clearcoat {
+    /// Intensity of the clearcoat layer, multiplied by the red channel of
+    /// `texture_id`.
+    pub factor: f32,
+    /// Roughness of the clearcoat layer, multiplied by the green channel of
+    /// `roughness_texture_id`.
+    pub roughness_factor: f32,
+
+    pub texture_id: Id<AtlasTexture>,
+    pub roughness_texture_id: Id<AtlasTexture>,
+    /// Normal map of the clearcoat layer.
+    ///
+    /// Without a normal map the clearcoat layer uses the geometry's normal,
+    /// not the normal from the base layer's normal map.
+    pub normal_texture_id: Id<AtlasTexture>,
+
+    pub tex_coord: u32,
+    pub roughness_tex_coord: u32,
+    pub normal_tex_coord: u32,
+
+    pub tex_transform: TextureTransform,
+    pub roughness_tex_transform: TextureTransform,
+    pub normal_tex_transform: TextureTransform,
+}
+
+impl Default for Clearcoat {
+    fn default() -> Self {
+        Self {
+            factor: 0.0,
+            roughness_factor: 0.0,
+            texture_id: Id::NONE,
+            roughness_texture_id: Id::NONE,
+            normal_texture_id: Id::NONE,
+            tex_coord: 0,
+            roughness_tex_coord: 0,
+            normal_tex_coord: 0,
+            tex_transform: TextureTransform::default(),
+            roughness_tex_transform: TextureTransform::default(),
+            normal_tex_transform: TextureTransform::default(),
+        }
+    }
+}
+
+/// The clearcoat layer of a single fragment, after texture lookups.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct ClearcoatFragment {
+    pub factor: f32,
+    pub roughness: f32,
+    // normal of the clearcoat layer in world space
+    pub normal: Vec3,
+    // specular reflection of the environment along the clearcoat normal
+    pub prefiltered: Vec3,
+    pub brdf: Vec2,
+}
+
+/// Transmission of light through a [`Material`], like through glass, along
+/// with the volume the light travels through.
+///
+/// The stage draws renderlets with transmissive materials after opaque
+/// renderlets, so they can refract the renderlets behind them.
+///
+/// See
+/// * <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_transmission>
+/// * <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_volume>
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct Transmission {
+    /// Percentage of light transmitted through the surface, multiplied by the
+    /// red channel of `texture_id`.
+    pub factor: f32,
+    /// Thickness of the volume in the renderlet's local space, multiplied by
+    /// the green channel of `thickness_texture_id`.
+    ///
+    /// A thickness of zero means the material is thin-walled, so light is
+    /// neither refracted nor attenuated.
+    pub thickness_factor: f32,
+    /// Average distance in world space that light travels in the volume
+    /// before interacting with a particle.
+    pub attenuation_distance: f32,
+    /// Color that white light turns into after travelling
+    /// `attenuation_distance` through the volume.
+    pub attenuation_color: Vec3,
+
+    pub texture_id: Id<AtlasTexture>,
+    pub thickness_texture_id: Id<AtlasTexture>,
+
+    pub tex_coord: u32,
+    pub thickness_tex_coord: u32,
+
+    pub tex_transform: TextureTransform,
+    pub thickness_tex_transform: TextureTransform,
+}
+
+impl Default for Transmission {
+    fn default() -> Self {
+        Self {
+            factor: 0.0,
+            thickness_factor: 0.0,
+            attenuation_distance: f32::MAX,
+            attenuation_color: Vec3::ONE,
+            texture_id: Id::NONE,
+            thickness_texture_id: Id::NONE,
+            tex_coord: 0,
+            thickness_tex_coord: 0,
+            tex_transform: TextureTransform::default(),
+            thickness_tex_transform: TextureTransform::default(),
+        }
+    }
+}
+
+/// The transmission of a single fragment, after texture lookups.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct TransmissionFragment {
+    pub factor: f32,
+    // light transmitted from behind the fragment, tinted by the albedo and
+    // attenuated by the volume
+    pub transmitted: Vec3,
+}
+
+/// Soft, back-scattered highlights on the surface of a [`Material`], like
+/// the sheen of cloth.
+///
+/// See <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_sheen>
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct Sheen {
+    /// Color of the sheen, multiplied by the RGB channels of
+    /// `color_texture_id`.
+    pub color_factor: Vec3,
+    /// Roughness of the sheen, multiplied by the alpha channel of
+    /// `roughness_texture_id`.
+    pub roughness_factor: f32,
+
+    pub color_texture_id: Id<AtlasTexture>,
+    pub roughness_texture_id: Id<AtlasTexture>,
+
+    pub color_tex_coord: u32,
+    pub roughness_tex_coord: u32,
+
+    pub color_tex_transform: TextureTransform,
+    pub roughness_tex_transform: TextureTransform,
+}
+
+impl Default for Sheen {
+    fn default() -> Self {
+        Self {
+            color_factor: Vec3::ZERO,
+            roughness_factor: 0.0,
+            color_texture_id: Id::NONE,
+            roughness_texture_id: Id::NONE,
+            color_tex_coord: 0,
+            roughness_tex_coord: 0,
+            color_tex_transform: TextureTransform::default(),
+            roughness_tex_transform: TextureTransform::default(),
+        }
+    }
+}
+
+/// The sheen of a single fragment, after texture lookups.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct SheenFragment {
+    pub color: Vec3,
+    pub roughness: f32,
+    // reflection of the environment along the normal
+    pub prefiltered: Vec3,
+    // directional albedo of the sheen BRDF, from the sheen LUT
+    pub albedo: f32,
+}
+
+/// Strength and color of the specular reflection of the dielectric part of
+/// a [`Material`].
+///
+/// See <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_materials_specular>
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct Specular {
+    /// Strength of the specular reflection, multiplied by the alpha channel
+    /// of `texture_id`.
+    pub factor: f32,
+    /// Color of the specular reflection at normal incidence, multiplied by
+    /// the RGB channels of `color_texture_id`.
+    pub color_factor: Vec3,
+
+    pub texture_id: Id<AtlasTexture>,
+    pub color_texture_id: Id<AtlasTexture>,
+
+    pub tex_coord: u32,
+    pub color_tex_coord: u32,
+
+    pub tex_transform: TextureTransform,
+    pub color_tex_transform: TextureTransform,
+}
+
+impl Default for Specular {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            color_factor: Vec3::ONE,
+            texture_id: Id::NONE,
+            color_texture_id: Id::NONE,
+            tex_coord: 0,
+            color_tex_coord: 0,
+            tex_transform: TextureTransform::default(),
+            color_tex_transform: TextureTransform::default(),
+        }
+    }
+}
+
+/// The specular reflection of the dielectric part of a single fragment,
+/// after texture lookups.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpecularFragment {
+    pub factor: f32,
+    // specular color, scaled by the reflectance of the material's index of
+    // refraction relative to that of the default index of refraction
+    pub color: Vec3,
+}
+
+impl Default for SpecularFragment {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            color: Vec3::ONE,
+        }
+    }
+}
+
 /// Represents a material on the GPU.
 #[repr(C)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
@@ -48,8 +347,39 @@ pub struct Material {
     pub ao_tex_coord: u32,
     pub emissive_tex_coord: u32,
 
+    pub albedo_tex_transform: TextureTransform,
+    pub metallic_roughness_tex_transform: TextureTransform,
+    pub normal_tex_transform: TextureTransform,
+    pub ao_tex_transform: TextureTransform,
+    pub emissive_tex_transform: TextureTransform,
+
     pub has_lighting: bool,
     pub ao_strength: f32,
+
+    pub alpha_mode: AlphaMode,
+    /// Fragments with an alpha value below this cutoff are discarded when
+    /// `alpha_mode` is [`AlphaMode::Mask`].
+    pub alpha_cutoff: f32,
+    /// Whether back faces are drawn, and lit as if they were front faces by
+    /// flipping their normals.
+    ///
+    /// The back faces of renderlets with single-sided materials are culled.
+    /// Defaults to `true`, so renderlets are drawn without face culling unless
+    /// their material asks for it.
+    pub double_sided: bool,
+    /// Optional clearcoat layer.
+    pub clearcoat_id: Id<Clearcoat>,
+    /// Optional transmission and volume.
+    pub transmission_id: Id<Transmission>,
+    /// Index of refraction of the material.
+    ///
+    /// Determines the reflectance of the dielectric part of the material and
+    /// how transmitted light is refracted.
+    pub ior: f32,
+    /// Optional sheen layer.
+    pub sheen_id: Id<Sheen>,
+    /// Optional specular strength and color.
+    pub specular_id: Id<Specular>,
 }
 
 impl Default for Material {
@@ -72,6 +402,19 @@ impl Default for Material {
             ao_strength: 0.0,
             emissive_texture_id: Id::NONE,
             emissive_tex_coord: 0,
+            albedo_tex_transform: TextureTransform::default(),
+            metallic_roughness_tex_transform: TextureTransform::default(),
+            normal_tex_transform: TextureTransform::default(),
+            ao_tex_transform: TextureTransform::default(),
+            emissive_tex_transform: TextureTransform::default(),
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: true,
+            clearcoat_id: Id::NONE,
+            transmission_id: Id::NONE,
+            ior: 1.5,
+            sheen_id: Id::NONE,
+            specular_id: Id::NONE,
         }
     }
 }
@@ -135,6 +478,72 @@ fn fresnel_schlick_roughness(cos_theta: f32, f0: Vec3, roughness: f32) -> Vec3 {
     f0 + (Vec3::splat(1.0 - roughness).max(f0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powf(5.0)
 }
 
+/// Returns the reflectance at normal incidence of a dielectric with the given
+/// index of refraction.
+fn ior_to_f0(ior: f32) -> f32 {
+    let r = (ior - 1.0) / (ior + 1.0);
+    r * r
+}
+
+/// "Charlie" sheen normal distribution function.
+///
+/// See <https://blog.selfshadow.com/publications/s2017-shading-course/imageworks/s2017_pbs_imageworks_sheen.pdf>
+pub fn sheen_distribution_charlie(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness.max(0.000001);
+    let alpha = alpha * alpha;
+    let inv_alpha = 1.0 / alpha;
+    let sin2h = 1.0 - n_dot_h * n_dot_h;
+    (2.0 + inv_alpha) * sin2h.max(0.0).powf(inv_alpha * 0.5) / (2.0 * core::f32::consts::PI)
+}
+
+fn sheen_lambda_helper(x: f32, alpha: f32) -> f32 {
+    let one_minus_alpha_sq = (1.0 - alpha) * (1.0 - alpha);
+    let lerp = |a: f32, b: f32| a + (b - a) * one_minus_alpha_sq;
+    let a = lerp(21.5473, 25.3245);
+    let b = lerp(3.82987, 3.32435);
+    let c = lerp(0.19823, 0.16801);
+    let d = lerp(-1.97760, -1.27393);
+    let e = lerp(-4.32054, -4.85967);
+    a / (1.0 + b * x.powf(c)) + d * x + e
+}
+
+fn sheen_lambda(cos_theta: f32, alpha: f32) -> f32 {
+    if cos_theta.abs() < 0.5 {
+        sheen_lambda_helper(cos_theta, alpha).exp()
+    } else {
+        (2.0 * sheen_lambda_helper(0.5, alpha) - sheen_lambda_helper(1.0 - cos_theta, alpha)).exp()
+    }
+}
+
+/// Visibility term of the "Charlie" sheen BRDF.
+pub fn sheen_visibility(n_dot_l: f32, n_dot_v: f32, roughness: f32) -> f32 {
+    let alpha = roughness.max(0.000001);
+    let alpha = alpha * alpha;
+    let denominator = (1.0 + sheen_lambda(n_dot_v, alpha) + sheen_lambda(n_dot_l, alpha))
+        * 4.0
+        * n_dot_v
+        * n_dot_l;
+    (1.0 / denominator).clamp(0.0, 1.0)
+}
+
+/// Outgoing radiance of a sheen layer.
+fn sheen_radiance(
+    light_color: Vec4,
+    attenuation: f32,
+    v: Vec3,
+    l: Vec3,
+    n: Vec3,
+    color: Vec3,
+    roughness: f32,
+) -> Vec3 {
+    let radiance = light_color.xyz() * attenuation;
+    let h = (v + l).alt_norm_or_zero();
+    let n_dot_l = n.dot(l).max(0.0);
+    let d = sheen_distribution_charlie(n.dot(h).max(0.0), roughness);
+    let vis = sheen_visibility(n_dot_l, n.dot(v).max(0.0), roughness);
+    color * d * vis * radiance * n_dot_l
+}
+
 #[allow(clippy::too_many_arguments)]
 fn outgoing_radiance(
     light_color: Vec4,
@@ -145,6 +554,8 @@ fn outgoing_radiance(
     n: Vec3,
     metalness: f32,
     roughness: f32,
+    transmission: f32,
+    specular_layer: SpecularFragment,
 ) -> Vec3 {
     my_println!("outgoing_radiance");
     my_println!("    light_color: {light_color:?}");
@@ -156,7 +567,7 @@ fn outgoing_radiance(
     my_println!("    metalness: {metalness:?}");
     my_println!("    roughness: {roughness:?}");
 
-    let f0 = Vec3::splat(0.4).lerp(albedo, metalness);
+    let f0 = (Vec3::splat(0.4) * specular_layer.color).lerp(albedo, metalness);
     my_println!("    f0: {f0:?}");
     let radiance = light_color.xyz() * attenuation;
     my_println!("    radiance: {radiance:?}");
@@ -167,11 +578,14 @@ fn outgoing_radiance(
     my_println!("    ndf: {ndf:?}");
     let g: f32 = geometry_smith(n, v, l, roughness);
     my_println!("    g: {g:?}");
-    let f: Vec3 = fresnel_schlick(h.dot(v).max(0.0), f0);
+    // the specular factor only weakens the reflection of dielectrics
+    let f: Vec3 = fresnel_schlick(h.dot(v).max(0.0), f0)
+        * (specular_layer.factor + (1.0 - specular_layer.factor) * metalness);
     my_println!("    f: {f:?}");
 
     let k_s = f;
-    let k_d = (Vec3::splat(1.0) - k_s) * (1.0 - metalness);
+    // transmitted light replaces diffuse light
+    let k_d = (Vec3::splat(1.0) - k_s) * (1.0 - metalness) * (1.0 - transmission);
     my_println!("    k_s: {k_s:?}");
 
     let numerator: Vec3 = ndf * g * f;
@@ -186,6 +600,26 @@ fn outgoing_radiance(
     (k_d * albedo / core::f32::consts::PI + specular) * radiance * n_dot_l
 }
 
+/// Outgoing radiance of the specular lobe of a clearcoat layer, which is a
+/// dielectric with an index of refraction of 1.5.
+fn clearcoat_radiance(
+    light_color: Vec4,
+    attenuation: f32,
+    v: Vec3,
+    l: Vec3,
+    n: Vec3,
+    roughness: f32,
+) -> Vec3 {
+    let radiance = light_color.xyz() * attenuation;
+    let h = (v + l).alt_norm_or_zero();
+    let ndf = normal_distribution_ggx(n, h, roughness);
+    let g = geometry_smith(n, v, l, roughness);
+    let f = fresnel_schlick(h.dot(v).max(0.0), Vec3::splat(0.04));
+    let n_dot_l = n.dot(l).max(0.0);
+    let denominator = 4.0 * n.dot(v).max(0.0) * n_dot_l + 0.0001;
+    ndf * g * f / denominator * radiance * n_dot_l
+}
+
 pub fn sample_irradiance<T: SampleCube<Sampler = S>, S: IsSampler>(
     irradiance: &T,
     irradiance_sampler: &S,
@@ -238,6 +672,7 @@ pub struct PbrConfig {
     pub debug_mode: debug::DebugMode,
     pub has_lighting: bool,
     pub light_array: Array<Id<light::Light>>,
+    pub light_tiling: Id<tiling::LightTilingDescriptor>,
 }
 
 impl Default for PbrConfig {
@@ -248,6 +683,7 @@ impl Default for PbrConfig {
             debug_mode: Default::default(),
             has_lighting: true,
             light_array: Default::default(),
+            light_tiling: Id::NONE,
         }
     }
 }
@@ -288,8 +724,14 @@ pub fn texture_color<T: Sample2d<Sampler = S>, S: IsSampler>(
 }
 
 /// PBR fragment shader capable of being run on CPU or GPU.
+///
+/// Returns whether the fragment should be discarded, which is the case for
+/// fragments of [`AlphaMode::Mask`] materials with an alpha below the
+/// material's cutoff and for fully transparent fragments of
+/// [`AlphaMode::Blend`] materials. Discarded fragments are written as
+/// [`Vec4::ZERO`].
 #[allow(clippy::too_many_arguments)]
-pub fn fragment_impl<T, C, S>(
+pub fn fragment_impl<T, C, A, S>(
     atlas: &T,
     atlas_sampler: &S,
     irradiance: &C,
@@ -298,18 +740,26 @@ pub fn fragment_impl<T, C, S>(
     prefiltered_sampler: &S,
     brdf: &T,
     brdf_sampler: &S,
+    sheen_lut: &T,
+    sheen_lut_sampler: &S,
+    shadow_maps: &A,
+    shadow_maps_sampler: &S,
+    transmission_background: &T,
+    transmission_background_sampler: &S,
     slab: &[u32],
 
     PbrConfig {
         atlas_size,
-        resolution: _,
+        resolution,
         debug_mode,
         has_lighting,
         light_array,
+        light_tiling,
     }: PbrConfig,
 
     in_camera: Id<Camera>,
     in_material: Id<Material>,
+    in_front_facing: bool,
     in_color: Vec4,
     in_uv0: Vec2,
     in_uv1: Vec2,
@@ -319,19 +769,30 @@ pub fn fragment_impl<T, C, S>(
     in_pos: Vec3,
 
     output: &mut Vec4,
-) where
+) -> bool
+where
     T: Sample2d<Sampler = S>,
     C: SampleCube<Sampler = S>,
+    A: Sample2dArray<Sampler = S>,
     S: IsSampler,
 {
     let material = get_material(in_material, has_lighting, slab);
     my_println!("material: {:?}", material);
 
-    let albedo_tex_uv = if material.albedo_tex_coord == 0 {
-        in_uv0
+    // back faces of double-sided materials are lit from the back
+    let (in_norm, in_tangent, in_bitangent) = if material.double_sided && !in_front_facing {
+        (-in_norm, -in_tangent, -in_bitangent)
     } else {
-        in_uv1
+        (in_norm, in_tangent, in_bitangent)
     };
+
+    let albedo_tex_uv = material.albedo_tex_transform.transform_uv(
+        if material.albedo_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
     let albedo_tex_color = texture_color(
         material.albedo_texture_id,
         albedo_tex_uv,
@@ -342,11 +803,13 @@ pub fn fragment_impl<T, C, S>(
     );
     my_println!("albedo_tex_color: {:?}", albedo_tex_color);
 
-    let metallic_roughness_uv = if material.metallic_roughness_tex_coord == 0 {
-        in_uv0
-    } else {
-        in_uv1
-    };
+    let metallic_roughness_uv = material.metallic_roughness_tex_transform.transform_uv(
+        if material.metallic_roughness_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
     let metallic_roughness_tex_color = texture_color(
         material.metallic_roughness_texture_id,
         metallic_roughness_uv,
@@ -360,11 +823,13 @@ pub fn fragment_impl<T, C, S>(
         metallic_roughness_tex_color
     );
 
-    let normal_tex_uv = if material.normal_tex_coord == 0 {
-        in_uv0
-    } else {
-        in_uv1
-    };
+    let normal_tex_uv = material.normal_tex_transform.transform_uv(
+        if material.normal_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
     let normal_tex_color = texture_color(
         material.normal_texture_id,
         normal_tex_uv,
@@ -375,11 +840,13 @@ pub fn fragment_impl<T, C, S>(
     );
     my_println!("normal_tex_color: {:?}", normal_tex_color);
 
-    let ao_tex_uv = if material.ao_tex_coord == 0 {
-        in_uv0
-    } else {
-        in_uv1
-    };
+    let ao_tex_uv = material.ao_tex_transform.transform_uv(
+        if material.ao_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
     let ao_tex_color = texture_color(
         material.ao_texture_id,
         ao_tex_uv,
@@ -389,11 +856,13 @@ pub fn fragment_impl<T, C, S>(
         slab,
     );
 
-    let emissive_tex_uv = if material.emissive_tex_coord == 0 {
-        in_uv0
-    } else {
-        in_uv1
-    };
+    let emissive_tex_uv = material.emissive_tex_transform.transform_uv(
+        if material.emissive_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
     let emissive_tex_color = texture_color(
         material.emissive_texture_id,
         emissive_tex_uv,
@@ -421,6 +890,10 @@ pub fn fragment_impl<T, C, S>(
 
     let n = norm;
     let albedo = albedo_tex_color * material.albedo_factor * in_color;
+    if material.alpha_mode == AlphaMode::Mask && albedo.w < material.alpha_cutoff {
+        *output = Vec4::ZERO;
+        return true;
+    }
     let roughness = metallic_roughness_tex_color.y * material.roughness_factor;
     let metallic = metallic_roughness_tex_color.z * material.metallic_factor;
     let ao = 1.0 + material.ao_strength * (ao_tex_color.x - 1.0);
@@ -428,6 +901,211 @@ pub fn fragment_impl<T, C, S>(
         emissive_tex_color.xyz() * material.emissive_factor * material.emissive_strength_multiplier;
     let irradiance = sample_irradiance(irradiance, irradiance_sampler, n);
     let camera = slab.read(in_camera);
+    let mut clearcoat = ClearcoatFragment::default();
+    if material.clearcoat_id.is_some() {
+        let layer = slab.read(material.clearcoat_id);
+        let tex_color = texture_color(
+            layer.texture_id,
+            layer
+                .tex_transform
+                .transform_uv(if layer.tex_coord == 0 { in_uv0 } else { in_uv1 }),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        let roughness_tex_color = texture_color(
+            layer.roughness_texture_id,
+            layer.roughness_tex_transform.transform_uv(
+                if layer.roughness_tex_coord == 0 {
+                    in_uv0
+                } else {
+                    in_uv1
+                },
+            ),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        clearcoat.factor = tex_color.x * layer.factor;
+        clearcoat.roughness = roughness_tex_color.y * layer.roughness_factor;
+        clearcoat.normal = if layer.normal_texture_id.is_none() {
+            in_norm.alt_norm_or_zero()
+        } else {
+            let normal_tex_color = texture_color(
+                layer.normal_texture_id,
+                layer.normal_tex_transform.transform_uv(
+                    if layer.normal_tex_coord == 0 {
+                        in_uv0
+                    } else {
+                        in_uv1
+                    },
+                ),
+                atlas,
+                atlas_sampler,
+                atlas_size,
+                slab,
+            );
+            let sampled_norm = (normal_tex_color.xyz() * 2.0 - Vec3::splat(1.0)).alt_norm_or_zero();
+            let tbn = glam::mat3(
+                in_tangent.alt_norm_or_zero(),
+                in_bitangent.alt_norm_or_zero(),
+                in_norm.alt_norm_or_zero(),
+            );
+            (tbn * sampled_norm).alt_norm_or_zero()
+        };
+        clearcoat.prefiltered = sample_specular_reflection(
+            prefiltered,
+            prefiltered_sampler,
+            camera.position,
+            in_pos,
+            clearcoat.normal,
+            clearcoat.roughness,
+        );
+        clearcoat.brdf = sample_brdf(
+            brdf,
+            brdf_sampler,
+            camera.position,
+            in_pos,
+            clearcoat.normal,
+            clearcoat.roughness,
+        );
+    }
+
+    let mut sheen = SheenFragment::default();
+    if material.sheen_id.is_some() {
+        let layer = slab.read(material.sheen_id);
+        let color_tex_color = texture_color(
+            layer.color_texture_id,
+            layer.color_tex_transform.transform_uv(
+                if layer.color_tex_coord == 0 {
+                    in_uv0
+                } else {
+                    in_uv1
+                },
+            ),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        let roughness_tex_color = texture_color(
+            layer.roughness_texture_id,
+            layer.roughness_tex_transform.transform_uv(
+                if layer.roughness_tex_coord == 0 {
+                    in_uv0
+                } else {
+                    in_uv1
+                },
+            ),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        sheen.color = color_tex_color.xyz() * layer.color_factor;
+        sheen.roughness = roughness_tex_color.w * layer.roughness_factor;
+        sheen.prefiltered = sample_specular_reflection(
+            prefiltered,
+            prefiltered_sampler,
+            camera.position,
+            in_pos,
+            n,
+            sheen.roughness,
+        );
+        let v = (camera.position - in_pos).alt_norm_or_zero();
+        sheen.albedo = sheen_lut
+            .sample_by_lod(
+                *sheen_lut_sampler,
+                Vec2::new(n.dot(v).max(0.0), sheen.roughness),
+                0.0,
+            )
+            .x;
+    }
+
+    // the reflectance of dielectrics is relative to the default index of
+    // refraction of 1.5
+    let mut specular_layer = SpecularFragment {
+        factor: 1.0,
+        color: Vec3::splat(ior_to_f0(material.ior) / ior_to_f0(1.5)),
+    };
+    if material.specular_id.is_some() {
+        let layer = slab.read(material.specular_id);
+        let tex_color = texture_color(
+            layer.texture_id,
+            layer
+                .tex_transform
+                .transform_uv(if layer.tex_coord == 0 { in_uv0 } else { in_uv1 }),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        let color_tex_color = texture_color(
+            layer.color_texture_id,
+            layer.color_tex_transform.transform_uv(
+                if layer.color_tex_coord == 0 {
+                    in_uv0
+                } else {
+                    in_uv1
+                },
+            ),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        specular_layer.factor = tex_color.w * layer.factor;
+        specular_layer.color *= color_tex_color.xyz() * layer.color_factor;
+    }
+
+    let mut transmission = TransmissionFragment::default();
+    if material.transmission_id.is_some() {
+        let layer = slab.read(material.transmission_id);
+        let tex_color = texture_color(
+            layer.texture_id,
+            layer
+                .tex_transform
+                .transform_uv(if layer.tex_coord == 0 { in_uv0 } else { in_uv1 }),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        let thickness_tex_color = texture_color(
+            layer.thickness_texture_id,
+            layer.thickness_tex_transform.transform_uv(
+                if layer.thickness_tex_coord == 0 {
+                    in_uv0
+                } else {
+                    in_uv1
+                },
+            ),
+            atlas,
+            atlas_sampler,
+            atlas_size,
+            slab,
+        );
+        transmission.factor = tex_color.x * layer.factor;
+        let thickness = thickness_tex_color.y * layer.thickness_factor;
+        // refract the view ray through the volume and sample the opaque scene
+        // where it exits
+        let v = (camera.position - in_pos).alt_norm_or_zero();
+        let refracted = math::refract(-v, n, 1.0 / material.ior);
+        let exit_pos = in_pos + refracted * thickness;
+        let clip_pos = camera.projection * camera.view * exit_pos.extend(1.0);
+        let ndc_pos = clip_pos.xyz() / clip_pos.w;
+        let uv = Vec2::new(ndc_pos.x * 0.5 + 0.5, 0.5 - ndc_pos.y * 0.5);
+        let background = transmission_background
+            .sample_by_lod(*transmission_background_sampler, uv, 0.0)
+            .xyz();
+        let attenuation = layer
+            .attenuation_color
+            .powf(thickness / layer.attenuation_distance);
+        transmission.transmitted = background * albedo.xyz() * attenuation;
+    }
+
     let specular = sample_specular_reflection(
         prefiltered,
         prefiltered_sampler,
@@ -447,84 +1125,100 @@ pub fn fragment_impl<T, C, S>(
         DebugMode::None => {}
         DebugMode::UvCoords0 => {
             *output = colorize(Vec3::new(in_uv0.x, in_uv0.y, 0.0));
-            return;
+            return false;
         }
         DebugMode::UvCoords1 => {
             *output = colorize(Vec3::new(in_uv1.x, in_uv1.y, 0.0));
-            return;
+            return false;
         }
         DebugMode::Normals => {
             *output = colorize(norm);
-            return;
+            return false;
         }
         DebugMode::VertexColor => {
             *output = in_color;
-            return;
+            return false;
         }
         DebugMode::VertexNormals => {
             *output = colorize(in_norm);
-            return;
+            return false;
         }
         DebugMode::UvNormals => {
             *output = colorize(uv_norm);
-            return;
+            return false;
         }
         DebugMode::Tangents => {
             *output = colorize(in_tangent);
-            return;
+            return false;
         }
         DebugMode::Bitangents => {
             *output = colorize(in_bitangent);
-            return;
+            return false;
         }
         DebugMode::DiffuseIrradiance => {
             *output = irradiance.extend(1.0);
-            return;
+            return false;
         }
         DebugMode::SpecularReflection => {
             *output = specular.extend(1.0);
-            return;
+            return false;
         }
         DebugMode::Brdf => {
             *output = brdf.extend(1.0).extend(1.0);
-            return;
+            return false;
         }
         DebugMode::Roughness => {
             *output = Vec3::splat(roughness).extend(1.0);
-            return;
+            return false;
         }
         DebugMode::Metallic => {
             *output = Vec3::splat(metallic).extend(1.0);
-            return;
+            return false;
         }
         DebugMode::Albedo => {
             *output = albedo;
-            return;
+            return false;
         }
         DebugMode::Occlusion => {
             *output = Vec3::splat(ao).extend(1.0);
-            return;
+            return false;
         }
         DebugMode::Emissive => {
             *output = emissive.extend(1.0);
-            return;
+            return false;
         }
         DebugMode::UvEmissive => {
             *output = emissive_tex_color.xyz().extend(1.0);
-            return;
+            return false;
         }
         DebugMode::EmissiveFactor => {
             *output = material.emissive_factor.extend(1.0);
-            return;
+            return false;
         }
         DebugMode::EmissiveStrength => {
             *output = Vec3::splat(material.emissive_strength_multiplier).extend(1.0);
-            return;
+            return false;
+        }
+        DebugMode::ShadowCascade => {
+            *output = shadow::debug_shadow_cascade(in_pos, light_array, slab);
+            return false;
+        }
+    }
+
+    // with light tiling only the lights of the fragment's tile are evaluated
+    let mut lights = light_array;
+    if light_tiling.is_some() {
+        let tiling = slab.read(light_tiling);
+        if tiling.camera_id == in_camera && !tiling.tile_light_counts.is_empty() {
+            let tile_index = tiling.tile_index(in_pos, &camera, resolution);
+            lights = tiling.get_tile_lights(tile_index, slab);
         }
     }
 
     *output = if material.has_lighting {
         shade_fragment(
+            shadow_maps,
+            shadow_maps_sampler,
             camera.position,
             n,
             in_pos,
@@ -536,17 +1230,30 @@ pub fn fragment_impl<T, C, S>(
             irradiance,
             specular,
             brdf,
-            light_array,
+            clearcoat,
+            transmission,
+            sheen,
+            specular_layer,
+            lights,
             slab,
         )
     } else {
         crate::println!("no shading!");
-        in_color * albedo_tex_color * material.albedo_factor
+        albedo
     };
+    if material.alpha_mode == AlphaMode::Blend {
+        output.w = albedo.w;
+        albedo.w == 0.0
+    } else {
+        output.w = 1.0;
+        false
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
-pub fn shade_fragment(
+pub fn shade_fragment<A: Sample2dArray<Sampler = S>, S: IsSampler>(
+    shadow_maps: &A,
+    shadow_maps_sampler: &S,
     // camera's position in world space
     camera_pos: Vec3,
     // normal of the fragment
@@ -562,6 +1269,10 @@ pub fn shade_fragment(
     irradiance: Vec3,
     prefiltered: Vec3,
     brdf: Vec2,
+    clearcoat: ClearcoatFragment,
+    transmission: TransmissionFragment,
+    sheen: SheenFragment,
+    specular_layer: SpecularFragment,
 
     lights: Array<Id<Light>>,
     slab: &[u32],
@@ -573,6 +1284,11 @@ pub fn shade_fragment(
     my_println!("v: {v:?}");
     // reflectance
     let mut lo = Vec3::ZERO;
+    // reflectance of the clearcoat layer
+    let mut lo_clearcoat = Vec3::ZERO;
+    // reflectance of the sheen layer
+    let mut lo_sheen = Vec3::ZERO;
+    let has_sheen = sheen.color.max_element() > 0.0;
     for i in 0..lights.len() {
         // calculate per-light radiance
         let light_id = slab.read(lights.at(i));
@@ -584,7 +1300,7 @@ pub fn shade_fragment(
         let transform = Mat4::from(transform);
 
         // determine the light ray and the radiance
-        match light.light_type {
+        let (color, l, attenuation) = match light.light_type {
             LightStyle::Point => {
                 let PointLight {
                     position,
@@ -598,8 +1314,20 @@ pub fn shade_fragment(
                     continue;
                 }
                 let l = frag_to_light.alt_norm_or_zero();
-                let attenuation = intensity * 1.0 / (distance * distance);
-                lo += outgoing_radiance(color, albedo, attenuation, v, l, n, metallic, roughness);
+                let mut attenuation = intensity * 1.0 / (distance * distance);
+                if light.has_shadow_map() {
+                    let shadow_map = slab.read(light.shadow_map);
+                    attenuation *= shadow_map.shadow_factor(
+                        shadow_maps,
+                        shadow_maps_sampler,
+                        light,
+                        in_pos,
+                        n,
+                        l,
+                        slab,
+                    );
+                }
+                (color, l, attenuation)
             }
 
             LightStyle::Spot => {
@@ -623,7 +1351,7 @@ pub fn shade_fragment(
                 let epsilon: f32 = inner_cutoff - outer_cutoff;
                 let attenuation: f32 =
                     intensity * ((theta - outer_cutoff) / epsilon).clamp(0.0, 1.0);
-                lo += outgoing_radiance(color, albedo, attenuation, v, l, n, metallic, roughness);
+                (color, l, attenuation)
             }
 
             LightStyle::Directional => {
@@ -634,12 +1362,48 @@ pub fn shade_fragment(
                 } = slab.read(light.into_directional_id());
                 let direction = transform.transform_vector3(direction);
                 let l = -direction.alt_norm_or_zero();
-                let attenuation = intensity;
-                let radiance =
-                    outgoing_radiance(color, albedo, attenuation, v, l, n, metallic, roughness);
-                my_println!("radiance: {radiance:?}");
-                lo += radiance;
+                let mut attenuation = intensity;
+                if light.has_shadow_map() {
+                    let shadow_map = slab.read(light.shadow_map);
+                    attenuation *= shadow_map.shadow_factor(
+                        shadow_maps,
+                        shadow_maps_sampler,
+                        light,
+                        in_pos,
+                        n,
+                        l,
+                        slab,
+                    );
+                }
+                (color, l, attenuation)
             }
+        };
+        let radiance = outgoing_radiance(
+            color,
+            albedo,
+            attenuation,
+            v,
+            l,
+            n,
+            metallic,
+            roughness,
+            transmission.factor,
+            specular_layer,
+        );
+        my_println!("radiance: {radiance:?}");
+        lo += radiance;
+        if has_sheen {
+            lo_sheen += sheen_radiance(color, attenuation, v, l, n, sheen.color, sheen.roughness);
+        }
+        if clearcoat.factor > 0.0 {
+            lo_clearcoat += clearcoat_radiance(
+                color,
+                attenuation,
+                v,
+                l,
+                clearcoat.normal,
+                clearcoat.roughness,
+            );
         }
     }
 
@@ -647,14 +1411,34 @@ pub fn shade_fragment(
     // calculate reflectance at normal incidence; if dia-electric (like plastic) use
     // F0 of 0.04 and if it's a metal, use the albedo color as F0 (metallic
     // workflow)
-    let f0: Vec3 = Vec3::splat(0.04).lerp(albedo, metallic);
+    let f0: Vec3 = (Vec3::splat(0.04) * specular_layer.color).lerp(albedo, metallic);
     let cos_theta = n.dot(v).max(0.0);
-    let fresnel = fresnel_schlick_roughness(cos_theta, f0, roughness);
+    let fresnel = fresnel_schlick_roughness(cos_theta, f0, roughness)
+        * (specular_layer.factor + (1.0 - specular_layer.factor) * metallic);
     let ks = fresnel;
     let kd = (1.0 - ks) * (1.0 - metallic);
-    let diffuse = irradiance * albedo;
+    let diffuse = (irradiance * albedo).lerp(transmission.transmitted, transmission.factor);
     let specular = prefiltered * (fresnel * brdf.x + brdf.y);
-    let color = (kd * diffuse + specular) * ao + lo + emissive;
+    let mut color = (kd * diffuse + specular) * ao + lo;
+
+    if has_sheen {
+        // the sheen layer sits on top of the base layer and scatters some of
+        // the light that would otherwise reach it
+        let albedo_scaling = 1.0 - sheen.color.max_element() * sheen.albedo;
+        let specular = sheen.prefiltered * sheen.color * sheen.albedo;
+        color = color * albedo_scaling + specular * ao + lo_sheen;
+    }
+    color += emissive;
+
+    if clearcoat.factor > 0.0 {
+        // the clearcoat layer reflects some light before it reaches the base layer
+        let cos_theta = clearcoat.normal.dot(v).max(0.0);
+        let layer_fresnel = fresnel_schlick(cos_theta, Vec3::splat(0.04));
+        let fresnel = fresnel_schlick_roughness(cos_theta, Vec3::splat(0.04), clearcoat.roughness);
+        let specular = clearcoat.prefiltered * (fresnel * clearcoat.brdf.x + clearcoat.brdf.y);
+        color = color * (1.0 - clearcoat.factor * layer_fresnel)
+            + clearcoat.factor * (specular * ao + lo_clearcoat);
+    }
     color.extend(1.0)
 }
 
@@ -664,10 +1448,32 @@ mod test {
         atlas::AtlasImage,
         camera::Camera,
         math::{Vec3, Vec4},
-        pbr::Material,
+        pbr::{AlphaMode, Material, TextureTransform},
         stage::{Renderlet, Vertex},
         transform::Transform,
     };
+    use glam::Vec2;
+
+    #[test]
+    fn texture_transform_uv() {
+        let uv = Vec2::new(0.25, 0.5);
+        assert_eq!(uv, TextureTransform::default().transform_uv(uv));
+
+        let transform = TextureTransform {
+            offset: Vec2::new(0.5, 1.0),
+            scale: Vec2::new(2.0, 4.0),
+            ..Default::default()
+        };
+        assert_eq!(Vec2::new(1.0, 3.0), transform.transform_uv(uv));
+
+        // Rotation is counter-clockwise in UV space, where v points down
+        let transform = TextureTransform {
+            rotation: std::f32::consts::FRAC_PI_2,
+            ..Default::default()
+        };
+        let rotated = transform.transform_uv(Vec2::X);
+        assert!(rotated.abs_diff_eq(Vec2::NEG_Y, f32::EPSILON), "{rotated}");
+    }
 
     #[test]
     // Tests the initial implementation of pbr metallic roughness on an array of
@@ -760,4 +1566,66 @@ mod test {
         let img = frame.read_image().unwrap();
         img_diff::assert_img_eq("pbr/metallic_roughness_spheres.png", img);
     }
+
+    #[test]
+    // Tests that opaque materials ignore alpha, masked materials discard
+    // fragments below their cutoff and blended materials are blended over
+    // opaque renderlets.
+    fn alpha_modes_sanity() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_background_color(Vec4::ONE)
+            .with_bloom(false);
+        let camera = stage.new_value(Camera::default_ortho2d(100.0, 100.0));
+
+        let quad = |x: f32, y: f32| {
+            let tl = Vertex::default().with_position([x, y, 0.0]);
+            let tr = Vertex::default().with_position([x + 50.0, y, 0.0]);
+            let bl = Vertex::default().with_position([x, y + 50.0, 0.0]);
+            let br = Vertex::default().with_position([x + 50.0, y + 50.0, 0.0]);
+            vec![tl, bl, br, tl, br, tr]
+        };
+        let mut stage_quad = |x: f32, y: f32, albedo_factor: Vec4, alpha_mode: AlphaMode| {
+            let vertices = stage.new_array(quad(x, y));
+            let material = stage.new_value(Material {
+                albedo_factor,
+                has_lighting: false,
+                alpha_mode,
+                ..Default::default()
+            });
+            let renderlet = stage.new_value(Renderlet {
+                camera_id: camera.id(),
+                vertices_array: vertices.array(),
+                material_id: material.id(),
+                ..Default::default()
+            });
+            stage.add_renderlet(&renderlet);
+            (vertices, material, renderlet)
+        };
+        // Stage the blended quad first, it should still be drawn last.
+        let _blended = stage_quad(50.0, 50.0, Vec4::new(1.0, 0.0, 0.0, 0.5), AlphaMode::Blend);
+        let _opaque = stage_quad(0.0, 0.0, Vec4::new(0.0, 1.0, 0.0, 0.5), AlphaMode::Opaque);
+        let _masked = stage_quad(50.0, 0.0, Vec4::new(1.0, 0.0, 0.0, 0.25), AlphaMode::Mask);
+        let _under_left = stage_quad(0.0, 50.0, Vec4::new(0.0, 0.0, 1.0, 1.0), AlphaMode::Opaque);
+        let _under_right = stage_quad(50.0, 50.0, Vec4::new(0.0, 0.0, 1.0, 1.0), AlphaMode::Opaque);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::save("pbr/alpha_modes.png", img.clone());
+
+        let opaque = img.get_pixel(25, 25).0;
+        assert_eq!([0, 255, 0, 255], opaque, "opaque should ignore alpha");
+        let masked = img.get_pixel(75, 25).0;
+        assert_eq!([255, 255, 255, 255], masked, "mask should be discarded");
+        let under = img.get_pixel(25, 75).0;
+        assert_eq!([0, 0, 255, 255], under);
+        let [r, g, b, _] = img.get_pixel(75, 75).0;
+        assert!(
+            r > 0 && r < 255 && g == 0 && b > 0 && b < 255,
+            "blend should mix red over blue, saw {:?}",
+            [r, g, b]
+        );
+    }
 }
@@ -70,4 +70,11 @@ pub enum DebugMode {
     /// Displays only the emissive strength of the fragment
     /// (KHR_materials_emissive_strength).
     EmissiveStrength,
+
+    /// Colors fragments by the index of the shadow map cascade they fall in:
+    /// red, green, blue and yellow, repeating.
+    ///
+    /// Fragments beyond the last cascade are white, fragments lit by no
+    /// cascaded shadow map are black.
+    ShadowCascade,
 }
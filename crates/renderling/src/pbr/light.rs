@@ -2,7 +2,7 @@
 use crabslab::{Id, SlabItem};
 use glam::{Vec3, Vec4};
 
-use crate::transform::Transform;
+use crate::{pbr::shadow::ShadowMapDescriptor, transform::Transform};
 
 #[repr(C)]
 #[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
@@ -121,6 +121,8 @@ pub struct Light {
     pub index: u32,
     // The id of a transform to apply to the position and direction of the light.
     pub transform: Id<Transform>,
+    // The id of the light's shadow map, if it casts shadows.
+    pub shadow_map: Id<ShadowMapDescriptor>,
 }
 
 impl Default for Light {
@@ -129,6 +131,7 @@ impl Default for Light {
             light_type: LightStyle::Directional,
             index: Id::<()>::NONE.inner(),
             transform: Id::NONE,
+            shadow_map: Id::NONE,
         }
     }
 }
@@ -139,6 +142,7 @@ impl From<Id<DirectionalLight>> for Light {
             light_type: LightStyle::Directional,
             index: id.inner(),
             transform: Id::NONE,
+            shadow_map: Id::NONE,
         }
     }
 }
@@ -149,6 +153,7 @@ impl From<Id<SpotLight>> for Light {
             light_type: LightStyle::Spot,
             index: id.inner(),
             transform: Id::NONE,
+            shadow_map: Id::NONE,
         }
     }
 }
@@ -159,6 +164,7 @@ impl From<Id<PointLight>> for Light {
             light_type: LightStyle::Point,
             index: id.inner(),
             transform: Id::NONE,
+            shadow_map: Id::NONE,
         }
     }
 }
@@ -175,6 +181,11 @@ impl Light {
     pub fn into_point_id(self) -> Id<PointLight> {
         Id::from(self.index)
     }
+
+    /// Returns whether this light casts shadows.
+    pub fn has_shadow_map(&self) -> bool {
+        self.shadow_map.is_some()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,817 @@
+//! Shadow mapping.
+//!
+//! Lights that cast shadows own a [`ShadowMapDescriptor`] on the slab. Each
+//! frame the stage renders the depth of its renderlets, as seen from the
+//! light, into a layer of the shadow map atlas - a depth texture array. The
+//! PBR fragment shader then compares the depth of each fragment against the
+//! shadow map to determine how much of the light reaches it.
+//!
+//! Directional lights render one layer. Point lights render an
+//! omnidirectional shadow map as six layers, one per cubemap face.
+//!
+//! Directional lights may instead use cascaded shadow maps, where each layer
+//! covers one slice of a camera's frustum. Slices close to the camera cover
+//! less of the scene, giving nearby shadows more resolution.
+//!
+//! ## References
+//! * <https://learnopengl.com/Advanced-Lighting/Shadows/Shadow-Mapping>
+//! * <https://learnopengl.com/Advanced-Lighting/Shadows/Point-Shadows>
+//! * <https://learnopengl.com/Guest-Articles/2021/CSM>
+//! * <https://developer.nvidia.com/gpugems/gpugems3/part-ii-light-and-shadows/chapter-10-parallel-split-shadow-maps-programmable-gpus>
+use crabslab::{Array, Id, Slab, SlabItem};
+use glam::{Mat4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::spirv;
+
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use crate::{
+    camera::Camera,
+    cubemap,
+    math::{IsSampler, IsVector, Sample2dArray},
+    pbr::light::{Light, LightStyle},
+    stage::Renderlet,
+};
+
+#[cfg(not(target_arch = "spirv"))]
+mod cpu;
+#[cfg(not(target_arch = "spirv"))]
+pub use cpu::*;
+
+/// Describes the shadow map of one light.
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct ShadowMapDescriptor {
+    /// Projection from light space into the shadow map's clip space.
+    ///
+    /// For directional lights light space is centered on the origin, looking
+    /// down the light's direction, so this is typically an orthographic
+    /// projection that encloses the shadow casting parts of the scene.
+    ///
+    /// For point lights light space is centered on the light, looking down
+    /// each cubemap face in turn, so this is a perspective projection with a
+    /// 90 degree field of view and an aspect ratio of `1.0`.
+    ///
+    /// Cascaded shadow maps don't use this projection, each cascade's
+    /// projection is fit to its [`ShadowCascade`] instead.
+    pub projection: Mat4,
+    /// The first layer of the shadow map atlas that this shadow map is
+    /// rendered into.
+    ///
+    /// This is maintained by the stage.
+    pub atlas_layer: u32,
+    /// The size of the shadow map in texels.
+    pub size: UVec2,
+    /// The size of each layer of the shadow map atlas in texels.
+    ///
+    /// This is maintained by the stage.
+    pub atlas_size: UVec2,
+    /// Constant depth bias, used to prevent "shadow acne".
+    ///
+    /// The bias is scaled by the angle between the surface and the light.
+    pub depth_bias: f32,
+    /// Radius of the percentage-closer filtering kernel, in texels.
+    ///
+    /// `0` results in hard shadows.
+    pub pcf_radius: u32,
+    /// The camera whose frustum the cascades are fit to.
+    ///
+    /// Only used by cascaded shadow maps.
+    pub camera_id: Id<Camera>,
+    /// One cascade per layer, ordered from nearest to farthest from the
+    /// camera.
+    ///
+    /// Empty unless this is a cascaded shadow map.
+    pub cascades: Array<ShadowCascade>,
+    /// How far beyond each cascade, towards the light, shadow casters are
+    /// included in the cascade.
+    ///
+    /// Only used by cascaded shadow maps.
+    pub caster_distance: f32,
+}
+
+impl Default for ShadowMapDescriptor {
+    fn default() -> Self {
+        Self {
+            projection: Mat4::orthographic_rh(-10.0, 10.0, -10.0, 10.0, -50.0, 50.0),
+            atlas_layer: 0,
+            size: UVec2::splat(1024),
+            atlas_size: UVec2::ZERO,
+            depth_bias: 0.001,
+            pcf_radius: 1,
+            camera_id: Id::NONE,
+            cascades: Array::default(),
+            caster_distance: 50.0,
+        }
+    }
+}
+
+/// One cascade of a cascaded shadow map.
+///
+/// Describes the bounding sphere of one slice of a camera's frustum.
+/// The cascades of a shadow map are fit by the stage each frame.
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub struct ShadowCascade {
+    /// Center of the slice's bounding sphere, in world space.
+    pub center: Vec3,
+    /// Radius of the slice's bounding sphere.
+    pub radius: f32,
+    /// Distance from the camera to the far end of the slice, along the
+    /// camera's view direction.
+    pub split_far: f32,
+}
+
+/// Returns the view matrix of a directional light shining in the given
+/// direction.
+pub fn directional_light_view(direction: Vec3) -> Mat4 {
+    let direction = direction.alt_norm_or_zero();
+    let up = if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    };
+    Mat4::look_to_rh(Vec3::ZERO, direction, up)
+}
+
+/// Returns the number of shadow map atlas layers a light of the given style
+/// renders into.
+pub fn shadow_map_layer_count(light_type: LightStyle) -> u32 {
+    match light_type {
+        LightStyle::Point => 6,
+        LightStyle::Directional | LightStyle::Spot => 1,
+    }
+}
+
+impl ShadowMapDescriptor {
+    /// Returns the transform from world space into the clip space of the
+    /// given layer of the shadow map of the given light.
+    ///
+    /// `layer` is relative to [`ShadowMapDescriptor::atlas_layer`]. For point
+    /// lights it is the index of a cubemap face, for cascaded shadow maps it
+    /// is the index of a cascade, otherwise it is ignored.
+    pub fn light_space_transform(&self, light: Light, layer: u32, slab: &[u32]) -> Mat4 {
+        let transform = Mat4::from(slab.read(light.transform));
+        match light.light_type {
+            LightStyle::Directional => {
+                let directional = slab.read(light.into_directional_id());
+                let direction = transform.transform_vector3(directional.direction);
+                if self.cascades.is_empty() {
+                    self.projection * directional_light_view(direction)
+                } else {
+                    let cascade = slab.read(self.cascades.at(layer as usize));
+                    self.cascade_transform(direction, cascade)
+                }
+            }
+            LightStyle::Point => {
+                let point = slab.read(light.into_point_id());
+                let position = transform.transform_point3(point.position);
+                self.projection * cubemap::cube_face_view(position, layer as usize)
+            }
+            // Spot lights don't cast shadows.
+            LightStyle::Spot => Mat4::IDENTITY,
+        }
+    }
+
+    /// Returns the transform from world space into the clip space of the
+    /// given cascade of a directional light shining in `direction`.
+    ///
+    /// The projection is an orthographic projection enclosing the cascade's
+    /// bounding sphere, snapped to the shadow map's texels so that shadow
+    /// edges don't shimmer as the camera moves.
+    pub fn cascade_transform(&self, direction: Vec3, cascade: ShadowCascade) -> Mat4 {
+        let view = directional_light_view(direction);
+        let center = view.transform_point3(cascade.center);
+        let radius = cascade.radius.max(f32::EPSILON);
+        let texels_per_unit = (self.size.x as f32).max(1.0) / (2.0 * radius);
+        let x = (center.x * texels_per_unit).floor() / texels_per_unit;
+        let y = (center.y * texels_per_unit).floor() / texels_per_unit;
+        let projection = Mat4::orthographic_rh(
+            x - radius,
+            x + radius,
+            y - radius,
+            y + radius,
+            -center.z - radius - self.caster_distance,
+            -center.z + radius,
+        );
+        projection * view
+    }
+
+    /// Returns the index of the cascade that contains the given world space
+    /// position.
+    ///
+    /// Returns the number of cascades if the position is beyond the last
+    /// cascade.
+    pub fn cascade_index(&self, in_pos: Vec3, slab: &[u32]) -> u32 {
+        let camera = slab.read(self.camera_id);
+        let depth = -camera.view.transform_point3(in_pos).z;
+        let mut index = self.cascades.len() as u32;
+        for i in 0..self.cascades.len() {
+            let cascade = slab.read(self.cascades.at(i));
+            if depth <= cascade.split_far {
+                index = i as u32;
+                break;
+            }
+        }
+        index
+    }
+
+    /// Returns the amount of the given light that reaches the fragment, where
+    /// `0.0` is completely in shadow and `1.0` is completely lit.
+    ///
+    /// * `in_pos` is the fragment's position in world space
+    /// * `n` is the fragment's normal
+    /// * `l` is the direction from the fragment to the light
+    #[allow(clippy::too_many_arguments)]
+    pub fn shadow_factor<A: Sample2dArray<Sampler = S>, S: IsSampler>(
+        &self,
+        shadow_maps: &A,
+        shadow_maps_sampler: &S,
+        light: Light,
+        in_pos: Vec3,
+        n: Vec3,
+        l: Vec3,
+        slab: &[u32],
+    ) -> f32 {
+        if self.atlas_size.x == 0 || self.atlas_size.y == 0 {
+            // the shadow map has not been rendered yet
+            return 1.0;
+        }
+        let layer = match light.light_type {
+            // look up the cubemap face that the fragment is in, as seen from
+            // the light
+            LightStyle::Point => cubemap::cube_face_index(-l) as u32,
+            LightStyle::Directional if !self.cascades.is_empty() => {
+                let index = self.cascade_index(in_pos, slab);
+                if index as usize >= self.cascades.len() {
+                    // beyond the last cascade
+                    return 1.0;
+                }
+                index
+            }
+            LightStyle::Directional | LightStyle::Spot => 0,
+        };
+        let light_space_transform = self.light_space_transform(light, layer, slab);
+        let atlas_layer = self.atlas_layer + layer;
+        let clip_pos: Vec4 = light_space_transform * in_pos.extend(1.0);
+        if clip_pos.w == 0.0 {
+            return 1.0;
+        }
+        let ndc = clip_pos.xyz() / clip_pos.w;
+        let uv = Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if ndc.z < 0.0 || ndc.z > 1.0 || uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 {
+            // outside of the shadow map, which means outside of the light's view
+            return 1.0;
+        }
+        let bias = (self.depth_bias * (1.0 - n.dot(l))).max(self.depth_bias * 0.1);
+        let depth = ndc.z - bias;
+        let atlas_size = self.atlas_size.as_vec2();
+        let scale = self.size.as_vec2() / atlas_size;
+        let texel = Vec2::ONE / atlas_size;
+        let diameter = self.pcf_radius * 2 + 1;
+        let mut lit = 0.0;
+        for i in 0..diameter {
+            for j in 0..diameter {
+                let offset = Vec2::new(
+                    i as f32 - self.pcf_radius as f32,
+                    j as f32 - self.pcf_radius as f32,
+                ) * texel;
+                let sample_uv = (uv * scale + offset).clamp(Vec2::ZERO, scale - texel);
+                let closest_depth = shadow_maps
+                    .sample_by_lod(
+                        *shadow_maps_sampler,
+                        sample_uv.extend(atlas_layer as f32),
+                        0.0,
+                    )
+                    .x;
+                if depth <= closest_depth {
+                    lit += 1.0;
+                }
+            }
+        }
+        lit / (diameter * diameter) as f32
+    }
+}
+
+/// Returns a color for the cascade of the first cascaded shadow map that
+/// contains the given world space position.
+///
+/// Used by [`DebugMode::ShadowCascade`](crate::pbr::debug::DebugMode::ShadowCascade).
+pub fn debug_shadow_cascade(in_pos: Vec3, lights: Array<Id<Light>>, slab: &[u32]) -> Vec4 {
+    const COLORS: [Vec4; 4] = [
+        Vec4::new(1.0, 0.0, 0.0, 1.0),
+        Vec4::new(0.0, 1.0, 0.0, 1.0),
+        Vec4::new(0.0, 0.0, 1.0, 1.0),
+        Vec4::new(1.0, 1.0, 0.0, 1.0),
+    ];
+    for i in 0..lights.len() {
+        let light_id = slab.read(lights.at(i));
+        if light_id.is_none() {
+            break;
+        }
+        let light = slab.read(light_id);
+        if !light.has_shadow_map() {
+            continue;
+        }
+        let shadow_map = slab.read(light.shadow_map);
+        if shadow_map.cascades.is_empty() {
+            continue;
+        }
+        let index = shadow_map.cascade_index(in_pos, slab) as usize;
+        if index >= shadow_map.cascades.len() {
+            // beyond the last cascade
+            return Vec4::ONE;
+        }
+        return COLORS[index % COLORS.len()];
+    }
+    Vec4::new(0.0, 0.0, 0.0, 1.0)
+}
+
+/// Identifies one layer of a shadow map being rendered.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub struct ShadowMapPass {
+    /// The shadow casting light.
+    pub light_id: Id<Light>,
+    /// The layer being rendered, relative to
+    /// [`ShadowMapDescriptor::atlas_layer`].
+    pub layer: u32,
+}
+
+/// The [`Id`] of the [`ShadowMapPass`] on the shadow map pass's buffer.
+pub const SHADOW_MAP_PASS_ID: Id<ShadowMapPass> = Id::new(0);
+
+#[cfg(feature = "shadow_mapping_vertex")]
+/// Shadow mapping vertex shader.
+///
+/// Renders the depth of a renderlet's vertices as seen from the light whose
+/// shadow map is being rendered. The [`ShadowMapPass`] is read from
+/// `pass_slab` at [`SHADOW_MAP_PASS_ID`].
+#[spirv(vertex)]
+pub fn shadow_mapping_vertex(
+    // Points at a `Renderlet`, or one of its instance slots
+    #[spirv(instance_index)] instance_index: u32,
+    // Which vertex within the renderlet are we rendering
+    #[spirv(vertex_index)] vertex_index: u32,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &[u32],
+    #[spirv(storage_buffer, descriptor_set = 1, binding = 0)] pass_slab: &[u32],
+    #[spirv(position)] out_clip_pos: &mut Vec4,
+) {
+    let (_, renderlet, instance) = Renderlet::read_instance(instance_index, slab);
+    let (vertex, transform) = renderlet.get_instance_vertex_info(vertex_index, instance, slab);
+    let world_pos = Mat4::from(transform).transform_point3(vertex.position);
+
+    let pass = pass_slab.read(SHADOW_MAP_PASS_ID);
+    let light = slab.read(pass.light_id);
+    let shadow_map = slab.read(light.shadow_map);
+    *out_clip_pos =
+        shadow_map.light_space_transform(light, pass.layer, slab) * world_pos.extend(1.0);
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec3, Vec4};
+
+    use crate::{
+        camera::Camera,
+        math,
+        pbr::{
+            debug::DebugMode,
+            light::{DirectionalLight, Light, PointLight},
+            Material,
+        },
+        stage::{Renderlet, Vertex},
+        transform::Transform,
+    };
+
+    use super::*;
+
+    #[test]
+    fn directional_light_view_looks_down_direction() {
+        let direction = Vec3::new(-1.0, -1.0, 0.0).normalize();
+        let view = directional_light_view(direction);
+        // points along the light's direction are in front of the light
+        let p = view.transform_point3(direction * 10.0);
+        assert!(p.z < 0.0, "{p}");
+        assert!(p.x.abs() < 1.0e-5 && p.y.abs() < 1.0e-5, "{p}");
+        // straight down still produces a valid view
+        let view = directional_light_view(Vec3::NEG_Y);
+        assert!(!view.is_nan());
+        let p = view.transform_point3(Vec3::new(0.0, -5.0, 0.0));
+        assert!(p.z < 0.0, "{p}");
+
+        // the default projection covers the origin from either side
+        let descriptor = ShadowMapDescriptor::default();
+        let clip = descriptor.projection * view * Vec3::new(0.0, 5.0, 0.0).extend(1.0);
+        let ndc = clip.xyz() / clip.w;
+        assert!((0.0..=1.0).contains(&ndc.z), "{ndc}");
+    }
+
+    #[test]
+    // Tests that a cube floating above a plane casts a shadow onto the plane.
+    fn directional_shadow_sanity() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec4::ZERO);
+        let (projection, _) = crate::camera::default_perspective(100.0, 100.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 6.0, 6.0), Vec3::ZERO, Vec3::Y);
+        let camera = stage.new_value(Camera::new(projection, view));
+
+        let directional = stage.new_value(DirectionalLight {
+            direction: Vec3::NEG_Y,
+            color: Vec4::ONE,
+            intensity: 5.0,
+        });
+        let light = stage.new_value(Light::from(directional.id()));
+        stage.set_lights([light.id()]);
+
+        let material = stage.new_value(Material::default());
+        let geometry = stage.new_array(
+            math::unit_cube()
+                .into_iter()
+                .map(|(p, n)| Vertex::default().with_position(p).with_normal(n)),
+        );
+        let ground_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, -0.05, 0.0),
+            scale: Vec3::new(8.0, 0.1, 8.0),
+            ..Default::default()
+        });
+        let ground = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: ground_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&ground);
+        let cube_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, 1.5, 0.0),
+            ..Default::default()
+        });
+        let cube = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: cube_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&cube);
+
+        let pixel_at = |p: Vec3| -> (u32, u32) {
+            let clip = projection * view * p.extend(1.0);
+            let ndc = clip.xyz() / clip.w;
+            (
+                ((ndc.x * 0.5 + 0.5) * 100.0) as u32,
+                ((0.5 - ndc.y * 0.5) * 100.0) as u32,
+            )
+        };
+        let luminance = |img: &image::RgbaImage, (x, y): (u32, u32)| -> u32 {
+            let image::Rgba([r, g, b, _]) = *img.get_pixel(x, y);
+            r as u32 + g as u32 + b as u32
+        };
+        let in_shadow = pixel_at(Vec3::new(0.0, 0.0, 0.3));
+        let in_light = pixel_at(Vec3::new(2.5, 0.0, 2.5));
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let no_shadows = frame.read_image().unwrap();
+        frame.present();
+
+        let shadow_map = stage
+            .new_shadow_map(&light, UVec2::splat(256))
+            .with_pcf_radius(0);
+        assert_eq!(shadow_map.descriptor.id(), light.get().shadow_map);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let shadows = frame.read_image().unwrap();
+        img_diff::save("shadows/directional.png", shadows.clone());
+
+        assert!(
+            luminance(&shadows, in_shadow) < luminance(&no_shadows, in_shadow),
+            "the plane beneath the cube should be in shadow"
+        );
+        assert_eq!(
+            luminance(&no_shadows, in_light),
+            luminance(&shadows, in_light),
+            "the plane away from the cube should be lit"
+        );
+
+        // dropping the shadow map stops the light from casting shadows
+        drop(shadow_map);
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        assert!(light.get().shadow_map.is_none());
+    }
+
+    #[test]
+    fn point_light_layers_cover_each_cube_face() {
+        assert_eq!(6, shadow_map_layer_count(LightStyle::Point));
+        assert_eq!(1, shadow_map_layer_count(LightStyle::Directional));
+        let descriptor = ShadowMapDescriptor {
+            projection: Mat4::perspective_rh(core::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0),
+            ..Default::default()
+        };
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        for (i, (forward, _)) in cubemap::CUBE_FACES.iter().enumerate() {
+            // a point in front of the light along the face direction projects
+            // into the center of that face's layer
+            let transform = descriptor.projection * cubemap::cube_face_view(position, i);
+            let clip = transform * (position + *forward * 5.0).extend(1.0);
+            let ndc = clip.xyz() / clip.w;
+            assert!(ndc.x.abs() < 1.0e-5 && ndc.y.abs() < 1.0e-5, "{i}: {ndc}");
+            assert!((0.0..=1.0).contains(&ndc.z), "{i}: {ndc}");
+        }
+    }
+
+    #[test]
+    // Tests that a cube between a point light and a plane casts a shadow onto
+    // the plane.
+    fn point_light_shadow_sanity() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec4::ZERO);
+        let (projection, _) = crate::camera::default_perspective(100.0, 100.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 6.0, 6.0), Vec3::ZERO, Vec3::Y);
+        let camera = stage.new_value(Camera::new(projection, view));
+
+        let point = stage.new_value(PointLight {
+            position: Vec3::new(0.0, 4.0, 0.0),
+            color: Vec4::ONE,
+            intensity: 20.0,
+        });
+        let light = stage.new_value(Light::from(point.id()));
+        stage.set_lights([light.id()]);
+
+        let material = stage.new_value(Material::default());
+        let geometry = stage.new_array(
+            math::unit_cube()
+                .into_iter()
+                .map(|(p, n)| Vertex::default().with_position(p).with_normal(n)),
+        );
+        let ground_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, -0.05, 0.0),
+            scale: Vec3::new(8.0, 0.1, 8.0),
+            ..Default::default()
+        });
+        let ground = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: ground_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&ground);
+        let cube_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, 1.5, 0.0),
+            ..Default::default()
+        });
+        let cube = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: cube_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&cube);
+
+        let pixel_at = |p: Vec3| -> (u32, u32) {
+            let clip = projection * view * p.extend(1.0);
+            let ndc = clip.xyz() / clip.w;
+            (
+                ((ndc.x * 0.5 + 0.5) * 100.0) as u32,
+                ((0.5 - ndc.y * 0.5) * 100.0) as u32,
+            )
+        };
+        let luminance = |img: &image::RgbaImage, (x, y): (u32, u32)| -> u32 {
+            let image::Rgba([r, g, b, _]) = *img.get_pixel(x, y);
+            r as u32 + g as u32 + b as u32
+        };
+        let in_shadow = pixel_at(Vec3::new(0.0, 0.0, 0.5));
+        let in_light = pixel_at(Vec3::new(3.0, 0.0, 3.0));
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let no_shadows = frame.read_image().unwrap();
+        frame.present();
+
+        let shadow_map = stage
+            .new_shadow_map(&light, UVec2::splat(256))
+            .with_pcf_radius(0);
+        assert_eq!(6, shadow_map.layer_count());
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let shadows = frame.read_image().unwrap();
+        img_diff::save("shadows/point.png", shadows.clone());
+
+        assert!(
+            luminance(&shadows, in_shadow) < luminance(&no_shadows, in_shadow),
+            "the plane beneath the cube should be in shadow"
+        );
+        assert_eq!(
+            luminance(&no_shadows, in_light),
+            luminance(&shadows, in_light),
+            "the plane away from the cube should be lit"
+        );
+    }
+
+    #[test]
+    fn cascade_splits_cover_the_frustum() {
+        let (near, far) = (0.1, 100.0);
+        for splits in [
+            CascadeSplits::Uniform,
+            CascadeSplits::Logarithmic,
+            CascadeSplits::Practical { lambda: 0.5 },
+        ] {
+            let distances = splits.split_distances(4, near, far);
+            assert_eq!(4, distances.len());
+            assert!(distances.windows(2).all(|w| w[0] < w[1]), "{distances:?}");
+            assert!((distances[3] - far).abs() < 1.0e-3, "{distances:?}");
+        }
+        let uniform = CascadeSplits::Uniform.split_distances(4, near, far);
+        assert!((uniform[0] - (near + (far - near) * 0.25)).abs() < 1.0e-4);
+        let logarithmic = CascadeSplits::Logarithmic.split_distances(4, near, far);
+        assert!((logarithmic[0] - near * (far / near).powf(0.25)).abs() < 1.0e-4);
+        let practical = CascadeSplits::Practical { lambda: 0.5 }.split_distances(4, near, far);
+        assert!(logarithmic[0] < practical[0] && practical[0] < uniform[0]);
+
+        // missing distances end at the far plane, out of range distances are
+        // clamped
+        let distances = CascadeSplits::Distances(vec![5.0, 1000.0]).split_distances(3, near, far);
+        assert_eq!(vec![5.0, far, far], distances);
+    }
+
+    #[test]
+    fn fitted_cascades_enclose_frustum_slices() {
+        let camera = Camera::default_perspective(160.0, 90.0);
+        let (near, far) = camera_depth_range(&camera);
+        assert!((near - 0.1).abs() < 1.0e-4, "{near}");
+        assert!((far - 100.0).abs() < 1.0e-2, "{far}");
+
+        let splits = CascadeSplits::default().split_distances(3, near, 50.0);
+        let cascades = fit_shadow_cascades(&camera, &splits);
+        assert_eq!(3, cascades.len());
+        let inverse = (camera.projection * camera.view).inverse();
+        let mut split_near = near;
+        for cascade in cascades.iter() {
+            for depth in [split_near, cascade.split_far] {
+                let clip = camera.projection * Vec4::new(0.0, 0.0, -depth, 1.0);
+                let z = clip.z / clip.w;
+                for (x, y) in [
+                    (-1.0, -1.0),
+                    (1.0, -1.0),
+                    (1.0, 1.0),
+                    (-1.0, 1.0),
+                    (0.0, 0.0),
+                ] {
+                    let p = inverse.project_point3(Vec3::new(x, y, z));
+                    assert!(
+                        p.distance(cascade.center) <= cascade.radius * 1.001,
+                        "{p} is outside of {cascade:?}"
+                    );
+                }
+            }
+            split_near = cascade.split_far;
+        }
+        assert!(cascades[0].radius < cascades[1].radius);
+        assert!(cascades[1].radius < cascades[2].radius);
+    }
+
+    #[test]
+    fn cascade_index_and_transform() {
+        let camera = Camera::new(
+            crate::camera::perspective(100.0, 100.0),
+            Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y),
+        );
+        let cascades = fit_shadow_cascades(&camera, &[5.0, 20.0]);
+        let camera_id = Id::<Camera>::new(0);
+        let cascades_array = Array::new(Camera::SLAB_SIZE as u32, 2);
+        let descriptor = ShadowMapDescriptor {
+            camera_id,
+            cascades: cascades_array,
+            ..Default::default()
+        };
+        let mut slab = vec![0u32; Camera::SLAB_SIZE + 2 * ShadowCascade::SLAB_SIZE];
+        slab.write(camera_id, &camera);
+        slab.write_array(cascades_array, &cascades);
+
+        assert_eq!(
+            0,
+            descriptor.cascade_index(Vec3::new(0.0, 0.0, -1.0), &slab)
+        );
+        assert_eq!(
+            0,
+            descriptor.cascade_index(Vec3::new(1.0, 0.0, -5.0), &slab)
+        );
+        assert_eq!(
+            1,
+            descriptor.cascade_index(Vec3::new(0.0, 2.0, -10.0), &slab)
+        );
+        assert_eq!(
+            2,
+            descriptor.cascade_index(Vec3::new(0.0, 0.0, -30.0), &slab)
+        );
+
+        // each cascade's bounding sphere is inside its clip space
+        let direction = Vec3::new(1.0, -1.0, 0.5).normalize();
+        for cascade in cascades {
+            let transform = descriptor.cascade_transform(direction, cascade);
+            for offset in [Vec3::ZERO, Vec3::X, Vec3::NEG_Y, Vec3::Z, direction] {
+                let p = cascade.center + offset * cascade.radius * 0.99;
+                let clip = transform * p.extend(1.0);
+                let ndc = clip.xyz() / clip.w;
+                assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "{ndc}");
+                assert!((0.0..=1.0).contains(&ndc.z), "{ndc}");
+            }
+        }
+    }
+
+    #[test]
+    // Tests that the cascade debug mode colors fragments by cascade.
+    fn cascaded_shadow_debug_colors() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec4::ZERO)
+            .with_debug_mode(DebugMode::ShadowCascade);
+        let (projection, _) = crate::camera::default_perspective(100.0, 100.0);
+        let view = Mat4::look_at_rh(
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, -20.0),
+            Vec3::Y,
+        );
+        let camera = stage.new_value(Camera::new(projection, view));
+
+        let directional = stage.new_value(DirectionalLight {
+            direction: Vec3::new(0.5, -1.0, 0.0),
+            color: Vec4::ONE,
+            intensity: 5.0,
+        });
+        let light = stage.new_value(Light::from(directional.id()));
+        stage.set_lights([light.id()]);
+
+        let material = stage.new_value(Material::default());
+        let geometry = stage.new_array(
+            math::unit_cube()
+                .into_iter()
+                .map(|(p, n)| Vertex::default().with_position(p).with_normal(n)),
+        );
+        let ground_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, -0.05, -50.0),
+            scale: Vec3::new(100.0, 0.1, 100.0),
+            ..Default::default()
+        });
+        let ground = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: ground_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&ground);
+
+        let shadow_map = stage
+            .new_cascaded_shadow_map(&light, &camera, UVec2::splat(256), 3)
+            .with_cascade_splits(CascadeSplits::Distances(vec![5.0, 15.0, 40.0]));
+        assert!(shadow_map.is_cascaded());
+        assert_eq!(3, shadow_map.layer_count());
+        assert_eq!(
+            vec![5.0, 15.0, 40.0],
+            shadow_map
+                .get_cascades()
+                .iter()
+                .map(|c| c.split_far)
+                .collect::<Vec<_>>()
+        );
+
+        let pixel_at = |p: Vec3| -> (u32, u32) {
+            let clip = projection * view * p.extend(1.0);
+            let ndc = clip.xyz() / clip.w;
+            (
+                ((ndc.x * 0.5 + 0.5) * 100.0) as u32,
+                ((0.5 - ndc.y * 0.5) * 100.0) as u32,
+            )
+        };
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::save("shadows/cascade_debug.png", img.clone());
+
+        let color_at = |p: Vec3| {
+            let (x, y) = pixel_at(p);
+            let image::Rgba([r, g, b, _]) = *img.get_pixel(x, y);
+            (r > 127, g > 127, b > 127)
+        };
+        assert_eq!((true, false, false), color_at(Vec3::new(0.0, 0.0, -3.0)));
+        assert_eq!((false, true, false), color_at(Vec3::new(0.0, 0.0, -10.0)));
+        assert_eq!((false, false, true), color_at(Vec3::new(0.0, 0.0, -25.0)));
+    }
+}
@@ -0,0 +1,616 @@
+//! CPU side of shadow mapping.
+use std::sync::{Arc, RwLock};
+
+use crabslab::{Id, Slab, SlabItem};
+use glam::{Mat4, UVec2, Vec2, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Camera,
+    pbr::light::{Light, LightStyle},
+    slab::{Hybrid, HybridArray, SlabAllocator},
+    stage::{Renderlet, Topology},
+    texture::Texture,
+};
+
+use super::{shadow_map_layer_count, ShadowCascade, ShadowMapDescriptor, ShadowMapPass};
+
+fn shadow_map_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("shadow map"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+/// Creates the depth-only pipeline that renders shadow maps.
+pub(crate) fn create_shadow_map_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
+    log::trace!("creating shadow map pipeline");
+    let label = Some("shadow map");
+    let vertex_linkage = crate::linkage::shadow_mapping_vertex::linkage(device);
+    let stage_slab_buffers_layout = crate::linkage::slab_bindgroup_layout(device);
+    let shadow_map_layout = shadow_map_bindgroup_layout(device);
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &[&stage_slab_buffers_layout, &shadow_map_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label,
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &vertex_linkage.module,
+            entry_point: vertex_linkage.entry_point,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: None,
+        multiview: None,
+    })
+}
+
+/// How the frustum of a camera is split into the cascades of a cascaded
+/// shadow map.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CascadeSplits {
+    /// Splits the frustum into slices of equal depth.
+    Uniform,
+    /// Splits the frustum logarithmically, so each slice is proportionally
+    /// deeper than the last.
+    Logarithmic,
+    /// Blends between [`CascadeSplits::Uniform`] and
+    /// [`CascadeSplits::Logarithmic`], where a `lambda` of `0.0` is uniform
+    /// and `1.0` is logarithmic.
+    ///
+    /// This is the "practical split scheme" of parallel-split shadow maps.
+    Practical { lambda: f32 },
+    /// The distance from the camera to the far end of each cascade, in
+    /// increasing order.
+    ///
+    /// Cascades without a distance end at the far end of the frustum.
+    Distances(Vec<f32>),
+}
+
+impl Default for CascadeSplits {
+    fn default() -> Self {
+        CascadeSplits::Practical { lambda: 0.5 }
+    }
+}
+
+impl CascadeSplits {
+    /// Returns the distance from the camera to the far end of each of
+    /// `count` cascades covering the depths from `near` to `far`.
+    pub fn split_distances(&self, count: u32, near: f32, far: f32) -> Vec<f32> {
+        let lambda = match self {
+            CascadeSplits::Uniform => 0.0,
+            CascadeSplits::Logarithmic => 1.0,
+            CascadeSplits::Practical { lambda } => lambda.clamp(0.0, 1.0),
+            CascadeSplits::Distances(distances) => {
+                return (0..count as usize)
+                    .map(|i| distances.get(i).copied().unwrap_or(far).clamp(near, far))
+                    .collect();
+            }
+        };
+        (1..=count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                let uniform = near + (far - near) * t;
+                let logarithmic = near * (far / near).powf(t);
+                uniform + (logarithmic - uniform) * lambda
+            })
+            .collect()
+    }
+}
+
+/// Returns the distances from the camera to the near and far planes of its
+/// frustum.
+///
+/// The far distance is infinite for projections without a far plane.
+pub fn camera_depth_range(camera: &Camera) -> (f32, f32) {
+    let inverse = camera.projection.inverse();
+    let near = -inverse.project_point3(Vec3::ZERO).z;
+    let far = -inverse.project_point3(Vec3::Z).z;
+    if far.is_finite() && far > near {
+        (near, far)
+    } else {
+        (near, f32::INFINITY)
+    }
+}
+
+/// Returns the bounding sphere of each slice of the camera's frustum, where
+/// `splits` are the distances from the camera to the far end of each slice.
+///
+/// The first slice starts at the camera's near plane.
+pub fn fit_shadow_cascades(camera: &Camera, splits: &[f32]) -> Vec<ShadowCascade> {
+    let inverse_projection = camera.projection.inverse();
+    let inverse_view = camera.view.inverse();
+    // the view space point on each of the frustum's corner edges at the given
+    // distance from the camera
+    let corners_at = |distance: f32| -> [Vec3; 4] {
+        [
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(1.0, -1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(-1.0, 1.0),
+        ]
+        .map(|ndc| {
+            let a = inverse_projection.project_point3(ndc.extend(0.0));
+            let b = inverse_projection.project_point3(ndc.extend(0.5));
+            let t = (distance + a.z) / (a.z - b.z);
+            inverse_view.transform_point3(a + (b - a) * t)
+        })
+    };
+    let (near, _) = camera_depth_range(camera);
+    let mut split_near = near;
+    splits
+        .iter()
+        .map(|&split_far| {
+            let corners = corners_at(split_near)
+                .into_iter()
+                .chain(corners_at(split_far))
+                .collect::<Vec<_>>();
+            split_near = split_far;
+            let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+            let radius = corners
+                .iter()
+                .map(|corner| corner.distance(center))
+                .fold(0.0, f32::max);
+            ShadowCascade {
+                center,
+                radius,
+                split_far,
+            }
+        })
+        .collect()
+}
+
+/// The cascades of a cascaded shadow map and how they're fit.
+pub(crate) struct Cascades {
+    camera: Hybrid<Camera>,
+    cascades: HybridArray<ShadowCascade>,
+    splits: CascadeSplits,
+    max_distance: f32,
+}
+
+impl Cascades {
+    /// Fit the cascades to the current frustum of the camera.
+    fn update(&self) {
+        let camera = self.camera.get();
+        let (near, far) = camera_depth_range(&camera);
+        let far = far.min(self.max_distance).max(near);
+        let splits = self
+            .splits
+            .split_distances(self.cascades.len() as u32, near, far);
+        for (i, cascade) in fit_shadow_cascades(&camera, &splits)
+            .into_iter()
+            .enumerate()
+        {
+            if self.cascades.get(i) != Some(cascade) {
+                self.cascades.set_item(i, cascade);
+            }
+        }
+    }
+}
+
+/// A depth texture array that holds the shadow maps of all of a stage's
+/// lights, one shadow map per layer.
+pub(crate) struct ShadowMapAtlas {
+    pub(crate) texture: Texture,
+    pub(crate) layer_views: Vec<wgpu::TextureView>,
+}
+
+impl ShadowMapAtlas {
+    pub fn new(device: &wgpu::Device, size: UVec2, layers: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow map atlas"),
+            size: wgpu::Extent3d {
+                width: size.x.max(1),
+                height: size.y.max(1),
+                depth_or_array_layers: layers.max(1),
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow map atlas"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..texture.depth_or_array_layers())
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow map atlas layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        // Depth is compared manually in the shader, so the sampler must not
+        // filter.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow map atlas"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture: Texture {
+                texture: Arc::new(texture),
+                view: Arc::new(view),
+                sampler: Arc::new(sampler),
+            },
+            layer_views,
+        }
+    }
+
+    /// The size of each layer, in texels.
+    pub fn get_size(&self) -> UVec2 {
+        UVec2::new(self.texture.width(), self.texture.height())
+    }
+
+    pub fn get_layers(&self) -> u32 {
+        self.layer_views.len() as u32
+    }
+
+    /// Returns whether the atlas can hold `layers` shadow maps of the given
+    /// size.
+    pub fn can_hold(&self, size: UVec2, layers: u32) -> bool {
+        let atlas_size = self.get_size();
+        size.x <= atlas_size.x && size.y <= atlas_size.y && layers <= self.get_layers()
+    }
+}
+
+/// A shadow map for one light.
+///
+/// Create a shadow map with
+/// [`Stage::new_shadow_map`](crate::stage::Stage::new_shadow_map).
+///
+/// Clones all reference the same shadow map. Once all clones are dropped the
+/// light stops casting shadows.
+///
+/// Every visible triangle renderlet casts shadows, including blended and
+/// transmissive renderlets. Shadows are depth-only, so renderlets cast
+/// shadows as if they were opaque, regardless of their material's alpha or
+/// transmission.
+#[derive(Clone)]
+pub struct ShadowMap {
+    pub(crate) descriptor: Hybrid<ShadowMapDescriptor>,
+    pub(crate) light: Hybrid<Light>,
+    // One bindgroup per layer, each binding a buffer that holds the
+    // `ShadowMapPass` read by the shadow mapping vertex shader.
+    pub(crate) bindgroups: Arc<Vec<wgpu::BindGroup>>,
+    pub(crate) cascades: Option<Arc<RwLock<Cascades>>>,
+}
+
+fn create_pass_bindgroups(
+    device: &wgpu::Device,
+    pipeline: &wgpu::RenderPipeline,
+    light: &Hybrid<Light>,
+    layers: u32,
+) -> Vec<wgpu::BindGroup> {
+    let layout = pipeline.get_bind_group_layout(1);
+    (0..layers)
+        .map(|layer| {
+            let pass = ShadowMapPass {
+                light_id: light.id(),
+                layer,
+            };
+            let mut contents = vec![0u32; ShadowMapPass::SLAB_SIZE];
+            contents.write(Id::new(0), &pass);
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("shadow map pass"),
+                contents: bytemuck::cast_slice(&contents),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("shadow map"),
+                layout: &layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            })
+        })
+        .collect()
+}
+
+impl ShadowMap {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        mngr: &mut SlabAllocator<wgpu::Buffer>,
+        pipeline: &wgpu::RenderPipeline,
+        light: &Hybrid<Light>,
+        size: UVec2,
+    ) -> Self {
+        let light_type = light.get().light_type;
+        let projection = match light_type {
+            LightStyle::Point => Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0),
+            LightStyle::Directional | LightStyle::Spot => ShadowMapDescriptor::default().projection,
+        };
+        let descriptor = mngr.new_value(ShadowMapDescriptor {
+            projection,
+            size,
+            ..Default::default()
+        });
+        light.modify(|light| light.shadow_map = descriptor.id());
+        let bindgroups =
+            create_pass_bindgroups(device, pipeline, light, shadow_map_layer_count(light_type));
+        Self {
+            descriptor,
+            light: light.clone(),
+            bindgroups: Arc::new(bindgroups),
+            cascades: None,
+        }
+    }
+
+    pub(crate) fn new_cascaded(
+        device: &wgpu::Device,
+        mngr: &mut SlabAllocator<wgpu::Buffer>,
+        pipeline: &wgpu::RenderPipeline,
+        light: &Hybrid<Light>,
+        camera: &Hybrid<Camera>,
+        size: UVec2,
+        cascade_count: u32,
+    ) -> Self {
+        let cascade_count = cascade_count.max(1);
+        let cascades = mngr.new_array(vec![ShadowCascade::default(); cascade_count as usize]);
+        let descriptor = mngr.new_value(ShadowMapDescriptor {
+            size,
+            camera_id: camera.id(),
+            cascades: cascades.array(),
+            ..Default::default()
+        });
+        light.modify(|light| light.shadow_map = descriptor.id());
+        let bindgroups = create_pass_bindgroups(device, pipeline, light, cascade_count);
+        let cascades = Cascades {
+            camera: camera.clone(),
+            cascades,
+            splits: CascadeSplits::default(),
+            max_distance: 100.0,
+        };
+        cascades.update();
+        Self {
+            descriptor,
+            light: light.clone(),
+            bindgroups: Arc::new(bindgroups),
+            cascades: Some(Arc::new(RwLock::new(cascades))),
+        }
+    }
+
+    /// Returns the number of shadow map atlas layers this shadow map renders
+    /// into.
+    ///
+    /// This is `6` for point lights - one layer per cubemap face - the number
+    /// of cascades for cascaded shadow maps and `1` otherwise.
+    pub fn layer_count(&self) -> u32 {
+        self.bindgroups.len() as u32
+    }
+
+    /// Returns the shadow map's descriptor.
+    pub fn get_descriptor(&self) -> ShadowMapDescriptor {
+        self.descriptor.get()
+    }
+
+    /// Returns the light that casts this shadow map.
+    pub fn get_light(&self) -> &Hybrid<Light> {
+        &self.light
+    }
+
+    /// Set the projection from light space into the shadow map's clip space.
+    ///
+    /// See [`ShadowMapDescriptor::projection`].
+    pub fn set_projection(&self, projection: Mat4) {
+        self.descriptor.modify(|d| d.projection = projection);
+    }
+
+    /// Set the projection from light space into the shadow map's clip space
+    /// and return the shadow map.
+    pub fn with_projection(self, projection: Mat4) -> Self {
+        self.set_projection(projection);
+        self
+    }
+
+    /// Set the size of the shadow map, in texels.
+    ///
+    /// The shadow maps of point lights should be square.
+    pub fn set_size(&self, size: UVec2) {
+        self.descriptor.modify(|d| d.size = size);
+    }
+
+    /// Set the size of the shadow map, in texels, and return the shadow map.
+    pub fn with_size(self, size: UVec2) -> Self {
+        self.set_size(size);
+        self
+    }
+
+    /// Set the constant depth bias.
+    ///
+    /// See [`ShadowMapDescriptor::depth_bias`].
+    pub fn set_depth_bias(&self, depth_bias: f32) {
+        self.descriptor.modify(|d| d.depth_bias = depth_bias);
+    }
+
+    /// Set the constant depth bias and return the shadow map.
+    pub fn with_depth_bias(self, depth_bias: f32) -> Self {
+        self.set_depth_bias(depth_bias);
+        self
+    }
+
+    /// Set the radius of the percentage-closer filtering kernel, in texels.
+    ///
+    /// `0` results in hard shadows.
+    pub fn set_pcf_radius(&self, pcf_radius: u32) {
+        self.descriptor.modify(|d| d.pcf_radius = pcf_radius);
+    }
+
+    /// Set the radius of the percentage-closer filtering kernel and return
+    /// the shadow map.
+    pub fn with_pcf_radius(self, pcf_radius: u32) -> Self {
+        self.set_pcf_radius(pcf_radius);
+        self
+    }
+
+    /// Returns whether this is a cascaded shadow map.
+    pub fn is_cascaded(&self) -> bool {
+        self.cascades.is_some()
+    }
+
+    /// Returns the cascades of a cascaded shadow map, as of the last time
+    /// they were fit to the camera.
+    ///
+    /// Returns an empty `Vec` if this is not a cascaded shadow map.
+    pub fn get_cascades(&self) -> Vec<ShadowCascade> {
+        self.cascades
+            .as_ref()
+            .map(|cascades| {
+                let cascades = cascades.read().unwrap();
+                (0..cascades.cascades.len())
+                    .filter_map(|i| cascades.cascades.get(i))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Set how the camera's frustum is split into cascades.
+    ///
+    /// Has no effect if this is not a cascaded shadow map.
+    pub fn set_cascade_splits(&self, splits: CascadeSplits) {
+        if let Some(cascades) = self.cascades.as_ref() {
+            // UNWRAP: if we can't acquire the lock we want to panic.
+            let mut cascades = cascades.write().unwrap();
+            cascades.splits = splits;
+            cascades.update();
+        }
+    }
+
+    /// Set how the camera's frustum is split into cascades and return the
+    /// shadow map.
+    pub fn with_cascade_splits(self, splits: CascadeSplits) -> Self {
+        self.set_cascade_splits(splits);
+        self
+    }
+
+    /// Set the maximum distance from the camera that cascades cover.
+    ///
+    /// Fragments farther away than this are not shadowed. Defaults to `100.0`.
+    ///
+    /// Has no effect if this is not a cascaded shadow map.
+    pub fn set_max_distance(&self, max_distance: f32) {
+        if let Some(cascades) = self.cascades.as_ref() {
+            // UNWRAP: if we can't acquire the lock we want to panic.
+            let mut cascades = cascades.write().unwrap();
+            cascades.max_distance = max_distance;
+            cascades.update();
+        }
+    }
+
+    /// Set the maximum distance from the camera that cascades cover and
+    /// return the shadow map.
+    pub fn with_max_distance(self, max_distance: f32) -> Self {
+        self.set_max_distance(max_distance);
+        self
+    }
+
+    /// Set how far beyond each cascade, towards the light, shadow casters
+    /// are included.
+    ///
+    /// See [`ShadowMapDescriptor::caster_distance`].
+    pub fn set_caster_distance(&self, caster_distance: f32) {
+        self.descriptor
+            .modify(|d| d.caster_distance = caster_distance);
+    }
+
+    /// Set how far beyond each cascade shadow casters are included and
+    /// return the shadow map.
+    pub fn with_caster_distance(self, caster_distance: f32) -> Self {
+        self.set_caster_distance(caster_distance);
+        self
+    }
+
+    /// Fit the cascades of a cascaded shadow map to the current frustum of
+    /// its camera.
+    pub(crate) fn update_cascades(&self) {
+        if let Some(cascades) = self.cascades.as_ref() {
+            // UNWRAP: if we can't acquire the lock we want to panic.
+            cascades.read().unwrap().update();
+        }
+    }
+
+    /// Render each layer of the shadow map into the atlas.
+    pub(crate) fn render<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        slab_buffers_bindgroup: &wgpu::BindGroup,
+        atlas: &ShadowMapAtlas,
+        renderlets: impl IntoIterator<Item = &'a Hybrid<Renderlet>> + Clone,
+    ) {
+        let descriptor = self.descriptor.get();
+        let size = descriptor.size;
+        for (layer, bindgroup) in self.bindgroups.iter().enumerate() {
+            let layer_view = &atlas.layer_views[descriptor.atlas_layer as usize + layer];
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow map"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: layer_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, slab_buffers_bindgroup, &[]);
+            render_pass.set_bind_group(1, bindgroup, &[]);
+            render_pass.set_viewport(0.0, 0.0, size.x as f32, size.y as f32, 0.0, 1.0);
+            for hybrid in renderlets.clone() {
+                let rlet = hybrid.get();
+                // only triangles cast shadows
+                if rlet.visible && rlet.topology == Topology::TriangleList {
+                    render_pass.draw(
+                        0..rlet.get_vertex_count(),
+                        rlet.get_instance_range(hybrid.id()),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,672 @@
+//! Forward+ tiled light culling.
+//!
+//! Without light tiling every fragment is shaded by every light in
+//! [`PbrConfig::light_array`], which gets expensive as the number of lights
+//! grows. With light tiling the stage first renders a depth prepass, then a
+//! compute shader splits the screen into tiles and, for each tile, finds the
+//! lights that may reach the geometry within the tile. The fragment shader
+//! then only evaluates the lights of the fragment's tile.
+//!
+//! Point lights reach as far as their illuminance stays above
+//! [`LightTilingDescriptor::minimum_illuminance`]. Directional and spot
+//! lights are included in every tile.
+//!
+//! ## References
+//! * <https://takahiroharada.files.wordpress.com/2015/04/forward_plus.pdf>
+use crabslab::{Array, Id, Slab, SlabItem};
+use glam::{Mat4, UVec2, UVec3, Vec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::{
+    image::{sample_with, Image2d, ImageWithMethods},
+    spirv, Sampler,
+};
+
+#[cfg(target_arch = "spirv")]
+use spirv_std::num_traits::Float;
+
+use crate::{
+    camera::Camera,
+    math::{IsSampler, Sample2d},
+    pbr::{
+        light::{Light, LightStyle},
+        AlphaMode, Material, PbrConfig,
+    },
+};
+
+#[cfg(not(target_arch = "spirv"))]
+mod cpu;
+#[cfg(not(target_arch = "spirv"))]
+pub(crate) use cpu::*;
+
+/// Configures Forward+ tiled light culling.
+#[repr(C)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, PartialEq, SlabItem)]
+pub struct LightTilingDescriptor {
+    /// The camera whose view is split into tiles.
+    ///
+    /// Renderlets drawn with other cameras are shaded by every light.
+    pub camera_id: Id<Camera>,
+    /// The width and height of each tile, in pixels.
+    pub tile_size: u32,
+    /// The maximum number of lights that shade one tile.
+    ///
+    /// Lights beyond this number are ignored.
+    pub max_lights_per_tile: u32,
+    /// The illuminance below which a point light no longer contributes to
+    /// shading.
+    ///
+    /// Point light illuminance falls off with the square of the distance
+    /// from the light, so this determines how far each point light reaches.
+    pub minimum_illuminance: f32,
+    /// The number of tiles horizontally and vertically.
+    ///
+    /// This is maintained by the stage.
+    pub tile_grid: UVec2,
+    /// `max_lights_per_tile` light slots for each tile, in row-major tile
+    /// order.
+    ///
+    /// This is maintained by the stage and filled in by
+    /// [`compute_light_tiles`].
+    pub tile_lights: Array<Id<Light>>,
+    /// The number of lights in each tile.
+    ///
+    /// This is maintained by the stage and filled in by
+    /// [`compute_light_tiles`].
+    pub tile_light_counts: Array<u32>,
+}
+
+impl Default for LightTilingDescriptor {
+    fn default() -> Self {
+        Self {
+            camera_id: Id::NONE,
+            tile_size: 16,
+            max_lights_per_tile: 64,
+            minimum_illuminance: 0.05,
+            tile_grid: UVec2::ZERO,
+            tile_lights: Array::default(),
+            tile_light_counts: Array::default(),
+        }
+    }
+}
+
+/// Returns the number of tiles needed to cover the given resolution.
+pub fn tile_grid_size(resolution: UVec2, tile_size: u32) -> UVec2 {
+    let tile_size = if tile_size == 0 { 1 } else { tile_size };
+    (resolution + UVec2::splat(tile_size - 1)) / tile_size
+}
+
+/// Returns the distance at which the illuminance of a point light of the
+/// given intensity drops to `minimum_illuminance`.
+///
+/// Returns `f32::MAX` if `minimum_illuminance` is not positive, as the light
+/// then reaches everywhere.
+pub fn point_light_radius(intensity: f32, minimum_illuminance: f32) -> f32 {
+    if minimum_illuminance <= 0.0 {
+        f32::MAX
+    } else {
+        (intensity / minimum_illuminance).sqrt()
+    }
+}
+
+/// Returns whether the sphere intersects the axis-aligned bounding box.
+pub fn sphere_intersects_aabb(center: Vec3, radius: f32, min: Vec3, max: Vec3) -> bool {
+    let closest = center.clamp(min, max);
+    closest.distance_squared(center) <= radius * radius
+}
+
+/// Returns the axis-aligned bounding box, in view space, of the part of a
+/// tile's frustum between the given depths.
+///
+/// `min_depth` and `max_depth` are normalized device depths, as stored in
+/// the depth buffer.
+pub fn tile_view_aabb(
+    inverse_projection: Mat4,
+    tile: UVec2,
+    tile_size: u32,
+    resolution: UVec2,
+    min_depth: f32,
+    max_depth: f32,
+) -> (Vec3, Vec3) {
+    let resolution = resolution.as_vec2();
+    let top_left = (tile * tile_size).as_vec2();
+    let bottom_right = ((tile + UVec2::ONE) * tile_size).as_vec2().min(resolution);
+    let ndc_min = Vec2::new(
+        top_left.x / resolution.x * 2.0 - 1.0,
+        1.0 - bottom_right.y / resolution.y * 2.0,
+    );
+    let ndc_max = Vec2::new(
+        bottom_right.x / resolution.x * 2.0 - 1.0,
+        1.0 - top_left.y / resolution.y * 2.0,
+    );
+    let unproject =
+        |x: f32, y: f32, depth: f32| inverse_projection.project_point3(Vec3::new(x, y, depth));
+    let a = unproject(ndc_min.x, ndc_min.y, min_depth);
+    let b = unproject(ndc_max.x, ndc_min.y, min_depth);
+    let c = unproject(ndc_max.x, ndc_max.y, min_depth);
+    let d = unproject(ndc_min.x, ndc_max.y, min_depth);
+    let e = unproject(ndc_min.x, ndc_min.y, max_depth);
+    let f = unproject(ndc_max.x, ndc_min.y, max_depth);
+    let g = unproject(ndc_max.x, ndc_max.y, max_depth);
+    let h = unproject(ndc_min.x, ndc_max.y, max_depth);
+    let min = a.min(b).min(c).min(d).min(e).min(f).min(g).min(h);
+    let max = a.max(b).max(c).max(d).max(e).max(f).max(g).max(h);
+    (min, max)
+}
+
+impl LightTilingDescriptor {
+    /// Returns the index of the tile that contains the given world space
+    /// position, as seen by the given camera.
+    pub fn tile_index(&self, in_pos: Vec3, camera: &Camera, resolution: UVec2) -> u32 {
+        let clip: Vec4 = camera.projection * camera.view * in_pos.extend(1.0);
+        let ndc = clip.xy() / clip.w;
+        let pixel = Vec2::new(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * resolution.as_vec2();
+        let tile_size = if self.tile_size == 0 {
+            1
+        } else {
+            self.tile_size
+        };
+        let tile = pixel.max(Vec2::ZERO).as_uvec2() / tile_size;
+        // fragments on the bottom and right edges may land just outside the
+        // grid
+        let x = if tile.x < self.tile_grid.x {
+            tile.x
+        } else if self.tile_grid.x > 0 {
+            self.tile_grid.x - 1
+        } else {
+            0
+        };
+        let y = if tile.y < self.tile_grid.y {
+            tile.y
+        } else if self.tile_grid.y > 0 {
+            self.tile_grid.y - 1
+        } else {
+            0
+        };
+        y * self.tile_grid.x + x
+    }
+
+    /// Returns the lights of the given tile.
+    pub fn get_tile_lights(&self, tile_index: u32, slab: &[u32]) -> Array<Id<Light>> {
+        let count = slab.read(self.tile_light_counts.at(tile_index as usize));
+        let count = if count < self.max_lights_per_tile {
+            count
+        } else {
+            self.max_lights_per_tile
+        };
+        Array::new(
+            self.tile_lights.starting_index() as u32 + tile_index * self.max_lights_per_tile,
+            count,
+        )
+    }
+
+    /// Returns whether the given light may reach geometry within the view
+    /// space bounding box of a tile.
+    pub fn light_intersects_tile(
+        &self,
+        light: Light,
+        view: Mat4,
+        min: Vec3,
+        max: Vec3,
+        slab: &[u32],
+    ) -> bool {
+        match light.light_type {
+            LightStyle::Point => {
+                let point = slab.read(light.into_point_id());
+                let transform = Mat4::from(slab.read(light.transform));
+                let position = view.transform_point3(transform.transform_point3(point.position));
+                let radius = point_light_radius(point.intensity, self.minimum_illuminance);
+                sphere_intersects_aabb(position, radius, min, max)
+            }
+            LightStyle::Directional | LightStyle::Spot => true,
+        }
+    }
+}
+
+/// Returns whether the depth prepass should discard a fragment.
+///
+/// This is the case for fragments of [`AlphaMode::Mask`] materials with an
+/// alpha below the material's cutoff, which the main pass discards as well,
+/// so they don't occlude the geometry behind them.
+#[allow(clippy::too_many_arguments)]
+pub fn depth_prepass_discards<T: Sample2d<Sampler = S>, S: IsSampler>(
+    atlas: &T,
+    atlas_sampler: &S,
+    slab: &[u32],
+    atlas_size: UVec2,
+    in_material: Id<Material>,
+    in_color: Vec4,
+    in_uv0: Vec2,
+    in_uv1: Vec2,
+) -> bool {
+    let material = slab.read(in_material);
+    if material.alpha_mode != AlphaMode::Mask {
+        return false;
+    }
+    let albedo_tex_uv = material.albedo_tex_transform.transform_uv(
+        if material.albedo_tex_coord == 0 {
+            in_uv0
+        } else {
+            in_uv1
+        },
+    );
+    let albedo_tex_color = crate::pbr::texture_color(
+        material.albedo_texture_id,
+        albedo_tex_uv,
+        atlas,
+        atlas_sampler,
+        atlas_size,
+        slab,
+    );
+    let alpha = albedo_tex_color.w * material.albedo_factor.w * in_color.w;
+    alpha < material.alpha_cutoff
+}
+
+#[cfg(feature = "depth_prepass_fragment")]
+/// Depth prepass fragment shader.
+///
+/// Writes no color, only discards the fragments that
+/// [`depth_prepass_discards`].
+#[allow(clippy::too_many_arguments)]
+#[spirv(fragment)]
+pub fn depth_prepass_fragment(
+    #[spirv(descriptor_set = 1, binding = 0)] atlas: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 1)] atlas_sampler: &Sampler,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &[u32],
+    #[spirv(flat)] _in_camera: Id<Camera>,
+    #[spirv(flat)] in_material: Id<Material>,
+    #[spirv(flat)] in_pbr_config: Id<PbrConfig>,
+    in_color: Vec4,
+    in_uv0: Vec2,
+    in_uv1: Vec2,
+) {
+    let atlas_size = slab.read(in_pbr_config).atlas_size;
+    if depth_prepass_discards(
+        atlas,
+        atlas_sampler,
+        slab,
+        atlas_size,
+        in_material,
+        in_color,
+        in_uv0,
+        in_uv1,
+    ) {
+        spirv_std::arch::kill();
+    }
+}
+
+/// The [`Id`] of the [`PbrConfig`]'s [`Id`] on the light tiling buffer.
+pub const PBR_CONFIG_ID_ID: Id<Id<PbrConfig>> = Id::new(0);
+
+#[cfg(feature = "compute_light_tiles")]
+/// Fills in the light list of each tile of the screen.
+///
+/// `slab` is the stage's slab and `tiling_slab` holds the [`Id`] of the
+/// stage's [`PbrConfig`] at [`PBR_CONFIG_ID_ID`]. `depth_texture` is the
+/// result of the depth prepass.
+///
+/// Each invocation handles one tile.
+#[spirv(compute(threads(8, 8)))]
+pub fn compute_light_tiles(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &mut [u32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] tiling_slab: &[u32],
+    #[spirv(descriptor_set = 0, binding = 2)] depth_texture: &Image2d,
+    #[spirv(global_invocation_id)] global_id: UVec3,
+) {
+    let config = slab.read(tiling_slab.read(PBR_CONFIG_ID_ID));
+    if config.light_tiling.is_none() {
+        return;
+    }
+    let tiling = slab.read(config.light_tiling);
+    let tile = UVec2::new(global_id.x, global_id.y);
+    if tile.x >= tiling.tile_grid.x || tile.y >= tiling.tile_grid.y {
+        return;
+    }
+    let tile_index = tile.y * tiling.tile_grid.x + tile.x;
+
+    // find the depth range of the geometry in the tile
+    let mut min_depth = 1.0f32;
+    let mut max_depth = 0.0f32;
+    for y in 0..tiling.tile_size {
+        for x in 0..tiling.tile_size {
+            let pixel = tile * tiling.tile_size + UVec2::new(x, y);
+            if pixel.x < config.resolution.x && pixel.y < config.resolution.y {
+                let depth: Vec4 = depth_texture.fetch_with(pixel, sample_with::lod(0i32));
+                // skip pixels that are still clear
+                if depth.x < 1.0 {
+                    min_depth = min_depth.min(depth.x);
+                    max_depth = max_depth.max(depth.x);
+                }
+            }
+        }
+    }
+
+    let mut count = 0;
+    if min_depth <= max_depth {
+        let camera = slab.read(tiling.camera_id);
+        let (min, max) = tile_view_aabb(
+            camera.projection.inverse(),
+            tile,
+            tiling.tile_size,
+            config.resolution,
+            min_depth,
+            max_depth,
+        );
+        let first_slot = tile_index * tiling.max_lights_per_tile;
+        for i in 0..config.light_array.len() {
+            let light_id = slab.read(config.light_array.at(i));
+            if light_id.is_none() || count >= tiling.max_lights_per_tile {
+                break;
+            }
+            let light = slab.read(light_id);
+            if tiling.light_intersects_tile(light, camera.view, min, max, slab) {
+                slab.write(
+                    tiling.tile_lights.at((first_slot + count) as usize),
+                    &light_id,
+                );
+                count += 1;
+            }
+        }
+    }
+    slab.write(tiling.tile_light_counts.at(tile_index as usize), &count);
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{Vec3, Vec4};
+
+    use crate::{
+        math,
+        pbr::light::{Light, PointLight},
+        stage::{Renderlet, Vertex},
+        transform::Transform,
+    };
+
+    use super::*;
+
+    #[test]
+    fn tile_grid_covers_resolution() {
+        assert_eq!(UVec2::new(7, 4), tile_grid_size(UVec2::new(100, 64), 16));
+        assert_eq!(UVec2::new(6, 4), tile_grid_size(UVec2::new(96, 64), 16));
+        assert_eq!(UVec2::new(3, 2), tile_grid_size(UVec2::new(3, 2), 0));
+    }
+
+    #[test]
+    fn point_light_radius_reaches_minimum_illuminance() {
+        let radius = point_light_radius(20.0, 0.05);
+        assert!((20.0 / (radius * radius) - 0.05).abs() < 1.0e-6);
+        assert_eq!(f32::MAX, point_light_radius(1.0, 0.0));
+    }
+
+    #[test]
+    fn sphere_aabb_intersection() {
+        let (min, max) = (Vec3::splat(-1.0), Vec3::ONE);
+        assert!(sphere_intersects_aabb(Vec3::ZERO, 0.1, min, max));
+        assert!(sphere_intersects_aabb(
+            Vec3::new(2.0, 0.0, 0.0),
+            1.0,
+            min,
+            max
+        ));
+        assert!(!sphere_intersects_aabb(
+            Vec3::new(2.0, 0.0, 0.0),
+            0.9,
+            min,
+            max
+        ));
+        // the corner is farther than the faces
+        assert!(!sphere_intersects_aabb(Vec3::splat(2.0), 1.5, min, max));
+    }
+
+    #[test]
+    fn tile_view_aabb_contains_tile_geometry() {
+        let resolution = UVec2::new(160, 90);
+        let camera = Camera::default_perspective(160.0, 90.0);
+        let descriptor = LightTilingDescriptor {
+            tile_grid: tile_grid_size(resolution, 16),
+            ..Default::default()
+        };
+        let inverse_projection = camera.projection.inverse();
+        for p in [
+            Vec3::ZERO,
+            Vec3::new(5.0, 1.0, -3.0),
+            Vec3::new(-7.0, 2.0, 4.0),
+            Vec3::new(1.0, -2.0, 8.0),
+        ] {
+            let tile_index = descriptor.tile_index(p, &camera, resolution);
+            let tile = UVec2::new(
+                tile_index % descriptor.tile_grid.x,
+                tile_index / descriptor.tile_grid.x,
+            );
+            let clip = camera.projection * camera.view * p.extend(1.0);
+            let depth = clip.z / clip.w;
+            let (min, max) = tile_view_aabb(
+                inverse_projection,
+                tile,
+                descriptor.tile_size,
+                resolution,
+                depth - 1.0e-4,
+                depth + 1.0e-4,
+            );
+            let view_pos = camera.view.transform_point3(p);
+            assert!(
+                sphere_intersects_aabb(view_pos, 1.0e-3, min, max),
+                "{view_pos} is outside of tile {tile}: {min} {max}"
+            );
+            // a light far to the side of the tile doesn't reach it
+            assert!(!sphere_intersects_aabb(
+                view_pos + Vec3::new(10.0, 0.0, 0.0),
+                1.0,
+                min,
+                max
+            ));
+        }
+    }
+
+    #[test]
+    fn depth_prepass_discards_masked_fragments() {
+        let atlas = crate::math::CpuTexture2d::default();
+        let sampler = crate::math::CpuSampler;
+        let mut slab = vec![0u32; Material::SLAB_SIZE];
+        let id = Id::<Material>::new(0);
+        let discards = |slab: &[u32], color: Vec4| {
+            depth_prepass_discards(
+                &atlas,
+                &sampler,
+                slab,
+                UVec2::ONE,
+                id,
+                color,
+                Vec2::ZERO,
+                Vec2::ZERO,
+            )
+        };
+
+        slab.write(
+            id,
+            &Material {
+                albedo_factor: Vec4::new(1.0, 1.0, 1.0, 0.25),
+                alpha_mode: AlphaMode::Mask,
+                ..Default::default()
+            },
+        );
+        assert!(discards(&slab, Vec4::ONE));
+        slab.write(
+            id,
+            &Material {
+                alpha_mode: AlphaMode::Mask,
+                ..Default::default()
+            },
+        );
+        assert!(!discards(&slab, Vec4::ONE));
+        assert!(discards(&slab, Vec4::new(1.0, 1.0, 1.0, 0.25)));
+        // only masked materials are discarded
+        slab.write(
+            id,
+            &Material {
+                albedo_factor: Vec4::ZERO,
+                ..Default::default()
+            },
+        );
+        assert!(!discards(&slab, Vec4::ONE));
+    }
+
+    #[test]
+    // Tests that masked out fragments don't occlude the geometry behind them
+    // in the depth prepass.
+    fn light_tiling_masked_occluder() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec4::ZERO);
+        let (projection, view) = crate::camera::default_perspective(100.0, 100.0);
+        let camera = stage.new_value(Camera::new(projection, view));
+        let geometry = stage.new_array(
+            math::unit_cube()
+                .into_iter()
+                .map(|(p, n)| Vertex::default().with_position(p).with_normal(n)),
+        );
+
+        let red = stage.new_value(Material {
+            albedo_factor: Vec4::new(1.0, 0.0, 0.0, 1.0),
+            has_lighting: false,
+            ..Default::default()
+        });
+        let cube_transform = stage.new_value(Transform {
+            scale: Vec3::splat(2.0),
+            ..Default::default()
+        });
+        let cube = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: cube_transform.id(),
+            material_id: red.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&cube);
+
+        // a fully masked out wall between the camera and the cube
+        let masked = stage.new_value(Material {
+            albedo_factor: Vec4::new(0.0, 1.0, 0.0, 0.0),
+            alpha_mode: AlphaMode::Mask,
+            has_lighting: false,
+            ..Default::default()
+        });
+        let wall_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, 0.0, 2.0),
+            scale: Vec3::new(8.0, 8.0, 0.1),
+            ..Default::default()
+        });
+        let wall = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: wall_transform.id(),
+            material_id: masked.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&wall);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let untiled = frame.read_image().unwrap();
+        frame.present();
+
+        stage.set_light_tiling(Some(LightTilingDescriptor {
+            camera_id: camera.id(),
+            ..Default::default()
+        }));
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let tiled = frame.read_image().unwrap();
+        frame.present();
+        img_diff::save("tiling/masked_occluder.png", tiled.clone());
+        img_diff::assert_eq("tiling/masked_occluder.png", untiled, tiled.clone());
+        // the cube is visible through the wall
+        assert!(tiled
+            .pixels()
+            .any(|image::Rgba([r, g, _, _])| *r > 128 && *g == 0));
+    }
+
+    #[test]
+    // Tests that shading with light tiling matches shading without it.
+    fn light_tiling_sanity() {
+        let ctx = crate::Context::headless(100, 100);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec4::ZERO);
+        let (projection, _) = crate::camera::default_perspective(100.0, 100.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 10.0, 10.0), Vec3::ZERO, Vec3::Y);
+        let camera = stage.new_value(Camera::new(projection, view));
+
+        // a grid of small point lights above a plane
+        let mut point_lights = vec![];
+        let mut lights = vec![];
+        for x in -4..=4 {
+            for z in -4..=4 {
+                let point = stage.new_value(PointLight {
+                    position: Vec3::new(x as f32 * 1.5, 0.5, z as f32 * 1.5),
+                    color: Vec4::new((x + 4) as f32 / 8.0, 1.0, (z + 4) as f32 / 8.0, 1.0),
+                    intensity: 0.5,
+                });
+                lights.push(stage.new_value(Light::from(point.id())));
+                point_lights.push(point);
+            }
+        }
+        stage.set_lights(lights.iter().map(|light| light.id()));
+
+        let material = stage.new_value(Material::default());
+        let geometry = stage.new_array(
+            math::unit_cube()
+                .into_iter()
+                .map(|(p, n)| Vertex::default().with_position(p).with_normal(n)),
+        );
+        let ground_transform = stage.new_value(Transform {
+            translation: Vec3::new(0.0, -0.05, 0.0),
+            scale: Vec3::new(16.0, 0.1, 16.0),
+            ..Default::default()
+        });
+        let ground = stage.new_value(Renderlet {
+            camera_id: camera.id(),
+            vertices_array: geometry.array(),
+            transform_id: ground_transform.id(),
+            material_id: material.id(),
+            ..Default::default()
+        });
+        stage.add_renderlet(&ground);
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let untiled = frame.read_image().unwrap();
+        frame.present();
+
+        stage.set_light_tiling(Some(LightTilingDescriptor {
+            camera_id: camera.id(),
+            minimum_illuminance: 0.01,
+            ..Default::default()
+        }));
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let tiled = frame.read_image().unwrap();
+        frame.present();
+        img_diff::save("tiling/tiled.png", tiled.clone());
+        img_diff::assert_eq("tiling/tiled.png", untiled, tiled.clone());
+
+        // with no room for lights in the tiles, the plane is only lit by
+        // ambient light
+        stage.set_light_tiling(Some(LightTilingDescriptor {
+            camera_id: camera.id(),
+            max_lights_per_tile: 0,
+            ..Default::default()
+        }));
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let unlit = frame.read_image().unwrap();
+        let total = |img: &image::RgbaImage| -> u64 {
+            img.pixels()
+                .map(|image::Rgba([r, g, b, _])| *r as u64 + *g as u64 + *b as u64)
+                .sum()
+        };
+        assert!(total(&unlit) < total(&tiled));
+    }
+}
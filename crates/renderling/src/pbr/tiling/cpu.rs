@@ -0,0 +1,277 @@
+//! CPU side of tiled light culling.
+use crabslab::{Id, Slab, SlabItem};
+use glam::UVec2;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    pbr::{light::Light, PbrConfig},
+    slab::{GpuArray, Hybrid, SlabAllocator},
+    texture::Texture,
+};
+
+use super::{tile_grid_size, LightTilingDescriptor, PBR_CONFIG_ID_ID};
+
+fn light_tiling_bindgroup_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("light tiling"),
+        entries: &[
+            storage(0, false),
+            storage(1, true),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn create_light_tiling_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    let label = Some("light tiling");
+    let linkage = crate::linkage::compute_light_tiles::linkage(device);
+    let bindgroup_layout = light_tiling_bindgroup_layout(device);
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &[&bindgroup_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label,
+        layout: Some(&layout),
+        module: &linkage.module,
+        entry_point: linkage.entry_point,
+        compilation_options: Default::default(),
+    })
+}
+
+/// Creates the depth-only pipeline that renders the depth prepass.
+fn create_depth_prepass_pipeline(
+    device: &wgpu::Device,
+    cull_mode: Option<wgpu::Face>,
+    front_face: wgpu::FrontFace,
+) -> wgpu::RenderPipeline {
+    let label = Some("depth prepass");
+    let vertex_linkage = crate::linkage::renderlet_vertex::linkage(device);
+    let fragment_linkage = crate::linkage::depth_prepass_fragment::linkage(device);
+    let stage_slab_buffers_layout = crate::linkage::slab_bindgroup_layout(device);
+    let atlas_and_skybox_layout = crate::linkage::atlas_and_skybox_bindgroup_layout(device);
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label,
+        bind_group_layouts: &[&stage_slab_buffers_layout, &atlas_and_skybox_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label,
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &vertex_linkage.module,
+            entry_point: vertex_linkage.entry_point,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face,
+            cull_mode,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: &fragment_linkage.module,
+            entry_point: fragment_linkage.entry_point,
+            targets: &[],
+            compilation_options: Default::default(),
+        }),
+        multiview: None,
+    })
+}
+
+/// Depth prepass pipelines for each sidedness and winding of triangles.
+///
+/// These match the stage's triangle pipelines, so the prepass writes the
+/// same depth the main pass does.
+pub(crate) struct DepthPrepassPipelines {
+    double_sided: wgpu::RenderPipeline,
+    single_sided: wgpu::RenderPipeline,
+    mirrored_double_sided: wgpu::RenderPipeline,
+    mirrored_single_sided: wgpu::RenderPipeline,
+}
+
+impl DepthPrepassPipelines {
+    fn new(device: &wgpu::Device) -> Self {
+        let back = Some(wgpu::Face::Back);
+        let (ccw, cw) = (wgpu::FrontFace::Ccw, wgpu::FrontFace::Cw);
+        Self {
+            double_sided: create_depth_prepass_pipeline(device, None, ccw),
+            single_sided: create_depth_prepass_pipeline(device, back, ccw),
+            mirrored_double_sided: create_depth_prepass_pipeline(device, None, cw),
+            mirrored_single_sided: create_depth_prepass_pipeline(device, back, cw),
+        }
+    }
+
+    /// Returns the pipeline for the given sidedness and winding.
+    pub fn get(&self, double_sided: bool, mirrored: bool) -> &wgpu::RenderPipeline {
+        match (double_sided, mirrored) {
+            (true, false) => &self.double_sided,
+            (false, false) => &self.single_sided,
+            (true, true) => &self.mirrored_double_sided,
+            (false, true) => &self.mirrored_single_sided,
+        }
+    }
+}
+
+/// Forward+ tiled light culling.
+///
+/// Owns the tile light lists on the stage's slab, as well as the depth
+/// prepass and light culling pipelines.
+pub(crate) struct LightTiling {
+    pub(crate) descriptor: Hybrid<LightTilingDescriptor>,
+    pub(crate) depth_prepass_pipelines: DepthPrepassPipelines,
+    pipeline: wgpu::ComputePipeline,
+    // Holds the `Id` of the stage's `PbrConfig`, read by the light culling
+    // compute shader.
+    pbr_config_buffer: wgpu::Buffer,
+    tile_lights: Option<GpuArray<Id<Light>>>,
+    tile_light_counts: Option<GpuArray<u32>>,
+    bindgroup: Option<wgpu::BindGroup>,
+}
+
+impl LightTiling {
+    pub fn new(
+        device: &wgpu::Device,
+        mngr: &mut SlabAllocator<wgpu::Buffer>,
+        pbr_config_id: Id<PbrConfig>,
+        descriptor: LightTilingDescriptor,
+    ) -> Self {
+        let mut contents = vec![0u32; Id::<PbrConfig>::SLAB_SIZE];
+        contents.write(PBR_CONFIG_ID_ID, &pbr_config_id);
+        let pbr_config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("light tiling pbr config"),
+            contents: bytemuck::cast_slice(&contents),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        Self {
+            descriptor: mngr.new_value(LightTilingDescriptor {
+                tile_grid: UVec2::ZERO,
+                tile_lights: Default::default(),
+                tile_light_counts: Default::default(),
+                ..descriptor
+            }),
+            depth_prepass_pipelines: DepthPrepassPipelines::new(device),
+            pipeline: create_light_tiling_pipeline(device),
+            pbr_config_buffer,
+            tile_lights: None,
+            tile_light_counts: None,
+            bindgroup: None,
+        }
+    }
+
+    /// Set the configuration of the light tiling, keeping the tile light
+    /// lists maintained by the stage.
+    pub fn set_descriptor(&self, descriptor: LightTilingDescriptor) {
+        self.descriptor.modify(|d| {
+            d.camera_id = descriptor.camera_id;
+            d.tile_size = descriptor.tile_size;
+            d.max_lights_per_tile = descriptor.max_lights_per_tile;
+            d.minimum_illuminance = descriptor.minimum_illuminance;
+        });
+    }
+
+    /// Allocate the tile light lists for the given resolution, if need be.
+    pub fn upkeep(&mut self, mngr: &mut SlabAllocator<wgpu::Buffer>, resolution: UVec2) {
+        let descriptor = self.descriptor.get();
+        let tile_grid = tile_grid_size(resolution, descriptor.tile_size);
+        let tile_count = (tile_grid.x * tile_grid.y) as usize;
+        let slot_count = tile_count * descriptor.max_lights_per_tile as usize;
+        let needs_alloc = descriptor.tile_grid != tile_grid
+            || self.tile_lights.as_ref().map(|a| a.len()) != Some(slot_count)
+            || self.tile_light_counts.as_ref().map(|a| a.len()) != Some(tile_count);
+        if !needs_alloc {
+            return;
+        }
+        log::trace!("allocating {tile_grid} light tiles");
+        let tile_lights = GpuArray::new(mngr, &vec![Id::<Light>::NONE; slot_count]);
+        let tile_light_counts = GpuArray::new(mngr, &vec![0u32; tile_count]);
+        self.descriptor.modify(|d| {
+            d.tile_grid = tile_grid;
+            d.tile_lights = tile_lights.array();
+            d.tile_light_counts = tile_light_counts.array();
+        });
+        self.tile_lights = Some(tile_lights);
+        self.tile_light_counts = Some(tile_light_counts);
+    }
+
+    /// Invalidate the bindgroup, which references the stage's slab buffer
+    /// and depth texture.
+    pub fn invalidate_bindgroup(&mut self) {
+        let _ = self.bindgroup.take();
+    }
+
+    /// Run the light culling compute pass.
+    ///
+    /// The depth texture must hold the result of the depth prepass.
+    pub fn compute_light_tiles(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        stage_slab_buffer: &wgpu::Buffer,
+        depth_texture: &Texture,
+    ) {
+        let tile_grid = self.descriptor.get().tile_grid;
+        if tile_grid.x == 0 || tile_grid.y == 0 {
+            return;
+        }
+        let bindgroup = self.bindgroup.get_or_insert_with(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("light tiling"),
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: stage_slab_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: self.pbr_config_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                    },
+                ],
+            })
+        });
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("light tiling"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, bindgroup, &[]);
+        compute_pass.dispatch_workgroups(tile_grid.x.div_ceil(8), tile_grid.y.div_ceil(8), 1);
+    }
+}
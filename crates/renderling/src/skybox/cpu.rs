@@ -73,11 +73,17 @@ pub(crate) fn create_skybox_bindgroup(
 }
 
 /// Create the skybox rendering pipeline.
+///
+/// `multisample_count` must match the sample count of the render pass's
+/// attachments.
 pub(crate) fn create_skybox_render_pipeline(
     device: &wgpu::Device,
     format: wgpu::TextureFormat,
+    multisample_count: u32,
 ) -> SkyboxRenderPipeline {
-    log::trace!("creating skybox render pipeline with format '{format:?}'");
+    log::trace!(
+        "creating skybox render pipeline with format '{format:?}' and {multisample_count} samples"
+    );
     let vertex_linkage = crate::linkage::skybox_vertex::linkage(device);
     let fragment_linkage = crate::linkage::skybox_cubemap_fragment::linkage(device);
     let bg_layout = skybox_bindgroup_layout(device);
@@ -115,7 +121,7 @@ pub(crate) fn create_skybox_render_pipeline(
             multisample: wgpu::MultisampleState {
                 mask: !0,
                 alpha_to_coverage_enabled: false,
-                count: 1,
+                count: multisample_count,
             },
             fragment: Some(wgpu::FragmentState {
                 module: &fragment_linkage.module,
@@ -148,6 +154,8 @@ pub struct Skybox {
     pub prefiltered_environment_cubemap: Texture,
     // Texture of the pre-computed brdf integration
     pub brdf_lut: Texture,
+    // Texture of the pre-computed sheen brdf integration
+    pub sheen_lut: Texture,
     // `Id` of the camera to use for rendering the skybox.
     //
     // The camera is used to determine the orientation of the skybox.
@@ -201,38 +209,8 @@ impl Skybox {
 
         let equirectangular_texture =
             Skybox::hdr_texture_from_atlas_image(&device, &queue, hdr_img);
-        let views = [
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(1.0, 0.0, 0.0),
-                Vec3::new(0.0, -1.0, 0.0),
-            ),
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(-1.0, 0.0, 0.0),
-                Vec3::new(0.0, -1.0, 0.0),
-            ),
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, -1.0, 0.0),
-                Vec3::new(0.0, 0.0, -1.0),
-            ),
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, 1.0, 0.0),
-                Vec3::new(0.0, 0.0, 1.0),
-            ),
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, 0.0, 1.0),
-                Vec3::new(0.0, -1.0, 0.0),
-            ),
-            Mat4::look_at_rh(
-                Vec3::new(0.0, 0.0, 0.0),
-                Vec3::new(0.0, 0.0, -1.0),
-                Vec3::new(0.0, -1.0, 0.0),
-            ),
-        ];
+        let views: [Mat4; 6] =
+            std::array::from_fn(|i| crate::cubemap::cube_face_view(Vec3::ZERO, i));
 
         // Create environment map.
         let environment_cubemap = Skybox::create_environment_map_from_hdr(
@@ -270,12 +248,14 @@ impl Skybox {
         );
 
         let brdf_lut = Skybox::create_precomputed_brdf_texture(&device, &queue);
+        let sheen_lut = Skybox::create_precomputed_sheen_texture(device, queue);
 
         Skybox {
             environment_cubemap,
             irradiance_cubemap,
             prefiltered_environment_cubemap,
             brdf_lut,
+            sheen_lut,
             camera: camera_id,
         }
     }
@@ -555,10 +535,44 @@ impl Skybox {
     }
 
     fn create_precomputed_brdf_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        Skybox::create_precomputed_lut_texture(
+            device,
+            queue,
+            "brdf_lut",
+            &crate::linkage::brdf_lut_convolution_fragment::linkage(device),
+            wgpu::TextureFormat::Rg16Float,
+            2,
+            512,
+        )
+    }
+
+    fn create_precomputed_sheen_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        Skybox::create_precomputed_lut_texture(
+            device,
+            queue,
+            "sheen_lut",
+            &crate::linkage::sheen_lut_convolution_fragment::linkage(device),
+            wgpu::TextureFormat::R16Float,
+            1,
+            128,
+        )
+    }
+
+    /// Renders a look-up table by running the given fragment shader over a
+    /// full screen quad, where the UV coordinates are the table's inputs.
+    fn create_precomputed_lut_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        fragment_linkage: &crate::linkage::ShaderLinkage,
+        format: wgpu::TextureFormat,
+        channels: u32,
+        size: u32,
+    ) -> Texture {
+        let convolution_label = format!("{label}_convolution");
         let vertex_linkage = crate::linkage::brdf_lut_convolution_vertex::linkage(device);
-        let fragment_linkage = crate::linkage::brdf_lut_convolution_fragment::linkage(device);
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("brdf_lut_convolution"),
+            label: Some(&convolution_label),
             layout: None,
             vertex: wgpu::VertexState {
                 module: &vertex_linkage.module,
@@ -585,7 +599,7 @@ impl Skybox {
                 module: &fragment_linkage.module,
                 entry_point: fragment_linkage.entry_point,
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::Rg16Float,
+                    format,
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent::REPLACE,
                         alpha: wgpu::BlendComponent::REPLACE,
@@ -600,18 +614,18 @@ impl Skybox {
         let framebuffer = Texture::new_with(
             device,
             queue,
-            Some("brdf_lut"),
+            Some(label),
             Some(
                 wgpu::TextureUsages::RENDER_ATTACHMENT
                     | wgpu::TextureUsages::TEXTURE_BINDING
                     | wgpu::TextureUsages::COPY_SRC,
             ),
             None,
-            wgpu::TextureFormat::Rg16Float,
+            format,
+            channels,
             2,
-            2,
-            512,
-            512,
+            size,
+            size,
             1,
             &[],
         );
@@ -619,7 +633,7 @@ impl Skybox {
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("brdf_lut_convolution"),
+                label: Some(&convolution_label),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                     view: &framebuffer.view,
                     resolve_target: None,
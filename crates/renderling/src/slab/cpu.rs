@@ -7,7 +7,11 @@ use core::{
 use crabslab::{Slab, SlabItem};
 use rustc_hash::FxHashMap;
 use snafu::prelude::*;
-use std::sync::{atomic::AtomicBool, Arc, Mutex, RwLock};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock, RwLockReadGuard},
+};
 
 pub use crabslab::{Array, Id};
 
@@ -216,6 +220,19 @@ pub struct SlabAllocator<Buffer> {
     update_k: Arc<AtomicUsize>,
     update_sources: Arc<RwLock<FxHashMap<usize, Box<dyn UpdatesSlab>>>>,
     recycles: Arc<RwLock<RangeManager<Range>>>,
+    /// Slab range of each update source, keyed by its starting index.
+    spans: Arc<RwLock<BTreeMap<usize, SourceSpan>>>,
+    /// Number of upkeeps so far.
+    upkeeps: Arc<AtomicUsize>,
+}
+
+/// Where an update source lives in the slab, and when it was last written.
+#[derive(Clone, Copy, Debug)]
+struct SourceSpan {
+    k: usize,
+    len: usize,
+    /// The upkeep during which the source was last written.
+    written: usize,
 }
 
 impl<Buffer> Clone for SlabAllocator<Buffer> {
@@ -229,6 +246,8 @@ impl<Buffer> Clone for SlabAllocator<Buffer> {
             update_k: self.update_k.clone(),
             update_sources: self.update_sources.clone(),
             recycles: self.recycles.clone(),
+            spans: self.spans.clone(),
+            upkeeps: self.upkeeps.clone(),
         }
     }
 }
@@ -244,6 +263,8 @@ impl<Buffer> Default for SlabAllocator<Buffer> {
             capacity: Default::default(),
             needs_expansion: Arc::new(true.into()),
             buffer: Default::default(),
+            spans: Default::default(),
+            upkeeps: Default::default(),
         }
     }
 }
@@ -310,11 +331,20 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
             std::any::type_name_of_val(&source)
         );
         let _ = self.notifier.0.try_send(k);
+        let array = source.u32_array();
         // UNWRAP: panic on purpose
-        self.update_sources
-            .write()
-            .unwrap()
-            .insert(k, Box::new(source));
+        let mut sources_guard = self.update_sources.write().unwrap();
+        if !array.is_null() && !array.is_empty() {
+            self.spans.write().unwrap().insert(
+                array.starting_index(),
+                SourceSpan {
+                    k,
+                    len: array.len(),
+                    written: 0,
+                },
+            );
+        }
+        sources_guard.insert(k, Box::new(source));
     }
 
     fn len(&self) -> usize {
@@ -456,6 +486,7 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
         if !update_set.is_empty() {
             log::trace!("sources {:?}", update_set);
         }
+        let upkeep = self.upkeeps.fetch_add(1, Ordering::Relaxed) + 1;
         // Prepare all of our GPU buffer writes
         let mut writes = RangeManager::<SlabUpdate>::default();
         {
@@ -463,7 +494,11 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
             // sources' updates into `writes`.
             let mut updates_guard = self.update_sources.write().unwrap();
             let mut recycles_guard = self.recycles.write().unwrap();
+            let mut spans_guard = self.spans.write().unwrap();
             for key in update_set {
+                let start = updates_guard
+                    .get(&key)
+                    .map(|hybrid| hybrid.u32_array().starting_index());
                 let delete = if let Some(hybrid) = updates_guard.get_mut(&key) {
                     let count = hybrid.strong_count();
                     if count <= 1 {
@@ -478,10 +513,13 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
                             true
                         }
                     } else {
-                        hybrid
-                            .get_update()
-                            .into_iter()
-                            .for_each(|u| writes.add_range(u));
+                        let updates = hybrid.get_update();
+                        if !updates.is_empty() {
+                            if let Some(span) = start.and_then(|i| spans_guard.get_mut(&i)) {
+                                span.written = upkeep;
+                            }
+                        }
+                        updates.into_iter().for_each(|u| writes.add_range(u));
                         false
                     }
                 } else {
@@ -490,6 +528,12 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
                 };
                 if delete {
                     let _ = updates_guard.remove(&key);
+                    if let Some(start) = start {
+                        // the range may already belong to a newer source
+                        if spans_guard.get(&start).is_some_and(|span| span.k == key) {
+                            let _ = spans_guard.remove(&start);
+                        }
+                    }
                 }
             }
             // Defrag the recycle ranges
@@ -528,6 +572,22 @@ impl<Buffer: IsBuffer> SlabAllocator<Buffer> {
         new_buffer
     }
 
+    /// Returns a read-only view of the CPU values of this slab's hybrids,
+    /// without reading back from the buffer.
+    ///
+    /// New values can't be staged, nor can upkeep be performed, while the
+    /// view is alive.
+    pub(crate) fn cpu_values(&self) -> CpuValues<'_> {
+        CpuValues {
+            len: self.len(),
+            upkeeps: self.upkeeps.load(Ordering::Relaxed),
+            // UNWRAP: panic on purpose
+            sources: self.update_sources.read().unwrap(),
+            spans: self.spans.read().unwrap(),
+            element: Default::default(),
+        }
+    }
+
     /// Defragments the internal "recycle" buffer.
     pub fn defrag(&self) {
         // UNWRAP: panic on purpose
@@ -629,6 +689,12 @@ pub(crate) trait UpdatesSlab: Send + Sync + std::any::Any {
 
     /// Return the latest update, if any.
     fn get_update(&self) -> Vec<SlabUpdate>;
+
+    /// Returns the CPU value of the element containing the given slab index,
+    /// if the source keeps one.
+    fn cpu_element(&self, _index: usize) -> Option<SlabUpdate> {
+        None
+    }
 }
 
 impl<T: SlabItem + Clone + Send + Sync + std::any::Any> UpdatesSlab for Gpu<T> {
@@ -657,6 +723,19 @@ impl<T: SlabItem + Clone + Send + Sync + std::any::Any> UpdatesSlab for Hybrid<T
     fn get_update(&self) -> Vec<SlabUpdate> {
         self.gpu_value.get_update()
     }
+
+    fn cpu_element(&self, _index: usize) -> Option<SlabUpdate> {
+        // no other clone keeps the CPU value, so it may be stale
+        if Arc::strong_count(&self.cpu_value) <= 1 {
+            return None;
+        }
+        let mut elements = vec![0u32; T::SLAB_SIZE];
+        elements.write(Id::new(0), &self.get());
+        Some(SlabUpdate {
+            array: self.u32_array(),
+            elements,
+        })
+    }
 }
 
 impl<T: SlabItem + Clone + Send + Sync + std::any::Any> UpdatesSlab for GpuArray<T> {
@@ -685,6 +764,98 @@ impl<T: SlabItem + Clone + Send + Sync + std::any::Any> UpdatesSlab for HybridAr
     fn get_update(&self) -> Vec<SlabUpdate> {
         self.gpu_value.get_update()
     }
+
+    fn cpu_element(&self, index: usize) -> Option<SlabUpdate> {
+        // no other clone keeps the CPU values, so they may be stale
+        if Arc::strong_count(&self.cpu_value) <= 1 {
+            return None;
+        }
+        let i = index
+            .checked_sub(self.array().starting_index())?
+            .checked_div(T::SLAB_SIZE)?;
+        let mut elements = vec![0u32; T::SLAB_SIZE];
+        elements.write(Id::new(0), &self.get(i)?);
+        Some(SlabUpdate {
+            array: Array::new(self.get_id(i).inner(), T::SLAB_SIZE as u32),
+            elements,
+        })
+    }
+}
+
+/// A read-only view of the CPU values of a [`SlabAllocator`]'s update
+/// sources, created with [`SlabAllocator::cpu_values`].
+///
+/// Values are serialized from the hybrids that own them as they are read,
+/// so they may be ahead of the buffer until the next upkeep. Ranges without
+/// CPU values, like those of [`Gpu`] and [`GpuArray`], read as zeros.
+pub(crate) struct CpuValues<'a> {
+    len: usize,
+    upkeeps: usize,
+    sources: RwLockReadGuard<'a, FxHashMap<usize, Box<dyn UpdatesSlab>>>,
+    spans: RwLockReadGuard<'a, BTreeMap<usize, SourceSpan>>,
+    /// The element of the last read, which the next read likely falls in.
+    element: RefCell<Option<SlabUpdate>>,
+}
+
+impl CpuValues<'_> {
+    /// Returns the number of upkeeps performed before this view was created.
+    pub(crate) fn upkeeps(&self) -> usize {
+        self.upkeeps
+    }
+
+    fn span(&self, index: usize) -> Option<&SourceSpan> {
+        let (start, span) = self.spans.range(..=index).next_back()?;
+        (index < start + span.len).then_some(span)
+    }
+
+    /// Returns the upkeep during which the source containing the given slab
+    /// index was last written, or `0` if it hasn't been.
+    ///
+    /// See [`CpuValues::upkeeps`].
+    pub(crate) fn written_at(&self, index: usize) -> usize {
+        self.span(index)
+            .map(|span| span.written)
+            .unwrap_or_default()
+    }
+
+    fn read_u32(&self, index: usize) -> u32 {
+        let contains = |element: &SlabUpdate| {
+            let start = element.array.starting_index();
+            (start..start + element.array.len()).contains(&index)
+        };
+        let mut element = self.element.borrow_mut();
+        if !element.as_ref().is_some_and(contains) {
+            *element = self
+                .span(index)
+                .and_then(|span| self.sources.get(&span.k)?.cpu_element(index));
+        }
+        element
+            .as_ref()
+            .filter(|element| contains(element))
+            .map(|element| element.elements[index - element.array.starting_index()])
+            .unwrap_or_default()
+    }
+}
+
+impl Slab for CpuValues<'_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read_unchecked<T: SlabItem>(&self, id: Id<T>) -> T {
+        let words = (id.index()..id.index() + T::SLAB_SIZE)
+            .map(|index| self.read_u32(index))
+            .collect::<Vec<_>>();
+        T::read_slab(0, &words)
+    }
+
+    fn write_indexed<T: SlabItem>(&mut self, _t: &T, _index: usize) -> usize {
+        unimplemented!("CpuValues is read-only")
+    }
+
+    fn write_indexed_slice<T: SlabItem>(&mut self, _t: &[T], _index: usize) -> usize {
+        unimplemented!("CpuValues is read-only")
+    }
 }
 
 /// A "hybrid" type that lives on the CPU and the GPU.
@@ -718,11 +889,13 @@ impl<T> Clone for Hybrid<T> {
 impl<T: SlabItem + Clone + Send + Sync + 'static> Hybrid<T> {
     pub fn new(mngr: &mut SlabAllocator<impl IsBuffer>, value: T) -> Self {
         let cpu_value = Arc::new(RwLock::new(value.clone()));
-        let gpu_value = Gpu::new(mngr, value);
-        Self {
+        let gpu_value = Gpu::allocate(mngr, value);
+        let hybrid = Self {
             cpu_value,
             gpu_value,
-        }
+        };
+        mngr.insert_update_source(hybrid.gpu_value.notifier_index, hybrid.clone());
+        hybrid
     }
 
     pub fn id(&self) -> Id<T> {
@@ -782,6 +955,14 @@ impl<T> Clone for Gpu<T> {
 
 impl<T: SlabItem + Clone + Send + Sync + 'static> Gpu<T> {
     pub fn new(mngr: &mut SlabAllocator<impl IsBuffer>, value: T) -> Self {
+        let s = Self::allocate(mngr, value);
+        mngr.insert_update_source(s.notifier_index, s.clone());
+        s
+    }
+
+    /// Allocate and write the value, without inserting it as an update
+    /// source.
+    fn allocate(mngr: &mut SlabAllocator<impl IsBuffer>, value: T) -> Self {
         let id = mngr.allocate::<T>();
         let notifier_index = mngr.next_update_k();
         let s = Self {
@@ -791,7 +972,6 @@ impl<T: SlabItem + Clone + Send + Sync + 'static> Gpu<T> {
             update: Default::default(),
         };
         s.set(value);
-        mngr.insert_update_source(notifier_index, s.clone());
         s
     }
 
@@ -858,6 +1038,14 @@ impl<T> Clone for GpuArray<T> {
 
 impl<T: SlabItem + Clone + Send + Sync + 'static> GpuArray<T> {
     pub fn new(mngr: &mut SlabAllocator<impl IsBuffer>, values: &[T]) -> Self {
+        let g = Self::allocate(mngr, values);
+        mngr.insert_update_source(g.notifier_index, g.clone());
+        g
+    }
+
+    /// Allocate and write the values, without inserting them as an update
+    /// source.
+    fn allocate(mngr: &mut SlabAllocator<impl IsBuffer>, values: &[T]) -> Self {
         let array = mngr.allocate_array::<T>(values.len());
         let update = {
             let mut elements = vec![0u32; T::SLAB_SIZE * array.len()];
@@ -868,14 +1056,12 @@ impl<T: SlabItem + Clone + Send + Sync + 'static> GpuArray<T> {
             }
         };
         let notifier_index = mngr.next_update_k();
-        let g = GpuArray {
+        GpuArray {
             notifier_index,
             notifier: mngr.notifier.0.clone(),
             array,
             updates: Arc::new(Mutex::new(vec![update])),
-        };
-        mngr.insert_update_source(notifier_index, g.clone());
-        g
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -955,12 +1141,14 @@ impl<T: SlabItem + Clone + Send + Sync + 'static> HybridArray<T> {
         values: impl IntoIterator<Item = T>,
     ) -> Self {
         let values = values.into_iter().collect::<Vec<_>>();
-        let gpu_value = GpuArray::<T>::new(mngr, &values);
+        let gpu_value = GpuArray::<T>::allocate(mngr, &values);
         let cpu_value = Arc::new(RwLock::new(values));
-        HybridArray {
+        let hybrid = HybridArray {
             cpu_value,
             gpu_value,
-        }
+        };
+        mngr.insert_update_source(hybrid.gpu_value.notifier_index, hybrid.clone());
+        hybrid
     }
 
     pub fn len(&self) -> usize {
@@ -979,6 +1167,11 @@ impl<T: SlabItem + Clone + Send + Sync + 'static> HybridArray<T> {
         self.cpu_value.read().unwrap().get(index).cloned()
     }
 
+    /// Returns a copy of the CPU values.
+    pub fn get_vec(&self) -> Vec<T> {
+        self.cpu_value.read().unwrap().clone()
+    }
+
     pub fn get_id(&self, index: usize) -> Id<T> {
         self.gpu_value.get_id(index)
     }
@@ -1021,6 +1214,56 @@ mod test {
         assert_eq!(0, mngr.update_sources.read().unwrap().len());
     }
 
+    #[test]
+    fn cpu_values_read_hybrids() {
+        let mut mngr = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let value = mngr.new_value(666u32);
+        let values = mngr.new_array([1u32, 2, 3]);
+        let gpu = Gpu::new(&mut mngr, 7u32);
+        let untouched = mngr.new_value(1u32);
+        {
+            let cpu = mngr.cpu_values();
+            assert_eq!(666, cpu.read(value.id()));
+            assert_eq!(vec![1, 2, 3], cpu.read_vec(values.array()));
+            assert_eq!(0, cpu.read(gpu.id()));
+            assert_eq!(0, cpu.written_at(value.id().index()));
+        }
+        let buffer = mngr.get_updated_buffer(());
+        {
+            let cpu = mngr.cpu_values();
+            assert_eq!(1, cpu.upkeeps());
+            assert_eq!(1, cpu.written_at(value.id().index()));
+            assert_eq!(1, cpu.written_at(untouched.id().index()));
+            assert_eq!(
+                &buffer.lock().unwrap()[values.array().into_u32_array().starting_index()..][..3],
+                cpu.read_vec(values.array()).as_slice()
+            );
+        }
+
+        value.set(420);
+        values.set_item(1, 5);
+        {
+            // reads are ahead of the buffer until upkeep
+            let cpu = mngr.cpu_values();
+            assert_eq!(420, cpu.read(value.id()));
+            assert_eq!(5, cpu.read(values.array().at(1)));
+        }
+        let _ = mngr.upkeep(());
+        {
+            let cpu = mngr.cpu_values();
+            assert_eq!(2, cpu.written_at(value.id().index()));
+            assert_eq!(2, cpu.written_at(values.array().at(2).index()));
+            assert_eq!(1, cpu.written_at(untouched.id().index()));
+        }
+
+        let id = value.id();
+        let gpu_only = value.into_gpu_only();
+        assert_eq!(0, mngr.cpu_values().read(id));
+        drop(gpu_only);
+        let _ = mngr.upkeep(());
+        assert_eq!(0, mngr.cpu_values().written_at(id.index()));
+    }
+
     #[test]
     fn range_sanity() {
         let a = Range {
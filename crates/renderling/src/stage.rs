@@ -5,13 +5,14 @@
 use crabslab::{Array, Id, Slab, SlabItem};
 use glam::{Mat4, UVec2, Vec2, Vec3, Vec4, Vec4Swizzles};
 use spirv_std::{
-    image::{Cubemap, Image2d},
+    image::{Cubemap, Image2d, Image2dArray},
     spirv, Sampler,
 };
 
 use crate::{
+    bvol::{Aabb, Frustum},
     camera::Camera,
-    math::IsVector,
+    math::{DualQuat, IsVector},
     pbr::{Material, PbrConfig},
     transform::Transform,
 };
@@ -24,11 +25,35 @@ mod cpu;
 #[cfg(not(target_arch = "spirv"))]
 pub use cpu::*;
 
+#[cfg(not(target_arch = "spirv"))]
+mod ik;
+#[cfg(not(target_arch = "spirv"))]
+pub use ik::*;
+
 #[cfg(all(feature = "gltf", not(target_arch = "spirv")))]
 mod gltf_support;
 #[cfg(all(feature = "gltf", not(target_arch = "spirv")))]
 pub use gltf_support::*;
 
+/// How the joints of a [`Skin`] are blended to deform its vertices.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub enum SkinningMode {
+    /// Joint matrices are blended linearly.
+    ///
+    /// Vertices influenced by joints that twist away from each other collapse
+    /// toward the joint, the "candy-wrapper" artifact.
+    #[default]
+    LinearBlend,
+
+    /// Joint rotations and translations are blended as dual quaternions,
+    /// which preserves volume around twisting joints.
+    ///
+    /// Joint scales are blended linearly, and any shear in the joint matrices
+    /// is lost.
+    DualQuaternion,
+}
+
 /// A vertex skin.
 ///
 /// For more info on vertex skinning, see
@@ -43,30 +68,163 @@ pub struct Skin {
     // When is none, each matrix is assumed to be the 4x4 identity matrix
     // which implies that the inverse-bind matrices were pre-applied.
     pub inverse_bind_matrices: Array<Mat4>,
+    // How joints are blended, linearly by default.
+    pub mode: SkinningMode,
 }
 
 impl Skin {
-    pub fn get_inverse_bind_matrix(&self, i: usize, slab: &[u32]) -> Mat4 {
+    pub fn get_inverse_bind_matrix(&self, i: usize, slab: &(impl Slab + ?Sized)) -> Mat4 {
         slab.read(self.inverse_bind_matrices.at(i))
     }
 
-    pub fn get_joint_matrix(&self, i: usize, vertex: Vertex, slab: &[u32]) -> Mat4 {
-        let joint_index = vertex.joints[i] as usize;
+    /// Returns the matrix of the joint at `joint_index` in this skin's joints.
+    pub fn get_joint_index_matrix(&self, joint_index: usize, slab: &(impl Slab + ?Sized)) -> Mat4 {
         let joint_id = slab.read(self.joints.at(joint_index));
         let joint_transform = slab.read(joint_id);
-        let inverse_bind_matrix = slab.read(self.inverse_bind_matrices.at(i));
+        let inverse_bind_matrix = slab.read(self.inverse_bind_matrices.at(joint_index));
         Mat4::from(joint_transform) * inverse_bind_matrix
     }
 
-    pub fn get_skinning_matrix(&self, vertex: Vertex, slab: &[u32]) -> Mat4 {
+    /// Returns the matrix of the joint of the vertex's `i`th influence.
+    pub fn get_joint_matrix(&self, i: usize, vertex: Vertex, slab: &(impl Slab + ?Sized)) -> Mat4 {
+        self.get_joint_index_matrix(vertex.joints[i] as usize, slab)
+    }
+
+    /// Returns the matrix that deforms the vertex according to its joint
+    /// influences, including any `extra_influences` beyond its first four,
+    /// blended according to this skin's [`SkinningMode`].
+    ///
+    /// See [`Renderlet::get_extra_influences`].
+    pub fn get_skinning_matrix(
+        &self,
+        vertex: Vertex,
+        extra_influences: Array<JointInfluences>,
+        slab: &(impl Slab + ?Sized),
+    ) -> Mat4 {
+        if self.mode == SkinningMode::DualQuaternion {
+            self.get_dual_quaternion_skinning_matrix(vertex, extra_influences, slab)
+        } else {
+            self.get_linear_blend_skinning_matrix(vertex, extra_influences, slab)
+        }
+    }
+
+    /// Returns the weighted sum of the joint matrices of the vertex's
+    /// influences.
+    pub fn get_linear_blend_skinning_matrix(
+        &self,
+        vertex: Vertex,
+        extra_influences: Array<JointInfluences>,
+        slab: &(impl Slab + ?Sized),
+    ) -> Mat4 {
         let mut skinning_matrix = Mat4::ZERO;
         for i in 0..vertex.joints.len() {
             let joint_matrix = self.get_joint_matrix(i, vertex, slab);
             skinning_matrix += vertex.weights[i] * joint_matrix;
         }
+        for i in 0..extra_influences.len() {
+            let influences = slab.read(extra_influences.at(i));
+            for j in 0..influences.joints.len() {
+                let weight = influences.weights[j];
+                if weight != 0.0 {
+                    let joint_matrix =
+                        self.get_joint_index_matrix(influences.joints[j] as usize, slab);
+                    skinning_matrix += weight * joint_matrix;
+                }
+            }
+        }
 
         skinning_matrix
     }
+
+    /// Returns the matrix of the weighted blend of the vertex's joint
+    /// transforms as dual quaternions.
+    pub fn get_dual_quaternion_skinning_matrix(
+        &self,
+        vertex: Vertex,
+        extra_influences: Array<JointInfluences>,
+        slab: &(impl Slab + ?Sized),
+    ) -> Mat4 {
+        let mut blend = DualQuat::ZERO;
+        let mut scale = Vec3::ZERO;
+        let mut weight_sum = 0.0;
+        for i in 0..vertex.joints.len() + extra_influences.len() * 4 {
+            let (joint_index, weight) = if i < vertex.joints.len() {
+                (vertex.joints[i], vertex.weights[i])
+            } else {
+                let influences = slab.read(extra_influences.at((i - vertex.joints.len()) / 4));
+                let j = i % 4;
+                (influences.joints[j], influences.weights[j])
+            };
+            if weight != 0.0 {
+                let joint =
+                    Transform::from(self.get_joint_index_matrix(joint_index as usize, slab));
+                let dq = DualQuat::from_rotation_translation(joint.rotation, joint.translation);
+                blend = blend.add_weighted(dq, weight);
+                scale += weight * joint.scale;
+                weight_sum += weight;
+            }
+        }
+        if weight_sum == 0.0 {
+            return Mat4::ZERO;
+        }
+        let (rotation, translation) = blend.to_rotation_translation();
+        Mat4::from_scale_rotation_translation(scale / weight_sum, rotation, translation)
+    }
+}
+
+/// Four joint influences of a vertex, beyond the four in the vertex itself.
+///
+/// See [`Renderlet::extra_influences`].
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct JointInfluences {
+    // Indices that point to the influencing joints.
+    pub joints: [u32; 4],
+    // The weights of influence that each joint has over the vertex.
+    pub weights: [f32; 4],
+}
+
+/// Per-vertex displacements of a morph target.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub struct MorphTarget {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub tangent: Vec3,
+}
+
+/// Morph targets and their weights.
+///
+/// For more info on morph targets, see
+/// <https://github.khronos.org/glTF-Tutorials/gltfTutorial/gltfTutorial_017_SimpleMorphTarget.html>
+#[derive(Clone, Copy, Default, SlabItem)]
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+pub struct Morph {
+    // Displacements of each morph target, each array holding one displacement
+    // per vertex.
+    pub targets: Array<Array<MorphTarget>>,
+    // Weight of each morph target.
+    pub weights: Array<f32>,
+}
+
+impl Morph {
+    /// Returns the vertex at `vertex_index` displaced by each morph target
+    /// according to its weight.
+    pub fn morph_vertex(
+        &self,
+        mut vertex: Vertex,
+        vertex_index: usize,
+        slab: &(impl Slab + ?Sized),
+    ) -> Vertex {
+        for i in 0..self.targets.len() {
+            if i < self.weights.len() {
+                let weight = slab.read(self.weights.at(i));
+                let targets = slab.read(self.targets.at(i));
+                vertex = vertex.morph(slab.read(targets.at(vertex_index)), weight);
+            }
+        }
+        vertex
+    }
 }
 
 /// A vertex in a mesh.
@@ -101,6 +259,15 @@ impl Default for Vertex {
 }
 
 impl Vertex {
+    /// Returns this vertex displaced by the given morph target, scaled by
+    /// `weight`.
+    pub fn morph(mut self, target: MorphTarget, weight: f32) -> Self {
+        self.position += weight * target.position;
+        self.normal += weight * target.normal;
+        self.tangent += (weight * target.tangent).extend(0.0);
+        self
+    }
+
     pub fn with_position(mut self, p: impl Into<Vec3>) -> Self {
         self.position = p.into();
         self
@@ -164,6 +331,33 @@ impl Vertex {
     }
 }
 
+/// How the vertices of a [`Renderlet`] are assembled into primitives.
+///
+/// Strips, fans and loops are not supported, they should be converted into
+/// lists before staging.
+#[cfg_attr(not(target_arch = "spirv"), derive(Debug))]
+#[repr(u32)]
+#[derive(Clone, Copy, Default, PartialEq, SlabItem)]
+pub enum Topology {
+    /// Every three vertices form a triangle.
+    #[default]
+    TriangleList,
+
+    /// Every two vertices form a line.
+    LineList,
+
+    /// Every vertex is a point.
+    PointList,
+}
+
+/// Set in the instance index of the draw calls of instanced
+/// [`Renderlet`]s.
+///
+/// Without this bit the instance index is the [`Id`] of the renderlet being
+/// drawn. With it, the remaining bits are the index of one of the
+/// renderlet's [`Renderlet::instance_slots`].
+pub const INSTANCE_SLOT_BIT: u32 = 1 << 31;
+
 /// A draw call used to render some geometry.
 ///
 /// ## Note
@@ -180,7 +374,49 @@ pub struct Renderlet {
     pub transform_id: Id<Transform>,
     pub material_id: Id<Material>,
     pub skin_id: Id<Skin>,
+    pub morph_id: Id<Morph>,
     pub pbr_config_id: Id<PbrConfig>,
+    /// Bounding box of the renderlet's vertices, in model space.
+    ///
+    /// Used for frustum culling. A zero-sized box means the renderlet is
+    /// never culled.
+    pub bounds: Aabb,
+    /// Whether `bounds` follow the renderlet's vertices in their current
+    /// pose.
+    ///
+    /// When this is set the stage recomputes `bounds` with
+    /// [`Renderlet::get_posed_bounds`] when it is ticked or rendered after
+    /// the renderlet, its vertices, its skin's joints or its morph weights
+    /// have changed. Each recomputation visits every vertex of the renderlet
+    /// on the CPU, so animated renderlets pay this cost every frame, along
+    /// with an extra slab upload when their bounds change.
+    ///
+    /// Skinned and morphed renderlets are only frustum culled when this is
+    /// set, as otherwise their bounds are those of their rest pose.
+    pub bounds_are_posed: bool,
+    /// How the renderlet's vertices are assembled into primitives.
+    ///
+    /// Only triangles cast shadows.
+    pub topology: Topology,
+    /// Transform of each instance of the renderlet, applied before the
+    /// transform at `transform_id`.
+    ///
+    /// A null array means the renderlet is not instanced. Instanced
+    /// renderlets are never frustum culled.
+    pub instances: Array<Transform>,
+    /// The [`Id`] of this renderlet, once for each of its `instances`.
+    ///
+    /// Instanced renderlets are drawn with the indices of these slots as
+    /// instance indices, which lets shaders find the renderlet and the
+    /// instance being drawn. See [`INSTANCE_SLOT_BIT`].
+    pub instance_slots: Array<Id<Renderlet>>,
+    /// Joint influences of skinned vertices beyond the four in each
+    /// [`Vertex`].
+    ///
+    /// Each vertex has the same number of [`JointInfluences`], stored
+    /// consecutively in vertex order. An empty array means no vertex has more
+    /// than four influences.
+    pub extra_influences: Array<JointInfluences>,
 }
 
 impl Default for Renderlet {
@@ -193,8 +429,185 @@ impl Default for Renderlet {
             transform_id: Id::NONE,
             material_id: Id::NONE,
             skin_id: Id::NONE,
+            morph_id: Id::NONE,
             pbr_config_id: Id::new(0),
+            bounds: Aabb::default(),
+            bounds_are_posed: false,
+            topology: Topology::TriangleList,
+            instances: Array::default(),
+            instance_slots: Array::default(),
+            extra_influences: Array::default(),
+        }
+    }
+}
+
+impl Renderlet {
+    /// Returns the number of vertices this renderlet draws.
+    ///
+    /// This is the number of indices if the renderlet is indexed, otherwise
+    /// it is the number of vertices.
+    pub fn get_vertex_count(&self) -> u32 {
+        if self.indices_array.is_null() {
+            self.vertices_array.len() as u32
+        } else {
+            self.indices_array.len() as u32
+        }
+    }
+
+    /// Returns the number of instances this renderlet draws.
+    pub fn get_instance_count(&self) -> u32 {
+        if self.instances.is_null() {
+            1
+        } else {
+            self.instances.len() as u32
+        }
+    }
+
+    /// Returns the first instance index of draw calls of the renderlet with
+    /// the given [`Id`].
+    pub fn get_first_instance(&self, id: Id<Renderlet>) -> u32 {
+        if self.instances.is_null() {
+            id.inner()
+        } else {
+            self.instance_slots.starting_index() as u32 | INSTANCE_SLOT_BIT
+        }
+    }
+
+    /// Returns the instance range of draw calls of the renderlet with the
+    /// given [`Id`].
+    pub fn get_instance_range(&self, id: Id<Renderlet>) -> core::ops::Range<u32> {
+        let first = self.get_first_instance(id);
+        first..first + self.get_instance_count()
+    }
+
+    /// Reads the renderlet drawn with the given instance index from the slab,
+    /// returning its [`Id`], the renderlet and the index of the instance
+    /// being drawn.
+    pub fn read_instance(instance_index: u32, slab: &[u32]) -> (Id<Renderlet>, Renderlet, u32) {
+        if instance_index & INSTANCE_SLOT_BIT == 0 {
+            let id = Id::new(instance_index);
+            (id, slab.read_unchecked(id), 0)
+        } else {
+            let slot = instance_index & !INSTANCE_SLOT_BIT;
+            let id = slab.read_unchecked(Id::<Id<Renderlet>>::new(slot));
+            let renderlet = slab.read_unchecked(id);
+            let instance = slot - renderlet.instance_slots.starting_index() as u32;
+            (id, renderlet, instance)
+        }
+    }
+
+    /// Returns the joint influences of the vertex at the given index of
+    /// `vertices_array` beyond the four in the vertex itself.
+    ///
+    /// See [`Renderlet::extra_influences`].
+    pub fn get_extra_influences(&self, index: usize) -> Array<JointInfluences> {
+        if self.extra_influences.is_empty() || self.vertices_array.is_empty() {
+            return Array::default();
+        }
+        let sets = self.extra_influences.len() / self.vertices_array.len();
+        Array::new(
+            (self.extra_influences.starting_index() + index * sets) as u32,
+            sets as u32,
+        )
+    }
+
+    /// Returns the vertex at the given vertex index displaced by its morph
+    /// targets, along with its index in `vertices_array`.
+    fn get_morphed_vertex(
+        &self,
+        vertex_index: u32,
+        slab: &(impl Slab + ?Sized),
+    ) -> (usize, Vertex) {
+        let index = if self.indices_array.is_null() {
+            vertex_index as usize
+        } else {
+            slab.read(self.indices_array.at(vertex_index as usize)) as usize
+        };
+        let vertex_id = self.vertices_array.at(index);
+        let mut vertex = slab.read_unchecked(vertex_id);
+        if self.morph_id.is_some() {
+            let morph = slab.read(self.morph_id);
+            vertex = morph.morph_vertex(vertex, index, slab);
+        }
+        (index, vertex)
+    }
+
+    /// Returns the vertex at the given vertex index in its current pose, that
+    /// is displaced by its morph targets and deformed by its skin, in model
+    /// space.
+    pub fn get_posed_vertex(&self, vertex_index: u32, slab: &(impl Slab + ?Sized)) -> Vertex {
+        let (index, mut vertex) = self.get_morphed_vertex(vertex_index, slab);
+        if self.skin_id.is_some() {
+            let skin = slab.read(self.skin_id);
+            let extra_influences = self.get_extra_influences(index);
+            let skinning_matrix = skin.get_skinning_matrix(vertex, extra_influences, slab);
+            vertex.position = (skinning_matrix * vertex.position.extend(1.0)).xyz();
+            vertex.normal = (skinning_matrix * vertex.normal.extend(0.0))
+                .xyz()
+                .alt_norm_or_zero();
+        }
+        vertex
+    }
+
+    /// Returns the vertex at the given vertex index, along with its model
+    /// transform.
+    ///
+    /// If the renderlet is instanced this is the vertex of the first
+    /// instance, see [`Renderlet::get_instance_vertex_info`].
+    pub fn get_vertex_info(
+        &self,
+        vertex_index: u32,
+        slab: &(impl Slab + ?Sized),
+    ) -> (Vertex, Transform) {
+        self.get_instance_vertex_info(vertex_index, 0, slab)
+    }
+
+    /// Returns the vertex at the given vertex index of the given instance,
+    /// along with its model transform.
+    ///
+    /// The vertex index is the index of the vertex within the draw call, so
+    /// if the renderlet is indexed it is first used to look up the index of
+    /// the vertex. If the renderlet is morphed the vertex is displaced by its
+    /// morph targets. If the renderlet is instanced the transform includes
+    /// the instance's transform, and if it is skinned the transform includes
+    /// the vertex's skinning matrix.
+    pub fn get_instance_vertex_info(
+        &self,
+        vertex_index: u32,
+        instance: u32,
+        slab: &(impl Slab + ?Sized),
+    ) -> (Vertex, Transform) {
+        let (index, vertex) = self.get_morphed_vertex(vertex_index, slab);
+        let mut model = Mat4::from(slab.read(self.transform_id));
+        if !self.instances.is_null() {
+            model *= Mat4::from(slab.read(self.instances.at(instance as usize)));
+        }
+        if self.skin_id.is_some() {
+            let skin = slab.read(self.skin_id);
+            let extra_influences = self.get_extra_influences(index);
+            model *= skin.get_skinning_matrix(vertex, extra_influences, slab);
+        }
+        (vertex, Transform::from(model))
+    }
+
+    /// Returns whether this renderlet's bounds lie completely outside of its
+    /// camera's frustum.
+    ///
+    /// Renderlets without bounds are never culled, and neither are instanced
+    /// renderlets, or skinned or morphed renderlets whose bounds are not
+    /// posed, as their bounds change as they animate.
+    pub fn is_outside_camera_view(&self, slab: &[u32]) -> bool {
+        let is_animated = self.skin_id.is_some() || self.morph_id.is_some();
+        if self.bounds.is_zero()
+            || (is_animated && !self.bounds_are_posed)
+            || !self.instances.is_null()
+        {
+            return false;
         }
+        let camera = slab.read(self.camera_id);
+        let transform = slab.read(self.transform_id);
+        self.bounds
+            .is_outside_frustum(&Frustum::from_camera(&camera), Mat4::from(transform))
     }
 }
 
@@ -229,8 +642,8 @@ impl RenderletVertexLog {
 #[spirv(vertex)]
 #[allow(dead_code)]
 pub fn renderlet_vertex(
-    // Points at a `Renderlet`
-    #[spirv(instance_index)] renderlet_id: Id<Renderlet>,
+    // Points at a `Renderlet`, or one of its instance slots
+    #[spirv(instance_index)] instance_index: u32,
     // Which vertex within the renderlet are we rendering
     #[spirv(vertex_index)] vertex_index: u32,
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &[u32],
@@ -247,31 +660,17 @@ pub fn renderlet_vertex(
     out_world_pos: &mut Vec3,
     #[spirv(position)] out_clip_pos: &mut Vec4,
 ) {
-    let renderlet = slab.read_unchecked(renderlet_id);
+    let (_, renderlet, instance) = Renderlet::read_instance(instance_index, slab);
 
     *out_camera = renderlet.camera_id;
     *out_material = renderlet.material_id;
     *out_pbr_config = renderlet.pbr_config_id;
 
-    let index = if renderlet.indices_array.is_null() {
-        vertex_index as usize
-    } else {
-        slab.read(renderlet.indices_array.at(vertex_index as usize)) as usize
-    };
-    let vertex_id = renderlet.vertices_array.at(index as usize);
-    let vertex = slab.read_unchecked(vertex_id);
+    let (vertex, transform) = renderlet.get_instance_vertex_info(vertex_index, instance, slab);
     *out_color = vertex.color;
     *out_uv0 = vertex.uv0;
     *out_uv1 = vertex.uv1;
 
-    let transform = if renderlet.skin_id.is_some() {
-        let skin = slab.read(renderlet.skin_id);
-        Transform::from(
-            Mat4::from(slab.read(renderlet.transform_id)) * skin.get_skinning_matrix(vertex, slab),
-        )
-    } else {
-        slab.read(renderlet.transform_id)
-    };
     let scale2 = transform.scale * transform.scale;
     let normal = vertex.normal.alt_norm_or_zero();
     let tangent = vertex.tangent.xyz().alt_norm_or_zero();
@@ -340,8 +739,19 @@ pub fn renderlet_fragment(
 
     #[spirv(descriptor_set = 1, binding = 6)] brdf: &Image2d,
     #[spirv(descriptor_set = 1, binding = 7)] brdf_sampler: &Sampler,
+
+    #[spirv(descriptor_set = 1, binding = 10)] shadow_maps: &Image2dArray,
+    #[spirv(descriptor_set = 1, binding = 11)] shadow_maps_sampler: &Sampler,
+
+    #[spirv(descriptor_set = 1, binding = 12)] transmission_background: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 13)] transmission_background_sampler: &Sampler,
+
+    #[spirv(descriptor_set = 1, binding = 14)] sheen_lut: &Image2d,
+    #[spirv(descriptor_set = 1, binding = 15)] sheen_lut_sampler: &Sampler,
+
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] slab: &[u32],
     #[spirv(frag_coord)] _frag_coord: Vec4,
+    #[spirv(front_facing)] front_facing: bool,
     #[spirv(flat)] in_camera: Id<Camera>,
     #[spirv(flat)] in_material: Id<Material>,
     #[spirv(flat)] in_pbr_config: Id<PbrConfig>,
@@ -354,7 +764,7 @@ pub fn renderlet_fragment(
     world_pos: Vec3,
     output: &mut Vec4,
 ) {
-    crate::pbr::fragment_impl(
+    let discard = crate::pbr::fragment_impl(
         atlas,
         atlas_sampler,
         irradiance,
@@ -363,10 +773,17 @@ pub fn renderlet_fragment(
         prefiltered_sampler,
         brdf,
         brdf_sampler,
+        sheen_lut,
+        sheen_lut_sampler,
+        shadow_maps,
+        shadow_maps_sampler,
+        transmission_background,
+        transmission_background_sampler,
         slab,
         slab.read(in_pbr_config),
         in_camera,
         in_material,
+        front_facing,
         in_color,
         in_uv0,
         in_uv1,
@@ -376,6 +793,9 @@ pub fn renderlet_fragment(
         world_pos,
         output,
     );
+    if discard {
+        spirv_std::arch::kill();
+    }
 }
 
 #[cfg(feature = "test_atomic_i_increment")]
@@ -413,10 +833,13 @@ pub fn test_i8_i16_extraction(
 
 #[cfg(test)]
 mod test {
-    use glam::{Mat4, Vec3};
+    use crabslab::{CpuSlab, GrowableSlab};
+    use glam::{Mat4, Quat, Vec3};
 
     use crate::transform::Transform;
 
+    use super::{JointInfluences, Skin, SkinningMode, Vertex};
+
     #[test]
     fn matrix_hierarchy_sanity() {
         let a: Mat4 = Transform {
@@ -433,4 +856,127 @@ mod test {
         let c2 = b * a;
         assert_ne!(c1, c2);
     }
+
+    #[test]
+    fn skinning_with_extra_influences() {
+        let mut slab = CpuSlab::new(vec![]);
+        let joint_ids = (0..5)
+            .map(|i| {
+                slab.append(&Transform {
+                    translation: Vec3::new(i as f32, 0.0, 0.0),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let joints = slab.append_array(&joint_ids);
+        let mut inverse_bind_matrices = vec![Mat4::IDENTITY; 5];
+        inverse_bind_matrices[4] = Mat4::from_translation(Vec3::new(-1.0, 0.0, 0.0));
+        let inverse_bind_matrices = slab.append_array(&inverse_bind_matrices);
+        let skin = Skin {
+            joints,
+            inverse_bind_matrices,
+            ..Default::default()
+        };
+        let vertex = Vertex {
+            joints: [0, 1, 2, 3],
+            weights: [0.1; 4],
+            ..Default::default()
+        };
+        let extra_influences = slab.append_array(&[JointInfluences {
+            joints: [4, 0, 0, 0],
+            weights: [0.6, 0.0, 0.0, 0.0],
+        }]);
+        let slab = slab.as_ref().as_slice();
+
+        let without_extra = skin.get_skinning_matrix(vertex, Default::default(), slab);
+        let position = without_extra.w_axis.truncate();
+        assert!((Vec3::new(0.6, 0.0, 0.0) - position).length() < 1e-6);
+
+        let skinning_matrix = skin.get_skinning_matrix(vertex, extra_influences, slab);
+        let position = skinning_matrix.w_axis.truncate();
+        assert!(
+            (Vec3::new(0.6 + 0.6 * 3.0, 0.0, 0.0) - position).length() < 1e-6,
+            "{position}"
+        );
+        assert_eq!(
+            Mat4::from_translation(Vec3::new(3.0, 0.0, 0.0)),
+            skin.get_joint_index_matrix(4, slab)
+        );
+    }
+
+    #[test]
+    fn joint_matrix_uses_the_inverse_bind_matrix_of_the_joint() {
+        let mut slab = CpuSlab::new(vec![]);
+        let joint_ids = [
+            slab.append(&Transform::default()),
+            slab.append(&Transform {
+                translation: Vec3::Y,
+                ..Default::default()
+            }),
+        ];
+        let joints = slab.append_array(&joint_ids);
+        let inverse_bind_matrices = slab.append_array(&[
+            Mat4::from_translation(Vec3::new(-1.0, 0.0, 0.0)),
+            Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0)),
+        ]);
+        let skin = Skin {
+            joints,
+            inverse_bind_matrices,
+            ..Default::default()
+        };
+        // the first influence slot refers to the second joint
+        let vertex = Vertex {
+            joints: [1, 0, 0, 0],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            ..Default::default()
+        };
+        let slab = slab.as_ref().as_slice();
+
+        let expected = Mat4::from_translation(Vec3::new(0.0, 1.0, -1.0));
+        assert_eq!(expected, skin.get_joint_matrix(0, vertex, slab));
+        assert_eq!(
+            expected,
+            skin.get_skinning_matrix(vertex, Default::default(), slab)
+        );
+    }
+
+    #[test]
+    fn dual_quaternion_skinning_preserves_volume() {
+        let mut slab = CpuSlab::new(vec![]);
+        let joint_ids = [
+            slab.append(&Transform::default()),
+            slab.append(&Transform {
+                rotation: Quat::from_rotation_x(120f32.to_radians()),
+                scale: Vec3::splat(2.0),
+                ..Default::default()
+            }),
+        ];
+        let joints = slab.append_array(&joint_ids);
+        let inverse_bind_matrices = slab.append_array(&[Mat4::IDENTITY; 2]);
+        let mut skin = Skin {
+            joints,
+            inverse_bind_matrices,
+            ..Default::default()
+        };
+        let vertex = Vertex {
+            position: Vec3::Y,
+            joints: [0, 1, 0, 0],
+            weights: [0.5, 0.5, 0.0, 0.0],
+            ..Default::default()
+        };
+        let slab = slab.as_ref().as_slice();
+
+        // a twisted joint collapses linearly blended vertices
+        let linear = skin
+            .get_skinning_matrix(vertex, Default::default(), slab)
+            .transform_point3(vertex.position);
+        assert!((linear.length() - 0.75f32.sqrt()).abs() < 1e-6, "{linear}");
+
+        skin.mode = SkinningMode::DualQuaternion;
+        let dual_quat = skin
+            .get_skinning_matrix(vertex, Default::default(), slab)
+            .transform_point3(vertex.position);
+        let expected = Quat::from_rotation_x(60f32.to_radians()) * (1.5 * Vec3::Y);
+        assert!(expected.abs_diff_eq(dual_quat, 1e-5), "{dual_quat}");
+    }
 }
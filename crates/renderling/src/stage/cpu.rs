@@ -4,17 +4,28 @@
 //! It is used to stage [`Renderlet`]s for rendering.
 use core::sync::atomic::Ordering;
 use crabslab::{Array, Id, Slab, SlabItem};
+use rustc_hash::FxHashMap;
 use snafu::Snafu;
 use std::{
     ops::{Deref, DerefMut},
-    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc, Mutex, RwLock,
+    },
 };
 
 use crate::{
     atlas::{Atlas, AtlasError, AtlasImage, AtlasImageError, AtlasTexture},
     bloom::Bloom,
     camera::Camera,
-    pbr::{debug::DebugMode, light::Light, PbrConfig},
+    draw_indirect::{DrawIndirect, IndirectDraws, IndirectDrawsError},
+    pbr::{
+        debug::DebugMode,
+        light::Light,
+        shadow::{ShadowMap, ShadowMapAtlas},
+        tiling::{LightTiling, LightTilingDescriptor},
+        AlphaMode, Material, PbrConfig,
+    },
     skybox::Skybox,
     slab::*,
     stage::Renderlet,
@@ -29,6 +40,9 @@ use super::*;
 pub enum StageError {
     #[snafu(display("{source}"))]
     Atlas { source: AtlasError },
+
+    #[snafu(display("{source}"))]
+    IndirectDraws { source: IndirectDrawsError },
 }
 
 impl From<AtlasError> for StageError {
@@ -37,16 +51,429 @@ impl From<AtlasError> for StageError {
     }
 }
 
+impl From<IndirectDrawsError> for StageError {
+    fn from(source: IndirectDrawsError) -> Self {
+        Self::IndirectDraws { source }
+    }
+}
+
 /// Provides a way to communicate with the stage about how you'd like your
 /// objects drawn.
 pub(crate) enum StageDrawStrategy {
-    // TODO: Add `Indirect` to `StageDrawStrategy` which uses `RenderPass::multi_draw_indirect`
+    /// One `RenderPass::draw` call per renderlet.
     Direct(Vec<Hybrid<Renderlet>>),
+    /// One `RenderPass::multi_draw_indirect` call for all renderlets.
+    Indirect(Box<IndirectDraws>),
+}
+
+impl From<Topology> for wgpu::PrimitiveTopology {
+    fn from(topology: Topology) -> Self {
+        match topology {
+            Topology::TriangleList => wgpu::PrimitiveTopology::TriangleList,
+            Topology::LineList => wgpu::PrimitiveTopology::LineList,
+            Topology::PointList => wgpu::PrimitiveTopology::PointList,
+        }
+    }
+}
+
+impl StageDrawStrategy {
+    /// Create the best strategy supported by the given device.
+    fn new(
+        device: &wgpu::Device,
+        renderlets: Vec<Hybrid<Renderlet>>,
+        has_frustum_culling: bool,
+    ) -> Self {
+        if crate::draw_indirect::supports_indirect_draws(device.features()) {
+            let mut indirect = IndirectDraws::new(renderlets);
+            indirect.set_has_frustum_culling(device, has_frustum_culling);
+            StageDrawStrategy::Indirect(Box::new(indirect))
+        } else {
+            log::warn!("device does not support indirect draws, falling back to direct draws");
+            StageDrawStrategy::Direct(renderlets)
+        }
+    }
+
+    fn renderlets(&self) -> &Vec<Hybrid<Renderlet>> {
+        match self {
+            StageDrawStrategy::Direct(units) => units,
+            StageDrawStrategy::Indirect(indirect) => &indirect.renderlets,
+        }
+    }
+
+    fn renderlets_mut(&mut self) -> &mut Vec<Hybrid<Renderlet>> {
+        match self {
+            StageDrawStrategy::Direct(units) => units,
+            StageDrawStrategy::Indirect(indirect) => &mut indirect.renderlets,
+        }
+    }
+
+    /// Orders the renderlets by pass, with opaque renderlets first, then by
+    /// topology, with triangles first, then so that double-sided renderlets
+    /// come before single-sided renderlets, and then so that mirrored
+    /// renderlets come last, keeping their relative order.
+    ///
+    /// Assumes the keys have been updated with [`PipelineKeys::update`].
+    fn order_by_pipeline(&mut self, keys: &PipelineKeys) {
+        self.renderlets_mut()
+            .sort_by_key(|hybrid| keys.get(hybrid.id()));
+    }
+
+    /// Returns the non-empty ranges of opaque renderlets that share a
+    /// topology, sidedness and winding, along with that topology, whether they
+    /// are double-sided and whether they are mirrored.
+    ///
+    /// Assumes the renderlets have been ordered with
+    /// [`StageDrawStrategy::order_by_pipeline`].
+    fn pipeline_ranges(
+        &self,
+        keys: &PipelineKeys,
+    ) -> Vec<(Topology, bool, bool, std::ops::Range<usize>)> {
+        let mut ranges: Vec<(Topology, bool, bool, std::ops::Range<usize>)> = vec![];
+        for (i, hybrid) in self.renderlets().iter().enumerate() {
+            let topology = hybrid.get().topology;
+            let (pass, _, single_sided, mirrored) = keys.get(hybrid.id());
+            if pass != DrawPass::Opaque {
+                // opaque renderlets are ordered first
+                break;
+            }
+            let double_sided = !single_sided;
+            match ranges.last_mut() {
+                Some((t, d, m, range))
+                    if *t == topology && *d == double_sided && *m == mirrored =>
+                {
+                    range.end = i + 1;
+                }
+                _ => ranges.push((topology, double_sided, mirrored, i..i + 1)),
+            }
+        }
+        ranges
+    }
+
+    /// Returns the renderlets drawn in the given pass.
+    fn pass_renderlets(&self, keys: &PipelineKeys, pass: DrawPass) -> Vec<Hybrid<Renderlet>> {
+        self.renderlets()
+            .iter()
+            .filter(|hybrid| keys.get(hybrid.id()).0 == pass)
+            .cloned()
+            .collect()
+    }
+
+    /// Draw the given range of renderlets into the given render pass, which
+    /// must already have its pipeline and bindgroups set.
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        may_indirect_buffer: Option<&'a wgpu::Buffer>,
+        range: std::ops::Range<usize>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        match self {
+            StageDrawStrategy::Direct(units) => {
+                for hybrid in &units[range] {
+                    let rlet = hybrid.get();
+                    if rlet.visible {
+                        let vertex_range = 0..rlet.get_vertex_count();
+                        let instance_range = rlet.get_instance_range(hybrid.id());
+                        log::trace!(
+                            "drawing vertices {vertex_range:?} and instances {instance_range:?}"
+                        );
+                        render_pass.draw(vertex_range, instance_range);
+                    }
+                }
+            }
+            StageDrawStrategy::Indirect(indirect) => {
+                if let Some(indirect_buffer) = may_indirect_buffer {
+                    // the draws may lag behind the renderlets until the next upkeep
+                    let draw_count = (indirect.draw_count() as usize).min(range.end);
+                    if draw_count > range.start {
+                        let draw_count = (draw_count - range.start) as u32;
+                        log::trace!("drawing {draw_count} renderlets indirectly");
+                        let offset = indirect.offset()
+                            + (range.start * DrawIndirect::SLAB_SIZE * std::mem::size_of::<u32>())
+                                as u64;
+                        render_pass.multi_draw_indirect(indirect_buffer, offset, draw_count);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The pass a renderlet is drawn in, as determined by its material.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum DrawPass {
+    /// Drawn first, writing depth.
+    #[default]
+    Opaque,
+    /// Drawn after opaque renderlets and the skybox, refracting a copy of
+    /// them, because the material has a
+    /// [`Transmission`](crate::pbr::Transmission).
+    Transmissive,
+    /// Drawn last without writing depth, sorted back-to-front, because the
+    /// material's [`AlphaMode`] is `Blend`.
+    Blended,
+}
+
+/// The key renderlets are ordered by, which groups renderlets drawn with the
+/// same pipeline.
+///
+/// This is the renderlet's pass, its topology, whether it is single-sided
+/// and whether it is mirrored, see [`is_double_sided`] and [`is_mirrored`].
+type PipelineKey = (DrawPass, u32, bool, bool);
+
+/// The [`PipelineKey`] of each staged renderlet, along with the upkeep it
+/// was computed after.
+#[derive(Default)]
+pub(crate) struct PipelineKeys(FxHashMap<Id<Renderlet>, (usize, PipelineKey)>);
+
+impl PipelineKeys {
+    /// Updates the keys of the given renderlets, forgetting any others.
+    ///
+    /// A key is only recomputed when its renderlet, or the renderlet's
+    /// material, transform or instances, have been written since.
+    fn update<'a>(
+        &mut self,
+        renderlets: impl IntoIterator<Item = &'a Hybrid<Renderlet>>,
+        slab: &CpuValues,
+    ) {
+        let mut keys = FxHashMap::default();
+        for hybrid in renderlets {
+            let id = hybrid.id();
+            let renderlet = hybrid.get();
+            let key = match self.0.remove(&id) {
+                Some((computed, key))
+                    if [
+                        id.index(),
+                        renderlet.material_id.index(),
+                        renderlet.transform_id.index(),
+                        renderlet.instances.starting_index(),
+                    ]
+                    .into_iter()
+                    .all(|index| slab.written_at(index) <= computed) =>
+                {
+                    (computed, key)
+                }
+                _ => (slab.upkeeps(), pipeline_key(&renderlet, slab)),
+            };
+            keys.insert(id, key);
+        }
+        self.0 = keys;
+    }
+
+    /// Returns the key of the renderlet with the given id as of the last
+    /// [`PipelineKeys::update`].
+    fn get(&self, id: Id<Renderlet>) -> PipelineKey {
+        self.0.get(&id).map(|(_, key)| *key).unwrap_or_default()
+    }
+}
+
+/// Returns the renderlet's [`PipelineKey`], reading its material and
+/// transforms from the given slab.
+fn pipeline_key(renderlet: &Renderlet, slab: &(impl Slab + ?Sized)) -> PipelineKey {
+    let material = slab.read::<Material>(renderlet.material_id);
+    let pass = if material.transmission_id.is_some() {
+        DrawPass::Transmissive
+    } else if material.alpha_mode == AlphaMode::Blend {
+        DrawPass::Blended
+    } else {
+        DrawPass::Opaque
+    };
+    (
+        pass,
+        renderlet.topology as u32,
+        !is_double_sided(renderlet, slab),
+        is_mirrored(renderlet, slab),
+    )
+}
+
+/// Returns whether the renderlet is drawn without culling back faces.
+///
+/// Triangles are double-sided if their material is, see
+/// [`Material::double_sided`], or if only some of their instances are
+/// mirrored, see [`mirroring`]. Lines and points have no sides, so they are
+/// always double-sided.
+fn is_double_sided(renderlet: &Renderlet, slab: &(impl Slab + ?Sized)) -> bool {
+    renderlet.topology != Topology::TriangleList
+        || slab.read::<Material>(renderlet.material_id).double_sided
+        || mirroring(renderlet, slab).is_none()
+}
+
+/// Returns whether the renderlet's transform mirrors its triangles, which
+/// turns their counter-clockwise front faces clockwise.
+///
+/// Instanced renderlets are drawn with a single draw call, so this is `None`
+/// if some of their instances are mirrored and others aren't.
+fn mirroring(renderlet: &Renderlet, slab: &(impl Slab + ?Sized)) -> Option<bool> {
+    let determinant = Mat4::from(slab.read(renderlet.transform_id)).determinant();
+    if renderlet.instances.is_null() || renderlet.instances.is_empty() {
+        return Some(determinant < 0.0);
+    }
+    let mut instances = renderlet.instances.iter().map(|id| {
+        let instance_determinant = Mat4::from(slab.read(id)).determinant();
+        determinant * instance_determinant < 0.0
+    });
+    let first = instances.next()?;
+    instances.all(|mirrored| mirrored == first).then_some(first)
+}
+
+/// Returns whether the renderlet's triangles are drawn with clockwise front
+/// faces, see [`mirroring`].
+fn is_mirrored(renderlet: &Renderlet, slab: &(impl Slab + ?Sized)) -> bool {
+    renderlet.topology == Topology::TriangleList && mirroring(renderlet, slab) == Some(true)
+}
+
+/// Returns the visible blended or transmissive renderlets, sorted
+/// back-to-front by the distance from the center of their bounds to their
+/// camera.
+///
+/// Cameras and transforms are read from the given slab.
+fn sort_back_to_front(
+    renderlets: &[Hybrid<Renderlet>],
+    slab: &(impl Slab + ?Sized),
+) -> Vec<Hybrid<Renderlet>> {
+    let mut keyed = renderlets
+        .iter()
+        .filter_map(|hybrid| {
+            let renderlet = hybrid.get();
+            if !renderlet.visible {
+                return None;
+            }
+            let center = (renderlet.bounds.min + renderlet.bounds.max) * 0.5;
+            let center = Mat4::from(slab.read(renderlet.transform_id)).transform_point3(center);
+            let camera = slab.read(renderlet.camera_id);
+            Some((center.distance_squared(camera.position), hybrid.clone()))
+        })
+        .collect::<Vec<_>>();
+    // `sort_by` is stable, so equidistant renderlets keep their staging order
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed.into_iter().map(|(_, renderlet)| renderlet).collect()
+}
+
+/// The upkeep after which the bounds of each staged renderlet with posed
+/// bounds were last computed.
+#[derive(Default)]
+pub(crate) struct PosedBounds(FxHashMap<Id<Renderlet>, usize>);
+
+impl PosedBounds {
+    /// Updates the bounds of the given renderlets with posed bounds to those
+    /// of their vertices in their current pose, forgetting any others.
+    ///
+    /// Bounds are only recomputed when the renderlet, its vertices, or the
+    /// skin or morph that pose them have been written since, see
+    /// [`is_pose_written_since`].
+    ///
+    /// Returns whether any bounds changed.
+    fn update<'a>(
+        &mut self,
+        renderlets: impl IntoIterator<Item = &'a Hybrid<Renderlet>>,
+        slab: &CpuValues,
+    ) -> bool {
+        let mut changed = false;
+        let mut posed = FxHashMap::default();
+        for hybrid in renderlets {
+            let renderlet = hybrid.get();
+            if !renderlet.bounds_are_posed {
+                continue;
+            }
+            let id = hybrid.id();
+            match self.0.remove(&id) {
+                Some(computed) if !is_pose_written_since(id, &renderlet, computed, slab) => {
+                    posed.insert(id, computed);
+                }
+                _ => {
+                    let bounds = renderlet.get_posed_bounds(slab);
+                    if bounds != renderlet.bounds {
+                        hybrid.modify(|r| r.bounds = bounds);
+                        changed = true;
+                    }
+                    posed.insert(id, slab.upkeeps());
+                }
+            }
+        }
+        self.0 = posed;
+        changed
+    }
+}
+
+/// Returns whether the renderlet with the given id, its vertices, or the
+/// skin or morph that pose them, including joint transforms and morph
+/// weights, have been written after the given upkeep.
+fn is_pose_written_since(
+    id: Id<Renderlet>,
+    renderlet: &Renderlet,
+    upkeep: usize,
+    slab: &CpuValues,
+) -> bool {
+    let written = |index: usize| slab.written_at(index) > upkeep;
+    if written(id.index())
+        || written(renderlet.vertices_array.starting_index())
+        || written(renderlet.indices_array.starting_index())
+        || written(renderlet.extra_influences.starting_index())
+    {
+        return true;
+    }
+    if renderlet.skin_id.is_some() {
+        let skin = slab.read(renderlet.skin_id);
+        if written(renderlet.skin_id.index())
+            || written(skin.joints.starting_index())
+            || written(skin.inverse_bind_matrices.starting_index())
+            || skin
+                .joints
+                .iter()
+                .any(|joint| written(slab.read(joint).index()))
+        {
+            return true;
+        }
+    }
+    if renderlet.morph_id.is_some() {
+        let morph = slab.read(renderlet.morph_id);
+        if written(renderlet.morph_id.index())
+            || written(morph.weights.starting_index())
+            || written(morph.targets.starting_index())
+            || morph
+                .targets
+                .iter()
+                .any(|targets| written(slab.read(targets).starting_index()))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the largest sample count no greater than `requested` that is
+/// supported by formats with each of the given feature flags.
+fn supported_sample_count(requested: u32, flags: &[wgpu::TextureFormatFeatureFlags]) -> u32 {
+    (1..=requested.max(1))
+        .rev()
+        .find(|&count| flags.iter().all(|f| f.sample_count_supported(count)))
+        .unwrap_or(1)
 }
 
-fn create_stage_render_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
-    log::trace!("creating stage render pipeline");
-    let label = Some("stage render");
+/// Creates the stage's render pipeline.
+///
+/// The pipeline used for blended renderlets doesn't write depth.
+fn create_stage_render_pipeline(
+    device: &wgpu::Device,
+    multisample_count: u32,
+    is_blend: bool,
+    cull_mode: Option<wgpu::Face>,
+    front_face: wgpu::FrontFace,
+    topology: Topology,
+) -> wgpu::RenderPipeline {
+    log::trace!(
+        "creating stage render pipeline with {multisample_count} samples, blending: {is_blend}, \
+         culling {cull_mode:?} of {front_face:?} faces and topology {topology:?}"
+    );
+    let label = Some(
+        if is_blend {
+            "stage blend render"
+        } else {
+            "stage render"
+        },
+    );
     let vertex_linkage = crate::linkage::renderlet_vertex::linkage(device);
     let fragment_linkage = crate::linkage::renderlet_fragment::linkage(device);
     let stage_slab_buffers_layout = crate::linkage::slab_bindgroup_layout(device);
@@ -66,25 +493,27 @@ fn create_stage_render_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
             compilation_options: Default::default(),
         },
         primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
+            topology: topology.into(),
             strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: None,
+            front_face,
+            cull_mode,
             unclipped_depth: false,
             polygon_mode: wgpu::PolygonMode::Fill,
             conservative: false,
         },
         depth_stencil: Some(wgpu::DepthStencilState {
             format: wgpu::TextureFormat::Depth32Float,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_write_enabled: !is_blend,
+            // `LessEqual` so fragments pass the depth written by the depth
+            // prepass
+            depth_compare: wgpu::CompareFunction::LessEqual,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
             mask: !0,
             alpha_to_coverage_enabled: false,
-            count: 1,
+            count: multisample_count,
         },
         fragment: Some(wgpu::FragmentState {
             module: &fragment_linkage.module,
@@ -101,6 +530,120 @@ fn create_stage_render_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
     pipeline
 }
 
+/// Begins a pass rendering into the stage's color and depth attachments,
+/// keeping their contents.
+fn begin_stage_render_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: Option<&str>,
+    color_view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    depth_view: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label,
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: color_view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: depth_view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            }),
+            stencil_ops: None,
+        }),
+        ..Default::default()
+    })
+}
+
+/// The stage's render pipelines, one for each combination of blending, face
+/// culling and winding of triangles, and one for each blending of lines and
+/// points.
+#[derive(Clone)]
+pub(crate) struct StagePipelines {
+    double_sided: Arc<wgpu::RenderPipeline>,
+    single_sided: Arc<wgpu::RenderPipeline>,
+    blend_double_sided: Arc<wgpu::RenderPipeline>,
+    blend_single_sided: Arc<wgpu::RenderPipeline>,
+    mirrored_double_sided: Arc<wgpu::RenderPipeline>,
+    mirrored_single_sided: Arc<wgpu::RenderPipeline>,
+    blend_mirrored_double_sided: Arc<wgpu::RenderPipeline>,
+    blend_mirrored_single_sided: Arc<wgpu::RenderPipeline>,
+    lines: Arc<wgpu::RenderPipeline>,
+    blend_lines: Arc<wgpu::RenderPipeline>,
+    points: Arc<wgpu::RenderPipeline>,
+    blend_points: Arc<wgpu::RenderPipeline>,
+}
+
+impl StagePipelines {
+    fn new(device: &wgpu::Device, multisample_count: u32) -> Self {
+        let back = Some(wgpu::Face::Back);
+        let create = |is_blend, cull_mode, front_face, topology| {
+            Arc::new(create_stage_render_pipeline(
+                device,
+                multisample_count,
+                is_blend,
+                cull_mode,
+                front_face,
+                topology,
+            ))
+        };
+        let triangles = |is_blend, cull_mode, front_face| {
+            create(is_blend, cull_mode, front_face, Topology::TriangleList)
+        };
+        let (ccw, cw) = (wgpu::FrontFace::Ccw, wgpu::FrontFace::Cw);
+        Self {
+            double_sided: triangles(false, None, ccw),
+            single_sided: triangles(false, back, ccw),
+            blend_double_sided: triangles(true, None, ccw),
+            blend_single_sided: triangles(true, back, ccw),
+            mirrored_double_sided: triangles(false, None, cw),
+            mirrored_single_sided: triangles(false, back, cw),
+            blend_mirrored_double_sided: triangles(true, None, cw),
+            blend_mirrored_single_sided: triangles(true, back, cw),
+            lines: create(false, None, ccw, Topology::LineList),
+            blend_lines: create(true, None, ccw, Topology::LineList),
+            points: create(false, None, ccw, Topology::PointList),
+            blend_points: create(true, None, ccw, Topology::PointList),
+        }
+    }
+
+    /// Returns the pipeline for the given blending, sidedness, winding and
+    /// topology.
+    ///
+    /// Single-sided triangle pipelines cull back faces. Front faces of
+    /// mirrored triangles are clockwise, so that back faces are culled and
+    /// double-sided materials flip the normals of back faces as if the
+    /// triangles weren't mirrored. Lines and points have no sides.
+    fn get(
+        &self,
+        is_blend: bool,
+        double_sided: bool,
+        mirrored: bool,
+        topology: Topology,
+    ) -> &Arc<wgpu::RenderPipeline> {
+        match (topology, is_blend, double_sided, mirrored) {
+            (Topology::TriangleList, false, true, false) => &self.double_sided,
+            (Topology::TriangleList, false, false, false) => &self.single_sided,
+            (Topology::TriangleList, true, true, false) => &self.blend_double_sided,
+            (Topology::TriangleList, true, false, false) => &self.blend_single_sided,
+            (Topology::TriangleList, false, true, true) => &self.mirrored_double_sided,
+            (Topology::TriangleList, false, false, true) => &self.mirrored_single_sided,
+            (Topology::TriangleList, true, true, true) => &self.blend_mirrored_double_sided,
+            (Topology::TriangleList, true, false, true) => &self.blend_mirrored_single_sided,
+            (Topology::LineList, false, _, _) => &self.lines,
+            (Topology::LineList, true, _, _) => &self.blend_lines,
+            (Topology::PointList, false, _, _) => &self.points,
+            (Topology::PointList, true, _, _) => &self.blend_points,
+        }
+    }
+}
+
 /// Represents an entire scene worth of rendering data.
 ///
 /// A clone of a stage is a reference to the same stage.
@@ -109,6 +652,7 @@ fn create_stage_render_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
 /// Only available on the CPU. Not available in shaders.
 #[derive(Clone)]
 pub struct Stage {
+    pub(crate) adapter: Arc<wgpu::Adapter>,
     pub(crate) device: Arc<wgpu::Device>,
     pub(crate) queue: Arc<wgpu::Queue>,
 
@@ -117,13 +661,20 @@ pub struct Stage {
     pub(crate) pbr_config: Hybrid<PbrConfig>,
     pub(crate) lights: HybridArray<Id<Light>>,
 
-    pub(crate) stage_pipeline: Arc<wgpu::RenderPipeline>,
+    pub(crate) stage_pipelines: Arc<RwLock<StagePipelines>>,
     pub(crate) skybox_pipeline: Arc<RwLock<Option<Arc<wgpu::RenderPipeline>>>>,
+    pub(crate) shadow_map_pipeline: Arc<wgpu::RenderPipeline>,
 
     pub(crate) hdr_texture: Arc<RwLock<Texture>>,
+    /// Copy of the HDR texture before transmissive renderlets are drawn.
+    pub(crate) opaque_hdr_texture: Arc<RwLock<Texture>>,
     pub(crate) depth_texture: Arc<RwLock<Texture>>,
+    pub(crate) msaa_sample_count: Arc<AtomicU32>,
+    pub(crate) msaa_render_target: Arc<RwLock<Option<Texture>>>,
+    pub(crate) msaa_depth_texture: Arc<RwLock<Option<Texture>>>,
 
     pub(crate) atlas: Atlas,
+    pub(crate) shadow_map_atlas: Arc<RwLock<ShadowMapAtlas>>,
     pub(crate) bloom: Bloom,
     pub(crate) skybox: Arc<RwLock<Skybox>>,
     pub(crate) tonemapping: Tonemapping,
@@ -131,12 +682,17 @@ pub struct Stage {
 
     pub(crate) has_skybox: Arc<AtomicBool>,
     pub(crate) has_bloom: Arc<AtomicBool>,
+    pub(crate) has_frustum_culling: Arc<AtomicBool>,
 
     pub(crate) skybox_bindgroup: Arc<Mutex<Option<Arc<wgpu::BindGroup>>>>,
     pub(crate) buffers_bindgroup: Arc<Mutex<Option<Arc<wgpu::BindGroup>>>>,
     pub(crate) textures_bindgroup: Arc<Mutex<Option<Arc<wgpu::BindGroup>>>>,
 
     pub(crate) draws: Arc<RwLock<StageDrawStrategy>>,
+    pub(crate) pipeline_keys: Arc<RwLock<PipelineKeys>>,
+    pub(crate) posed_bounds: Arc<RwLock<PosedBounds>>,
+    pub(crate) shadow_maps: Arc<RwLock<Vec<ShadowMap>>>,
+    pub(crate) light_tiling: Arc<RwLock<Option<LightTiling>>>,
 }
 
 impl Deref for Stage {
@@ -169,6 +725,9 @@ impl Stage {
         let hdr_texture = Arc::new(RwLock::new(Texture::create_hdr_texture(
             &device, &queue, w, h,
         )));
+        let opaque_hdr_texture = Arc::new(RwLock::new(Texture::create_hdr_texture(
+            &device, &queue, w, h,
+        )));
         let depth_texture = Arc::new(RwLock::new(Texture::create_depth_texture(&device, w, h)));
         // UNWRAP: safe because no other references at this point (created above^)
         let bloom = Bloom::new(&device, &queue, &hdr_texture.read().unwrap());
@@ -184,8 +743,10 @@ impl Stage {
             pbr_config,
             lights,
 
-            stage_pipeline: create_stage_render_pipeline(&device).into(),
+            stage_pipelines: Arc::new(RwLock::new(StagePipelines::new(&device, 1))),
+            shadow_map_pipeline: crate::pbr::shadow::create_shadow_map_pipeline(&device).into(),
             atlas,
+            shadow_map_atlas: Arc::new(RwLock::new(ShadowMapAtlas::new(&device, UVec2::ONE, 1))),
             skybox: Arc::new(RwLock::new(Skybox::empty(&device, &queue))),
             skybox_bindgroup: Default::default(),
             skybox_pipeline: Default::default(),
@@ -195,10 +756,20 @@ impl Stage {
             has_bloom: AtomicBool::from(true).into(),
             buffers_bindgroup: Default::default(),
             textures_bindgroup: Default::default(),
-            draws: Arc::new(RwLock::new(StageDrawStrategy::Direct(vec![]))),
+            has_frustum_culling: AtomicBool::from(true).into(),
+            draws: Arc::new(RwLock::new(StageDrawStrategy::new(&device, vec![], true))),
+            pipeline_keys: Default::default(),
+            posed_bounds: Default::default(),
+            shadow_maps: Default::default(),
+            light_tiling: Default::default(),
             hdr_texture,
+            opaque_hdr_texture,
             depth_texture,
+            msaa_sample_count: AtomicU32::new(1).into(),
+            msaa_render_target: Default::default(),
+            msaa_depth_texture: Default::default(),
             background_color: Arc::new(RwLock::new(wgpu::Color::TRANSPARENT)),
+            adapter: ctx.get_adapter_owned(),
             device,
             queue,
         }
@@ -255,6 +826,45 @@ impl Stage {
         });
     }
 
+    /// Set Forward+ tiled light culling.
+    ///
+    /// With light tiling the stage renders a depth prepass and then bins the
+    /// lights into screen tiles, so that each fragment is only shaded by the
+    /// lights that may reach it. This pays off in scenes with many point
+    /// lights.
+    ///
+    /// Pass `None` to shade every fragment with every light.
+    ///
+    /// See [`LightTilingDescriptor`] for configuration.
+    pub fn set_light_tiling(&mut self, tiling: Option<LightTilingDescriptor>) {
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let mut guard = self.light_tiling.write().unwrap();
+        match (tiling, guard.as_ref()) {
+            (Some(descriptor), Some(light_tiling)) => light_tiling.set_descriptor(descriptor),
+            (Some(descriptor), None) => {
+                let light_tiling = LightTiling::new(
+                    &self.device,
+                    &mut self.mngr,
+                    self.pbr_config.id(),
+                    descriptor,
+                );
+                self.pbr_config
+                    .modify(|cfg| cfg.light_tiling = light_tiling.descriptor.id());
+                *guard = Some(light_tiling);
+            }
+            (None, _) => {
+                self.pbr_config.modify(|cfg| cfg.light_tiling = Id::NONE);
+                *guard = None;
+            }
+        }
+    }
+
+    /// Set Forward+ tiled light culling.
+    pub fn with_light_tiling(mut self, tiling: LightTilingDescriptor) -> Self {
+        self.set_light_tiling(Some(tiling));
+        self
+    }
+
     pub fn get_size(&self) -> UVec2 {
         // UNWRAP: panic on purpose
         let hdr = self.hdr_texture.read().unwrap();
@@ -273,13 +883,19 @@ impl Stage {
         // UNWRAP: panic on purpose
         *self.depth_texture.write().unwrap() =
             Texture::create_depth_texture(&self.device, size.x, size.y);
+        *self.opaque_hdr_texture.write().unwrap() =
+            Texture::create_hdr_texture(&self.device, &self.queue, size.x, size.y);
         self.bloom
             .set_hdr_texture(&self.device, &self.queue, &hdr_texture);
         self.tonemapping.set_hdr_texture(&self.device, &hdr_texture);
         *self.hdr_texture.write().unwrap() = hdr_texture;
+        self.create_msaa_textures(size);
 
         let _ = self.skybox_bindgroup.lock().unwrap().take();
         let _ = self.textures_bindgroup.lock().unwrap().take();
+        if let Some(light_tiling) = self.light_tiling.write().unwrap().as_mut() {
+            light_tiling.invalidate_bindgroup();
+        }
     }
 
     pub fn with_size(self, size: UVec2) -> Self {
@@ -287,6 +903,117 @@ impl Stage {
         self
     }
 
+    /// (Re)create the multisampled render target and depth texture, if
+    /// multisampling is enabled.
+    fn create_msaa_textures(&self, size: UVec2) {
+        let sample_count = self.get_msaa_sample_count();
+        let (render_target, depth_texture) = if sample_count > 1 {
+            (
+                Some(Texture::create_msaa_texture(
+                    &self.device,
+                    Some("stage msaa render target"),
+                    Texture::HDR_TEXTURE_FORMAT,
+                    sample_count,
+                    size.x,
+                    size.y,
+                )),
+                Some(Texture::create_msaa_texture(
+                    &self.device,
+                    Some("stage msaa depth"),
+                    Texture::DEPTH_FORMAT,
+                    sample_count,
+                    size.x,
+                    size.y,
+                )),
+            )
+        } else {
+            (None, None)
+        };
+        // UNWRAP: panic on purpose
+        *self.msaa_render_target.write().unwrap() = render_target;
+        *self.msaa_depth_texture.write().unwrap() = depth_texture;
+    }
+
+    /// Returns the number of samples per pixel used when rendering.
+    ///
+    /// A count of `1` means multisample anti-aliasing is disabled.
+    pub fn get_msaa_sample_count(&self) -> u32 {
+        self.msaa_sample_count.load(Ordering::Relaxed)
+    }
+
+    /// Set the multisample anti-aliasing sample count.
+    ///
+    /// When the count is greater than `1` the stage renders into
+    /// multisampled HDR and depth attachments, which are resolved into the
+    /// HDR texture before bloom and tonemapping.
+    ///
+    /// A count of `1` disables MSAA. A count of `4` is supported on all
+    /// backends, other counts depend on the adapter. Unsupported counts fall
+    /// back to the largest supported count below them, see
+    /// [`Stage::get_msaa_sample_count`].
+    ///
+    /// ## Note
+    /// With MSAA enabled the depth texture returned by
+    /// [`Stage::get_depth_texture`] only contains depth written by the light
+    /// tiling depth prepass, if any.
+    pub fn set_msaa_sample_count(&self, multisample_count: u32) {
+        let flags = [Texture::HDR_TEXTURE_FORMAT, Texture::DEPTH_FORMAT]
+            .map(|format| self.get_texture_format_features(format).flags);
+        let requested = multisample_count;
+        let multisample_count = supported_sample_count(requested, &flags);
+        if multisample_count != requested {
+            log::warn!(
+                "msaa sample count {requested} is not supported, using {multisample_count} instead"
+            );
+        }
+        let prev = self
+            .msaa_sample_count
+            .swap(multisample_count, Ordering::Relaxed);
+        if prev == multisample_count {
+            return;
+        }
+        log::debug!("setting msaa sample count to {multisample_count}");
+        // UNWRAP: panic on purpose
+        *self.stage_pipelines.write().unwrap() =
+            StagePipelines::new(&self.device, multisample_count);
+        let _ = self.skybox_pipeline.write().unwrap().take();
+        let _ = self.buffers_bindgroup.lock().unwrap().take();
+        let _ = self.textures_bindgroup.lock().unwrap().take();
+        self.create_msaa_textures(self.get_size());
+    }
+
+    /// Returns the features of the given texture format, as validated by the
+    /// device.
+    fn get_texture_format_features(
+        &self,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureFormatFeatures {
+        // Without adapter specific format features the device only allows
+        // those guaranteed by WebGPU, unless it is a downlevel device
+        let is_adapter_specific = self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            || !self
+                .adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::WEBGPU_TEXTURE_FORMAT_SUPPORT);
+        if is_adapter_specific {
+            self.adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(self.device.features())
+        }
+    }
+
+    /// Set the multisample anti-aliasing sample count and return the stage.
+    ///
+    /// See [`Stage::set_msaa_sample_count`].
+    pub fn with_msaa(self, multisample_count: u32) -> Self {
+        self.set_msaa_sample_count(multisample_count);
+        self
+    }
+
     /// Set the images to use for the atlas.
     ///
     /// Resets the atlas, packing it with the given images and returning a
@@ -385,6 +1112,7 @@ impl Stage {
                 crate::skybox::create_skybox_render_pipeline(
                     &self.device,
                     Texture::HDR_TEXTURE_FORMAT,
+                    self.get_msaa_sample_count(),
                 )
                 .0,
             );
@@ -418,7 +1146,13 @@ impl Stage {
                 crate::linkage::slab_bindgroup(
                     device,
                     slab_buffer,
-                    &self.stage_pipeline.get_bind_group_layout(0),
+                    // UNWRAP: panic on purpose
+                    &self
+                        .stage_pipelines
+                        .read()
+                        .unwrap()
+                        .double_sided
+                        .get_bind_group_layout(0),
                 )
             });
             *bindgroup = Some(b.clone());
@@ -434,14 +1168,18 @@ impl Stage {
         } else {
             let b = Arc::new(crate::linkage::atlas_and_skybox_bindgroup(
                 &self.device,
-                &{
-                    let this = &self;
-                    this.stage_pipeline.clone()
-                }
-                .get_bind_group_layout(1),
+                // UNWRAP: panic on purpose
+                &self
+                    .stage_pipelines
+                    .read()
+                    .unwrap()
+                    .double_sided
+                    .get_bind_group_layout(1),
                 // UNWRAP: if we can't acquire locks we want to panic
                 &self.atlas,
                 &self.skybox.read().unwrap(),
+                &self.shadow_map_atlas.read().unwrap().texture,
+                &self.opaque_hdr_texture.read().unwrap(),
             ));
             *bindgroup = Some(b.clone());
             b
@@ -456,14 +1194,24 @@ impl Stage {
     /// If you drop the renderlet and no other references are kept, it will be
     /// removed automatically from the internal list and will cease to be
     /// drawn each frame.
+    ///
+    /// Back faces of triangles are culled unless the renderlet's material is
+    /// [double-sided](crate::pbr::Material::double_sided). Front faces are
+    /// those with counter-clockwise winding.
+    ///
+    /// Renderlets whose material's [`AlphaMode`] is `Blend` are drawn after
+    /// all other renderlets without writing depth, sorted back-to-front each
+    /// frame by the distance from the center of their bounds to their camera.
+    ///
+    /// Renderlets whose material has a
+    /// [`Transmission`](crate::pbr::Transmission) are drawn after all opaque
+    /// renderlets and the skybox, which are copied into a texture the
+    /// transmissive renderlets refract. They are sorted back-to-front like
+    /// blended renderlets, but they don't see each other.
     pub fn add_renderlet(&mut self, renderlet: &Hybrid<Renderlet>) {
         // UNWRAP: if we can't acquire the lock we want to panic.
         let mut draws = self.draws.write().unwrap();
-        match draws.deref_mut() {
-            StageDrawStrategy::Direct(units) => {
-                units.push(renderlet.clone());
-            }
-        }
+        draws.renderlets_mut().push(renderlet.clone());
     }
 
     /// Erase the given renderlet from the internal list of renderlets to be
@@ -471,22 +1219,110 @@ impl Stage {
     pub fn remove_renderlet(&self, renderlet: &Hybrid<Renderlet>) {
         let id = renderlet.id();
         let mut draws = self.draws.write().unwrap();
-        match draws.deref_mut() {
-            StageDrawStrategy::Direct(units) => {
-                units.retain(|hybrid| hybrid.id() != id);
-            }
-        }
+        draws.renderlets_mut().retain(|hybrid| hybrid.id() != id);
     }
 
     /// Returns a clone of all the staged [`Renderlet`]s.
     pub fn get_renderlets(&self) -> Vec<Hybrid<Renderlet>> {
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let draws = self.draws.read().unwrap();
+        draws.renderlets().clone()
+    }
+
+    /// Set whether the stage draws its renderlets with a single
+    /// `RenderPass::multi_draw_indirect` call.
+    ///
+    /// Indirect draws are used by default when the device supports them.
+    /// If the device doesn't support them this has no effect and the stage
+    /// continues to use one draw call per renderlet.
+    pub fn set_use_indirect_draws(&self, use_indirect_draws: bool) {
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let mut draws = self.draws.write().unwrap();
+        let renderlets = std::mem::take(draws.renderlets_mut());
+        *draws = if use_indirect_draws {
+            let has_frustum_culling = self.has_frustum_culling.load(Ordering::Relaxed);
+            StageDrawStrategy::new(&self.device, renderlets, has_frustum_culling)
+        } else {
+            StageDrawStrategy::Direct(renderlets)
+        };
+    }
+
+    /// Set whether the stage draws its renderlets with a single
+    /// `RenderPass::multi_draw_indirect` call and return the stage.
+    ///
+    /// See [`Stage::set_use_indirect_draws`].
+    pub fn with_indirect_draws(self, use_indirect_draws: bool) -> Self {
+        self.set_use_indirect_draws(use_indirect_draws);
+        self
+    }
+
+    /// Returns whether the stage is drawing with
+    /// `RenderPass::multi_draw_indirect`.
+    pub fn is_using_indirect_draws(&self) -> bool {
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let draws = self.draws.read().unwrap();
+        matches!(draws.deref(), StageDrawStrategy::Indirect(_))
+    }
+
+    /// Set whether renderlets outside of their camera's frustum are culled
+    /// on the GPU.
+    ///
+    /// Culling is on by default. It requires indirect draws, so it has no
+    /// effect when the stage is using direct draws.
+    ///
+    /// A renderlet is culled using its [`Renderlet::bounds`].
+    pub fn set_has_frustum_culling(&self, has_frustum_culling: bool) {
+        self.has_frustum_culling
+            .store(has_frustum_culling, Ordering::Relaxed);
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let mut draws = self.draws.write().unwrap();
+        if let StageDrawStrategy::Indirect(indirect) = draws.deref_mut() {
+            indirect.set_has_frustum_culling(&self.device, has_frustum_culling);
+        }
+    }
+
+    /// Set whether renderlets outside of their camera's frustum are culled
+    /// on the GPU and return the stage.
+    ///
+    /// See [`Stage::set_has_frustum_culling`].
+    pub fn with_frustum_culling(self, has_frustum_culling: bool) -> Self {
+        self.set_has_frustum_culling(has_frustum_culling);
+        self
+    }
+
+    /// Read back the number of visible renderlets that were culled during
+    /// the last call to [`Stage::render`].
+    ///
+    /// This is primarily used for stats and debugging, as it waits on the
+    /// GPU.
+    pub fn read_culled_count(&self) -> Result<u32, StageError> {
         // UNWRAP: if we can't acquire the lock we want to panic.
         let draws = self.draws.read().unwrap();
         match draws.deref() {
-            StageDrawStrategy::Direct(units) => units.clone(),
+            StageDrawStrategy::Direct(_) => Ok(0),
+            StageDrawStrategy::Indirect(indirect) => {
+                let count = futures_lite::future::block_on(
+                    indirect.read_culled_count(&self.device, &self.queue),
+                )?;
+                Ok(count)
+            }
         }
     }
 
+    /// Returns the world space positions of the vertices of the given
+    /// instance of `renderlet` in their pose as of the last call to
+    /// [`Stage::tick`] or [`Stage::render`], in draw order.
+    ///
+    /// Skinned and morphed renderlets are otherwise only deformed on the GPU,
+    /// so this is useful for hit testing them. It does not wait on the GPU.
+    ///
+    /// See [`Renderlet::get_posed_positions`].
+    pub fn get_posed_positions(&self, renderlet: &Hybrid<Renderlet>, instance: u32) -> Vec<Vec3> {
+        renderlet
+            .get()
+            .get_posed_positions(instance, &self.mngr.cpu_values())
+    }
+
     /// Returns a clone of the current depth texture.
     pub fn get_depth_texture(&self) -> DepthTexture {
         DepthTexture {
@@ -509,15 +1345,124 @@ impl Stage {
         NestedTransform::new(&mut self.mngr)
     }
 
-    fn tick_internal(&mut self) -> Arc<wgpu::Buffer> {
-        {
-            let mut draw_guard = self.draws.write().unwrap();
-            match draw_guard.deref_mut() {
-                StageDrawStrategy::Direct(draws) => {
-                    draws.retain(|d| d.strong_count() > 2);
-                }
+    /// Draw the given renderlet once for each of the given transforms.
+    ///
+    /// Each transform is applied before the renderlet's own transform.
+    /// The renderlet is drawn instanced as long as the returned
+    /// [`RenderletInstances`] (or a clone of it) is kept alive, after which
+    /// it is drawn once again.
+    ///
+    /// The instances keep a reference to the renderlet.
+    pub fn new_instances(
+        &mut self,
+        renderlet: &Hybrid<Renderlet>,
+        transforms: impl IntoIterator<Item = Transform>,
+    ) -> RenderletInstances {
+        RenderletInstances::new(&mut self.mngr, renderlet, transforms)
+    }
+
+    /// Create a new shadow map of the given size, in texels, for the given
+    /// light.
+    ///
+    /// The light will cast shadows as long as the returned [`ShadowMap`] (or
+    /// a clone of it) is kept alive.
+    ///
+    /// ## Note
+    /// Only directional and point lights cast shadows. The shadow maps of
+    /// point lights should be square.
+    pub fn new_shadow_map(&mut self, light: &Hybrid<Light>, size: UVec2) -> ShadowMap {
+        let shadow_map = ShadowMap::new(
+            &self.device,
+            &mut self.mngr,
+            &self.shadow_map_pipeline,
+            light,
+            size,
+        );
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        self.shadow_maps.write().unwrap().push(shadow_map.clone());
+        shadow_map
+    }
+
+    /// Create a new cascaded shadow map for the given directional light.
+    ///
+    /// The shadow map has `cascade_count` layers of the given size, in texels,
+    /// each covering one slice of the given camera's frustum. The cascades
+    /// are fit to the camera every frame, so the camera should be the one
+    /// used by the renderlets that receive the shadows.
+    ///
+    /// The light will cast shadows as long as the returned [`ShadowMap`] (or
+    /// a clone of it) is kept alive.
+    pub fn new_cascaded_shadow_map(
+        &mut self,
+        light: &Hybrid<Light>,
+        camera: &Hybrid<Camera>,
+        size: UVec2,
+        cascade_count: u32,
+    ) -> ShadowMap {
+        let shadow_map = ShadowMap::new_cascaded(
+            &self.device,
+            &mut self.mngr,
+            &self.shadow_map_pipeline,
+            light,
+            camera,
+            size,
+            cascade_count,
+        );
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        self.shadow_maps.write().unwrap().push(shadow_map.clone());
+        shadow_map
+    }
+
+    /// Drop shadow maps that are no longer in use and assign the rest to
+    /// layers of the shadow map atlas, growing the atlas if need be.
+    fn update_shadow_maps(&self) {
+        // UNWRAP: if we can't acquire the lock we want to panic.
+        let mut shadow_maps = self.shadow_maps.write().unwrap();
+        shadow_maps.retain(|shadow_map| {
+            let keep = shadow_map.descriptor.strong_count() > 2;
+            if !keep {
+                let id = shadow_map.descriptor.id();
+                shadow_map.light.modify(|light| {
+                    if light.shadow_map == id {
+                        light.shadow_map = Id::NONE;
+                    }
+                });
             }
+            keep
+        });
+        if shadow_maps.is_empty() {
+            return;
+        }
+        let size = shadow_maps.iter().fold(UVec2::ONE, |size, shadow_map| {
+            size.max(shadow_map.descriptor.get().size)
+        });
+        let layers = shadow_maps.iter().map(ShadowMap::layer_count).sum::<u32>();
+        let mut atlas = self.shadow_map_atlas.write().unwrap();
+        if !atlas.can_hold(size, layers) {
+            let size = size.max(atlas.get_size());
+            let layers = layers.max(atlas.get_layers());
+            log::trace!("resizing the shadow map atlas to {size} with {layers} layers");
+            *atlas = ShadowMapAtlas::new(&self.device, size, layers);
+            // invalidate the bindgroup that holds the atlas
+            let _ = self.textures_bindgroup.lock().unwrap().take();
         }
+        let atlas_size = atlas.get_size();
+        let mut atlas_layer = 0;
+        for shadow_map in shadow_maps.iter() {
+            shadow_map.update_cascades();
+            let descriptor = shadow_map.descriptor.get();
+            if descriptor.atlas_layer != atlas_layer || descriptor.atlas_size != atlas_size {
+                shadow_map.descriptor.modify(|d| {
+                    d.atlas_layer = atlas_layer;
+                    d.atlas_size = atlas_size;
+                });
+            }
+            atlas_layer += shadow_map.layer_count();
+        }
+    }
+
+    /// Synchronizes the slab with the GPU, returning the slab buffer.
+    fn upkeep_slab(&self) -> Arc<wgpu::Buffer> {
         if let Some(new_slab_buffer) = self.mngr.upkeep((
             &self.device,
             &self.queue,
@@ -527,6 +1472,12 @@ impl Stage {
             // invalidate our bindgroups, etc
             let _ = self.skybox_bindgroup.lock().unwrap().take();
             let _ = self.buffers_bindgroup.lock().unwrap().take();
+            if let StageDrawStrategy::Indirect(indirect) = self.draws.write().unwrap().deref_mut() {
+                indirect.invalidate_slab_bindgroups();
+            }
+            if let Some(light_tiling) = self.light_tiling.write().unwrap().as_mut() {
+                light_tiling.invalidate_bindgroup();
+            }
             new_slab_buffer
         } else {
             // UNWRAP: safe because we called `SlabManager::upkeep` above^, which ensures
@@ -535,6 +1486,31 @@ impl Stage {
         }
     }
 
+    fn tick_internal(&mut self) -> Arc<wgpu::Buffer> {
+        // The slab is synchronized first, so renderlets are ordered by the
+        // materials they currently have and posed with their current joints
+        // and morph weights
+        let mut slab_buffer = self.upkeep_slab();
+        let posed_bounds_changed = {
+            let mut draw_guard = self.draws.write().unwrap();
+            draw_guard.renderlets_mut().retain(|d| d.strong_count() > 2);
+            let slab = self.mngr.cpu_values();
+            let mut keys = self.pipeline_keys.write().unwrap();
+            keys.update(draw_guard.renderlets(), &slab);
+            draw_guard.order_by_pipeline(&keys);
+            let mut posed_bounds = self.posed_bounds.write().unwrap();
+            posed_bounds.update(draw_guard.renderlets(), &slab)
+        };
+        if posed_bounds_changed {
+            // upload the new bounds so they're culled against this frame
+            slab_buffer = self.upkeep_slab();
+        }
+        if let StageDrawStrategy::Indirect(indirect) = self.draws.write().unwrap().deref_mut() {
+            let _ = indirect.upkeep(&self.device, &self.queue);
+        }
+        slab_buffer
+    }
+
     /// Ticks the stage, synchronizing changes with the GPU.
     ///
     /// It's good to call this after dropping assets to free up space on the
@@ -553,14 +1529,32 @@ impl Stage {
             Some(&self.depth_texture.read().unwrap().view),
             *self.background_color.read().unwrap(),
         );
+        // UNWRAP: panic on purpose
+        if let (Some(msaa_render_target), Some(msaa_depth_texture)) = (
+            self.msaa_render_target.read().unwrap().as_ref(),
+            self.msaa_depth_texture.read().unwrap().as_ref(),
+        ) {
+            log::trace!("clearing msaa attachments");
+            crate::conduct_clear_pass(
+                &self.device,
+                &self.queue,
+                Some("stage msaa clear pass"),
+                vec![msaa_render_target.view.as_ref()],
+                Some(&msaa_depth_texture.view),
+                *self.background_color.read().unwrap(),
+            );
+        }
 
         {
             log::trace!("rendering the stage");
             let label = Some("stage render");
-            let pipeline = {
-                let this = &self;
-                this.stage_pipeline.clone()
-            };
+            // UNWRAP: panic on purpose
+            let pipelines = self.stage_pipelines.read().unwrap().clone();
+            self.update_shadow_maps();
+            let size = self.get_size();
+            if let Some(light_tiling) = self.light_tiling.write().unwrap().as_mut() {
+                light_tiling.upkeep(&mut self.mngr, size);
+            }
             let slab_buffer = self.tick_internal();
             let slab_buffers_bindgroup = self.get_slab_buffers_bindgroup(&slab_buffer);
             let textures_bindgroup = self.get_textures_bindgroup();
@@ -571,68 +1565,195 @@ impl Stage {
                 None
             };
 
-            // UNWRAP: if we can't read we want to panic.
-            let draws = self.draws.read().unwrap();
+            // UNWRAP: if we can't acquire the lock we want to panic.
+            let mut draws = self.draws.write().unwrap();
+            let keys = self.pipeline_keys.read().unwrap();
+            let pipeline_ranges = draws.pipeline_ranges(&keys);
+            let (blended_renderlets, transmissive_renderlets) = {
+                let slab = self.mngr.cpu_values();
+                (
+                    sort_back_to_front(&draws.pass_renderlets(&keys, DrawPass::Blended), &slab),
+                    sort_back_to_front(
+                        &draws.pass_renderlets(&keys, DrawPass::Transmissive),
+                        &slab,
+                    ),
+                )
+            };
+            let may_indirect_buffer = match draws.deref() {
+                StageDrawStrategy::Direct(_) => None,
+                StageDrawStrategy::Indirect(indirect) => indirect.slab.get_buffer(),
+            };
 
             let mut encoder = self
                 .device
                 .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            if let StageDrawStrategy::Indirect(indirect) = draws.deref_mut() {
+                log::trace!("frustum culling");
+                indirect.compute_frustum_culling(&self.device, &mut encoder, &slab_buffer);
+            }
+            {
+                // UNWRAP: if we can't acquire the locks we want to panic.
+                let shadow_maps = self.shadow_maps.read().unwrap();
+                let atlas = self.shadow_map_atlas.read().unwrap();
+                for shadow_map in shadow_maps.iter() {
+                    log::trace!("rendering shadow map");
+                    shadow_map.render(
+                        &mut encoder,
+                        &self.shadow_map_pipeline,
+                        &slab_buffers_bindgroup,
+                        &atlas,
+                        draws.renderlets(),
+                    );
+                }
+            }
+            // UNWRAP: if we can't acquire the lock we want to panic.
+            if let Some(light_tiling) = self.light_tiling.write().unwrap().as_mut() {
+                let depth_texture = self.depth_texture.read().unwrap();
+                {
+                    log::trace!("depth prepass");
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("depth prepass"),
+                        color_attachments: &[],
+                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                            view: &depth_texture.view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Load,
+                                store: wgpu::StoreOp::Store,
+                            }),
+                            stencil_ops: None,
+                        }),
+                        ..Default::default()
+                    });
+                    render_pass.set_bind_group(0, &slab_buffers_bindgroup, &[]);
+                    render_pass.set_bind_group(1, &textures_bindgroup, &[]);
+                    for (_, is_double_sided, is_mirrored, range) in pipeline_ranges
+                        .iter()
+                        .filter(|(topology, _, _, _)| *topology == Topology::TriangleList)
+                    {
+                        render_pass.set_pipeline(
+                            light_tiling
+                                .depth_prepass_pipelines
+                                .get(*is_double_sided, *is_mirrored),
+                        );
+                        draws.draw(
+                            &mut render_pass,
+                            may_indirect_buffer.as_deref(),
+                            range.clone(),
+                        );
+                    }
+                }
+                log::trace!("light tiling");
+                light_tiling.compute_light_tiles(
+                    &self.device,
+                    &mut encoder,
+                    &slab_buffer,
+                    &depth_texture,
+                );
+            }
             {
                 let hdr_texture = self.hdr_texture.read().unwrap();
                 let depth_texture = self.depth_texture.read().unwrap();
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label,
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &hdr_texture.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: wgpu::StoreOp::Store,
+                let msaa_render_target = self.msaa_render_target.read().unwrap();
+                let msaa_depth_texture = self.msaa_depth_texture.read().unwrap();
+                // With MSAA we render into the multisampled attachments and
+                // resolve into the HDR texture.
+                let (color_view, resolve_target, depth_view) =
+                    match (msaa_render_target.as_ref(), msaa_depth_texture.as_ref()) {
+                        (Some(target), Some(depth)) => (
+                            target.view.as_ref(),
+                            Some(hdr_texture.view.as_ref()),
+                            depth.view.as_ref(),
+                        ),
+                        _ => (hdr_texture.view.as_ref(), None, depth_texture.view.as_ref()),
+                    };
+                {
+                    let mut render_pass = begin_stage_render_pass(
+                        &mut encoder,
+                        label,
+                        color_view,
+                        resolve_target,
+                        depth_view,
+                    );
+                    render_pass.set_bind_group(0, &slab_buffers_bindgroup, &[]);
+                    render_pass.set_bind_group(1, &textures_bindgroup, &[]);
+                    for (topology, is_double_sided, is_mirrored, range) in pipeline_ranges {
+                        render_pass.set_pipeline(pipelines.get(
+                            false,
+                            is_double_sided,
+                            is_mirrored,
+                            topology,
+                        ));
+                        draws.draw(&mut render_pass, may_indirect_buffer.as_deref(), range);
+                    }
+
+                    if let Some((pipeline, bindgroup)) = may_skybox_pipeline_and_bindgroup.as_ref()
+                    {
+                        log::trace!("rendering skybox");
+                        // UNWRAP: if we can't acquire the lock we want to panic.
+                        let skybox = self.skybox.read().unwrap();
+                        render_pass.set_pipeline(pipeline);
+                        render_pass.set_bind_group(0, bindgroup, &[]);
+                        render_pass.draw(0..36, skybox.camera.inner()..skybox.camera.inner() + 1);
+                    }
+                }
+
+                if !transmissive_renderlets.is_empty() {
+                    log::trace!("copying the opaque scene for transmission");
+                    let opaque_hdr_texture = self.opaque_hdr_texture.read().unwrap();
+                    encoder.copy_texture_to_texture(
+                        wgpu::ImageCopyTexture {
+                            texture: &hdr_texture.texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d::ZERO,
+                            aspect: wgpu::TextureAspect::All,
                         },
-                    })],
-                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                        view: &depth_texture.view,
-                        depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: wgpu::StoreOp::Store,
-                        }),
-                        stencil_ops: None,
-                    }),
-                    ..Default::default()
-                });
-                render_pass.set_pipeline(&pipeline);
-                render_pass.set_bind_group(0, &slab_buffers_bindgroup, &[]);
-                render_pass.set_bind_group(1, &textures_bindgroup, &[]);
-                match draws.deref() {
-                    StageDrawStrategy::Direct(units) => {
-                        for hybrid in units {
-                            let rlet = hybrid.get();
-                            if rlet.visible {
-                                let vertex_range = if rlet.indices_array.is_null() {
-                                    0..rlet.vertices_array.len() as u32
-                                } else {
-                                    0..rlet.indices_array.len() as u32
-                                };
-                                let id = hybrid.id();
-                                let instance_range = id.inner()..id.inner() + 1;
-                                log::trace!(
-                                    "drawing vertices {vertex_range:?} and instances \
-                                     {instance_range:?}"
-                                );
-                                render_pass.draw(vertex_range, instance_range);
-                            }
-                        }
-                    } /* render_pass.multi_draw_indirect(&indirect_buffer, 0,
-                       * stage.number_of_indirect_draws()); */
+                        wgpu::ImageCopyTexture {
+                            texture: &opaque_hdr_texture.texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d::ZERO,
+                            aspect: wgpu::TextureAspect::All,
+                        },
+                        wgpu::Extent3d {
+                            width: hdr_texture.width(),
+                            height: hdr_texture.height(),
+                            depth_or_array_layers: 1,
+                        },
+                    );
                 }
 
-                if let Some((pipeline, bindgroup)) = may_skybox_pipeline_and_bindgroup.as_ref() {
-                    log::trace!("rendering skybox");
-                    // UNWRAP: if we can't acquire the lock we want to panic.
-                    let skybox = self.skybox.read().unwrap();
-                    render_pass.set_pipeline(pipeline);
-                    render_pass.set_bind_group(0, bindgroup, &[]);
-                    render_pass.draw(0..36, skybox.camera.inner()..skybox.camera.inner() + 1);
+                if !transmissive_renderlets.is_empty() || !blended_renderlets.is_empty() {
+                    log::trace!(
+                        "rendering {} transmissive and {} blended renderlets",
+                        transmissive_renderlets.len(),
+                        blended_renderlets.len()
+                    );
+                    let mut render_pass = begin_stage_render_pass(
+                        &mut encoder,
+                        label,
+                        color_view,
+                        resolve_target,
+                        depth_view,
+                    );
+                    render_pass.set_bind_group(0, &slab_buffers_bindgroup, &[]);
+                    render_pass.set_bind_group(1, &textures_bindgroup, &[]);
+                    for (renderlets, is_blend) in [
+                        (&transmissive_renderlets, false),
+                        (&blended_renderlets, true),
+                    ] {
+                        for hybrid in renderlets.iter() {
+                            let rlet = hybrid.get();
+                            let (_, _, single_sided, mirrored) = keys.get(hybrid.id());
+                            render_pass.set_pipeline(pipelines.get(
+                                is_blend,
+                                !single_sided,
+                                mirrored,
+                                rlet.topology,
+                            ));
+                            let vertex_count = rlet.get_vertex_count();
+                            let instance_range = rlet.get_instance_range(hybrid.id());
+                            render_pass.draw(0..vertex_count, instance_range);
+                        }
+                    }
                 }
             }
             self.queue.submit(std::iter::once(encoder.finish()));
@@ -678,6 +1799,108 @@ impl Stage {
     }
 }
 
+impl Renderlet {
+    /// Returns the world space positions of the vertices of the given
+    /// instance in their current pose, in draw order.
+    ///
+    /// The positions are those computed by the vertex shader, so for
+    /// triangle lists every three positions form a rendered triangle.
+    pub fn get_posed_positions(&self, instance: u32, slab: &(impl Slab + ?Sized)) -> Vec<Vec3> {
+        (0..self.get_vertex_count())
+            .map(|i| {
+                let (vertex, transform) = self.get_instance_vertex_info(i, instance, slab);
+                Mat4::from(transform).transform_point3(vertex.position)
+            })
+            .collect()
+    }
+
+    /// Returns the bounding box of the vertices in their current pose, in
+    /// model space.
+    ///
+    /// See [`Renderlet::get_posed_vertex`].
+    pub fn get_posed_bounds(&self, slab: &(impl Slab + ?Sized)) -> Aabb {
+        let mut positions =
+            (0..self.get_vertex_count()).map(|i| self.get_posed_vertex(i, slab).position);
+        let Some(first) = positions.next() else {
+            return Aabb::default();
+        };
+        let (min, max) = positions.fold((first, first), |(min, max), p| (min.min(p), max.max(p)));
+        Aabb::new(min, max)
+    }
+}
+
+/// The instances of an instanced [`Renderlet`].
+///
+/// Created with [`Stage::new_instances`].
+#[derive(Clone, Debug)]
+pub struct RenderletInstances {
+    /// Transform of each instance, which may be updated to move instances.
+    pub transforms: HybridArray<Transform>,
+    slots: HybridArray<Id<Renderlet>>,
+    _instanced: Arc<InstancedRenderlet>,
+}
+
+/// Stops drawing a renderlet instanced when the last clone of its
+/// [`RenderletInstances`] is dropped, before the instance arrays are
+/// recycled.
+#[derive(Debug)]
+struct InstancedRenderlet {
+    renderlet: Hybrid<Renderlet>,
+    instances: Array<Transform>,
+}
+
+impl Drop for InstancedRenderlet {
+    fn drop(&mut self) {
+        let instances = self.instances;
+        self.renderlet.modify(|r| {
+            // the renderlet may have been given other instances since
+            if r.instances == instances {
+                r.instances = Array::default();
+                r.instance_slots = Array::default();
+            }
+        });
+    }
+}
+
+impl RenderletInstances {
+    pub(crate) fn new(
+        slab: &mut SlabAllocator<impl IsBuffer>,
+        renderlet: &Hybrid<Renderlet>,
+        transforms: impl IntoIterator<Item = Transform>,
+    ) -> Self {
+        let transforms = slab.new_array(transforms);
+        let slots = slab.new_array(vec![renderlet.id(); transforms.len()]);
+        renderlet.modify(|r| {
+            r.instances = transforms.array();
+            r.instance_slots = slots.array();
+        });
+        let instanced = Arc::new(InstancedRenderlet {
+            renderlet: renderlet.clone(),
+            instances: transforms.array(),
+        });
+        RenderletInstances {
+            transforms,
+            slots,
+            _instanced: instanced,
+        }
+    }
+
+    /// Returns the number of instances.
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    /// Returns whether there are no instances.
+    pub fn is_empty(&self) -> bool {
+        self.transforms.len() == 0
+    }
+
+    /// Returns the array of instance slots on the slab.
+    pub fn slots(&self) -> Array<Id<Renderlet>> {
+        self.slots.array()
+    }
+}
+
 /// Manages scene heirarchy on the [`Stage`].
 ///
 /// Clones all reference the same nested transform.
@@ -743,6 +1966,10 @@ impl UpdatesSlab for NestedTransform {
         elements.write_indexed(&transform, 0);
         vec![SlabUpdate { array, elements }]
     }
+
+    fn cpu_element(&self, _index: usize) -> Option<SlabUpdate> {
+        self.get_update().pop()
+    }
 }
 
 impl NestedTransform {
@@ -831,14 +2058,26 @@ impl NestedTransform {
 mod test {
     use std::sync::Mutex;
 
-    use crabslab::{Array, Slab};
+    use crabslab::{Array, Id, Slab};
     use glam::{Mat4, Vec2, Vec3};
 
     use crate::{
-        stage::{cpu::SlabAllocator, NestedTransform, Vertex},
+        bvol::Aabb,
+        camera::Camera,
+        pbr::{AlphaMode, Material, Transmission},
+        slab::Hybrid,
+        stage::{
+            cpu::SlabAllocator, Morph, MorphTarget, NestedTransform, Renderlet, RenderletInstances,
+            Skin, Topology, Vertex,
+        },
         transform::Transform,
     };
 
+    use super::{
+        sort_back_to_front, supported_sample_count, DrawPass, PipelineKeys, PosedBounds,
+        StageDrawStrategy,
+    };
+
     #[test]
     fn vertex_slab_roundtrip() {
         let initial_vertices = {
@@ -897,4 +2136,619 @@ mod test {
             "Grandchild's global translation should   2.0 along the x-axis"
         );
     }
+    #[test]
+    fn blended_renderlets_sort_back_to_front() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let near = slab.new_value(Camera::new(
+            Mat4::IDENTITY,
+            Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y),
+        ));
+        let far = slab.new_value(Camera::new(
+            Mat4::IDENTITY,
+            Mat4::look_at_rh(Vec3::new(0.0, 0.0, -10.0), Vec3::NEG_Z, Vec3::Y),
+        ));
+        let transforms = [-1.0f32, -5.0, -3.0]
+            .into_iter()
+            .map(|z| {
+                let transform = NestedTransform::new(&mut slab);
+                transform.set_local_transform(Transform {
+                    translation: Vec3::new(0.0, 0.0, z),
+                    ..Default::default()
+                });
+                transform
+            })
+            .collect::<Vec<_>>();
+        let blended = transforms
+            .iter()
+            .map(|transform| {
+                slab.new_value(Renderlet {
+                    camera_id: near.id(),
+                    transform_id: transform.global_transform_id(),
+                    bounds: (Vec3::splat(-0.5), Vec3::splat(0.5)).into(),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let sorted_ids = |slab: &SlabAllocator<Mutex<Vec<u32>>>| {
+            sort_back_to_front(&blended, &slab.cpu_values())
+                .iter()
+                .map(|r| r.id())
+                .collect::<Vec<_>>()
+        };
+
+        let expected = [1, 2, 0].map(|i| blended[i].id());
+        assert_eq!(&expected, sorted_ids(&slab).as_slice());
+
+        // each renderlet is sorted by the distance to its own camera
+        for renderlet in blended.iter() {
+            renderlet.modify(|r| r.camera_id = far.id());
+        }
+        let expected = [0, 2, 1].map(|i| blended[i].id());
+        assert_eq!(&expected, sorted_ids(&slab).as_slice());
+
+        blended[1].modify(|r| r.visible = false);
+        let expected = [0, 2].map(|i| blended[i].id());
+        assert_eq!(&expected, sorted_ids(&slab).as_slice());
+    }
+
+    #[test]
+    fn renderlets_order_by_sidedness() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let single_sided = slab.new_value(Material {
+            double_sided: false,
+            ..Default::default()
+        });
+        let double_sided = slab.new_value(Material::default());
+        // renderlets without a material are double-sided
+        let renderlets = [
+            single_sided.id(),
+            double_sided.id(),
+            Id::NONE,
+            single_sided.id(),
+            double_sided.id(),
+        ]
+        .map(|material_id| {
+            Hybrid::new(
+                &mut slab,
+                Renderlet {
+                    material_id,
+                    ..Default::default()
+                },
+            )
+        });
+        let mut draws = StageDrawStrategy::Direct(renderlets.to_vec());
+        let mut keys = PipelineKeys::default();
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        let ordered = draws
+            .renderlets()
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        let expected = [1, 2, 4, 0, 3].map(|i| renderlets[i].id());
+        assert_eq!(&expected, ordered.as_slice());
+        assert_eq!(
+            vec![
+                (Topology::TriangleList, true, false, 0..3),
+                (Topology::TriangleList, false, false, 3..5)
+            ],
+            draws.pipeline_ranges(&keys)
+        );
+    }
+
+    #[test]
+    fn mirrored_renderlets_order_last() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let transform = |scale: Vec3| Transform {
+            scale,
+            ..Default::default()
+        };
+        let identity = slab.new_value(Transform::default());
+        let mirrored = slab.new_value(transform(Vec3::new(-1.0, 1.0, 1.0)));
+        // mirroring twice is no mirroring at all
+        let unmirrored = slab.new_value(transform(Vec3::new(-1.0, -1.0, 1.0)));
+        let single_sided = slab.new_value(Material {
+            double_sided: false,
+            ..Default::default()
+        });
+        let mirrored_instances = slab.new_array([
+            transform(Vec3::new(-1.0, 1.0, 1.0)),
+            transform(Vec3::new(1.0, 1.0, -1.0)),
+        ]);
+        let mixed_instances =
+            slab.new_array([transform(Vec3::ONE), transform(Vec3::new(-1.0, 1.0, 1.0))]);
+        let renderlets = [
+            (mirrored.id(), Array::default()),
+            (unmirrored.id(), Array::default()),
+            (identity.id(), Array::default()),
+            (identity.id(), mirrored_instances.array()),
+            (identity.id(), mixed_instances.array()),
+        ]
+        .map(|(transform_id, instances)| {
+            Hybrid::new(
+                &mut slab,
+                Renderlet {
+                    transform_id,
+                    material_id: single_sided.id(),
+                    instances,
+                    ..Default::default()
+                },
+            )
+        });
+        let mut draws = StageDrawStrategy::Direct(renderlets.to_vec());
+        let mut keys = PipelineKeys::default();
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        let ordered = draws
+            .renderlets()
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        // instances that don't agree on their winding are drawn double-sided
+        let expected = [4, 1, 2, 0, 3].map(|i| renderlets[i].id());
+        assert_eq!(&expected, ordered.as_slice());
+        assert_eq!(
+            vec![
+                (Topology::TriangleList, true, false, 0..1),
+                (Topology::TriangleList, false, false, 1..3),
+                (Topology::TriangleList, false, true, 3..5),
+            ],
+            draws.pipeline_ranges(&keys)
+        );
+    }
+
+    #[test]
+    fn renderlets_order_by_topology() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let single_sided = slab.new_value(Material {
+            double_sided: false,
+            ..Default::default()
+        });
+        // lines and points have no sides, so they are never single-sided
+        let renderlets = [
+            (Topology::PointList, Id::NONE),
+            (Topology::TriangleList, single_sided.id()),
+            (Topology::LineList, single_sided.id()),
+            (Topology::TriangleList, Id::NONE),
+            (Topology::PointList, Id::NONE),
+        ]
+        .map(|(topology, material_id)| {
+            Hybrid::new(
+                &mut slab,
+                Renderlet {
+                    topology,
+                    material_id,
+                    ..Default::default()
+                },
+            )
+        });
+        let mut draws = StageDrawStrategy::Direct(renderlets.to_vec());
+        let mut keys = PipelineKeys::default();
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        let ordered = draws
+            .renderlets()
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        let expected = [3, 1, 2, 0, 4].map(|i| renderlets[i].id());
+        assert_eq!(&expected, ordered.as_slice());
+        assert_eq!(
+            vec![
+                (Topology::TriangleList, true, false, 0..1),
+                (Topology::TriangleList, false, false, 1..2),
+                (Topology::LineList, true, false, 2..3),
+                (Topology::PointList, true, false, 3..5)
+            ],
+            draws.pipeline_ranges(&keys)
+        );
+    }
+
+    #[test]
+    fn pipeline_keys_follow_writes() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let material = slab.new_value(Material::default());
+        let transform = slab.new_value(Transform::default());
+        let renderlet = slab.new_value(Renderlet {
+            material_id: material.id(),
+            transform_id: transform.id(),
+            ..Default::default()
+        });
+        let renderlets = [renderlet.clone()];
+        let mut keys = PipelineKeys::default();
+        let _ = slab.upkeep(());
+        keys.update(&renderlets, &slab.cpu_values());
+        assert_eq!(
+            (DrawPass::Opaque, 0, false, false),
+            keys.get(renderlet.id())
+        );
+
+        // keys are kept until their sources are written
+        material.modify(|m| m.double_sided = false);
+        keys.update(&renderlets, &slab.cpu_values());
+        assert_eq!(
+            (DrawPass::Opaque, 0, false, false),
+            keys.get(renderlet.id())
+        );
+        let _ = slab.upkeep(());
+        keys.update(&renderlets, &slab.cpu_values());
+        assert_eq!((DrawPass::Opaque, 0, true, false), keys.get(renderlet.id()));
+
+        transform.modify(|t| t.scale = Vec3::new(-1.0, 1.0, 1.0));
+        let _ = slab.upkeep(());
+        keys.update(&renderlets, &slab.cpu_values());
+        assert_eq!((DrawPass::Opaque, 0, true, true), keys.get(renderlet.id()));
+
+        // renderlets that are no longer staged are forgotten
+        keys.update(&[], &slab.cpu_values());
+        assert!(keys.0.is_empty());
+    }
+
+    #[test]
+    fn blended_materials_draw_last() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let opaque = slab.new_value(Material::default());
+        let blended = slab.new_value(Material {
+            alpha_mode: AlphaMode::Blend,
+            ..Default::default()
+        });
+        let renderlets =
+            [blended.id(), opaque.id(), blended.id(), opaque.id()].map(|material_id| {
+                slab.new_value(Renderlet {
+                    material_id,
+                    ..Default::default()
+                })
+            });
+        let mut draws = StageDrawStrategy::Direct(renderlets.to_vec());
+        let mut keys = PipelineKeys::default();
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        let ordered = draws
+            .renderlets()
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        let expected = [1, 3, 0, 2].map(|i| renderlets[i].id());
+        assert_eq!(&expected, ordered.as_slice());
+        // only opaque renderlets are drawn in pipeline ranges
+        assert_eq!(
+            vec![(Topology::TriangleList, true, false, 0..2)],
+            draws.pipeline_ranges(&keys)
+        );
+        let blended_ids = |draws: &StageDrawStrategy, keys: &PipelineKeys| {
+            draws
+                .pass_renderlets(keys, DrawPass::Blended)
+                .iter()
+                .map(|r| r.id())
+                .collect::<Vec<_>>()
+        };
+        let expected = [0, 2].map(|i| renderlets[i].id());
+        assert_eq!(&expected, blended_ids(&draws, &keys).as_slice());
+
+        // the pass follows the material
+        blended.modify(|m| m.alpha_mode = AlphaMode::Opaque);
+        let _ = slab.upkeep(());
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        assert!(blended_ids(&draws, &keys).is_empty());
+        assert_eq!(
+            vec![(Topology::TriangleList, true, false, 0..4)],
+            draws.pipeline_ranges(&keys)
+        );
+    }
+
+    #[test]
+    fn transmissive_materials_draw_after_opaque() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let transmission = slab.new_value(Transmission::default());
+        let materials = [
+            slab.new_value(Material {
+                alpha_mode: AlphaMode::Blend,
+                ..Default::default()
+            }),
+            slab.new_value(Material {
+                transmission_id: transmission.id(),
+                ..Default::default()
+            }),
+            slab.new_value(Material::default()),
+            // transmission takes precedence over blending
+            slab.new_value(Material {
+                alpha_mode: AlphaMode::Blend,
+                transmission_id: transmission.id(),
+                ..Default::default()
+            }),
+        ];
+        let renderlets = materials.each_ref().map(|material| {
+            slab.new_value(Renderlet {
+                material_id: material.id(),
+                ..Default::default()
+            })
+        });
+        let mut draws = StageDrawStrategy::Direct(renderlets.to_vec());
+        let mut keys = PipelineKeys::default();
+        keys.update(&renderlets, &slab.cpu_values());
+        draws.order_by_pipeline(&keys);
+        let ordered = draws
+            .renderlets()
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        let expected = [2, 1, 3, 0].map(|i| renderlets[i].id());
+        assert_eq!(&expected, ordered.as_slice());
+        assert_eq!(
+            vec![(Topology::TriangleList, true, false, 0..1)],
+            draws.pipeline_ranges(&keys)
+        );
+        let transmissive = draws
+            .pass_renderlets(&keys, DrawPass::Transmissive)
+            .iter()
+            .map(|r| r.id())
+            .collect::<Vec<_>>();
+        let expected = [1, 3].map(|i| renderlets[i].id());
+        assert_eq!(&expected, transmissive.as_slice());
+    }
+
+    #[test]
+    fn renderlet_instances_resolve_from_instance_index() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let vertices = slab.new_array([Vertex::default().with_position([1.0, 0.0, 0.0])]);
+        let transform = slab.new_value(Transform {
+            scale: Vec3::splat(2.0),
+            ..Default::default()
+        });
+        let plain = slab.new_value(Renderlet {
+            vertices_array: vertices.array(),
+            transform_id: transform.id(),
+            ..Default::default()
+        });
+        let instanced = slab.new_value(plain.get());
+        let instances = slab.new_array([
+            Transform::default(),
+            Transform {
+                translation: Vec3::Y,
+                ..Default::default()
+            },
+        ]);
+        let slots = slab.new_array([instanced.id(); 2]);
+        instanced.modify(|r| {
+            r.instances = instances.array();
+            r.instance_slots = slots.array();
+        });
+
+        let plain_range = plain.get().get_instance_range(plain.id());
+        assert_eq!(plain.id().inner()..plain.id().inner() + 1, plain_range);
+        let instanced_range = instanced.get().get_instance_range(instanced.id());
+        assert_eq!(2, instanced_range.len());
+
+        let buffer = slab.upkeep(()).unwrap();
+        let data = buffer.lock().unwrap();
+        let (id, renderlet, instance) = Renderlet::read_instance(plain_range.start, &data);
+        assert_eq!((plain.id(), plain.get(), 0), (id, renderlet, instance));
+        let (_, transform) = renderlet.get_vertex_info(0, data.as_slice());
+        assert_eq!(Vec3::splat(2.0), transform.scale);
+
+        for (index, expected) in
+            instanced_range.zip([Vec3::new(2.0, 0.0, 0.0), Vec3::new(2.0, 2.0, 0.0)])
+        {
+            let (id, renderlet, instance) = Renderlet::read_instance(index, &data);
+            assert_eq!(instanced.id(), id);
+            assert_eq!(instanced.get(), renderlet);
+            let (vertex, transform) =
+                renderlet.get_instance_vertex_info(0, instance, data.as_slice());
+            let position = Mat4::from(transform).transform_point3(vertex.position);
+            assert_eq!(expected, position);
+        }
+    }
+
+    #[test]
+    fn renderlet_vertex_info_applies_morph_targets() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let vertices = slab.new_array([
+            Vertex::default().with_position([0.0, 0.0, 0.0]),
+            Vertex::default().with_position([1.0, 0.0, 0.0]),
+        ]);
+        let targets = [
+            slab.new_array([
+                MorphTarget {
+                    position: Vec3::Y,
+                    ..Default::default()
+                },
+                MorphTarget::default(),
+            ]),
+            slab.new_array([
+                MorphTarget::default(),
+                MorphTarget {
+                    position: Vec3::new(0.0, 2.0, 0.0),
+                    normal: Vec3::X,
+                    ..Default::default()
+                },
+            ]),
+        ];
+        let target_arrays = slab.new_array(targets.iter().map(|t| t.array()));
+        let weights = slab.new_array([1.0f32, 0.5]);
+        let morph = slab.new_value(Morph {
+            targets: target_arrays.array(),
+            weights: weights.array(),
+        });
+        let transform = slab.new_value(Transform::default());
+        let renderlet = Renderlet {
+            vertices_array: vertices.array(),
+            transform_id: transform.id(),
+            morph_id: morph.id(),
+            ..Default::default()
+        };
+        let buffer = slab.upkeep(()).unwrap();
+        let data = buffer.lock().unwrap();
+        let (a, _) = renderlet.get_vertex_info(0, data.as_slice());
+        assert_eq!(Vec3::Y, a.position);
+        let (b, _) = renderlet.get_vertex_info(1, data.as_slice());
+        assert_eq!(Vec3::new(1.0, 1.0, 0.0), b.position);
+        assert_eq!(Vec3::new(0.5, 0.0, 1.0), b.normal);
+    }
+
+    #[test]
+    fn renderlet_posed_positions_and_bounds_follow_skin() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let skinned = |position: [f32; 3]| Vertex {
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            ..Vertex::default().with_position(position)
+        };
+        let vertices = slab.new_array([
+            skinned([0.0, 0.0, 0.0]),
+            skinned([1.0, 0.0, 0.0]),
+            skinned([0.0, 1.0, 0.0]),
+        ]);
+        let joint = slab.new_value(Transform {
+            translation: Vec3::new(0.0, 0.0, 1000.0),
+            ..Default::default()
+        });
+        let joints = slab.new_array([joint.id()]);
+        let inverse_bind_matrices = slab.new_array([Mat4::IDENTITY]);
+        let skin = slab.new_value(Skin {
+            joints: joints.array(),
+            inverse_bind_matrices: inverse_bind_matrices.array(),
+            ..Default::default()
+        });
+        let transform = slab.new_value(Transform {
+            translation: Vec3::X,
+            ..Default::default()
+        });
+        let camera = slab.new_value(Camera::default_perspective(100.0, 100.0));
+        let mut renderlet = Renderlet {
+            vertices_array: vertices.array(),
+            transform_id: transform.id(),
+            skin_id: skin.id(),
+            camera_id: camera.id(),
+            bounds: Aabb::new(Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0)),
+            ..Default::default()
+        };
+        let buffer = slab.upkeep(()).unwrap();
+        let data = buffer.lock().unwrap();
+
+        let positions = renderlet.get_posed_positions(0, data.as_slice());
+        assert_eq!(
+            vec![
+                Vec3::new(1.0, 0.0, 1000.0),
+                Vec3::new(2.0, 0.0, 1000.0),
+                Vec3::new(1.0, 1.0, 1000.0)
+            ],
+            positions
+        );
+
+        // rest pose bounds of skinned renderlets are not used for culling
+        assert!(!renderlet.is_outside_camera_view(&data));
+        let bounds = renderlet.get_posed_bounds(data.as_slice());
+        assert_eq!(
+            Aabb::new(Vec3::new(0.0, 0.0, 1000.0), Vec3::new(1.0, 1.0, 1000.0)),
+            bounds
+        );
+        renderlet.bounds = bounds;
+        renderlet.bounds_are_posed = true;
+        assert!(renderlet.is_outside_camera_view(&data));
+    }
+
+    #[test]
+    fn posed_bounds_follow_pose() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let skinned = |position: [f32; 3]| Vertex {
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            ..Vertex::default().with_position(position)
+        };
+        let vertices = slab.new_array([
+            skinned([0.0, 0.0, 0.0]),
+            skinned([1.0, 0.0, 0.0]),
+            skinned([0.0, 1.0, 0.0]),
+        ]);
+        let joint = slab.new_value(Transform {
+            translation: Vec3::new(0.0, 0.0, 10.0),
+            ..Default::default()
+        });
+        let joints = slab.new_array([joint.id()]);
+        let inverse_bind_matrices = slab.new_array([Mat4::IDENTITY]);
+        let skin = slab.new_value(Skin {
+            joints: joints.array(),
+            inverse_bind_matrices: inverse_bind_matrices.array(),
+            ..Default::default()
+        });
+        let rest_bounds = Aabb::new(Vec3::ZERO, Vec3::new(1.0, 1.0, 0.0));
+        let posed = slab.new_value(Renderlet {
+            vertices_array: vertices.array(),
+            skin_id: skin.id(),
+            bounds: rest_bounds,
+            bounds_are_posed: true,
+            ..Default::default()
+        });
+        let unposed = slab.new_value(Renderlet {
+            bounds_are_posed: false,
+            ..posed.get()
+        });
+        let renderlets = [posed.clone(), unposed.clone()];
+        let mut posed_bounds = PosedBounds::default();
+        let _ = slab.upkeep(());
+
+        assert!(posed_bounds.update(&renderlets, &slab.cpu_values()));
+        assert_eq!(
+            Aabb::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(1.0, 1.0, 10.0)),
+            posed.get().bounds
+        );
+        assert_eq!(rest_bounds, unposed.get().bounds);
+        let _ = slab.upkeep(());
+        assert!(!posed_bounds.update(&renderlets, &slab.cpu_values()));
+
+        // bounds are only recomputed once the joint has been written
+        joint.modify(|t| t.translation = Vec3::new(0.0, 0.0, -10.0));
+        assert!(!posed_bounds.update(&renderlets, &slab.cpu_values()));
+        let _ = slab.upkeep(());
+        assert!(posed_bounds.update(&renderlets, &slab.cpu_values()));
+        assert_eq!(
+            Aabb::new(Vec3::new(0.0, 0.0, -10.0), Vec3::new(1.0, 1.0, -10.0)),
+            posed.get().bounds
+        );
+        assert_eq!(rest_bounds, unposed.get().bounds);
+    }
+
+    #[test]
+    fn dropped_instances_stop_instancing() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let renderlet = slab.new_value(Renderlet::default());
+        let instances = RenderletInstances::new(
+            &mut slab,
+            &renderlet,
+            [Vec3::X, Vec3::Y, Vec3::Z].map(|translation| Transform {
+                translation,
+                ..Default::default()
+            }),
+        );
+        assert_eq!(3, renderlet.get().get_instance_count());
+
+        let clone = instances.clone();
+        drop(instances);
+        assert_eq!(3, renderlet.get().get_instance_count());
+
+        drop(clone);
+        let r = renderlet.get();
+        assert_eq!(1, r.get_instance_count());
+        assert!(r.instance_slots.is_null());
+        assert_eq!(
+            renderlet.id().inner()..renderlet.id().inner() + 1,
+            r.get_instance_range(renderlet.id())
+        );
+    }
+
+    #[test]
+    fn unsupported_sample_counts_fall_back() {
+        use wgpu::TextureFormatFeatureFlags as Flags;
+        let x4 = Flags::MULTISAMPLE_X4;
+        let x2_x4_x8 = Flags::MULTISAMPLE_X2 | Flags::MULTISAMPLE_X4 | Flags::MULTISAMPLE_X8;
+        assert_eq!(1, supported_sample_count(0, &[x4, x2_x4_x8]));
+        assert_eq!(1, supported_sample_count(1, &[x4, x2_x4_x8]));
+        assert_eq!(1, supported_sample_count(2, &[x4, x2_x4_x8]));
+        assert_eq!(1, supported_sample_count(3, &[x4, x2_x4_x8]));
+        assert_eq!(4, supported_sample_count(5, &[x4, x2_x4_x8]));
+        assert_eq!(4, supported_sample_count(16, &[x4, x2_x4_x8]));
+        assert_eq!(8, supported_sample_count(16, &[x2_x4_x8]));
+        assert_eq!(1, supported_sample_count(4, &[Flags::empty()]));
+    }
 }
@@ -13,15 +13,22 @@ use crate::{
     camera::Camera,
     pbr::{
         light::{DirectionalLight, Light, LightStyle, PointLight, SpotLight},
-        Material,
+        AlphaMode, Clearcoat, Material, Sheen, Specular, TextureTransform, Transmission,
     },
     slab::*,
-    stage::{NestedTransform, Renderlet, Skin, Stage, Vertex},
+    stage::{
+        JointInfluences, Morph, MorphTarget, NestedTransform, Renderlet, RenderletInstances, Skin,
+        Stage, Topology, Vertex,
+    },
     transform::Transform,
 };
 
+mod accessor;
 mod anime;
+mod export;
+mod mixer;
 pub use anime::*;
+pub use mixer::*;
 
 #[derive(Debug, Snafu)]
 pub enum StageGltfError {
@@ -58,6 +65,9 @@ pub enum StageGltfError {
     #[snafu(display("Missing light with index {index}"))]
     MissingLight { index: usize },
 
+    /// Deprecated, as all glTF primitive modes are supported.
+    ///
+    /// This is never returned, and will be removed in a future release.
     #[snafu(display("Unsupported primitive mode: {:?}", mode))]
     PrimitiveMode { mode: gltf::mesh::Mode },
 
@@ -81,6 +91,15 @@ pub enum StageGltfError {
 
     #[snafu(display("{source}"))]
     Animation { source: anime::AnimationError },
+
+    #[snafu(display("Could not encode the image of texture {index}: {source}"))]
+    EncodeImage {
+        index: usize,
+        source: image::ImageError,
+    },
+
+    #[snafu(display("Could not write glb: {source}"))]
+    WriteGlb { source: std::io::Error },
 }
 
 impl From<gltf::Error> for StageGltfError {
@@ -121,6 +140,148 @@ impl TextureAddressMode {
     }
 }
 
+impl AlphaMode {
+    fn from_gltf(mode: gltf::material::AlphaMode) -> AlphaMode {
+        match mode {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
+impl TextureTransform {
+    fn from_gltf(transform: gltf::texture::TextureTransform) -> TextureTransform {
+        TextureTransform {
+            offset: transform.offset().into(),
+            rotation: transform.rotation(),
+            scale: transform.scale().into(),
+        }
+    }
+
+    fn from_gltf_json(
+        transform: gltf::json::extensions::texture::TextureTransform,
+    ) -> TextureTransform {
+        TextureTransform {
+            offset: transform.offset.0.into(),
+            rotation: transform.rotation.0,
+            scale: transform.scale.0.into(),
+        }
+    }
+}
+
+/// Returns the texture coordinate set and UV transform of a texture, which
+/// may be given by the `KHR_texture_transform` extension.
+fn tex_coord_and_transform(info: &gltf::texture::Info) -> (u32, TextureTransform) {
+    if let Some(transform) = info.texture_transform() {
+        (
+            transform.tex_coord().unwrap_or(info.tex_coord()),
+            TextureTransform::from_gltf(transform),
+        )
+    } else {
+        (info.tex_coord(), TextureTransform::default())
+    }
+}
+
+/// Returns the texture coordinate set and UV transform of a normal or
+/// occlusion texture.
+///
+/// `gltf` only exposes the `KHR_texture_transform` extension of these textures
+/// as JSON.
+fn tex_coord_and_transform_json(
+    tex_coord: u32,
+    extension: Option<&serde_json::Value>,
+) -> (u32, TextureTransform) {
+    let transform = extension.and_then(|value| {
+        serde_json::from_value::<gltf::json::extensions::texture::TextureTransform>(value.clone())
+            .map_err(|e| log::error!("could not read KHR_texture_transform: {e}"))
+            .ok()
+    });
+    if let Some(transform) = transform {
+        (
+            transform.tex_coord.unwrap_or(tex_coord),
+            TextureTransform::from_gltf_json(transform),
+        )
+    } else {
+        (tex_coord, TextureTransform::default())
+    }
+}
+
+/// Returns the texture, texture coordinate set and UV transform of a texture
+/// info object of a material extension, which `gltf` only exposes as JSON.
+fn texture_from_json(
+    info: Option<&serde_json::Value>,
+    textures: Array<AtlasTexture>,
+) -> (Id<AtlasTexture>, u32, TextureTransform) {
+    let index = info
+        .and_then(|info| info.get("index"))
+        .and_then(serde_json::Value::as_u64)
+        .map(|index| index as usize);
+    match (info, index) {
+        (Some(info), Some(index)) if index < textures.len() => {
+            let tex_coord = info
+                .get("texCoord")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or_default() as u32;
+            let (tex_coord, transform) = tex_coord_and_transform_json(
+                tex_coord,
+                info.get("extensions")
+                    .and_then(|extensions| extensions.get("KHR_texture_transform")),
+            );
+            (textures.at(index), tex_coord, transform)
+        }
+        (Some(_), _) => {
+            log::error!("invalid texture index {index:?} in material extension");
+            (Id::NONE, 0, TextureTransform::default())
+        }
+        _ => (Id::NONE, 0, TextureTransform::default()),
+    }
+}
+
+/// Returns the indices of the textures of a material's extensions that hold
+/// sRGB encoded colors.
+fn extension_color_texture_indices(material: &gltf::Material) -> Vec<usize> {
+    let sheen = material
+        .extension_value("KHR_materials_sheen")
+        .and_then(|extension| extension.get("sheenColorTexture"))
+        .and_then(|info| info.get("index"))
+        .and_then(serde_json::Value::as_u64)
+        .map(|index| index as usize);
+    let specular = material
+        .specular()
+        .and_then(|specular| specular.specular_color_texture())
+        .map(|info| info.texture().index());
+    sheen.into_iter().chain(specular).collect()
+}
+
+/// Marks the image of a texture as sRGB encoded, so it gets converted to
+/// linear color when it's packed into the atlas.
+fn apply_linear_transfer(
+    document: &gltf::Document,
+    texture_index: usize,
+    repacking: &mut RepackPreview,
+    atlas_offset: usize,
+) -> Result<(), StageGltfError> {
+    let Some(texture) = document.textures().nth(texture_index) else {
+        log::error!("missing texture {texture_index}");
+        return Ok(());
+    };
+    let image_index = texture.source().index();
+    let image = repacking
+        .get_mut(image_index + atlas_offset)
+        .context(MissingImageSnafu {
+            index: image_index,
+            offset: atlas_offset,
+        })?
+        .as_scene_img_mut()
+        .context(WrongImageSnafu {
+            index: image_index,
+            offset: atlas_offset,
+        })?;
+    image.apply_linear_transfer = true;
+    Ok(())
+}
+
 pub fn get_vertex_count(primitive: &gltf::Primitive<'_>) -> u32 {
     if let Some(indices) = primitive.indices() {
         let count = indices.count() as u32;
@@ -176,95 +337,120 @@ impl Material {
     ) -> Result<Material, StageGltfError> {
         let name = material.name().map(String::from);
         log::trace!("loading material {:?} {name:?}", material.index());
+        let alpha_mode = AlphaMode::from_gltf(material.alpha_mode());
+        let alpha_cutoff = material.alpha_cutoff().unwrap_or(0.5);
+        let double_sided = material.double_sided();
+        let ior = material.ior().unwrap_or(1.5);
         let pbr = material.pbr_metallic_roughness();
         let material = if material.unlit() {
             log::trace!("  is unlit");
-            let (albedo_texture, albedo_tex_coord) = if let Some(info) = pbr.base_color_texture() {
-                let texture = info.texture();
-                let index = texture.index();
-                let tex_id = textures.at(index);
-                // The index of the image in the original gltf document
-                let image_index = texture.source().index();
-                // Update the image to ensure it gets transferred correctly
-                let image = repacking
-                    .get_mut(atlas_offset + image_index)
-                    .context(MissingImageSnafu {
-                        index: image_index,
-                        offset: atlas_offset,
-                    })?
-                    .as_scene_img_mut()
-                    .context(WrongImageSnafu {
-                        index: image_index,
-                        offset: atlas_offset,
-                    })?;
-                image.apply_linear_transfer = true;
-                (tex_id, info.tex_coord())
-            } else {
-                (Id::NONE, 0)
-            };
+            let (albedo_texture, albedo_tex_coord, albedo_tex_transform) =
+                if let Some(info) = pbr.base_color_texture() {
+                    let texture = info.texture();
+                    let index = texture.index();
+                    let tex_id = textures.at(index);
+                    // The index of the image in the original gltf document
+                    let image_index = texture.source().index();
+                    // Update the image to ensure it gets transferred correctly
+                    let image = repacking
+                        .get_mut(atlas_offset + image_index)
+                        .context(MissingImageSnafu {
+                            index: image_index,
+                            offset: atlas_offset,
+                        })?
+                        .as_scene_img_mut()
+                        .context(WrongImageSnafu {
+                            index: image_index,
+                            offset: atlas_offset,
+                        })?;
+                    image.apply_linear_transfer = true;
+                    let (tex_coord, transform) = tex_coord_and_transform(&info);
+                    (tex_id, tex_coord, transform)
+                } else {
+                    (Id::NONE, 0, TextureTransform::default())
+                };
 
             Material {
                 albedo_texture_id: albedo_texture,
                 albedo_tex_coord,
+                albedo_tex_transform,
                 albedo_factor: pbr.base_color_factor().into(),
                 ..Default::default()
             }
         } else {
             log::trace!("  is pbr");
             let albedo_factor: Vec4 = pbr.base_color_factor().into();
-            let (albedo_texture, albedo_tex_coord) = if let Some(info) = pbr.base_color_texture() {
-                let texture = info.texture();
-                let index = texture.index();
-                let tex_id = textures.at(index);
-                let image_index = texture.source().index();
-                // Update the image to ensure it gets transferred correctly
-                let image = repacking
-                    .get_mut(image_index + atlas_offset)
-                    .context(MissingImageSnafu {
-                        index: image_index,
-                        offset: atlas_offset,
-                    })?
-                    .as_scene_img_mut()
-                    .context(WrongImageSnafu {
-                        index: image_index,
-                        offset: atlas_offset,
-                    })?;
-                image.apply_linear_transfer = true;
-                (tex_id, info.tex_coord())
-            } else {
-                (Id::NONE, 0)
-            };
+            let (albedo_texture, albedo_tex_coord, albedo_tex_transform) =
+                if let Some(info) = pbr.base_color_texture() {
+                    let texture = info.texture();
+                    let index = texture.index();
+                    let tex_id = textures.at(index);
+                    let image_index = texture.source().index();
+                    // Update the image to ensure it gets transferred correctly
+                    let image = repacking
+                        .get_mut(image_index + atlas_offset)
+                        .context(MissingImageSnafu {
+                            index: image_index,
+                            offset: atlas_offset,
+                        })?
+                        .as_scene_img_mut()
+                        .context(WrongImageSnafu {
+                            index: image_index,
+                            offset: atlas_offset,
+                        })?;
+                    image.apply_linear_transfer = true;
+                    let (tex_coord, transform) = tex_coord_and_transform(&info);
+                    (tex_id, tex_coord, transform)
+                } else {
+                    (Id::NONE, 0, TextureTransform::default())
+                };
 
             let (
                 metallic_factor,
                 roughness_factor,
                 metallic_roughness_texture,
                 metallic_roughness_tex_coord,
+                metallic_roughness_tex_transform,
             ) = if let Some(info) = pbr.metallic_roughness_texture() {
                 let index = info.texture().index();
                 let tex_id = textures.at(index);
-                (1.0, 1.0, tex_id, info.tex_coord())
+                let (tex_coord, transform) = tex_coord_and_transform(&info);
+                (1.0, 1.0, tex_id, tex_coord, transform)
             } else {
-                (pbr.metallic_factor(), pbr.roughness_factor(), Id::NONE, 0)
+                (
+                    pbr.metallic_factor(),
+                    pbr.roughness_factor(),
+                    Id::NONE,
+                    0,
+                    TextureTransform::default(),
+                )
             };
 
-            let (normal_texture, normal_tex_coord) =
+            let (normal_texture, normal_tex_coord, normal_tex_transform) =
                 if let Some(norm_tex) = material.normal_texture() {
                     let tex_id = textures.at(norm_tex.texture().index());
-                    (tex_id, norm_tex.tex_coord())
+                    let (tex_coord, transform) = tex_coord_and_transform_json(
+                        norm_tex.tex_coord(),
+                        norm_tex.extension_value("KHR_texture_transform"),
+                    );
+                    (tex_id, tex_coord, transform)
                 } else {
-                    (Id::NONE, 0)
+                    (Id::NONE, 0, TextureTransform::default())
                 };
 
-            let (ao_strength, ao_texture, ao_tex_coord) =
+            let (ao_strength, ao_texture, ao_tex_coord, ao_tex_transform) =
                 if let Some(occlusion_tex) = material.occlusion_texture() {
                     let tex_id = textures.at(occlusion_tex.texture().index());
-                    (occlusion_tex.strength(), tex_id, occlusion_tex.tex_coord())
+                    let (tex_coord, transform) = tex_coord_and_transform_json(
+                        occlusion_tex.tex_coord(),
+                        occlusion_tex.extension_value("KHR_texture_transform"),
+                    );
+                    (occlusion_tex.strength(), tex_id, tex_coord, transform)
                 } else {
-                    (0.0, Id::NONE, 0)
+                    (0.0, Id::NONE, 0, TextureTransform::default())
                 };
 
-            let (emissive_texture, emissive_tex_coord) =
+            let (emissive_texture, emissive_tex_coord, emissive_tex_transform) =
                 if let Some(emissive_tex) = material.emissive_texture() {
                     let texture = emissive_tex.texture();
                     let index = texture.index();
@@ -283,9 +469,10 @@ impl Material {
                             offset: atlas_offset,
                         })?;
                     image.apply_linear_transfer = true;
-                    (tex_id, emissive_tex.tex_coord())
+                    let (tex_coord, transform) = tex_coord_and_transform(&emissive_tex);
+                    (tex_id, tex_coord, transform)
                 } else {
-                    (Id::NONE, 0)
+                    (Id::NONE, 0, TextureTransform::default())
                 };
             let emissive_factor = Vec3::from(material.emissive_factor());
             let emissive_strength_multiplier = material.emissive_strength().unwrap_or(1.0);
@@ -307,20 +494,268 @@ impl Material {
                 emissive_strength_multiplier,
                 emissive_texture_id: emissive_texture,
                 emissive_tex_coord,
+                albedo_tex_transform,
+                metallic_roughness_tex_transform,
+                normal_tex_transform,
+                ao_tex_transform,
+                emissive_tex_transform,
                 has_lighting: true,
                 ..Default::default()
             }
         };
-        Ok(material)
+        Ok(Material {
+            alpha_mode,
+            alpha_cutoff,
+            double_sided,
+            ior,
+            ..material
+        })
+    }
+}
+
+impl Clearcoat {
+    /// Read the `KHR_materials_clearcoat` extension of a [`gltf::Material`],
+    /// if it has one.
+    pub fn from_gltf(
+        material: &gltf::Material,
+        textures: Array<AtlasTexture>,
+    ) -> Option<Clearcoat> {
+        let extension = material.extension_value("KHR_materials_clearcoat")?;
+        let factor = |key: &str| {
+            extension
+                .get(key)
+                .and_then(serde_json::Value::as_f64)
+                .unwrap_or_default() as f32
+        };
+        let (texture_id, tex_coord, tex_transform) =
+            texture_from_json(extension.get("clearcoatTexture"), textures);
+        let (roughness_texture_id, roughness_tex_coord, roughness_tex_transform) =
+            texture_from_json(extension.get("clearcoatRoughnessTexture"), textures);
+        let (normal_texture_id, normal_tex_coord, normal_tex_transform) =
+            texture_from_json(extension.get("clearcoatNormalTexture"), textures);
+        Some(Clearcoat {
+            factor: factor("clearcoatFactor"),
+            roughness_factor: factor("clearcoatRoughnessFactor"),
+            texture_id,
+            roughness_texture_id,
+            normal_texture_id,
+            tex_coord,
+            roughness_tex_coord,
+            normal_tex_coord,
+            tex_transform,
+            roughness_tex_transform,
+            normal_tex_transform,
+        })
+    }
+}
+
+impl Transmission {
+    /// Read the `KHR_materials_transmission` and `KHR_materials_volume`
+    /// extensions of a [`gltf::Material`], if it is transmissive.
+    pub fn from_gltf(
+        material: &gltf::Material,
+        textures: Array<AtlasTexture>,
+    ) -> Option<Transmission> {
+        let transmission = material.transmission()?;
+        let (texture_id, tex_coord, tex_transform) =
+            if let Some(info) = transmission.transmission_texture() {
+                let (tex_coord, transform) = tex_coord_and_transform(&info);
+                (textures.at(info.texture().index()), tex_coord, transform)
+            } else {
+                (Id::NONE, 0, TextureTransform::default())
+            };
+        let mut layer = Transmission {
+            factor: transmission.transmission_factor(),
+            texture_id,
+            tex_coord,
+            tex_transform,
+            ..Default::default()
+        };
+        if let Some(volume) = material.volume() {
+            layer.thickness_factor = volume.thickness_factor();
+            // an infinite distance means no attenuation, which `f32::MAX`
+            // gives just as well without producing NaNs
+            layer.attenuation_distance = volume.attenuation_distance().min(f32::MAX);
+            layer.attenuation_color = volume.attenuation_color().into();
+            if let Some(info) = volume.thickness_texture() {
+                let (tex_coord, transform) = tex_coord_and_transform(&info);
+                layer.thickness_texture_id = textures.at(info.texture().index());
+                layer.thickness_tex_coord = tex_coord;
+                layer.thickness_tex_transform = transform;
+            }
+        }
+        Some(layer)
+    }
+}
+
+impl Sheen {
+    /// Read the `KHR_materials_sheen` extension of a [`gltf::Material`], if
+    /// it has one.
+    pub fn from_gltf(material: &gltf::Material, textures: Array<AtlasTexture>) -> Option<Sheen> {
+        let extension = material.extension_value("KHR_materials_sheen")?;
+        let color_factor = extension
+            .get("sheenColorFactor")
+            .and_then(|factor| serde_json::from_value::<[f32; 3]>(factor.clone()).ok())
+            .unwrap_or_default();
+        let roughness_factor = extension
+            .get("sheenRoughnessFactor")
+            .and_then(serde_json::Value::as_f64)
+            .unwrap_or_default() as f32;
+        let (color_texture_id, color_tex_coord, color_tex_transform) =
+            texture_from_json(extension.get("sheenColorTexture"), textures);
+        let (roughness_texture_id, roughness_tex_coord, roughness_tex_transform) =
+            texture_from_json(extension.get("sheenRoughnessTexture"), textures);
+        Some(Sheen {
+            color_factor: color_factor.into(),
+            roughness_factor,
+            color_texture_id,
+            roughness_texture_id,
+            color_tex_coord,
+            roughness_tex_coord,
+            color_tex_transform,
+            roughness_tex_transform,
+        })
+    }
+}
+
+impl Specular {
+    /// Read the `KHR_materials_specular` extension of a [`gltf::Material`],
+    /// if it has one.
+    pub fn from_gltf(material: &gltf::Material, textures: Array<AtlasTexture>) -> Option<Specular> {
+        let specular = material.specular()?;
+        let texture = |info: Option<gltf::texture::Info>| {
+            if let Some(info) = info {
+                let (tex_coord, transform) = tex_coord_and_transform(&info);
+                (textures.at(info.texture().index()), tex_coord, transform)
+            } else {
+                (Id::NONE, 0, TextureTransform::default())
+            }
+        };
+        let (texture_id, tex_coord, tex_transform) = texture(specular.specular_texture());
+        let (color_texture_id, color_tex_coord, color_tex_transform) =
+            texture(specular.specular_color_texture());
+        Some(Specular {
+            factor: specular.specular_factor(),
+            color_factor: specular.specular_color_factor().into(),
+            texture_id,
+            color_texture_id,
+            tex_coord,
+            color_tex_coord,
+            tex_transform,
+            color_tex_transform,
+        })
+    }
+}
+
+/// Converts the indices of a primitive with the given mode into a list of
+/// the returned [`Topology`].
+///
+/// Strips, fans and loops are converted into lists. Non-indexed strips, fans
+/// and loops become indexed. Trailing indices of lists that don't form a
+/// whole primitive are dropped.
+fn primitive_topology_and_indices(
+    mode: gltf::mesh::Mode,
+    indices: Option<Vec<u32>>,
+    vertex_count: usize,
+) -> (Topology, Vec<u32>) {
+    use gltf::mesh::Mode;
+
+    let sequence = || {
+        indices
+            .clone()
+            .unwrap_or_else(|| (0..vertex_count as u32).collect())
+    };
+    let list = |per_primitive: usize| {
+        let mut indices = indices.clone().unwrap_or_default();
+        let remainder = indices.len() % per_primitive;
+        if remainder != 0 {
+            log::warn!("dropping {remainder} trailing indices of a {mode:?} primitive");
+            indices.truncate(indices.len() - remainder);
+        }
+        indices
+    };
+    match mode {
+        Mode::Points => (Topology::PointList, indices.unwrap_or_default()),
+        Mode::Lines => (Topology::LineList, list(2)),
+        Mode::LineStrip => {
+            let strip = sequence();
+            let lines = strip.windows(2).flatten().copied().collect();
+            (Topology::LineList, lines)
+        }
+        Mode::LineLoop => {
+            let strip = sequence();
+            let mut lines = strip.windows(2).flatten().copied().collect::<Vec<_>>();
+            if let [first, .., last] = strip.as_slice() {
+                lines.extend([*last, *first]);
+            }
+            (Topology::LineList, lines)
+        }
+        Mode::Triangles => (Topology::TriangleList, list(3)),
+        Mode::TriangleStrip => {
+            let strip = sequence();
+            let triangles = strip
+                .windows(3)
+                .enumerate()
+                .flat_map(|(i, w)| {
+                    // every other triangle is flipped to keep the winding order
+                    if i % 2 == 0 {
+                        [w[0], w[1], w[2]]
+                    } else {
+                        [w[0], w[2], w[1]]
+                    }
+                })
+                .collect();
+            (Topology::TriangleList, triangles)
+        }
+        Mode::TriangleFan => {
+            let fan = sequence();
+            let triangles = fan
+                .windows(2)
+                .skip(1)
+                .flat_map(|w| [w[0], w[1], fan[0]])
+                .collect();
+            (Topology::TriangleList, triangles)
+        }
     }
 }
 
+/// Returns the vertex indices of each triangle of a triangle list, as
+/// returned by [`primitive_topology_and_indices`].
+///
+/// Empty indices mean the list isn't indexed. Trailing vertices of lists that
+/// aren't indexed and don't form a whole triangle are skipped, just like
+/// trailing indices are dropped.
+fn list_triangles(indices: &[u32], vertex_count: usize) -> Vec<[usize; 3]> {
+    let indices = if indices.is_empty() {
+        (0..vertex_count).collect::<Vec<_>>()
+    } else {
+        indices.iter().map(|i| *i as usize).collect::<Vec<_>>()
+    };
+    indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct GltfPrimitive {
+    /// How the vertices are assembled into primitives.
+    ///
+    /// Strips, fans and loops are converted into lists when loading.
+    pub topology: Topology,
     pub indices: HybridArray<u32>,
     pub vertices: HybridArray<Vertex>,
     pub bounding_box: (Vec3, Vec3),
     pub material: Id<Material>,
+    pub double_sided: bool,
+    /// Displacements of each morph target, one per vertex.
+    pub morph_targets: Vec<HybridArray<MorphTarget>>,
+    /// Arrays of `morph_targets`, as seen by shaders.
+    pub morph_target_arrays: HybridArray<Array<MorphTarget>>,
+    /// Joint influences of each vertex beyond its first four, if any.
+    ///
+    /// See [`Renderlet::extra_influences`].
+    pub extra_influences: HybridArray<JointInfluences>,
 }
 
 impl GltfPrimitive {
@@ -335,100 +770,115 @@ impl GltfPrimitive {
             .index()
             .map(|index| materials.array().at(index))
             .unwrap_or_default();
+        let double_sided = primitive.material().double_sided();
 
         let reader = primitive.reader(|buffer| {
             let data = buffer_data.get(buffer.index())?;
             Some(data.0.as_slice())
         });
 
-        let indices = reader
-            .read_indices()
-            .map(|is| {
-                let indices = is.into_u32().collect::<Vec<_>>();
-                assert_eq!(indices.len() % 3, 0, "indices do not form triangles");
-                indices
-            })
-            .unwrap_or_default();
+        // Positions, normals, tangents, UVs and morph targets may be quantized, so
+        // they are decoded by `accessor` instead of the reader
+        let positions =
+            accessor::read_attribute::<3>(&primitive, gltf::Semantic::Positions, buffer_data)
+                .unwrap_or_default()
+                .into_iter()
+                .map(Vec3::from)
+                .collect::<Vec<_>>();
 
-        let positions = reader
-            .read_positions()
-            .into_iter()
-            .flat_map(|ps| ps.map(Vec3::from))
-            .collect::<Vec<_>>();
+        let (topology, indices) = primitive_topology_and_indices(
+            primitive.mode(),
+            reader.read_indices().map(|is| is.into_u32().collect()),
+            positions.len(),
+        );
+        log::debug!("  topology: {topology:?}");
 
-        let uv0s = reader
-            .read_tex_coords(0)
-            .into_iter()
-            .flat_map(|uvs| uvs.into_f32().map(Vec2::from))
-            .chain(std::iter::repeat(Vec2::ZERO))
-            .take(positions.len())
-            .collect::<Vec<_>>();
+        let uv0s =
+            accessor::read_attribute::<2>(&primitive, gltf::Semantic::TexCoords(0), buffer_data)
+                .into_iter()
+                .flatten()
+                .map(Vec2::from)
+                .chain(std::iter::repeat(Vec2::ZERO))
+                .take(positions.len())
+                .collect::<Vec<_>>();
 
-        let uv1s = reader
-            .read_tex_coords(0)
-            .into_iter()
-            .flat_map(|uvs| uvs.into_f32().map(Vec2::from))
-            .chain(std::iter::repeat(Vec2::ZERO))
-            .take(positions.len());
+        let uv1s =
+            accessor::read_attribute::<2>(&primitive, gltf::Semantic::TexCoords(1), buffer_data)
+                .into_iter()
+                .flatten()
+                .map(Vec2::from)
+                .chain(std::iter::repeat(Vec2::ZERO))
+                .take(positions.len());
 
         let mut normals = vec![Vec3::Z; positions.len()];
-        if let Some(ns) = reader.read_normals() {
-            let ns = ns.map(Vec3::from).collect::<Vec<_>>();
+        if let Some(ns) =
+            accessor::read_attribute::<3>(&primitive, gltf::Semantic::Normals, buffer_data)
+        {
+            let ns = ns.into_iter().map(Vec3::from).collect::<Vec<_>>();
             debug_assert_eq!(positions.len(), ns.len());
             normals = ns;
-        } else {
+        } else if topology == Topology::TriangleList {
             log::trace!("    generating normals");
-
-            let indices = if indices.is_empty() {
-                (0..positions.len() as u32).collect::<Vec<_>>()
-            } else {
-                indices.iter().copied().collect::<Vec<_>>()
-            };
-
-            indices.chunks(3).for_each(|chunk| match chunk {
-                [i, j, k] => {
-                    let a = positions[*i as usize];
-                    let b = positions[*j as usize];
-                    let c = positions[*k as usize];
-                    let n = Vertex::generate_normal(a, b, c);
-                    normals[*i as usize] = n;
-                    normals[*j as usize] = n;
-                    normals[*k as usize] = n;
-                }
-                _ => panic!("not triangles!"),
-            });
+            for [i, j, k] in list_triangles(&indices, positions.len()) {
+                let n = Vertex::generate_normal(positions[i], positions[j], positions[k]);
+                normals[i] = n;
+                normals[j] = n;
+                normals[k] = n;
+            }
         }
 
         let mut tangents = vec![Vec4::ZERO; positions.len()];
-        if let Some(ts) = reader.read_tangents() {
-            let ts = ts.map(Vec4::from).collect::<Vec<_>>();
+        if let Some(ts) =
+            accessor::read_attribute::<4>(&primitive, gltf::Semantic::Tangents, buffer_data)
+        {
+            let ts = ts.into_iter().map(Vec4::from).collect::<Vec<_>>();
             debug_assert_eq!(positions.len(), ts.len());
             tangents = ts;
-        } else {
+        } else if topology == Topology::TriangleList {
             log::trace!("    generating tangents");
-            let indices = if indices.is_empty() {
-                (0..positions.len() as u32).collect::<Vec<_>>()
-            } else {
-                indices.iter().copied().collect::<Vec<_>>()
-            };
-
-            indices.chunks(3).for_each(|chunk| match chunk {
-                [i, j, k] => {
-                    let a = positions[*i as usize];
-                    let b = positions[*j as usize];
-                    let c = positions[*k as usize];
-                    let a_uv = uv0s[*i as usize];
-                    let b_uv = uv0s[*j as usize];
-                    let c_uv = uv0s[*k as usize];
-
-                    let t = Vertex::generate_tangent(a, a_uv, b, b_uv, c, c_uv);
-                    tangents[*i as usize] = t;
-                    tangents[*j as usize] = t;
-                    tangents[*k as usize] = t;
-                }
-                _ => panic!("not triangles!"),
-            });
+            for [i, j, k] in list_triangles(&indices, positions.len()) {
+                let a = positions[i];
+                let b = positions[j];
+                let c = positions[k];
+                let a_uv = uv0s[i];
+                let b_uv = uv0s[j];
+                let c_uv = uv0s[k];
+
+                let t = Vertex::generate_tangent(a, a_uv, b, b_uv, c, c_uv);
+                tangents[i] = t;
+                tangents[j] = t;
+                tangents[k] = t;
+            }
         }
+        let read_displacements = |accessor: Option<gltf::Accessor>| {
+            accessor
+                .and_then(|accessor| accessor::read_f32s::<3>(&accessor, buffer_data))
+                .into_iter()
+                .flatten()
+                .map(Vec3::from)
+        };
+        let morph_targets = primitive
+            .morph_targets()
+            .map(|target| {
+                let ps = read_displacements(target.positions());
+                let ns = read_displacements(target.normals());
+                let ts = read_displacements(target.tangents());
+                let targets = ps
+                    .chain(std::iter::repeat(Vec3::ZERO))
+                    .zip(ns.chain(std::iter::repeat(Vec3::ZERO)))
+                    .zip(ts.chain(std::iter::repeat(Vec3::ZERO)))
+                    .take(positions.len())
+                    .map(|((position, normal), tangent)| MorphTarget {
+                        position,
+                        normal,
+                        tangent,
+                    });
+                stage.new_array(targets)
+            })
+            .collect::<Vec<_>>();
+        log::debug!("  {} morph targets", morph_targets.len());
+        let morph_target_arrays = stage.new_array(morph_targets.iter().map(|t| t.array()));
+
         let colors = reader
             .read_colors(0)
             .into_iter()
@@ -436,28 +886,76 @@ impl GltfPrimitive {
             .chain(std::iter::repeat(Vec4::ONE))
             .take(positions.len());
 
-        let joints = reader
-            .read_joints(0)
-            .into_iter()
-            .flat_map(|js| {
-                js.into_u16()
-                    .map(|[a, b, c, d]| [a as u32, b as u32, c as u32, d as u32])
-            })
-            .chain(std::iter::repeat([u32::MAX; 4]))
-            .take(positions.len());
-        let joints = joints.collect::<Vec<_>>();
+        // Each set of joints and weights holds four influences per vertex
+        let mut joint_sets = vec![];
+        let mut weight_sets = vec![];
+        while let Some(joints) = reader.read_joints(joint_sets.len() as u32) {
+            let set = joint_sets.len() as u32;
+            let joints = joints
+                .into_u16()
+                .map(|[a, b, c, d]| [a as u32, b as u32, c as u32, d as u32])
+                .chain(std::iter::repeat([0; 4]))
+                .take(positions.len())
+                .collect::<Vec<_>>();
+            let weights = reader
+                .read_weights(set)
+                .into_iter()
+                .flat_map(|ws| ws.into_f32())
+                .chain(std::iter::repeat([0.0; 4]))
+                .take(positions.len())
+                .collect::<Vec<_>>();
+            joint_sets.push(joints);
+            weight_sets.push(weights);
+        }
         let mut all_joints = FxHashSet::default();
-        for js in joints.iter() {
+        for js in joint_sets.iter().flatten() {
             all_joints.extend(*js);
         }
         log::debug!("  joints: {all_joints:?}");
+        if joint_sets.len() > 1 {
+            log::debug!("  {} influences per vertex", joint_sets.len() * 4);
+        }
+
+        // Weights of all sets are normalized together
+        let mut logged_not_normalized = false;
+        let mut unnormalized_weight_vertices_count = 0;
+        for i in 0..positions.len() {
+            let weight_sum: f32 = weight_sets.iter().flat_map(|ws| ws[i]).sum();
+            let are_normalized = (1.0 - weight_sum).abs() <= f32::EPSILON;
+            if !are_normalized && weight_sum > 0.0 {
+                unnormalized_weight_vertices_count += 1;
+                if !logged_not_normalized {
+                    let weights = weight_sets.iter().map(|ws| ws[i]).collect::<Vec<_>>();
+                    log::warn!("weights are not normalized: {weights:?}");
+                    logged_not_normalized = true;
+                }
+                for ws in weight_sets.iter_mut() {
+                    ws[i] = ws[i].map(|w| w / weight_sum);
+                }
+            }
+        }
+
+        let mut joint_sets = joint_sets.into_iter();
+        let mut weight_sets = weight_sets.into_iter();
+        let joints = joint_sets
+            .next()
+            .unwrap_or_else(|| vec![[u32::MAX; 4]; positions.len()]);
+        let weights = weight_sets
+            .next()
+            .unwrap_or_else(|| vec![[f32::MAX; 4]; positions.len()]);
+        let extra_joint_sets = joint_sets.collect::<Vec<_>>();
+        let extra_weight_sets = weight_sets.collect::<Vec<_>>();
+        let extra_influences = (0..positions.len()).flat_map(|i| {
+            extra_joint_sets
+                .iter()
+                .zip(extra_weight_sets.iter())
+                .map(move |(js, ws)| JointInfluences {
+                    joints: js[i],
+                    weights: ws[i],
+                })
+        });
+        let extra_influences = stage.new_array(extra_influences);
 
-        let weights = reader
-            .read_weights(0)
-            .into_iter()
-            .flat_map(|ws| ws.into_f32())
-            .chain(std::iter::repeat([f32::MAX; 4]))
-            .take(positions.len());
         let vs = joints.into_iter().zip(weights);
         let vs = colors.zip(vs);
         let vs = tangents.into_iter().zip(vs);
@@ -465,22 +963,9 @@ impl GltfPrimitive {
         let vs = uv1s.zip(vs);
         let vs = uv0s.into_iter().zip(vs);
         let vs = positions.into_iter().zip(vs);
-        let mut logged_not_normalized = false;
-        let mut unnormalized_weight_vertices_count = 0;
         let vertices = vs
             .map(
-                |(position, (uv0, (uv1, (normal, (tangent, (color, (joints, mut weights)))))))| {
-                    let weight_sum: f32 = weights.iter().sum();
-                    let are_normalized = 1.0 - weight_sum <= f32::EPSILON;
-                    if !are_normalized {
-                        unnormalized_weight_vertices_count += 1;
-                        if !logged_not_normalized {
-                            log::warn!("weights are not normalized: {weights:?}");
-                            logged_not_normalized = true;
-                            weights = weights.map(|w| w / weight_sum);
-                            log::warn!("  normalized to {weights:?}");
-                        }
-                    }
+                |(position, (uv0, (uv1, (normal, (tangent, (color, (joints, weights)))))))| {
                     Vertex {
                         position,
                         color,
@@ -494,6 +979,13 @@ impl GltfPrimitive {
                 },
             )
             .collect::<Vec<_>>();
+        // the accessor's min and max may be quantized, so the bounds are computed
+        // from the decoded positions instead
+        let (min, max) = vertices
+            .iter()
+            .map(|v| (v.position, v.position))
+            .reduce(|(min, max), (p, _)| (min.min(p), max.max(p)))
+            .unwrap_or_default();
         let vertices = stage.new_array(vertices);
         log::debug!("{} vertices, {:?}", vertices.len(), vertices.array());
         if logged_not_normalized {
@@ -503,14 +995,16 @@ impl GltfPrimitive {
         }
         let indices = stage.new_array(indices);
         log::debug!("{} indices, {:?}", indices.len(), indices.array());
-        let gltf::mesh::Bounds { min, max } = primitive.bounding_box();
-        let min = Vec3::from_array(min);
-        let max = Vec3::from_array(max);
         Self {
+            topology,
             vertices,
             indices,
             material,
+            double_sided,
             bounding_box: (min, max),
+            morph_targets,
+            morph_target_arrays,
+            extra_influences,
         }
     }
 }
@@ -626,7 +1120,9 @@ pub struct GltfNode {
     ///
     /// Each element indexes into the `GltfDocument`'s `nodes` field.
     pub children: Vec<usize>,
-    /// Array of weights
+    /// Array of morph target weights.
+    ///
+    /// Defaults to the weights of the node's mesh, if any.
     pub weights: HybridArray<f32>,
     /// This node's transform.
     pub transform: NestedTransform,
@@ -638,6 +1134,16 @@ impl From<&GltfNode> for (usize, NestedTransform) {
     }
 }
 
+impl From<&GltfNode> for AnimationNode {
+    fn from(node: &GltfNode) -> Self {
+        AnimationNode {
+            index: node.index,
+            transform: node.transform.clone(),
+            morph_weights: Some(node.weights.clone()),
+        }
+    }
+}
+
 impl GltfNode {
     pub fn global_transform(&self) -> Transform {
         self.transform.get_global_transform()
@@ -701,6 +1207,7 @@ impl GltfSkin {
                     .as_ref()
                     .map(|a| a.array())
                     .unwrap_or_default(),
+                ..Default::default()
             }),
             joint_nodes,
             joint_transforms,
@@ -719,6 +1226,16 @@ pub struct GltfDocument {
     pub meshes: Vec<GltfMesh>,
     pub default_material: Hybrid<Material>,
     pub materials: HybridArray<Material>,
+    /// Clearcoat layers of materials, referenced by [`Material::clearcoat_id`].
+    pub clearcoats: Vec<Hybrid<Clearcoat>>,
+    /// Transmission of materials, referenced by
+    /// [`Material::transmission_id`].
+    pub transmissions: Vec<Hybrid<Transmission>>,
+    /// Sheen layers of materials, referenced by [`Material::sheen_id`].
+    pub sheens: Vec<Hybrid<Sheen>>,
+    /// Specular strength and color of materials, referenced by
+    /// [`Material::specular_id`].
+    pub speculars: Vec<Hybrid<Specular>>,
     pub skins: Vec<GltfSkin>,
     /// Vector of scenes - each being a list of nodes.
     pub scenes: Vec<Vec<usize>>,
@@ -726,6 +1243,12 @@ pub struct GltfDocument {
     pub textures: HybridArray<AtlasTexture>,
     pub lights: Vec<GltfLight>,
     pub renderlets: FxHashMap<usize, Vec<Hybrid<Renderlet>>>,
+    /// Morph targets and weights of the renderlets of morphed nodes, keyed by
+    /// node index.
+    pub morphs: FxHashMap<usize, Vec<Hybrid<Morph>>>,
+    /// Instances of the renderlets of nodes using `EXT_mesh_gpu_instancing`,
+    /// keyed by node index.
+    pub instances: FxHashMap<usize, Vec<RenderletInstances>>,
 }
 
 impl GltfDocument {
@@ -798,7 +1321,12 @@ impl GltfDocument {
             let skin = node.skin().map(|skin| skin.index());
             let camera = node.camera().map(|camera| camera.index());
             let light = node.light().map(|light| light.index());
-            let weights = node.weights().map(|w| w.to_vec()).unwrap_or_default();
+            // Nodes without weights of their own use their mesh's default weights
+            let weights = node
+                .weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                .map(|w| w.to_vec())
+                .unwrap_or_default();
             let weights = stage.new_array(weights);
             let transform = transform_for_node(0, stage, &mut node_transforms, &node);
             nodes.push(GltfNode {
@@ -860,14 +1388,45 @@ impl GltfDocument {
         log::debug!("Creating materials");
         let default_material = stage.new_value(Material::default());
         let mut materials = vec![];
+        let mut clearcoats = vec![];
+        let mut transmissions = vec![];
+        let mut sheens = vec![];
+        let mut speculars = vec![];
         for gltf_material in document.materials() {
             let material_index = gltf_material.index();
-            let material = Material::from_gltf(
+            let clearcoat = Clearcoat::from_gltf(&gltf_material, textures.array())
+                .map(|clearcoat| stage.new_value(clearcoat));
+            let transmission = Transmission::from_gltf(&gltf_material, textures.array())
+                .map(|transmission| stage.new_value(transmission));
+            let sheen = Sheen::from_gltf(&gltf_material, textures.array())
+                .map(|sheen| stage.new_value(sheen));
+            let specular = Specular::from_gltf(&gltf_material, textures.array())
+                .map(|specular| stage.new_value(specular));
+            for texture_index in extension_color_texture_indices(&gltf_material) {
+                apply_linear_transfer(document, texture_index, &mut repacking, atlas_offset)?;
+            }
+            let mut material = Material::from_gltf(
                 gltf_material,
                 &mut repacking,
                 textures.array(),
                 atlas_offset,
             )?;
+            if let Some(clearcoat) = clearcoat {
+                material.clearcoat_id = clearcoat.id();
+                clearcoats.push(clearcoat);
+            }
+            if let Some(transmission) = transmission {
+                material.transmission_id = transmission.id();
+                transmissions.push(transmission);
+            }
+            if let Some(sheen) = sheen {
+                material.sheen_id = sheen.id();
+                sheens.push(sheen);
+            }
+            if let Some(specular) = specular {
+                material.specular_id = specular.id();
+                speculars.push(specular);
+            }
             if let Some(index) = material_index {
                 log::trace!("  created material {index}");
                 debug_assert_eq!(index, materials.len(), "unexpected material index");
@@ -980,8 +1539,15 @@ impl GltfDocument {
         log::debug!("Creating renderlets");
 
         let mut renderlets = FxHashMap::default();
+        let mut morphs = FxHashMap::default();
+        let mut instances = FxHashMap::default();
         for gltf_node in nodes.iter() {
             let mut node_renderlets = vec![];
+            let mut node_morphs = vec![];
+            let instance_transforms = document
+                .nodes()
+                .nth(gltf_node.index)
+                .and_then(|node| read_instance_transforms(document, &node, &buffer_data));
             let skin_id = if let Some(skin_index) = gltf_node.skin {
                 log::debug!("  node {} {:?} has skin", gltf_node.index, gltf_node.name);
                 let gltf_skin = skins
@@ -1004,6 +1570,17 @@ impl GltfDocument {
                 let num_prims = mesh.primitives.len();
                 log::debug!("    has {num_prims} primitives");
                 for (prim, i) in mesh.primitives.iter().zip(1..) {
+                    let morph_id = if prim.morph_targets.is_empty() {
+                        Id::NONE
+                    } else {
+                        let morph = stage.new_value(Morph {
+                            targets: prim.morph_target_arrays.array(),
+                            weights: gltf_node.weights.array(),
+                        });
+                        let id = morph.id();
+                        node_morphs.push(morph);
+                        id
+                    };
                     let hybrid = stage.new_value(Renderlet {
                         vertices_array: prim.vertices.array(),
                         indices_array: prim.indices.array(),
@@ -1011,6 +1588,10 @@ impl GltfDocument {
                         material_id: prim.material,
                         camera_id,
                         skin_id,
+                        bounds: prim.bounding_box.into(),
+                        morph_id,
+                        topology: prim.topology,
+                        extra_influences: prim.extra_influences.array(),
                         ..Default::default()
                     });
                     log::debug!("    created renderlet {i}/{num_prims}: {:#?}", hybrid.get());
@@ -1018,9 +1599,27 @@ impl GltfDocument {
                     node_renderlets.push(hybrid);
                 }
             }
+            if let Some(transforms) = instance_transforms {
+                log::debug!(
+                    "  node {} {:?} has {} instances",
+                    gltf_node.index,
+                    gltf_node.name,
+                    transforms.len()
+                );
+                let node_instances = node_renderlets
+                    .iter()
+                    .map(|hybrid| stage.new_instances(hybrid, transforms.iter().copied()))
+                    .collect::<Vec<_>>();
+                if !node_instances.is_empty() {
+                    instances.insert(gltf_node.index, node_instances);
+                }
+            }
             if !node_renderlets.is_empty() {
                 renderlets.insert(gltf_node.index, node_renderlets);
             }
+            if !node_morphs.is_empty() {
+                morphs.insert(gltf_node.index, node_morphs);
+            }
         }
 
         log::debug!("Extensions used: {:?}", document.extensions_used());
@@ -1032,6 +1631,10 @@ impl GltfDocument {
             lights,
             cameras,
             materials,
+            clearcoats,
+            transmissions,
+            sheens,
+            speculars,
             default_material,
             meshes,
             nodes,
@@ -1040,6 +1643,8 @@ impl GltfDocument {
             default_scene: document.default_scene().map(|scene| scene.index()),
             textures,
             renderlets,
+            morphs,
+            instances,
             extensions: document
                 .extensions()
                 .cloned()
@@ -1065,13 +1670,198 @@ impl GltfDocument {
     }
 }
 
+/// Reads the instance transforms of a node using `EXT_mesh_gpu_instancing`.
+///
+/// Returns `None` if the node isn't instanced, or if its instance attributes
+/// can't be decoded.
+fn read_instance_transforms(
+    document: &gltf::Document,
+    node: &gltf::Node,
+    buffer_data: &[gltf::buffer::Data],
+) -> Option<Vec<Transform>> {
+    let attributes = node
+        .extension_value("EXT_mesh_gpu_instancing")?
+        .get("attributes")?
+        .as_object()?;
+    let read = |name: &str| -> Option<Option<gltf::Accessor>> {
+        match attributes.get(name) {
+            None => Some(None),
+            Some(index) => {
+                let accessor = document.accessors().nth(index.as_u64()? as usize);
+                if accessor.is_none() {
+                    log::warn!(
+                        "node {} has a missing instance {name} accessor",
+                        node.index()
+                    );
+                }
+                accessor.map(Some)
+            }
+        }
+    };
+    let translation = read("TRANSLATION")?;
+    let rotation = read("ROTATION")?;
+    let scale = read("SCALE")?;
+    let count = [&translation, &rotation, &scale]
+        .into_iter()
+        .flatten()
+        .map(|accessor| accessor.count())
+        .max()?;
+    let decode = |accessor: Option<gltf::Accessor>, default: [f32; 3]| {
+        accessor.map_or(Some(vec![default; count]), |accessor| {
+            accessor::read_f32s::<3>(&accessor, buffer_data)
+        })
+    };
+    let translations = decode(translation, [0.0; 3])?;
+    let scales = decode(scale, [1.0; 3])?;
+    let rotations = rotation.map_or(Some(vec![[0.0, 0.0, 0.0, 1.0]; count]), |accessor| {
+        accessor::read_f32s::<4>(&accessor, buffer_data)
+    })?;
+    if translations.len() != count || rotations.len() != count || scales.len() != count {
+        log::warn!(
+            "node {} has instance attributes of different lengths",
+            node.index()
+        );
+        return None;
+    }
+    Some(
+        translations
+            .into_iter()
+            .zip(rotations)
+            .zip(scales)
+            .map(|((translation, rotation), scale)| Transform {
+                translation: Vec3::from(translation),
+                rotation: Quat::from_array(rotation).normalize(),
+                scale: Vec3::from(scale),
+            })
+            .collect(),
+    )
+}
+
+/// Extensions we support that the `gltf` crate doesn't know about, and would
+/// otherwise reject when they are required.
+const REQUIRED_EXTENSIONS: &[&str] = &[
+    "KHR_mesh_quantization",
+    "EXT_mesh_gpu_instancing",
+    "KHR_animation_pointer",
+];
+
+/// An imported gltf document, along with its buffers and images.
+type GltfImport = (
+    gltf::Document,
+    Vec<gltf::buffer::Data>,
+    Vec<gltf::image::Data>,
+);
+
+/// Validates a document and imports its buffers and images, like
+/// `gltf::import`, but also accepting files that require any of
+/// [`REQUIRED_EXTENSIONS`].
+fn import_gltf(
+    gltf: gltf::Gltf,
+    base: Option<&std::path::Path>,
+) -> Result<GltfImport, gltf::Error> {
+    let gltf::Gltf { document, blob } = gltf;
+    let mut json = document.into_json();
+    json.extensions_required
+        .retain(|extension| !REQUIRED_EXTENSIONS.contains(&extension.as_str()));
+    let document = gltf::Document::from_json(json)?;
+    let buffers = gltf::import_buffers(&document, base, blob)?;
+    let images = gltf::import_images(&document, base, &buffers)?;
+    Ok((document, buffers, images))
+}
+
+/// Rewrites the `KHR_animation_pointer` channels of each animation so the
+/// `gltf` crate can parse them.
+///
+/// The `gltf` crate requires every channel to target a node, and drops the
+/// extensions of channel targets. So channels that point to node properties
+/// become regular node channels, and the rest are moved into the animation's
+/// own `KHR_animation_pointer` extension as `{"sampler", "pointer"}` objects,
+/// where [`Animation::from_gltf`] picks them up.
+fn hoist_animation_pointers(root: &mut serde_json::Value) {
+    let animations = root
+        .get_mut("animations")
+        .and_then(serde_json::Value::as_array_mut);
+    for animation in animations.into_iter().flatten() {
+        let Some(channels) = animation
+            .get_mut("channels")
+            .and_then(serde_json::Value::as_array_mut)
+        else {
+            continue;
+        };
+        let mut pointer_channels = vec![];
+        channels.retain_mut(|channel| {
+            let Some(pointer) = channel
+                .pointer("/target/extensions/KHR_animation_pointer/pointer")
+                .and_then(serde_json::Value::as_str)
+                .map(String::from)
+            else {
+                return true;
+            };
+            let segments = pointer.split('/').collect::<Vec<_>>();
+            if let ["", "nodes", node, path @ ("translation" | "rotation" | "scale" | "weights")] =
+                segments.as_slice()
+            {
+                if let Ok(node) = node.parse::<usize>() {
+                    channel["target"] = serde_json::json!({"node": node, "path": path});
+                    return true;
+                }
+            }
+            pointer_channels.push(serde_json::json!({
+                "sampler": channel.get("sampler"),
+                "pointer": pointer,
+            }));
+            false
+        });
+        if !pointer_channels.is_empty() {
+            animation["extensions"]["KHR_animation_pointer"] =
+                serde_json::json!({ "channels": pointer_channels });
+        }
+    }
+}
+
+/// Parses gltf or GLB bytes without validating them, rewriting any
+/// `KHR_animation_pointer` channels along the way.
+///
+/// See [`hoist_animation_pointers`].
+fn parse_gltf(bytes: &[u8]) -> Result<gltf::Gltf, gltf::Error> {
+    let (mut json, blob): (serde_json::Value, _) = if bytes.starts_with(b"glTF") {
+        let glb = gltf::binary::Glb::from_slice(bytes)?;
+        (
+            gltf::json::deserialize::from_slice(&glb.json)?,
+            glb.bin.map(|bin| bin.into_owned()),
+        )
+    } else {
+        (gltf::json::deserialize::from_slice(bytes)?, None)
+    };
+    hoist_animation_pointers(&mut json);
+    let json = gltf::json::deserialize::from_value(json)?;
+    let document = gltf::Document::from_json_without_validation(json);
+    Ok(gltf::Gltf { document, blob })
+}
+
+/// Imports a gltf file from the file system.
+///
+/// See [`import_gltf`].
+fn import_gltf_path(path: &std::path::Path) -> Result<GltfImport, gltf::Error> {
+    let base = path.parent().unwrap_or_else(|| std::path::Path::new("./"));
+    let bytes = std::fs::read(path).map_err(gltf::Error::Io)?;
+    import_gltf(parse_gltf(&bytes)?, Some(base))
+}
+
+/// Imports a gltf file from bytes.
+///
+/// See [`import_gltf`].
+fn import_gltf_slice(bytes: &[u8]) -> Result<GltfImport, gltf::Error> {
+    import_gltf(parse_gltf(bytes)?, None)
+}
+
 impl Stage {
     pub fn load_gltf_document_from_path(
         &mut self,
         path: impl AsRef<std::path::Path>,
         camera_id: Id<Camera>,
     ) -> Result<GltfDocument, StageGltfError> {
-        let (document, buffers, images) = gltf::import(path)?;
+        let (document, buffers, images) = import_gltf_path(path.as_ref())?;
         GltfDocument::from_gltf(self, &document, buffers, images, camera_id)
     }
 
@@ -1080,7 +1870,7 @@ impl Stage {
         bytes: impl AsRef<[u8]>,
         camera_id: Id<Camera>,
     ) -> Result<GltfDocument, StageGltfError> {
-        let (document, buffers, images) = gltf::import_slice(bytes)?;
+        let (document, buffers, images) = import_gltf_slice(bytes.as_ref())?;
         GltfDocument::from_gltf(self, &document, buffers, images, camera_id)
     }
 }
@@ -1088,14 +1878,15 @@ impl Stage {
 #[cfg(test)]
 mod test {
     use crate::{
+        atlas::AtlasTexture,
         camera::Camera,
-        pbr::{Material, PbrConfig},
-        stage::{Renderlet, Vertex},
+        pbr::{Clearcoat, Material, PbrConfig, Sheen, Specular, TextureTransform, Transmission},
+        stage::{AnimationPointer, Renderlet, Topology, TweenProperty, Vertex},
         transform::Transform,
         Context,
     };
-    use crabslab::{Id, Slab};
-    use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+    use crabslab::{Array, Id};
+    use glam::{Quat, Vec2, Vec3, Vec4, Vec4Swizzles};
 
     #[test]
     fn get_vertex_count_primitive_sanity() {
@@ -1112,6 +1903,635 @@ mod test {
         assert_eq!(3, vertex_count);
     }
 
+    #[test]
+    fn texture_transform_from_gltf() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": ["KHR_texture_transform"],
+                "images": [{"uri": "testTexture.png"}],
+                "textures": [{"source": 0}],
+                "materials": [{
+                    "pbrMetallicRoughness": {
+                        "baseColorTexture": {
+                            "index": 0,
+                            "extensions": {
+                                "KHR_texture_transform": {
+                                    "offset": [0.5, 0.0],
+                                    "scale": [2.0, 2.0],
+                                    "texCoord": 1
+                                }
+                            }
+                        }
+                    },
+                    "normalTexture": {
+                        "index": 0,
+                        "extensions": {
+                            "KHR_texture_transform": {"rotation": 1.5}
+                        }
+                    },
+                    "occlusionTexture": {"index": 0}
+                }]
+            }"#,
+        )
+        .unwrap();
+        let material = gltf.materials().next().unwrap();
+
+        let albedo = material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .unwrap();
+        let (tex_coord, transform) = super::tex_coord_and_transform(&albedo);
+        assert_eq!(1, tex_coord);
+        assert_eq!(
+            TextureTransform {
+                offset: Vec2::new(0.5, 0.0),
+                rotation: 0.0,
+                scale: Vec2::splat(2.0),
+            },
+            transform
+        );
+
+        let normal = material.normal_texture().unwrap();
+        let (tex_coord, transform) = super::tex_coord_and_transform_json(
+            normal.tex_coord(),
+            normal.extension_value("KHR_texture_transform"),
+        );
+        assert_eq!(0, tex_coord);
+        assert_eq!(
+            TextureTransform {
+                rotation: 1.5,
+                ..Default::default()
+            },
+            transform
+        );
+
+        let occlusion = material.occlusion_texture().unwrap();
+        let (_, transform) = super::tex_coord_and_transform_json(
+            occlusion.tex_coord(),
+            occlusion.extension_value("KHR_texture_transform"),
+        );
+        assert_eq!(TextureTransform::default(), transform);
+    }
+
+    #[test]
+    fn clearcoat_from_gltf() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": ["KHR_materials_clearcoat"],
+                "images": [{"uri": "testTexture.png"}],
+                "textures": [{"source": 0}, {"source": 0}],
+                "materials": [
+                    {
+                        "extensions": {
+                            "KHR_materials_clearcoat": {
+                                "clearcoatFactor": 1.0,
+                                "clearcoatRoughnessFactor": 0.25,
+                                "clearcoatRoughnessTexture": {"index": 1, "texCoord": 1},
+                                "clearcoatNormalTexture": {
+                                    "index": 0,
+                                    "extensions": {
+                                        "KHR_texture_transform": {"scale": [4.0, 4.0]}
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let textures = Array::<AtlasTexture>::new(10, 2);
+        let mut materials = gltf.materials();
+
+        let clearcoat = Clearcoat::from_gltf(&materials.next().unwrap(), textures).unwrap();
+        assert_eq!(
+            Clearcoat {
+                factor: 1.0,
+                roughness_factor: 0.25,
+                roughness_texture_id: textures.at(1),
+                roughness_tex_coord: 1,
+                normal_texture_id: textures.at(0),
+                normal_tex_transform: TextureTransform {
+                    scale: Vec2::splat(4.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            clearcoat
+        );
+
+        assert!(Clearcoat::from_gltf(&materials.next().unwrap(), textures).is_none());
+    }
+
+    #[test]
+    fn transmission_from_gltf() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": [
+                    "KHR_materials_transmission",
+                    "KHR_materials_volume",
+                    "KHR_materials_ior"
+                ],
+                "images": [{"uri": "testTexture.png"}],
+                "textures": [{"source": 0}, {"source": 0}],
+                "materials": [
+                    {
+                        "extensions": {
+                            "KHR_materials_transmission": {
+                                "transmissionFactor": 0.75,
+                                "transmissionTexture": {"index": 1}
+                            },
+                            "KHR_materials_volume": {
+                                "thicknessFactor": 2.0,
+                                "thicknessTexture": {"index": 0, "texCoord": 1},
+                                "attenuationDistance": 0.5,
+                                "attenuationColor": [1.0, 0.5, 0.25]
+                            },
+                            "KHR_materials_ior": {"ior": 1.33}
+                        }
+                    },
+                    {
+                        "extensions": {
+                            "KHR_materials_transmission": {"transmissionFactor": 1.0}
+                        }
+                    },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let textures = Array::<AtlasTexture>::new(10, 2);
+        let mut materials = gltf.materials();
+
+        let material = materials.next().unwrap();
+        let transmission = Transmission::from_gltf(&material, textures).unwrap();
+        assert_eq!(
+            Transmission {
+                factor: 0.75,
+                thickness_factor: 2.0,
+                attenuation_distance: 0.5,
+                attenuation_color: Vec3::new(1.0, 0.5, 0.25),
+                texture_id: textures.at(1),
+                thickness_texture_id: textures.at(0),
+                thickness_tex_coord: 1,
+                ..Default::default()
+            },
+            transmission
+        );
+        assert_eq!(Some(1.33), material.ior());
+
+        // thin-walled without a volume
+        let transmission = Transmission::from_gltf(&materials.next().unwrap(), textures).unwrap();
+        assert_eq!(
+            Transmission {
+                factor: 1.0,
+                ..Default::default()
+            },
+            transmission
+        );
+
+        assert!(Transmission::from_gltf(&materials.next().unwrap(), textures).is_none());
+    }
+
+    /// Packs a gltf json document and its binary buffer into a GLB.
+    fn glb(json: String, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut glb = vec![];
+        glb.extend(b"glTF");
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(bin);
+        glb
+    }
+
+    #[test]
+    fn quantized_and_sparse_attributes() {
+        let mut bin = vec![];
+        // 0: normalized i16 positions, padded to 4 byte alignment
+        for [x, y, z] in [[32767i16, 0, 0], [0, 32767, 0], [0, 0, -32767]] {
+            bin.extend([x, y, z, 0].map(i16::to_le_bytes).concat());
+        }
+        // 24: normalized i8 normals, padded to 4 byte alignment
+        for [x, y, z] in [[127i8, 0, 0], [0, 127, 0], [0, 0, -127]] {
+            bin.extend([x, y, z, 0].map(|c| c as u8));
+        }
+        // 36: normalized u16 uvs
+        for [u, v] in [[65535u16, 0], [0, 65535], [0, 0]] {
+            bin.extend([u, v].map(u16::to_le_bytes).concat());
+        }
+        // 48: u8 sparse index, padded to 4 byte alignment
+        bin.extend([2, 0, 0, 0]);
+        // 52: f32 sparse value
+        bin.extend([1.0f32, 2.0, 3.0].map(f32::to_le_bytes).concat());
+        // 64: i16 sparse value
+        bin.extend([-32767i16, 0, 0].map(i16::to_le_bytes).concat());
+        bin.resize(72, 0);
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "extensionsUsed": ["KHR_mesh_quantization"],
+                "extensionsRequired": ["KHR_mesh_quantization"],
+                "buffers": [{{"byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 24, "byteStride": 8}},
+                    {{"buffer": 0, "byteOffset": 24, "byteLength": 12, "byteStride": 4}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 12}},
+                    {{"buffer": 0, "byteOffset": 48, "byteLength": 1}},
+                    {{"buffer": 0, "byteOffset": 52, "byteLength": 12}},
+                    {{"buffer": 0, "byteOffset": 64, "byteLength": 6}}
+                ],
+                "accessors": [
+                    {{
+                        "bufferView": 0, "componentType": 5122, "normalized": true,
+                        "count": 3, "type": "VEC3",
+                        "min": [0, 0, -32767], "max": [32767, 32767, 0]
+                    }},
+                    {{
+                        "bufferView": 1, "componentType": 5120, "normalized": true,
+                        "count": 3, "type": "VEC3"
+                    }},
+                    {{
+                        "bufferView": 2, "componentType": 5123, "normalized": true,
+                        "count": 3, "type": "VEC2"
+                    }},
+                    {{
+                        "componentType": 5126, "count": 3, "type": "VEC3",
+                        "sparse": {{
+                            "count": 1,
+                            "indices": {{"bufferView": 3, "componentType": 5121}},
+                            "values": {{"bufferView": 4}}
+                        }}
+                    }},
+                    {{
+                        "bufferView": 0, "componentType": 5122, "normalized": true,
+                        "count": 3, "type": "VEC3",
+                        "sparse": {{
+                            "count": 1,
+                            "indices": {{"bufferView": 3, "componentType": 5121}},
+                            "values": {{"bufferView": 5}}
+                        }}
+                    }}
+                ],
+                "meshes": [{{
+                    "primitives": [{{
+                        "attributes": {{"POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2}},
+                        "targets": [{{"POSITION": 3}}]
+                    }}]
+                }}]
+            }}"#,
+            bin.len()
+        );
+        let glb = glb(json, &bin);
+
+        // the gltf crate rejects the required extension on its own
+        assert!(gltf::import_slice(&glb).is_err());
+        let (document, buffers, _images) = super::import_gltf_slice(&glb).unwrap();
+
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let read3 = |semantic| {
+            super::accessor::read_attribute::<3>(&primitive, semantic, &buffers).unwrap()
+        };
+        let axes = vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]];
+        assert_eq!(axes, read3(gltf::Semantic::Positions));
+        assert_eq!(axes, read3(gltf::Semantic::Normals));
+        assert_eq!(
+            vec![[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
+            super::accessor::read_attribute::<2>(
+                &primitive,
+                gltf::Semantic::TexCoords(0),
+                &buffers
+            )
+            .unwrap()
+        );
+
+        let accessors = document.accessors().collect::<Vec<_>>();
+        // sparse without a buffer view substitutes into zeros
+        assert_eq!(
+            vec![[0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 2.0, 3.0]],
+            super::accessor::read_f32s::<3>(&accessors[3], &buffers).unwrap()
+        );
+        // sparse with a buffer view substitutes into its values
+        assert_eq!(
+            vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]],
+            super::accessor::read_f32s::<3>(&accessors[4], &buffers).unwrap()
+        );
+        // the wrong number of components can't be decoded
+        assert!(super::accessor::read_f32s::<2>(&accessors[0], &buffers).is_none());
+    }
+
+    #[test]
+    fn mesh_gpu_instancing_transforms() {
+        let mut bin = vec![];
+        // 0: f32 translations
+        bin.extend(
+            [1.0f32, 0.0, 0.0, 0.0, 2.0, 0.0]
+                .map(f32::to_le_bytes)
+                .concat(),
+        );
+        // 24: normalized i16 rotations
+        bin.extend(
+            [0i16, 0, 0, 32767, 0, 0, 32767, 0]
+                .map(i16::to_le_bytes)
+                .concat(),
+        );
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "extensionsUsed": ["EXT_mesh_gpu_instancing"],
+                "extensionsRequired": ["EXT_mesh_gpu_instancing"],
+                "buffers": [{{"byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 24}},
+                    {{"buffer": 0, "byteOffset": 24, "byteLength": 16}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3"}},
+                    {{
+                        "bufferView": 1, "componentType": 5122, "normalized": true,
+                        "count": 2, "type": "VEC4"
+                    }}
+                ],
+                "nodes": [
+                    {{
+                        "extensions": {{
+                            "EXT_mesh_gpu_instancing": {{
+                                "attributes": {{"TRANSLATION": 0, "ROTATION": 1}}
+                            }}
+                        }}
+                    }},
+                    {{}}
+                ]
+            }}"#,
+            bin.len()
+        );
+        let glb = glb(json, &bin);
+
+        assert!(gltf::import_slice(&glb).is_err());
+        let (document, buffers, _images) = super::import_gltf_slice(&glb).unwrap();
+        let nodes = document.nodes().collect::<Vec<_>>();
+        let transforms = super::read_instance_transforms(&document, &nodes[0], &buffers).unwrap();
+        assert_eq!(
+            vec![
+                Transform {
+                    translation: Vec3::X,
+                    rotation: Quat::IDENTITY,
+                    scale: Vec3::ONE,
+                },
+                Transform {
+                    translation: Vec3::new(0.0, 2.0, 0.0),
+                    rotation: Quat::from_xyzw(0.0, 0.0, 1.0, 0.0),
+                    scale: Vec3::ONE,
+                }
+            ],
+            transforms
+        );
+        assert!(super::read_instance_transforms(&document, &nodes[1], &buffers).is_none());
+    }
+
+    #[test]
+    fn animation_pointer_channels() {
+        let mut bin = vec![];
+        // 0: keyframe times
+        bin.extend([0.0f32, 1.0].map(f32::to_le_bytes).concat());
+        // 8: base color factors
+        bin.extend(
+            [1.0f32, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0]
+                .map(f32::to_le_bytes)
+                .concat(),
+        );
+        // 40: light intensities
+        bin.extend([10.0f32, 0.0].map(f32::to_le_bytes).concat());
+        // 48: translations
+        bin.extend(
+            [0.0f32, 0.0, 0.0, 0.0, 1.0, 0.0]
+                .map(f32::to_le_bytes)
+                .concat(),
+        );
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "extensionsUsed": ["KHR_animation_pointer", "KHR_lights_punctual"],
+                "extensionsRequired": ["KHR_animation_pointer"],
+                "extensions": {{
+                    "KHR_lights_punctual": {{"lights": [{{"type": "point"}}]}}
+                }},
+                "buffers": [{{"byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 8, "byteLength": 32}},
+                    {{"buffer": 0, "byteOffset": 40, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 48, "byteLength": 24}}
+                ],
+                "accessors": [
+                    {{
+                        "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR",
+                        "min": [0.0], "max": [1.0]
+                    }},
+                    {{"bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC4"}},
+                    {{"bufferView": 2, "componentType": 5126, "count": 2, "type": "SCALAR"}},
+                    {{"bufferView": 3, "componentType": 5126, "count": 2, "type": "VEC3"}}
+                ],
+                "materials": [{{}}],
+                "nodes": [{{}}],
+                "animations": [{{
+                    "samplers": [
+                        {{"input": 0, "output": 1}},
+                        {{"input": 0, "output": 2, "interpolation": "STEP"}},
+                        {{"input": 0, "output": 3}}
+                    ],
+                    "channels": [
+                        {{"sampler": 0, "target": {{
+                            "path": "pointer",
+                            "extensions": {{"KHR_animation_pointer": {{
+                                "pointer": "/materials/0/pbrMetallicRoughness/baseColorFactor"
+                            }}}}
+                        }}}},
+                        {{"sampler": 1, "target": {{
+                            "path": "pointer",
+                            "extensions": {{"KHR_animation_pointer": {{
+                                "pointer": "/extensions/KHR_lights_punctual/lights/0/intensity"
+                            }}}}
+                        }}}},
+                        {{"sampler": 2, "target": {{
+                            "path": "pointer",
+                            "extensions": {{"KHR_animation_pointer": {{
+                                "pointer": "/nodes/0/translation"
+                            }}}}
+                        }}}}
+                    ]
+                }}]
+            }}"#,
+            bin.len()
+        );
+        let glb = glb(json, &bin);
+
+        assert!(gltf::import_slice(&glb).is_err());
+        let (document, buffers, _images) = super::import_gltf_slice(&glb).unwrap();
+        let animation = document.animations().next().unwrap();
+        let animation = super::Animation::from_gltf(&buffers, animation).unwrap();
+        assert_eq!(vec![0], animation.target_node_indices().collect::<Vec<_>>());
+
+        let properties = animation.get_properties_at_time(0.5).unwrap();
+        assert_eq!(3, properties.len());
+        let mut pointers = vec![];
+        for (node_index, property) in properties {
+            match property {
+                TweenProperty::Translation(translation) => {
+                    assert_eq!(0, node_index);
+                    assert_eq!(Vec3::new(0.0, 0.5, 0.0), translation);
+                }
+                TweenProperty::Pointer(pointer, values) => pointers.push((pointer, values)),
+                property => panic!("unexpected {} property", property.description()),
+            }
+        }
+        assert_eq!(
+            vec![
+                (AnimationPointer::LightIntensity(0), vec![10.0]),
+                (
+                    AnimationPointer::MaterialAlbedoFactor(0),
+                    vec![1.0, 1.0, 1.0, 0.5]
+                ),
+            ],
+            {
+                pointers.sort_by_key(|(pointer, _)| pointer.to_string());
+                pointers
+            }
+        );
+    }
+
+    #[test]
+    fn primitive_modes_convert_to_lists() {
+        use gltf::mesh::Mode;
+
+        let convert = |mode, indices: Option<&[u32]>, vertex_count| {
+            super::primitive_topology_and_indices(mode, indices.map(<[u32]>::to_vec), vertex_count)
+        };
+        assert_eq!(
+            (Topology::PointList, vec![]),
+            convert(Mode::Points, None, 3)
+        );
+        assert_eq!(
+            (Topology::LineList, vec![0, 1, 2, 3]),
+            convert(Mode::Lines, Some(&[0, 1, 2, 3, 4]), 5)
+        );
+        assert_eq!(
+            (Topology::LineList, vec![0, 1, 1, 2]),
+            convert(Mode::LineStrip, None, 3)
+        );
+        assert_eq!(
+            (Topology::LineList, vec![3, 2, 2, 1, 1, 3]),
+            convert(Mode::LineLoop, Some(&[3, 2, 1]), 4)
+        );
+        assert_eq!(
+            (Topology::TriangleList, vec![]),
+            convert(Mode::Triangles, None, 3)
+        );
+        assert_eq!(
+            (Topology::TriangleList, vec![0, 1, 2]),
+            convert(Mode::Triangles, Some(&[0, 1, 2, 3]), 4)
+        );
+        assert_eq!(
+            (Topology::TriangleList, vec![0, 1, 2, 1, 3, 2, 2, 3, 4]),
+            convert(Mode::TriangleStrip, None, 5)
+        );
+        assert_eq!(
+            (Topology::TriangleList, vec![5, 6, 4, 6, 7, 4]),
+            convert(Mode::TriangleFan, Some(&[4, 5, 6, 7]), 8)
+        );
+    }
+
+    #[test]
+    fn list_triangles_skip_trailing_vertices() {
+        assert_eq!(vec![[0, 1, 2]], super::list_triangles(&[], 4));
+        assert_eq!(
+            vec![[3, 2, 1], [0, 1, 2]],
+            super::list_triangles(&[3, 2, 1, 0, 1, 2], 4)
+        );
+        assert!(super::list_triangles(&[], 2).is_empty());
+    }
+
+    #[test]
+    fn sheen_and_specular_from_gltf() {
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": ["KHR_materials_sheen", "KHR_materials_specular"],
+                "images": [{"uri": "testTexture.png"}],
+                "textures": [{"source": 0}, {"source": 0}, {"source": 0}],
+                "materials": [
+                    {
+                        "extensions": {
+                            "KHR_materials_sheen": {
+                                "sheenColorFactor": [0.5, 0.25, 1.0],
+                                "sheenColorTexture": {"index": 2},
+                                "sheenRoughnessFactor": 0.75
+                            },
+                            "KHR_materials_specular": {
+                                "specularFactor": 0.5,
+                                "specularTexture": {"index": 0},
+                                "specularColorTexture": {"index": 1, "texCoord": 1}
+                            }
+                        }
+                    },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let textures = Array::<AtlasTexture>::new(10, 3);
+        let mut materials = gltf.materials();
+
+        let material = materials.next().unwrap();
+        assert_eq!(
+            Some(Sheen {
+                color_factor: Vec3::new(0.5, 0.25, 1.0),
+                roughness_factor: 0.75,
+                color_texture_id: textures.at(2),
+                ..Default::default()
+            }),
+            Sheen::from_gltf(&material, textures)
+        );
+        assert_eq!(
+            Some(Specular {
+                factor: 0.5,
+                texture_id: textures.at(0),
+                color_texture_id: textures.at(1),
+                color_tex_coord: 1,
+                ..Default::default()
+            }),
+            Specular::from_gltf(&material, textures)
+        );
+        // color textures are sRGB encoded
+        assert_eq!(
+            vec![2, 1],
+            super::extension_color_texture_indices(&material)
+        );
+
+        let material = materials.next().unwrap();
+        assert!(Sheen::from_gltf(&material, textures).is_none());
+        assert!(Specular::from_gltf(&material, textures).is_none());
+        assert!(super::extension_color_texture_indices(&material).is_empty());
+    }
+
     #[test]
     // ensures we can
     // * read simple meshes
@@ -1327,10 +2747,9 @@ mod test {
                 vertex_index,
                 ..Default::default()
             };
-            v.renderlet_id = Id::from(v.instance_index);
-            v.renderlet = slab.read(v.renderlet_id);
+            (v.renderlet_id, v.renderlet, _) = Renderlet::read_instance(v.instance_index, slab);
             crate::stage::renderlet_vertex(
-                v.renderlet_id,
+                v.instance_index,
                 v.vertex_index,
                 slab,
                 &mut v.out_camera,
@@ -0,0 +1,197 @@
+//! Decoding of gltf accessors.
+//!
+//! The `gltf` crate reads positions, normals, tangents and morph targets as
+//! `f32`s, regardless of the accessor's component type. Files using
+//! `KHR_mesh_quantization` store them as (possibly normalized) integers, so
+//! we decode those attributes ourselves.
+use gltf::accessor::{sparse::IndexType, DataType};
+
+/// Returns the size in bytes of one component of the given type.
+fn component_size(data_type: DataType) -> usize {
+    match data_type {
+        DataType::I8 | DataType::U8 => 1,
+        DataType::I16 | DataType::U16 => 2,
+        DataType::U32 | DataType::F32 => 4,
+    }
+}
+
+/// Decodes one component from the start of `bytes`.
+///
+/// Normalized integers are mapped to `[0.0, 1.0]` if unsigned and
+/// `[-1.0, 1.0]` if signed, as described by the glTF spec.
+fn read_component(bytes: &[u8], data_type: DataType, normalized: bool) -> Option<f32> {
+    Some(match data_type {
+        DataType::I8 => {
+            let c = *bytes.first()? as i8 as f32;
+            if normalized {
+                (c / 127.0).max(-1.0)
+            } else {
+                c
+            }
+        }
+        DataType::U8 => {
+            let c = *bytes.first()? as f32;
+            if normalized {
+                c / 255.0
+            } else {
+                c
+            }
+        }
+        DataType::I16 => {
+            let c = i16::from_le_bytes(bytes.get(..2)?.try_into().ok()?) as f32;
+            if normalized {
+                (c / 32767.0).max(-1.0)
+            } else {
+                c
+            }
+        }
+        DataType::U16 => {
+            let c = u16::from_le_bytes(bytes.get(..2)?.try_into().ok()?) as f32;
+            if normalized {
+                c / 65535.0
+            } else {
+                c
+            }
+        }
+        DataType::U32 => u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as f32,
+        DataType::F32 => f32::from_le_bytes(bytes.get(..4)?.try_into().ok()?),
+    })
+}
+
+/// Returns the bytes of a buffer view, along with its stride, or the given
+/// element size if the view is tightly packed.
+fn view_bytes<'a>(
+    view: &gltf::buffer::View,
+    buffer_data: &'a [gltf::buffer::Data],
+    element_size: usize,
+) -> Option<(&'a [u8], usize)> {
+    let buffer = buffer_data.get(view.buffer().index())?;
+    let bytes = buffer.0.get(view.offset()..view.offset() + view.length())?;
+    Some((bytes, view.stride().unwrap_or(element_size)))
+}
+
+/// Decodes `N` components of the given type at `offset` in `bytes`.
+fn read_element<const N: usize>(
+    bytes: &[u8],
+    offset: usize,
+    data_type: DataType,
+    normalized: bool,
+) -> Option<[f32; N]> {
+    let size = component_size(data_type);
+    let mut element = [0.0; N];
+    for (i, component) in element.iter_mut().enumerate() {
+        *component = read_component(bytes.get(offset + i * size..)?, data_type, normalized)?;
+    }
+    Some(element)
+}
+
+/// Reads a sparse index at `offset` in `bytes`.
+fn read_index(bytes: &[u8], offset: usize, index_type: &IndexType) -> Option<usize> {
+    let bytes = bytes.get(offset..)?;
+    Some(match index_type {
+        IndexType::U8 => *bytes.first()? as usize,
+        IndexType::U16 => u16::from_le_bytes(bytes.get(..2)?.try_into().ok()?) as usize,
+        IndexType::U32 => u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize,
+    })
+}
+
+/// Decodes the elements of an accessor with `N` components each, as `f32`s.
+///
+/// Integer components are converted, and dequantized if the accessor is
+/// normalized. Sparse values are substituted into the base values, which are
+/// zero if the accessor has no buffer view.
+///
+/// Returns `None` if the accessor doesn't have `N` components or reaches
+/// outside of its buffer.
+pub fn read_f32s<const N: usize>(
+    accessor: &gltf::Accessor,
+    buffer_data: &[gltf::buffer::Data],
+) -> Option<Vec<[f32; N]>> {
+    let multiplicity = accessor.dimensions().multiplicity();
+    if multiplicity != N {
+        log::warn!(
+            "accessor {} has {multiplicity} components, expected {N}",
+            accessor.index()
+        );
+        return None;
+    }
+    let data_type = accessor.data_type();
+    let normalized = accessor.normalized();
+    let element_size = component_size(data_type) * N;
+
+    let mut elements = if let Some(view) = accessor.view() {
+        let (bytes, stride) = view_bytes(&view, buffer_data, element_size)?;
+        (0..accessor.count())
+            .map(|i| read_element(bytes, accessor.offset() + i * stride, data_type, normalized))
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        vec![[0.0; N]; accessor.count()]
+    };
+
+    if let Some(sparse) = accessor.sparse() {
+        let indices = sparse.indices();
+        let index_type = indices.index_type();
+        let (index_bytes, index_stride) =
+            view_bytes(&indices.view(), buffer_data, index_type.size())?;
+        let values = sparse.values();
+        let (value_bytes, value_stride) = view_bytes(&values.view(), buffer_data, element_size)?;
+        for i in 0..sparse.count() {
+            let index = read_index(
+                index_bytes,
+                indices.offset() + i * index_stride,
+                &index_type,
+            )?;
+            let value = read_element(
+                value_bytes,
+                values.offset() + i * value_stride,
+                data_type,
+                normalized,
+            )?;
+            *elements.get_mut(index)? = value;
+        }
+    }
+
+    Some(elements)
+}
+
+/// Decodes the attribute of a primitive with the given semantic, if it has
+/// one.
+///
+/// See [`read_f32s`].
+pub fn read_attribute<const N: usize>(
+    primitive: &gltf::Primitive,
+    semantic: gltf::Semantic,
+    buffer_data: &[gltf::buffer::Data],
+) -> Option<Vec<[f32; N]>> {
+    let accessor = primitive.get(&semantic)?;
+    let values = read_f32s(&accessor, buffer_data);
+    if values.is_none() {
+        log::warn!(
+            "could not decode the {semantic:?} attribute of accessor {}",
+            accessor.index()
+        );
+    }
+    values
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalized_components_dequantize() {
+        let read = |bytes: &[u8], data_type| read_component(bytes, data_type, true).unwrap();
+        assert_eq!(1.0, read(&[127], DataType::I8));
+        // both -128 and -127 map to -1.0
+        assert_eq!(-1.0, read(&[-128i8 as u8], DataType::I8));
+        assert_eq!(-1.0, read(&[-127i8 as u8], DataType::I8));
+        assert_eq!(1.0, read(&[255], DataType::U8));
+        assert_eq!(1.0, read(&32767i16.to_le_bytes(), DataType::I16));
+        assert_eq!(-1.0, read(&(-32768i16).to_le_bytes(), DataType::I16));
+        assert_eq!(1.0, read(&65535u16.to_le_bytes(), DataType::U16));
+        assert_eq!(
+            300.0,
+            read_component(&300u16.to_le_bytes(), DataType::U16, false).unwrap()
+        );
+    }
+}
@@ -1,8 +1,15 @@
 //! Animation helpers for gltf.
-use glam::{Quat, Vec3};
+use glam::{Mat4, Quat, Vec3, Vec4};
 use snafu::prelude::*;
 
-use crate::stage::NestedTransform;
+use crate::{
+    camera::Camera,
+    pbr::{light::SpotLight, Material},
+    slab::{Hybrid, HybridArray},
+    stage::NestedTransform,
+};
+
+use super::{GltfDocument, LightDetails};
 
 #[derive(Debug, Snafu)]
 pub enum InterpolationError {
@@ -64,12 +71,131 @@ impl Interpolation {
 #[derive(Debug, Clone, Copy)]
 pub struct Keyframe(pub f32);
 
+/// A property of a material, light or camera targeted by a
+/// `KHR_animation_pointer` channel.
+///
+/// Each variant holds the gltf index of the object it targets.
+///
+/// See <https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Khronos/KHR_animation_pointer>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationPointer {
+    MaterialAlbedoFactor(usize),
+    MaterialMetallicFactor(usize),
+    MaterialRoughnessFactor(usize),
+    MaterialEmissiveFactor(usize),
+    MaterialEmissiveStrength(usize),
+    MaterialAlphaCutoff(usize),
+    MaterialOcclusionStrength(usize),
+    LightColor(usize),
+    LightIntensity(usize),
+    LightInnerConeAngle(usize),
+    LightOuterConeAngle(usize),
+    CameraYfov(usize),
+}
+
+impl std::fmt::Display for AnimationPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lights = "/extensions/KHR_lights_punctual/lights";
+        match self {
+            AnimationPointer::MaterialAlbedoFactor(i) => {
+                write!(f, "/materials/{i}/pbrMetallicRoughness/baseColorFactor")
+            }
+            AnimationPointer::MaterialMetallicFactor(i) => {
+                write!(f, "/materials/{i}/pbrMetallicRoughness/metallicFactor")
+            }
+            AnimationPointer::MaterialRoughnessFactor(i) => {
+                write!(f, "/materials/{i}/pbrMetallicRoughness/roughnessFactor")
+            }
+            AnimationPointer::MaterialEmissiveFactor(i) => {
+                write!(f, "/materials/{i}/emissiveFactor")
+            }
+            AnimationPointer::MaterialEmissiveStrength(i) => write!(
+                f,
+                "/materials/{i}/extensions/KHR_materials_emissive_strength/emissiveStrength"
+            ),
+            AnimationPointer::MaterialAlphaCutoff(i) => write!(f, "/materials/{i}/alphaCutoff"),
+            AnimationPointer::MaterialOcclusionStrength(i) => {
+                write!(f, "/materials/{i}/occlusionTexture/strength")
+            }
+            AnimationPointer::LightColor(i) => write!(f, "{lights}/{i}/color"),
+            AnimationPointer::LightIntensity(i) => write!(f, "{lights}/{i}/intensity"),
+            AnimationPointer::LightInnerConeAngle(i) => {
+                write!(f, "{lights}/{i}/spot/innerConeAngle")
+            }
+            AnimationPointer::LightOuterConeAngle(i) => {
+                write!(f, "{lights}/{i}/spot/outerConeAngle")
+            }
+            AnimationPointer::CameraYfov(i) => write!(f, "/cameras/{i}/perspective/yfov"),
+        }
+    }
+}
+
+impl AnimationPointer {
+    /// Parse a JSON pointer, returning `None` if it doesn't point to a
+    /// supported property.
+    ///
+    /// Pointers to node properties are not parsed here, as they are loaded
+    /// as regular node channels.
+    pub fn parse(pointer: &str) -> Option<Self> {
+        let segments = pointer.strip_prefix('/')?.split('/').collect::<Vec<_>>();
+        let index = |segment: &str| segment.parse::<usize>().ok();
+        Some(match segments.as_slice() {
+            ["materials", i, property @ ..] => {
+                let i = index(i)?;
+                match property {
+                    ["pbrMetallicRoughness", "baseColorFactor"] => {
+                        AnimationPointer::MaterialAlbedoFactor(i)
+                    }
+                    ["pbrMetallicRoughness", "metallicFactor"] => {
+                        AnimationPointer::MaterialMetallicFactor(i)
+                    }
+                    ["pbrMetallicRoughness", "roughnessFactor"] => {
+                        AnimationPointer::MaterialRoughnessFactor(i)
+                    }
+                    ["emissiveFactor"] => AnimationPointer::MaterialEmissiveFactor(i),
+                    ["extensions", "KHR_materials_emissive_strength", "emissiveStrength"] => {
+                        AnimationPointer::MaterialEmissiveStrength(i)
+                    }
+                    ["alphaCutoff"] => AnimationPointer::MaterialAlphaCutoff(i),
+                    ["occlusionTexture", "strength"] => {
+                        AnimationPointer::MaterialOcclusionStrength(i)
+                    }
+                    _ => return None,
+                }
+            }
+            ["extensions", "KHR_lights_punctual", "lights", i, property @ ..] => {
+                let i = index(i)?;
+                match property {
+                    ["color"] => AnimationPointer::LightColor(i),
+                    ["intensity"] => AnimationPointer::LightIntensity(i),
+                    ["spot", "innerConeAngle"] => AnimationPointer::LightInnerConeAngle(i),
+                    ["spot", "outerConeAngle"] => AnimationPointer::LightOuterConeAngle(i),
+                    _ => return None,
+                }
+            }
+            ["cameras", i, "perspective", "yfov"] => AnimationPointer::CameraYfov(index(i)?),
+            _ => return None,
+        })
+    }
+}
+
+/// Returns the given perspective projection with its vertical field of view
+/// replaced by `yfov`, keeping its aspect ratio and clipping planes.
+pub fn perspective_with_yfov(projection: Mat4, yfov: f32) -> Mat4 {
+    let focal_length = 1.0 / (0.5 * yfov).tan();
+    let mut projection = projection;
+    projection.x_axis.x *= focal_length / projection.y_axis.y;
+    projection.y_axis.y = focal_length;
+    projection
+}
+
 #[derive(Debug)]
 pub enum TweenProperty {
     Translation(Vec3),
     Rotation(Quat),
     Scale(Vec3),
     MorphTargetWeights(Vec<f32>),
+    Pointer(AnimationPointer, Vec<f32>),
 }
 
 impl TweenProperty {
@@ -101,12 +227,20 @@ impl TweenProperty {
         }
     }
 
+    fn as_pointer_values(&self) -> Option<&Vec<f32>> {
+        match self {
+            TweenProperty::Pointer(_, values) => Some(values),
+            _ => None,
+        }
+    }
+
     pub fn description(&self) -> &'static str {
         match self {
             TweenProperty::Translation(_) => "translation",
             TweenProperty::Rotation(_) => "rotation",
             TweenProperty::Scale(_) => "scale",
             TweenProperty::MorphTargetWeights(_) => "morph target",
+            TweenProperty::Pointer(..) => "pointer",
         }
     }
 }
@@ -118,6 +252,7 @@ pub enum TweenProperties {
     Rotations(Vec<Quat>),
     Scales(Vec<Vec3>),
     MorphTargetWeights(Vec<Vec<f32>>),
+    Pointer(AnimationPointer, Vec<Vec<f32>>),
 }
 
 impl TweenProperties {
@@ -135,6 +270,9 @@ impl TweenProperties {
             TweenProperties::MorphTargetWeights(weights) => weights
                 .get(index)
                 .map(|weights| TweenProperty::MorphTargetWeights(weights.clone())),
+            TweenProperties::Pointer(pointer, values) => values
+                .get(index)
+                .map(|values| TweenProperty::Pointer(*pointer, values.clone())),
         }
     }
 
@@ -186,6 +324,17 @@ impl TweenProperties {
                     None
                 }
             }
+            TweenProperties::Pointer(pointer, values) => {
+                if let Some([p0, p1, p2]) = values.get(start..end) {
+                    Some([
+                        TweenProperty::Pointer(*pointer, p0.clone()),
+                        TweenProperty::Pointer(*pointer, p1.clone()),
+                        TweenProperty::Pointer(*pointer, p2.clone()),
+                    ])
+                } else {
+                    None
+                }
+            }
         }
     }
 
@@ -195,6 +344,7 @@ impl TweenProperties {
             TweenProperties::Rotations(_) => "rotation",
             TweenProperties::Scales(_) => "scale",
             TweenProperties::MorphTargetWeights(_) => "morph targets",
+            TweenProperties::Pointer(..) => "pointer",
         }
     }
 }
@@ -207,7 +357,10 @@ pub struct Tween {
     pub properties: TweenProperties,
     // The type of interpolation
     pub interpolation: Interpolation,
-    // The gltf "nodes" index of the target node this tween applies to
+    // The gltf "nodes" index of the target node this tween applies to.
+    //
+    // Unused by tweens of [`TweenProperties::Pointer`], which target the
+    // object named by their pointer instead.
     pub target_node_index: usize,
 }
 
@@ -403,6 +556,25 @@ impl Tween {
                     });
                 TweenProperty::MorphTargetWeights(weights.collect())
             }
+            TweenProperty::Pointer(pointer, from) => {
+                let from_out = from_out
+                    .as_pointer_values()
+                    .context(MismatchedPropertiesSnafu)?;
+                let to_in = to_in
+                    .as_pointer_values()
+                    .context(MismatchedPropertiesSnafu)?;
+                let to = to.as_pointer_values().context(MismatchedPropertiesSnafu)?;
+
+                let values = from
+                    .into_iter()
+                    .zip(from_out.iter().zip(to_in.iter().zip(to.iter())))
+                    .map(|(from, (from_out, (to_in, to)))| -> f32 {
+                        let previous_tangent = from_out * delta_time;
+                        let next_tangent = to_in * delta_time;
+                        cubic_spline(from, previous_tangent, *to, next_tangent, amount)
+                    });
+                TweenProperty::Pointer(pointer, values.collect())
+            }
         }))
     }
 
@@ -461,6 +633,16 @@ impl Tween {
                         .collect(),
                 )
             }
+            TweenProperty::Pointer(pointer, a) => {
+                let b = to.as_pointer_values().context(MismatchedPropertiesSnafu)?;
+                TweenProperty::Pointer(
+                    pointer,
+                    a.into_iter()
+                        .zip(b)
+                        .map(|(a, b)| a + (b - a) * amount)
+                        .collect(),
+                )
+            }
         }))
     }
 
@@ -507,6 +689,16 @@ impl Tween {
                     ws.first().cloned().map(TweenProperty::MorphTargetWeights)
                 }
             }
+            TweenProperties::Pointer(pointer, vs) => {
+                let values = if self.interpolation.is_cubic_spline() {
+                    vs.get(1)
+                } else {
+                    vs.first()
+                };
+                values
+                    .cloned()
+                    .map(|values| TweenProperty::Pointer(*pointer, values))
+            }
         }
     }
 
@@ -546,6 +738,16 @@ impl Tween {
                     ws.last().cloned().map(TweenProperty::MorphTargetWeights)
                 }
             }
+            TweenProperties::Pointer(pointer, vs) => {
+                let values = if self.interpolation.is_cubic_spline() {
+                    vs.get(vs.len() - 2)
+                } else {
+                    vs.last()
+                };
+                values
+                    .cloned()
+                    .map(|values| TweenProperty::Pointer(*pointer, values))
+            }
         }
     }
 }
@@ -557,6 +759,9 @@ pub enum AnimationError {
 
     #[snafu(display("Missing outputs"))]
     MissingOutputs,
+
+    #[snafu(display("Missing sampler {index}"))]
+    MissingSampler { index: usize },
 }
 
 #[derive(Default, Debug, Clone)]
@@ -623,6 +828,47 @@ impl Animation {
             r_animation.tweens.push(tween);
         }
 
+        let pointer_channels = animation
+            .extension_value("KHR_animation_pointer")
+            .and_then(|extension| extension.get("channels"))
+            .and_then(|channels| channels.as_array());
+        for channel in pointer_channels.into_iter().flatten() {
+            let sampler_index = channel
+                .get("sampler")
+                .and_then(|sampler| sampler.as_u64())
+                .unwrap_or(u64::MAX) as usize;
+            let pointer = channel
+                .get("pointer")
+                .and_then(|pointer| pointer.as_str())
+                .unwrap_or_default();
+            log::trace!("  pointer channel {pointer:?}");
+            let Some(pointer) = AnimationPointer::parse(pointer) else {
+                log::warn!("animation {index} has an unsupported pointer {pointer:?}");
+                continue;
+            };
+            let sampler = animation
+                .samplers()
+                .nth(sampler_index)
+                .context(MissingSamplerSnafu {
+                    index: sampler_index,
+                })?;
+            let keyframes = super::accessor::read_f32s::<1>(&sampler.input(), buffer_data)
+                .context(MissingInputsSnafu)?
+                .into_iter()
+                .map(|[t]| Keyframe(t))
+                .collect::<Vec<_>>();
+            let outputs = read_pointer_outputs(&sampler.output(), buffer_data)
+                .context(MissingOutputsSnafu)?;
+            let interpolation = sampler.interpolation().into();
+            log::trace!("    tweens {pointer} with {interpolation} interpolation");
+            r_animation.tweens.push(Tween {
+                keyframes,
+                properties: TweenProperties::Pointer(pointer, outputs),
+                interpolation,
+                target_node_index: usize::MAX,
+            });
+        }
+
         let total_time = r_animation.length_in_seconds();
         log::trace!("  taking {total_time} seconds in total");
         Ok(r_animation)
@@ -663,54 +909,432 @@ impl Animation {
     }
 
     pub fn target_node_indices(&self) -> impl Iterator<Item = usize> + '_ {
-        self.tweens.iter().map(|t| t.target_node_index)
+        self.tweens
+            .iter()
+            .filter(|t| !matches!(t.properties, TweenProperties::Pointer(..)))
+            .map(|t| t.target_node_index)
+    }
+}
+
+/// Reads the output values of a pointer channel's sampler, one `Vec` of
+/// components per output element.
+fn read_pointer_outputs(
+    accessor: &gltf::Accessor,
+    buffer_data: &[gltf::buffer::Data],
+) -> Option<Vec<Vec<f32>>> {
+    fn read<const N: usize>(
+        accessor: &gltf::Accessor,
+        buffer_data: &[gltf::buffer::Data],
+    ) -> Option<Vec<Vec<f32>>> {
+        let values = super::accessor::read_f32s::<N>(accessor, buffer_data)?;
+        Some(values.into_iter().map(Vec::from).collect())
+    }
+    match accessor.dimensions().multiplicity() {
+        1 => read::<1>(accessor, buffer_data),
+        2 => read::<2>(accessor, buffer_data),
+        3 => read::<3>(accessor, buffer_data),
+        4 => read::<4>(accessor, buffer_data),
+        n => {
+            log::warn!("pointer outputs with {n} components are not supported");
+            None
+        }
     }
 }
 
+/// A node that can be animated by an [`Animator`].
+#[derive(Debug, Clone)]
+pub struct AnimationNode {
+    /// Index of the node, as targeted by [`Tween::target_node_index`].
+    pub index: usize,
+    /// The node's transform.
+    pub transform: NestedTransform,
+    /// The node's morph target weights, if any.
+    pub morph_weights: Option<HybridArray<f32>>,
+}
+
+impl From<(usize, NestedTransform)> for AnimationNode {
+    fn from((index, transform): (usize, NestedTransform)) -> Self {
+        AnimationNode {
+            index,
+            transform,
+            morph_weights: None,
+        }
+    }
+}
+
+/// How an [`Animator`] plays its animation.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackMode {
+    /// Wrap around to the start after reaching the end.
+    #[default]
+    Loop,
+    /// Stop after reaching the end.
+    Once,
+    /// Play back towards the start after reaching the end, and forwards again
+    /// after reaching the start.
+    PingPong,
+}
+
+/// A named time in an [`Animator`]'s animation.
+///
+/// Markers are returned from [`Animator::progress`] as they are passed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationMarker {
+    /// Name of the marker.
+    pub name: String,
+    /// Time of the marker, in seconds from the start of the animation.
+    pub time: f32,
+}
+
+/// Returns the positions of `position`, repeating every `period` if any,
+/// that lie between `start` (exclusive) and `end` (inclusive).
+fn passed_positions(start: f32, end: f32, position: f32, period: Option<f32>) -> Vec<f32> {
+    let is_passed = |u: f32| {
+        if start < end {
+            start < u && u <= end
+        } else {
+            end <= u && u < start
+        }
+    };
+    let Some(period) = period else {
+        return Some(position)
+            .filter(|u| is_passed(*u))
+            .into_iter()
+            .collect();
+    };
+    let (lo, hi) = (start.min(end), start.max(end));
+    let mut u = position + ((lo - position) / period).floor() * period;
+    let mut passed = vec![];
+    while u <= hi {
+        if is_passed(u) {
+            passed.push(u);
+        }
+        u += period;
+    }
+    passed
+}
+
 /// Combines [`NestedTransform`] and [`Animation`] to progress an animation.
 ///
-/// Applies animations to a list of `(usize, [NestedTransform])` and keeps track
+/// Applies animations to a list of [`AnimationNode`]s and keeps track
 /// of how much time has elapsed.
 ///
+/// By default the animation loops at normal speed, see [`PlaybackMode`] and
+/// [`Animator::set_speed`] for other ways to play it.
+///
 /// To function without errors, the [`Animation`]'s tweens'
 /// [`Tween::target_node_index`] must point to the index of [`NestedTransform`].
-#[derive(Default, Debug, Clone)]
+///
+/// Tweens of [`TweenProperties::Pointer`] are applied to the materials, lights
+/// and cameras given by [`Animator::set_pointer_targets`].
+#[derive(Debug, Clone)]
 pub struct Animator {
     /// A time to use as the current amount of seconds elapsed in the running
     /// of the current animation.
     pub timestamp: f32,
     /// All nodes under this animator's control.
     pub nodes: rustc_hash::FxHashMap<usize, NestedTransform>,
+    /// Morph target weights of the nodes under this animator's control.
+    pub morph_weights: rustc_hash::FxHashMap<usize, HybridArray<f32>>,
     /// The animation that will apply to the nodes.
     pub animation: Animation,
+    /// Materials targeted by [`AnimationPointer`]s, indexed by gltf material
+    /// index.
+    pub materials: Option<HybridArray<Material>>,
+    /// Lights targeted by [`AnimationPointer`]s, keyed by gltf light index.
+    pub lights: rustc_hash::FxHashMap<usize, LightDetails>,
+    /// Cameras targeted by [`AnimationPointer`]s, keyed by gltf camera index.
+    pub cameras: rustc_hash::FxHashMap<usize, Hybrid<Camera>>,
+    /// How the animation plays.
+    pub mode: PlaybackMode,
+    /// Multiplier of the time given to [`Animator::progress`].
+    ///
+    /// Negative speeds play the animation in reverse.
+    pub speed: f32,
+    /// Markers that are returned from [`Animator::progress`] as they are
+    /// passed.
+    pub markers: Vec<AnimationMarker>,
+    /// Whether a [`PlaybackMode::PingPong`] animation is on its way back to
+    /// the start.
+    returning: bool,
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Animator {
+            timestamp: 0.0,
+            nodes: Default::default(),
+            morph_weights: Default::default(),
+            animation: Default::default(),
+            materials: None,
+            lights: Default::default(),
+            cameras: Default::default(),
+            mode: PlaybackMode::default(),
+            speed: 1.0,
+            markers: vec![],
+            returning: false,
+        }
+    }
 }
 
 impl Animator {
     /// Create a new animator with the given nodes and animation.
     pub fn new(
-        nodes: impl IntoIterator<Item = impl Into<(usize, NestedTransform)>>,
+        nodes: impl IntoIterator<Item = impl Into<AnimationNode>>,
         animation: Animation,
     ) -> Self {
-        let nodes = nodes.into_iter().map(|n| n.into());
-        let nodes = rustc_hash::FxHashMap::from_iter(nodes);
-        Animator {
-            nodes,
+        let mut animator = Animator {
             animation,
             ..Default::default()
+        };
+        for node in nodes.into_iter().map(|n| n.into()) {
+            if let Some(weights) = node.morph_weights {
+                animator.morph_weights.insert(node.index, weights);
+            }
+            animator.nodes.insert(node.index, node.transform);
+        }
+        animator
+    }
+
+    /// Set the materials, lights and cameras of the given document as targets
+    /// of the animation's [`AnimationPointer`]s.
+    pub fn set_pointer_targets(&mut self, document: &GltfDocument) {
+        self.materials = Some(document.materials.clone());
+        self.lights = document
+            .lights
+            .iter()
+            .enumerate()
+            .map(|(i, light)| (i, light.details.clone()))
+            .collect();
+        self.cameras = document
+            .cameras
+            .iter()
+            .map(|camera| (camera.index, camera.camera.clone()))
+            .collect();
+    }
+
+    /// Set the materials, lights and cameras of the given document as targets
+    /// of the animation's [`AnimationPointer`]s.
+    pub fn with_pointer_targets(mut self, document: &GltfDocument) -> Self {
+        self.set_pointer_targets(document);
+        self
+    }
+
+    /// Set how the animation plays.
+    pub fn set_mode(&mut self, mode: PlaybackMode) {
+        self.mode = mode;
+    }
+
+    /// Set how the animation plays.
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.set_mode(mode);
+        self
+    }
+
+    /// Set the multiplier of the time given to [`Animator::progress`].
+    ///
+    /// Negative speeds play the animation in reverse.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Set the multiplier of the time given to [`Animator::progress`].
+    ///
+    /// Negative speeds play the animation in reverse.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.set_speed(speed);
+        self
+    }
+
+    /// Add a marker that will be returned from [`Animator::progress`] when
+    /// the given time is passed.
+    pub fn add_marker(&mut self, name: impl Into<String>, time: f32) {
+        self.markers.push(AnimationMarker {
+            name: name.into(),
+            time,
+        });
+    }
+
+    /// Add a marker that will be returned from [`Animator::progress`] when
+    /// the given time is passed.
+    pub fn with_marker(mut self, name: impl Into<String>, time: f32) -> Self {
+        self.add_marker(name, time);
+        self
+    }
+
+    /// Returns whether a [`PlaybackMode::Once`] animation has reached its end,
+    /// or its start when playing in reverse.
+    ///
+    /// Animations in other modes never finish.
+    pub fn is_finished(&self) -> bool {
+        self.mode == PlaybackMode::Once
+            && if self.speed < 0.0 {
+                self.timestamp <= 0.0
+            } else {
+                self.timestamp >= self.animation.length_in_seconds()
+            }
+    }
+
+    /// Jump to the given time, applying any tweened properties to the
+    /// animator's nodes.
+    ///
+    /// The time is clamped to the length of the animation. No markers are
+    /// passed by seeking, and [`PlaybackMode::PingPong`] animations continue
+    /// forwards from the new time.
+    pub fn seek(&mut self, time: f32) -> Result<(), InterpolationError> {
+        self.timestamp = time.clamp(0.0, self.animation.length_in_seconds());
+        self.returning = false;
+        self.apply()
+    }
+
+    /// Move the timestamp by `delta` seconds according to the playback mode,
+    /// returning the markers passed on the way.
+    pub(crate) fn advance(&mut self, delta: f32) -> Vec<AnimationMarker> {
+        let length = self.animation.length_in_seconds();
+        if length <= 0.0 {
+            self.timestamp = 0.0;
+            return vec![];
+        }
+        // Ping-pong animations are tracked along a timeline twice as long,
+        // where the second half plays the animation backwards.
+        let (start, period) = match self.mode {
+            PlaybackMode::Loop => (self.timestamp, Some(length)),
+            PlaybackMode::Once => (self.timestamp, None),
+            PlaybackMode::PingPong if self.returning => {
+                (2.0 * length - self.timestamp, Some(2.0 * length))
+            }
+            PlaybackMode::PingPong => (self.timestamp, Some(2.0 * length)),
+        };
+        let end = if self.mode == PlaybackMode::Once {
+            (start + delta).clamp(0.0, length)
+        } else {
+            start + delta
+        };
+
+        let mut passed = vec![];
+        for marker in self.markers.iter() {
+            let mut positions = vec![marker.time];
+            if self.mode == PlaybackMode::PingPong && 0.0 < marker.time && marker.time < length {
+                positions.push(2.0 * length - marker.time);
+            }
+            for position in positions {
+                for u in passed_positions(start, end, position, period) {
+                    passed.push(((u - start).abs(), marker.clone()));
+                }
+            }
+        }
+        passed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        match self.mode {
+            PlaybackMode::Loop => self.timestamp = end.rem_euclid(length),
+            PlaybackMode::Once => self.timestamp = end,
+            PlaybackMode::PingPong => {
+                let u = end.rem_euclid(2.0 * length);
+                self.returning = u > length;
+                self.timestamp = if self.returning { 2.0 * length - u } else { u };
+            }
+        }
+        passed.into_iter().map(|(_, marker)| marker).collect()
+    }
+
+    /// Apply the values of a pointer tween to its target.
+    fn apply_pointer(&self, pointer: AnimationPointer, values: &[f32]) {
+        let scalar = values.first().copied().unwrap_or_default();
+        let vec3 = || Vec3::from_slice(&[values, &[0.0; 3]].concat());
+        let material = |index: usize, f: &dyn Fn(&mut Material)| {
+            let modified = self
+                .materials
+                .as_ref()
+                .and_then(|materials| materials.modify(index, |material| f(material)));
+            if modified.is_none() {
+                log::warn!("material {index} isn't in the animator's list of materials");
+            }
+        };
+        let light = |index: usize, f: &dyn Fn(&mut Vec4, &mut f32)| match self.lights.get(&index) {
+            Some(LightDetails::Directional(light)) => {
+                light.modify(|l| f(&mut l.color, &mut l.intensity))
+            }
+            Some(LightDetails::Point(light)) => light.modify(|l| f(&mut l.color, &mut l.intensity)),
+            Some(LightDetails::Spot(light)) => light.modify(|l| f(&mut l.color, &mut l.intensity)),
+            None => log::warn!("light {index} isn't in the animator's list of lights"),
+        };
+        let spot = |index: usize, f: &dyn Fn(&mut SpotLight)| {
+            if let Some(LightDetails::Spot(light)) = self.lights.get(&index) {
+                light.modify(f);
+            } else {
+                log::warn!("light {index} isn't a spot light in the animator's list of lights");
+            }
+        };
+        match pointer {
+            AnimationPointer::MaterialAlbedoFactor(i) => {
+                let albedo = Vec4::from_slice(&[values, &[1.0; 4]].concat());
+                material(i, &|m| m.albedo_factor = albedo);
+            }
+            AnimationPointer::MaterialMetallicFactor(i) => {
+                material(i, &|m| m.metallic_factor = scalar)
+            }
+            AnimationPointer::MaterialRoughnessFactor(i) => {
+                material(i, &|m| m.roughness_factor = scalar)
+            }
+            AnimationPointer::MaterialEmissiveFactor(i) => {
+                let emissive = vec3();
+                material(i, &|m| m.emissive_factor = emissive);
+            }
+            AnimationPointer::MaterialEmissiveStrength(i) => {
+                material(i, &|m| m.emissive_strength_multiplier = scalar)
+            }
+            AnimationPointer::MaterialAlphaCutoff(i) => material(i, &|m| m.alpha_cutoff = scalar),
+            AnimationPointer::MaterialOcclusionStrength(i) => {
+                material(i, &|m| m.ao_strength = scalar)
+            }
+            AnimationPointer::LightColor(i) => {
+                let rgb = vec3();
+                light(i, &|color, _| *color = rgb.extend(color.w));
+            }
+            AnimationPointer::LightIntensity(i) => light(i, &|_, intensity| *intensity = scalar),
+            AnimationPointer::LightInnerConeAngle(i) => spot(i, &|l| l.inner_cutoff = scalar),
+            AnimationPointer::LightOuterConeAngle(i) => spot(i, &|l| l.outer_cutoff = scalar),
+            AnimationPointer::CameraYfov(i) => {
+                if let Some(camera) = self.cameras.get(&i) {
+                    camera.modify(|camera| {
+                        let projection = perspective_with_yfov(camera.projection, scalar);
+                        camera.set_projection(projection);
+                    });
+                } else {
+                    log::warn!("camera {i} isn't in the animator's list of cameras");
+                }
+            }
         }
     }
 
     /// Progress the animator's animation, applying any tweened properties to
     /// the animator's nodes.
-    pub fn progress(&mut self, dt_seconds: f32) -> Result<(), InterpolationError> {
+    ///
+    /// `dt_seconds` is scaled by the animator's speed, and the animation's
+    /// timestamp moves according to its [`PlaybackMode`].
+    ///
+    /// Returns the markers passed, in the order they were passed.
+    pub fn progress(
+        &mut self,
+        dt_seconds: f32,
+    ) -> Result<Vec<AnimationMarker>, InterpolationError> {
         log::trace!(
             "progressing '{}' {dt_seconds} seconds",
             self.animation.name.as_deref().unwrap_or("")
         );
-        let max_length_seconds = self.animation.length_in_seconds();
-        log::trace!("  total: {max_length_seconds}");
-        self.timestamp = (self.timestamp + dt_seconds) % max_length_seconds;
+        log::trace!("  total: {}", self.animation.length_in_seconds());
+        let markers = self.advance(dt_seconds * self.speed);
         log::trace!("  current: {}", self.timestamp);
+        for marker in markers.iter() {
+            log::trace!("  passed marker '{}'", marker.name);
+        }
+        self.apply()?;
+        Ok(markers)
+    }
+
+    /// Apply the tweened properties at the current timestamp to the animator's
+    /// nodes.
+    fn apply(&self) -> Result<(), InterpolationError> {
         let properties = self.animation.get_properties_at_time(self.timestamp)?;
         log::trace!("  {} properties", properties.len());
         for (node_index, property) in properties.into_iter() {
@@ -721,7 +1345,17 @@ impl Animator {
             // * business logic has removed it
             // * ...and the beat goes on
             // So we won't fret if we can't find it...
-            if let Some(transform) = self.nodes.get(&node_index) {
+            if let TweenProperty::Pointer(pointer, values) = property {
+                self.apply_pointer(pointer, &values);
+            } else if let TweenProperty::MorphTargetWeights(weights) = property {
+                if let Some(morph_weights) = self.morph_weights.get(&node_index) {
+                    for (i, weight) in weights.into_iter().enumerate().take(morph_weights.len()) {
+                        morph_weights.set_item(i, weight);
+                    }
+                } else {
+                    log::warn!("node {node_index} has no morph target weights in the animator");
+                }
+            } else if let Some(transform) = self.nodes.get(&node_index) {
                 match property {
                     TweenProperty::Translation(translation) => {
                         transform.modify_local_transform(|t| {
@@ -738,7 +1372,8 @@ impl Animator {
                             t.scale = scale;
                         });
                     }
-                    TweenProperty::MorphTargetWeights(_) => todo!(),
+                    // Handled above
+                    TweenProperty::MorphTargetWeights(_) | TweenProperty::Pointer(..) => {}
                 }
             } else {
                 log::warn!("node {node_index} isn't in the animator's list of nodes");
@@ -750,7 +1385,208 @@ impl Animator {
 
 #[cfg(test)]
 mod test {
-    use crate::{camera::Camera, math::Vec3, stage::Animator, Context};
+    use std::sync::Mutex;
+
+    use glam::{Mat4, Vec4};
+
+    use crate::{
+        camera::Camera,
+        math::Vec3,
+        pbr::{light::SpotLight, Material},
+        slab::SlabAllocator,
+        stage::{
+            Animation, AnimationMarker, AnimationPointer, Animator, Interpolation, Keyframe,
+            LightDetails, NestedTransform, PlaybackMode, Tween, TweenProperties,
+        },
+        Context,
+    };
+
+    #[test]
+    fn animation_pointers_round_trip() {
+        for pointer in [
+            AnimationPointer::MaterialAlbedoFactor(0),
+            AnimationPointer::MaterialMetallicFactor(1),
+            AnimationPointer::MaterialRoughnessFactor(2),
+            AnimationPointer::MaterialEmissiveFactor(3),
+            AnimationPointer::MaterialEmissiveStrength(4),
+            AnimationPointer::MaterialAlphaCutoff(5),
+            AnimationPointer::MaterialOcclusionStrength(6),
+            AnimationPointer::LightColor(7),
+            AnimationPointer::LightIntensity(8),
+            AnimationPointer::LightInnerConeAngle(9),
+            AnimationPointer::LightOuterConeAngle(10),
+            AnimationPointer::CameraYfov(11),
+        ] {
+            assert_eq!(Some(pointer), AnimationPointer::parse(&pointer.to_string()));
+        }
+        assert_eq!(None, AnimationPointer::parse("/nodes/0/translation"));
+        assert_eq!(None, AnimationPointer::parse("/materials/zero/alphaCutoff"));
+        assert_eq!(None, AnimationPointer::parse("materials/0/alphaCutoff"));
+    }
+
+    #[test]
+    fn perspective_with_yfov_sanity() {
+        let from = Mat4::perspective_rh(0.5, 1.5, 0.1, 100.0);
+        let to = Mat4::perspective_rh(1.2, 1.5, 0.1, 100.0);
+        assert!(super::perspective_with_yfov(from, 1.2).abs_diff_eq(to, 1e-5));
+
+        let from = Mat4::perspective_infinite_rh(0.5, 1.5, 0.1);
+        let to = Mat4::perspective_infinite_rh(1.2, 1.5, 0.1);
+        assert!(super::perspective_with_yfov(from, 1.2).abs_diff_eq(to, 1e-5));
+    }
+
+    #[test]
+    fn animator_applies_pointers() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let materials = slab.new_array([Material::default()]);
+        let spot = slab.new_value(SpotLight::default());
+        let projection = Mat4::perspective_rh(0.5, 1.0, 0.1, 100.0);
+        let camera = slab.new_value(Camera::new(projection, Mat4::IDENTITY));
+
+        let tween = |pointer, values: Vec<Vec<f32>>| Tween {
+            keyframes: vec![Keyframe(0.0), Keyframe(1.0)],
+            properties: TweenProperties::Pointer(pointer, values),
+            interpolation: Interpolation::Linear,
+            target_node_index: usize::MAX,
+        };
+        let animation = Animation {
+            tweens: vec![
+                tween(
+                    AnimationPointer::MaterialAlbedoFactor(0),
+                    vec![vec![1.0, 1.0, 1.0, 1.0], vec![1.0, 1.0, 1.0, 0.0]],
+                ),
+                tween(
+                    AnimationPointer::LightIntensity(0),
+                    vec![vec![4.0], vec![0.0]],
+                ),
+                tween(
+                    AnimationPointer::LightColor(0),
+                    vec![vec![1.0, 0.0, 0.0], vec![0.0, 0.0, 1.0]],
+                ),
+                tween(
+                    AnimationPointer::LightOuterConeAngle(0),
+                    vec![vec![1.0], vec![0.0]],
+                ),
+                tween(AnimationPointer::CameraYfov(0), vec![vec![0.5], vec![1.5]]),
+            ],
+            name: None,
+        };
+        let mut animator = Animator::new(Vec::<(usize, _)>::new(), animation);
+        animator.materials = Some(materials.clone());
+        animator.lights.insert(0, LightDetails::Spot(spot.clone()));
+        animator.cameras.insert(0, camera.clone());
+
+        animator.progress(0.25).unwrap();
+        assert_eq!(
+            Vec4::new(1.0, 1.0, 1.0, 0.75),
+            materials.get(0).unwrap().albedo_factor
+        );
+        let light = spot.get();
+        assert_eq!(3.0, light.intensity);
+        assert_eq!(Vec4::new(0.75, 0.0, 0.25, 1.0), light.color);
+        assert_eq!(0.75, light.outer_cutoff);
+        let expected = Mat4::perspective_rh(0.75, 1.0, 0.1, 100.0);
+        assert!(camera.get().projection.abs_diff_eq(expected, 1e-5));
+    }
+
+    /// Returns an animator that moves a node along the x axis from 0 to 2
+    /// over two seconds, along with the node's transform and its slab.
+    fn x_axis_animator() -> (Animator, NestedTransform, SlabAllocator<Mutex<Vec<u32>>>) {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let transform = NestedTransform::new(&mut slab);
+        let animation = Animation {
+            tweens: vec![Tween {
+                keyframes: vec![Keyframe(0.0), Keyframe(2.0)],
+                properties: TweenProperties::Translations(vec![Vec3::ZERO, Vec3::X * 2.0]),
+                interpolation: Interpolation::Linear,
+                target_node_index: 0,
+            }],
+            name: None,
+        };
+        let animator = Animator::new([(0, transform.clone())], animation);
+        (animator, transform, slab)
+    }
+
+    #[test]
+    fn playback_modes() {
+        let (animator, transform, _slab) = x_axis_animator();
+        let x = || transform.get_local_transform().translation.x;
+
+        let mut looping = animator.clone();
+        looping.progress(1.5).unwrap();
+        assert_eq!(1.5, x());
+        looping.progress(1.0).unwrap();
+        assert_eq!(0.5, looping.timestamp);
+        assert!(!looping.is_finished());
+
+        let mut once = animator.clone().with_mode(PlaybackMode::Once);
+        once.progress(1.5).unwrap();
+        assert!(!once.is_finished());
+        once.progress(1.0).unwrap();
+        assert_eq!(2.0, x());
+        assert!(once.is_finished());
+        once.progress(1.0).unwrap();
+        assert_eq!(2.0, x());
+
+        let mut ping_pong = animator.clone().with_mode(PlaybackMode::PingPong);
+        ping_pong.progress(1.5).unwrap();
+        assert_eq!(1.5, x());
+        ping_pong.progress(1.0).unwrap();
+        assert_eq!(1.5, x());
+        ping_pong.progress(1.0).unwrap();
+        assert_eq!(0.5, x());
+        ping_pong.progress(1.0).unwrap();
+        assert_eq!(0.5, x());
+
+        let mut reversed = animator
+            .clone()
+            .with_mode(PlaybackMode::Once)
+            .with_speed(-2.0);
+        reversed.seek(2.0).unwrap();
+        assert_eq!(2.0, x());
+        reversed.progress(0.25).unwrap();
+        assert_eq!(1.5, x());
+        assert!(!reversed.is_finished());
+        reversed.progress(1.0).unwrap();
+        assert_eq!(0.0, x());
+        assert!(reversed.is_finished());
+    }
+
+    #[test]
+    fn playback_markers() {
+        let (animator, _transform, _slab) = x_axis_animator();
+        let animator = animator
+            .with_marker("start", 0.0)
+            .with_marker("middle", 1.0);
+        let names = |markers: Vec<AnimationMarker>| {
+            markers
+                .into_iter()
+                .map(|marker| marker.name)
+                .collect::<Vec<_>>()
+        };
+
+        let mut looping = animator.clone();
+        assert!(looping.progress(0.5).unwrap().is_empty());
+        assert_eq!(vec!["middle"], names(looping.progress(1.0).unwrap()));
+        assert_eq!(
+            vec!["start", "middle", "start"],
+            names(looping.progress(3.0).unwrap())
+        );
+
+        let mut once = animator.clone().with_mode(PlaybackMode::Once);
+        assert_eq!(vec!["middle"], names(once.progress(5.0).unwrap()));
+        assert!(once.progress(5.0).unwrap().is_empty());
+
+        let mut ping_pong = animator.clone().with_mode(PlaybackMode::PingPong);
+        assert_eq!(
+            vec!["middle", "middle", "start"],
+            names(ping_pong.progress(4.0).unwrap())
+        );
+
+        let mut seeking = animator.clone();
+        seeking.seek(1.5).unwrap();
+        assert!(seeking.progress(0.25).unwrap().is_empty());
+    }
 
     #[test]
     fn gltf_simple_animation() {
@@ -788,4 +1624,50 @@ mod test {
             frame.present();
         }
     }
+
+    #[test]
+    fn gltf_simple_morph_animation() {
+        let ctx = Context::headless(16, 16);
+        let mut stage = ctx
+            .new_stage()
+            .with_bloom(false)
+            .with_background_color(Vec3::ZERO.extend(1.0));
+        let projection = crate::camera::perspective(50.0, 50.0);
+        let view =
+            crate::camera::look_at(Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.5, 0.5, 0.0), Vec3::Y);
+        let camera = stage.new_value(Camera::new(projection, view));
+
+        let doc = stage
+            .load_gltf_document_from_path("../../gltf/simple_morph_triangle.gltf", camera.id())
+            .unwrap();
+        let node = doc.nodes.first().unwrap();
+        let weights = || {
+            (0..node.weights.len())
+                .filter_map(|i| node.weights.get(i))
+                .collect::<Vec<_>>()
+        };
+        // The node has no weights of its own, so it uses its mesh's
+        assert_eq!(vec![1.0, 0.5], weights());
+
+        let nodes = doc.nodes_in_scene(doc.default_scene.unwrap_or_default());
+        let mut animator = Animator::new(nodes, doc.animations.first().unwrap().clone());
+
+        let frame = ctx.get_next_frame().unwrap();
+        stage.render(&frame.view());
+        let img = frame.read_image().unwrap();
+        img_diff::save("animation/morph_triangle.png", img);
+        frame.present();
+
+        let dt = 1.0 / 2.0;
+        for i in 1..=8 {
+            animator.progress(dt).unwrap();
+            let frame = ctx.get_next_frame().unwrap();
+            stage.render(&frame.view());
+            let img = frame.read_image().unwrap();
+            img_diff::save(&format!("animation/morph_triangle{i}.png"), img);
+            frame.present();
+        }
+        // The animation is 4 seconds long, so we've looped back to the start
+        assert_eq!(vec![0.0, 0.0], weights());
+    }
 }
@@ -0,0 +1,1219 @@
+//! Exporting staged gltf documents as binary gltf.
+//!
+//! Documents are written with the values they currently hold on the CPU, so
+//! edits made after loading (moved nodes, changed materials, modified
+//! vertices) are saved.
+use std::collections::BTreeSet;
+
+use crabslab::{Array, Id, SlabItem};
+use glam::{Mat4, Vec3};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_json::{json, Value};
+use snafu::ResultExt;
+
+use crate::{
+    atlas::{AtlasTexture, TextureAddressMode},
+    pbr::{AlphaMode, Material, TextureTransform},
+    slab::Hybrid,
+    stage::{JointInfluences, Stage, Topology, Vertex},
+};
+
+use super::{
+    EncodeImageSnafu, GltfCamera, GltfDocument, Interpolation, LightDetails, StageGltfError,
+    TweenProperties, WriteGlbSnafu,
+};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+/// Returns the index of the item with the given [`Id`] in an array, if the
+/// id points to one of its items.
+fn index_in<T: SlabItem>(array: Array<T>, id: Id<T>) -> Option<usize> {
+    if id.is_none() {
+        return None;
+    }
+    let offset = id.index().checked_sub(array.starting_index())?;
+    let index = offset / T::SLAB_SIZE;
+    (offset % T::SLAB_SIZE == 0 && index < array.len()).then_some(index)
+}
+
+/// Returns the value of the hybrid with the given [`Id`], if any.
+fn find<T: SlabItem + Clone + Send + Sync + 'static>(
+    hybrids: &[Hybrid<T>],
+    id: Id<T>,
+) -> Option<T> {
+    if id.is_none() {
+        return None;
+    }
+    hybrids
+        .iter()
+        .find(|hybrid| hybrid.id() == id)
+        .map(Hybrid::get)
+}
+
+fn wrapping_mode(mode: TextureAddressMode) -> u32 {
+    match mode {
+        TextureAddressMode::ClampToEdge => 33071,
+        TextureAddressMode::MirroredRepeat => 33648,
+        TextureAddressMode::Repeat => 10497,
+    }
+}
+
+fn interpolation_name(interpolation: Interpolation) -> &'static str {
+    match interpolation {
+        Interpolation::Linear => "LINEAR",
+        Interpolation::Step => "STEP",
+        Interpolation::CubicSpline => "CUBICSPLINE",
+    }
+}
+
+/// Returns the gltf camera with the given projection matrix.
+///
+/// Cameras are loaded into projection matrices, so their parameters are
+/// recovered from the matrix.
+fn projection_json(m: Mat4) -> Value {
+    if m.w_axis.w == 0.0 {
+        let mut perspective = json!({
+            "yfov": 2.0 * (1.0 / m.y_axis.y).atan(),
+            "aspectRatio": m.y_axis.y / m.x_axis.x,
+        });
+        if m.z_axis.z == -1.0 {
+            perspective["znear"] = json!(-m.w_axis.z);
+        } else {
+            let r = m.z_axis.z;
+            let znear = m.w_axis.z / r;
+            perspective["znear"] = json!(znear);
+            perspective["zfar"] = json!(r * znear / (1.0 + r));
+        }
+        json!({"type": "perspective", "perspective": perspective})
+    } else {
+        let znear = m.w_axis.z / m.z_axis.z;
+        json!({
+            "type": "orthographic",
+            "orthographic": {
+                "xmag": 1.0 / m.x_axis.x,
+                "ymag": 1.0 / m.y_axis.y,
+                "znear": znear,
+                "zfar": znear - 1.0 / m.z_axis.z,
+            }
+        })
+    }
+}
+
+fn camera_json(camera: &GltfCamera) -> Value {
+    let mut json = projection_json(camera.projection);
+    if let Some(name) = &camera.name {
+        json["name"] = json!(name);
+    }
+    json
+}
+
+/// The binary buffer of a GLB, along with the buffer views and accessors
+/// that describe it.
+#[derive(Default)]
+struct GlbBuffer {
+    bin: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl GlbBuffer {
+    /// Appends a buffer view of the given bytes, returning its index.
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Appends an accessor of the given bytes, returning its index.
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        target: Option<u32>,
+        component_type: u32,
+        count: usize,
+        element_type: &str,
+    ) -> usize {
+        let view = self.push_view(bytes, target);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component_type,
+            "count": count,
+            "type": element_type,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Appends an accessor of `N` floats per element, returning its index.
+    ///
+    /// The accessor's `min` and `max` are written if `bounds` is set, as
+    /// gltf requires for positions and animation inputs.
+    fn push_f32s<const N: usize>(
+        &mut self,
+        values: &[[f32; N]],
+        target: Option<u32>,
+        bounds: bool,
+    ) -> usize {
+        let element_type = match N {
+            1 => "SCALAR",
+            2 => "VEC2",
+            3 => "VEC3",
+            4 => "VEC4",
+            16 => "MAT4",
+            _ => unreachable!("no gltf element type has {N} floats"),
+        };
+        let bytes = values
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        let index = self.push_accessor(&bytes, target, FLOAT, values.len(), element_type);
+        if let (true, Some(first)) = (bounds, values.first()) {
+            let (min, max) = values
+                .iter()
+                .fold((*first, *first), |(mut min, mut max), v| {
+                    for i in 0..N {
+                        min[i] = min[i].min(v[i]);
+                        max[i] = max[i].max(v[i]);
+                    }
+                    (min, max)
+                });
+            self.accessors[index]["min"] = json!(min.to_vec());
+            self.accessors[index]["max"] = json!(max.to_vec());
+        }
+        index
+    }
+
+    /// Appends accessors of the attributes of a primitive's vertices,
+    /// returning the primitive's attributes object.
+    ///
+    /// Tangents are only written for triangles whose tangents all have a
+    /// handedness of `1` or `-1`, as gltf requires. Points, lines and meshes
+    /// without tangents leave them out.
+    fn vertex_attributes(
+        &mut self,
+        topology: Topology,
+        vertices: &[Vertex],
+        extra_influences: &[JointInfluences],
+    ) -> Value {
+        let positions = vertices
+            .iter()
+            .map(|v| v.position.to_array())
+            .collect::<Vec<_>>();
+        let normals = vertices
+            .iter()
+            .map(|v| v.normal.to_array())
+            .collect::<Vec<_>>();
+        let uv0s = vertices
+            .iter()
+            .map(|v| v.uv0.to_array())
+            .collect::<Vec<_>>();
+        let uv1s = vertices
+            .iter()
+            .map(|v| v.uv1.to_array())
+            .collect::<Vec<_>>();
+        let colors = vertices
+            .iter()
+            .map(|v| v.color.to_array())
+            .collect::<Vec<_>>();
+        let mut attributes = json!({
+            "POSITION": self.push_f32s(&positions, Some(ARRAY_BUFFER), true),
+            "NORMAL": self.push_f32s(&normals, Some(ARRAY_BUFFER), false),
+            "TEXCOORD_0": self.push_f32s(&uv0s, Some(ARRAY_BUFFER), false),
+            "TEXCOORD_1": self.push_f32s(&uv1s, Some(ARRAY_BUFFER), false),
+            "COLOR_0": self.push_f32s(&colors, Some(ARRAY_BUFFER), false),
+        });
+        let has_tangents =
+            !vertices.is_empty() && vertices.iter().all(|v| v.tangent.w.abs() == 1.0);
+        if topology == Topology::TriangleList && has_tangents {
+            let tangents = vertices
+                .iter()
+                .map(|v| v.tangent.to_array())
+                .collect::<Vec<_>>();
+            attributes["TANGENT"] = json!(self.push_f32s(&tangents, Some(ARRAY_BUFFER), false));
+        }
+        // vertices of meshes without joints are loaded with `f32::MAX` weights
+        if vertices.iter().any(|v| v.weights != [f32::MAX; 4]) {
+            let first = vertices
+                .iter()
+                .map(|v| JointInfluences {
+                    joints: v.joints,
+                    weights: v.weights,
+                })
+                .collect::<Vec<_>>();
+            let mut sets = vec![first];
+            let extra_sets = extra_influences.len() / vertices.len().max(1);
+            for set in 0..extra_sets {
+                sets.push(
+                    extra_influences
+                        .iter()
+                        .skip(set)
+                        .step_by(extra_sets)
+                        .copied()
+                        .collect(),
+                );
+            }
+            for (i, set) in sets.iter().enumerate() {
+                let joints = set
+                    .iter()
+                    .flat_map(|influence| influence.joints)
+                    .flat_map(|joint| (joint as u16).to_le_bytes())
+                    .collect::<Vec<_>>();
+                let weights = set
+                    .iter()
+                    .map(|influence| influence.weights)
+                    .collect::<Vec<_>>();
+                attributes[format!("JOINTS_{i}")] = json!(self.push_accessor(
+                    &joints,
+                    Some(ARRAY_BUFFER),
+                    UNSIGNED_SHORT,
+                    vertices.len(),
+                    "VEC4"
+                ));
+                attributes[format!("WEIGHTS_{i}")] =
+                    json!(self.push_f32s(&weights, Some(ARRAY_BUFFER), false));
+            }
+        }
+        attributes
+    }
+
+    /// Packs the buffer and the given document into a GLB.
+    fn into_glb(self, mut root: Value) -> Vec<u8> {
+        let GlbBuffer {
+            mut bin,
+            views,
+            accessors,
+        } = self;
+        if !bin.is_empty() {
+            root["buffers"] = json!([{"byteLength": bin.len()}]);
+            root["bufferViews"] = Value::Array(views);
+            root["accessors"] = Value::Array(accessors);
+        }
+        // UNWRAP: values built with `json!` always serialize
+        let mut json = serde_json::to_vec(&root).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+        let mut glb = Vec::with_capacity(length);
+        glb.extend(b"glTF");
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((length as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        if !bin.is_empty() {
+            glb.extend((bin.len() as u32).to_le_bytes());
+            glb.extend(b"BIN\0");
+            glb.extend(bin);
+        }
+        glb
+    }
+}
+
+/// Builds the json and binary buffer of an exported document.
+struct Exporter<'a> {
+    document: &'a GltfDocument,
+    buffer: GlbBuffer,
+    extensions_used: BTreeSet<&'static str>,
+    /// Indices of textures that hold colors, which are stored linearly in
+    /// the atlas but sRGB encoded in gltf.
+    color_textures: FxHashSet<usize>,
+}
+
+impl<'a> Exporter<'a> {
+    /// Returns a texture info object, if the id points to one of the
+    /// document's textures.
+    fn texture_info(
+        &mut self,
+        id: Id<AtlasTexture>,
+        tex_coord: u32,
+        transform: TextureTransform,
+    ) -> Option<Value> {
+        let index = index_in(self.document.textures.array(), id)?;
+        let mut info = json!({"index": index, "texCoord": tex_coord});
+        if transform != TextureTransform::default() {
+            self.extensions_used.insert("KHR_texture_transform");
+            info["extensions"] = json!({
+                "KHR_texture_transform": {
+                    "offset": transform.offset.to_array(),
+                    "rotation": transform.rotation,
+                    "scale": transform.scale.to_array(),
+                }
+            });
+        }
+        Some(info)
+    }
+
+    /// Like [`Exporter::texture_info`], for textures that hold colors.
+    fn color_texture_info(
+        &mut self,
+        id: Id<AtlasTexture>,
+        tex_coord: u32,
+        transform: TextureTransform,
+    ) -> Option<Value> {
+        let info = self.texture_info(id, tex_coord, transform)?;
+        if let Some(index) = index_in(self.document.textures.array(), id) {
+            self.color_textures.insert(index);
+        }
+        Some(info)
+    }
+
+    fn material(&mut self, material: &Material) -> Value {
+        let mut pbr = json!({
+            "baseColorFactor": material.albedo_factor.to_array(),
+            "metallicFactor": material.metallic_factor,
+            "roughnessFactor": material.roughness_factor,
+        });
+        if let Some(info) = self.color_texture_info(
+            material.albedo_texture_id,
+            material.albedo_tex_coord,
+            material.albedo_tex_transform,
+        ) {
+            pbr["baseColorTexture"] = info;
+        }
+        if let Some(info) = self.texture_info(
+            material.metallic_roughness_texture_id,
+            material.metallic_roughness_tex_coord,
+            material.metallic_roughness_tex_transform,
+        ) {
+            pbr["metallicRoughnessTexture"] = info;
+        }
+        let mut json = json!({
+            "pbrMetallicRoughness": pbr,
+            "emissiveFactor": material.emissive_factor.to_array(),
+            "alphaMode": match material.alpha_mode {
+                AlphaMode::Opaque => "OPAQUE",
+                AlphaMode::Mask => "MASK",
+                AlphaMode::Blend => "BLEND",
+            },
+            "doubleSided": material.double_sided,
+        });
+        if material.alpha_mode == AlphaMode::Mask {
+            json["alphaCutoff"] = json!(material.alpha_cutoff);
+        }
+        if let Some(info) = self.texture_info(
+            material.normal_texture_id,
+            material.normal_tex_coord,
+            material.normal_tex_transform,
+        ) {
+            json["normalTexture"] = info;
+        }
+        if let Some(mut info) = self.texture_info(
+            material.ao_texture_id,
+            material.ao_tex_coord,
+            material.ao_tex_transform,
+        ) {
+            info["strength"] = json!(material.ao_strength);
+            json["occlusionTexture"] = info;
+        }
+        if let Some(info) = self.color_texture_info(
+            material.emissive_texture_id,
+            material.emissive_tex_coord,
+            material.emissive_tex_transform,
+        ) {
+            json["emissiveTexture"] = info;
+        }
+
+        let mut extensions = serde_json::Map::new();
+        if !material.has_lighting {
+            extensions.insert("KHR_materials_unlit".into(), json!({}));
+        }
+        if material.emissive_strength_multiplier != 1.0 {
+            extensions.insert(
+                "KHR_materials_emissive_strength".into(),
+                json!({"emissiveStrength": material.emissive_strength_multiplier}),
+            );
+        }
+        if material.ior != 1.5 {
+            extensions.insert("KHR_materials_ior".into(), json!({"ior": material.ior}));
+        }
+        if let Some(clearcoat) = find(&self.document.clearcoats, material.clearcoat_id) {
+            let mut extension = json!({
+                "clearcoatFactor": clearcoat.factor,
+                "clearcoatRoughnessFactor": clearcoat.roughness_factor,
+            });
+            for (key, id, tex_coord, transform) in [
+                (
+                    "clearcoatTexture",
+                    clearcoat.texture_id,
+                    clearcoat.tex_coord,
+                    clearcoat.tex_transform,
+                ),
+                (
+                    "clearcoatRoughnessTexture",
+                    clearcoat.roughness_texture_id,
+                    clearcoat.roughness_tex_coord,
+                    clearcoat.roughness_tex_transform,
+                ),
+                (
+                    "clearcoatNormalTexture",
+                    clearcoat.normal_texture_id,
+                    clearcoat.normal_tex_coord,
+                    clearcoat.normal_tex_transform,
+                ),
+            ] {
+                if let Some(info) = self.texture_info(id, tex_coord, transform) {
+                    extension[key] = info;
+                }
+            }
+            extensions.insert("KHR_materials_clearcoat".into(), extension);
+        }
+        if let Some(transmission) = find(&self.document.transmissions, material.transmission_id) {
+            let mut extension = json!({"transmissionFactor": transmission.factor});
+            if let Some(info) = self.texture_info(
+                transmission.texture_id,
+                transmission.tex_coord,
+                transmission.tex_transform,
+            ) {
+                extension["transmissionTexture"] = info;
+            }
+            extensions.insert("KHR_materials_transmission".into(), extension);
+
+            let mut volume = json!({
+                "thicknessFactor": transmission.thickness_factor,
+                "attenuationColor": transmission.attenuation_color.to_array(),
+            });
+            // `f32::MAX` stands in for an infinite distance, which is the
+            // default
+            if transmission.attenuation_distance < f32::MAX {
+                volume["attenuationDistance"] = json!(transmission.attenuation_distance);
+            }
+            if let Some(info) = self.texture_info(
+                transmission.thickness_texture_id,
+                transmission.thickness_tex_coord,
+                transmission.thickness_tex_transform,
+            ) {
+                volume["thicknessTexture"] = info;
+            }
+            extensions.insert("KHR_materials_volume".into(), volume);
+        }
+        if let Some(sheen) = find(&self.document.sheens, material.sheen_id) {
+            let mut extension = json!({
+                "sheenColorFactor": sheen.color_factor.to_array(),
+                "sheenRoughnessFactor": sheen.roughness_factor,
+            });
+            if let Some(info) = self.color_texture_info(
+                sheen.color_texture_id,
+                sheen.color_tex_coord,
+                sheen.color_tex_transform,
+            ) {
+                extension["sheenColorTexture"] = info;
+            }
+            if let Some(info) = self.texture_info(
+                sheen.roughness_texture_id,
+                sheen.roughness_tex_coord,
+                sheen.roughness_tex_transform,
+            ) {
+                extension["sheenRoughnessTexture"] = info;
+            }
+            extensions.insert("KHR_materials_sheen".into(), extension);
+        }
+        if let Some(specular) = find(&self.document.speculars, material.specular_id) {
+            let mut extension = json!({
+                "specularFactor": specular.factor,
+                "specularColorFactor": specular.color_factor.to_array(),
+            });
+            if let Some(info) = self.texture_info(
+                specular.texture_id,
+                specular.tex_coord,
+                specular.tex_transform,
+            ) {
+                extension["specularTexture"] = info;
+            }
+            if let Some(info) = self.color_texture_info(
+                specular.color_texture_id,
+                specular.color_tex_coord,
+                specular.color_tex_transform,
+            ) {
+                extension["specularColorTexture"] = info;
+            }
+            extensions.insert("KHR_materials_specular".into(), extension);
+        }
+        if !extensions.is_empty() {
+            for name in [
+                "KHR_materials_unlit",
+                "KHR_materials_emissive_strength",
+                "KHR_materials_ior",
+                "KHR_materials_clearcoat",
+                "KHR_materials_transmission",
+                "KHR_materials_volume",
+                "KHR_materials_sheen",
+                "KHR_materials_specular",
+            ] {
+                if extensions.contains_key(name) {
+                    self.extensions_used.insert(name);
+                }
+            }
+            json["extensions"] = Value::Object(extensions);
+        }
+        json
+    }
+
+    fn meshes(&mut self) -> Vec<Value> {
+        let document = self.document;
+        let mut meshes = vec![];
+        for mesh in document.meshes.iter() {
+            let mut primitives = vec![];
+            for primitive in mesh.primitives.iter() {
+                let attributes = self.buffer.vertex_attributes(
+                    primitive.topology,
+                    &primitive.vertices.get_vec(),
+                    &primitive.extra_influences.get_vec(),
+                );
+                let has_tangents = attributes.get("TANGENT").is_some();
+                let mut json = json!({
+                    "attributes": attributes,
+                    "mode": match primitive.topology {
+                        Topology::PointList => 0,
+                        Topology::LineList => 1,
+                        Topology::TriangleList => 4,
+                    },
+                });
+                if !primitive.indices.is_empty() {
+                    let indices = primitive
+                        .indices
+                        .get_vec()
+                        .into_iter()
+                        .flat_map(u32::to_le_bytes)
+                        .collect::<Vec<_>>();
+                    json["indices"] = json!(self.buffer.push_accessor(
+                        &indices,
+                        Some(ELEMENT_ARRAY_BUFFER),
+                        UNSIGNED_INT,
+                        primitive.indices.len(),
+                        "SCALAR"
+                    ));
+                }
+                if let Some(index) = index_in(document.materials.array(), primitive.material) {
+                    json["material"] = json!(index);
+                }
+                if !primitive.morph_targets.is_empty() {
+                    let targets = primitive
+                        .morph_targets
+                        .iter()
+                        .map(|target| {
+                            let target = target.get_vec();
+                            let read = |f: fn(&_) -> Vec3| {
+                                target.iter().map(|t| f(t).to_array()).collect::<Vec<_>>()
+                            };
+                            let buffer = &mut self.buffer;
+                            let mut json = json!({
+                                "POSITION": buffer.push_f32s(&read(|t| t.position), None, true),
+                                "NORMAL": buffer.push_f32s(&read(|t| t.normal), None, false),
+                            });
+                            // targets may only displace attributes of the primitive
+                            if has_tangents {
+                                json["TANGENT"] =
+                                    json!(buffer.push_f32s(&read(|t| t.tangent), None, false));
+                            }
+                            json
+                        })
+                        .collect::<Vec<_>>();
+                    json["targets"] = Value::Array(targets);
+                }
+                primitives.push(json);
+            }
+            let mut json = json!({"primitives": primitives});
+            if !mesh.weights.is_empty() {
+                json["weights"] = json!(mesh.weights.get_vec());
+            }
+            meshes.push(json);
+        }
+        meshes
+    }
+
+    fn nodes(&mut self) -> Vec<Value> {
+        let document = self.document;
+        let mut nodes = vec![];
+        for node in document.nodes.iter() {
+            let transform = node.transform.get_local_transform();
+            let mut json = json!({
+                "translation": transform.translation.to_array(),
+                "rotation": transform.rotation.to_array(),
+                "scale": transform.scale.to_array(),
+            });
+            if let Some(name) = &node.name {
+                json["name"] = json!(name);
+            }
+            if !node.children.is_empty() {
+                json["children"] = json!(node.children);
+            }
+            if let Some(mesh) = node.mesh {
+                json["mesh"] = json!(mesh);
+                if !node.weights.is_empty() {
+                    json["weights"] = json!(node.weights.get_vec());
+                }
+            }
+            if let Some(camera) = node.camera {
+                json["camera"] = json!(camera);
+            }
+            if let Some(skin) = node.skin {
+                json["skin"] = json!(skin);
+            }
+            let mut extensions = serde_json::Map::new();
+            if let Some(light) = node.light {
+                extensions.insert("KHR_lights_punctual".into(), json!({"light": light}));
+            }
+            if let Some(instances) = document
+                .instances
+                .get(&node.index)
+                .and_then(|instances| instances.first())
+            {
+                self.extensions_used.insert("EXT_mesh_gpu_instancing");
+                let transforms = instances.transforms.get_vec();
+                let read = |f: fn(&crate::transform::Transform) -> [f32; 3]| {
+                    transforms.iter().map(f).collect::<Vec<_>>()
+                };
+                let translations = read(|t| t.translation.to_array());
+                let scales = read(|t| t.scale.to_array());
+                let rotations = transforms
+                    .iter()
+                    .map(|t| t.rotation.to_array())
+                    .collect::<Vec<_>>();
+                let buffer = &mut self.buffer;
+                extensions.insert(
+                    "EXT_mesh_gpu_instancing".into(),
+                    json!({
+                        "attributes": {
+                            "TRANSLATION": buffer.push_f32s(&translations, None, false),
+                            "ROTATION": buffer.push_f32s(&rotations, None, false),
+                            "SCALE": buffer.push_f32s(&scales, None, false),
+                        }
+                    }),
+                );
+            }
+            if !extensions.is_empty() {
+                json["extensions"] = Value::Object(extensions);
+            }
+            nodes.push(json);
+        }
+        nodes
+    }
+
+    fn lights(&mut self) -> Vec<Value> {
+        self.document
+            .lights
+            .iter()
+            .map(|light| match &light.details {
+                LightDetails::Directional(light) => {
+                    let light = light.get();
+                    json!({
+                        "type": "directional",
+                        "color": light.color.truncate().to_array(),
+                        "intensity": light.intensity,
+                    })
+                }
+                LightDetails::Point(light) => {
+                    let light = light.get();
+                    json!({
+                        "type": "point",
+                        "color": light.color.truncate().to_array(),
+                        "intensity": light.intensity,
+                    })
+                }
+                LightDetails::Spot(light) => {
+                    let light = light.get();
+                    json!({
+                        "type": "spot",
+                        "color": light.color.truncate().to_array(),
+                        "intensity": light.intensity,
+                        "spot": {
+                            "innerConeAngle": light.inner_cutoff,
+                            "outerConeAngle": light.outer_cutoff,
+                        },
+                    })
+                }
+            })
+            .collect()
+    }
+
+    fn skins(&mut self) -> Vec<Value> {
+        let mut skins = vec![];
+        for skin in self.document.skins.iter() {
+            let mut json = json!({"joints": skin.joint_nodes});
+            if let Some(matrices) = &skin.inverse_bind_matrices {
+                let matrices = matrices
+                    .get_vec()
+                    .iter()
+                    .map(Mat4::to_cols_array)
+                    .collect::<Vec<_>>();
+                json["inverseBindMatrices"] = json!(self.buffer.push_f32s(&matrices, None, false));
+            }
+            if let Some(skeleton) = skin.skeleton {
+                json["skeleton"] = json!(skeleton);
+            }
+            skins.push(json);
+        }
+        skins
+    }
+
+    fn animations(&mut self) -> Vec<Value> {
+        fn fixed<const N: usize>(values: &[Vec<f32>]) -> Vec<[f32; N]> {
+            values
+                .iter()
+                .map(|v| std::array::from_fn(|i| v.get(i).copied().unwrap_or_default()))
+                .collect()
+        }
+
+        let mut animations = vec![];
+        for animation in self.document.animations.iter() {
+            if animation.tweens.is_empty() {
+                continue;
+            }
+            let mut samplers = vec![];
+            let mut channels = vec![];
+            for tween in animation.tweens.iter() {
+                let buffer = &mut self.buffer;
+                let times = tween.keyframes.iter().map(|k| [k.0]).collect::<Vec<_>>();
+                let input = buffer.push_f32s(&times, None, true);
+                let node_target =
+                    |path: &str| json!({"node": tween.target_node_index, "path": path});
+                let (output, target) = match &tween.properties {
+                    TweenProperties::Translations(translations) => {
+                        let values = translations
+                            .iter()
+                            .map(|t| t.to_array())
+                            .collect::<Vec<_>>();
+                        (
+                            buffer.push_f32s(&values, None, false),
+                            node_target("translation"),
+                        )
+                    }
+                    TweenProperties::Rotations(rotations) => {
+                        let values = rotations.iter().map(|r| r.to_array()).collect::<Vec<_>>();
+                        (
+                            buffer.push_f32s(&values, None, false),
+                            node_target("rotation"),
+                        )
+                    }
+                    TweenProperties::Scales(scales) => {
+                        let values = scales.iter().map(|s| s.to_array()).collect::<Vec<_>>();
+                        (buffer.push_f32s(&values, None, false), node_target("scale"))
+                    }
+                    TweenProperties::MorphTargetWeights(weights) => {
+                        let values = weights.iter().flatten().map(|w| [*w]).collect::<Vec<_>>();
+                        (
+                            buffer.push_f32s(&values, None, false),
+                            node_target("weights"),
+                        )
+                    }
+                    TweenProperties::Pointer(pointer, values) => {
+                        self.extensions_used.insert("KHR_animation_pointer");
+                        let output = match values.first().map(Vec::len) {
+                            Some(4) => buffer.push_f32s(&fixed::<4>(values), None, false),
+                            Some(3) => buffer.push_f32s(&fixed::<3>(values), None, false),
+                            Some(2) => buffer.push_f32s(&fixed::<2>(values), None, false),
+                            _ => buffer.push_f32s(&fixed::<1>(values), None, false),
+                        };
+                        let target = json!({
+                            "path": "pointer",
+                            "extensions": {
+                                "KHR_animation_pointer": {"pointer": pointer.to_string()}
+                            },
+                        });
+                        (output, target)
+                    }
+                };
+                channels.push(json!({
+                    "sampler": samplers.len(),
+                    "target": target,
+                }));
+                samplers.push(json!({
+                    "input": input,
+                    "output": output,
+                    "interpolation": interpolation_name(tween.interpolation),
+                }));
+            }
+            let mut json = json!({"channels": channels, "samplers": samplers});
+            if let Some(name) = &animation.name {
+                json["name"] = json!(name);
+            }
+            animations.push(json);
+        }
+        animations
+    }
+
+    /// Writes the document's textures into `root`, along with their samplers
+    /// and the images they sample, cropped out of the given atlas image.
+    ///
+    /// Must be called after materials are written, so color textures are
+    /// known.
+    fn textures(
+        &mut self,
+        atlas: &image::RgbaImage,
+        root: &mut Value,
+    ) -> Result<(), StageGltfError> {
+        let textures = self.document.textures.get_vec();
+        let mut samplers = vec![];
+        let mut images = vec![];
+        // gltf images keyed by atlas image index
+        let mut image_indices = FxHashMap::<u32, usize>::default();
+        let mut json_textures = vec![];
+        for (i, texture) in textures.iter().enumerate() {
+            let sampler = json!({
+                "wrapS": wrapping_mode(texture.modes.s),
+                "wrapT": wrapping_mode(texture.modes.t),
+            });
+            let sampler = samplers
+                .iter()
+                .position(|s| *s == sampler)
+                .unwrap_or_else(|| {
+                    samplers.push(sampler);
+                    samplers.len() - 1
+                });
+            let image = if let Some(image) = image_indices.get(&texture.atlas_index) {
+                *image
+            } else {
+                let is_color = textures.iter().enumerate().any(|(j, t)| {
+                    t.atlas_index == texture.atlas_index && self.color_textures.contains(&j)
+                });
+                let mut img = image::imageops::crop_imm(
+                    atlas,
+                    texture.offset_px.x,
+                    texture.offset_px.y,
+                    texture.size_px.x,
+                    texture.size_px.y,
+                )
+                .to_image();
+                if is_color {
+                    img.pixels_mut().for_each(|p| {
+                        crate::color::opto_xfer_u8(&mut p.0[0]);
+                        crate::color::opto_xfer_u8(&mut p.0[1]);
+                        crate::color::opto_xfer_u8(&mut p.0[2]);
+                    });
+                }
+                let mut png = std::io::Cursor::new(vec![]);
+                img.write_to(&mut png, image::ImageOutputFormat::Png)
+                    .context(EncodeImageSnafu { index: i })?;
+                let view = self.buffer.push_view(png.get_ref(), None);
+                images.push(json!({"bufferView": view, "mimeType": "image/png"}));
+                image_indices.insert(texture.atlas_index, images.len() - 1);
+                images.len() - 1
+            };
+            json_textures.push(json!({"sampler": sampler, "source": image}));
+        }
+        root["textures"] = json!(json_textures);
+        root["samplers"] = json!(samplers);
+        root["images"] = json!(images);
+        Ok(())
+    }
+}
+
+impl GltfDocument {
+    /// Serialize the document into a binary gltf (`.glb`).
+    ///
+    /// Nodes are written with their current local transforms, and meshes,
+    /// materials, lights, cameras, skins and animations with their current
+    /// values. Texture images are read back from the atlas of the given
+    /// stage, which must be the stage the document was loaded into.
+    pub fn to_glb(&self, stage: &Stage) -> Result<Vec<u8>, StageGltfError> {
+        let mut exporter = Exporter {
+            document: self,
+            buffer: GlbBuffer::default(),
+            extensions_used: BTreeSet::new(),
+            color_textures: FxHashSet::default(),
+        };
+        let mut root = json!({
+            "asset": {"version": "2.0", "generator": "renderling"},
+            "scenes": self
+                .scenes
+                .iter()
+                .map(|nodes| json!({"nodes": nodes}))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(scene) = self.default_scene {
+            root["scene"] = json!(scene);
+        }
+        let materials = self
+            .materials
+            .get_vec()
+            .iter()
+            .map(|material| exporter.material(material))
+            .collect::<Vec<_>>();
+        root["materials"] = json!(materials);
+        root["meshes"] = json!(exporter.meshes());
+        root["nodes"] = json!(exporter.nodes());
+        root["cameras"] = self.cameras.iter().map(camera_json).collect();
+        root["skins"] = json!(exporter.skins());
+        root["animations"] = json!(exporter.animations());
+        let lights = exporter.lights();
+        if !lights.is_empty() {
+            exporter.extensions_used.insert("KHR_lights_punctual");
+            root["extensions"] = json!({"KHR_lights_punctual": {"lights": lights}});
+        }
+        if !self.textures.is_empty() {
+            let atlas = stage.atlas.atlas_img(&stage.device, &stage.queue);
+            exporter.textures(&atlas, &mut root)?;
+        }
+        if !exporter.extensions_used.is_empty() {
+            root["extensionsUsed"] = json!(exporter.extensions_used);
+        }
+        // gltf doesn't allow empty arrays
+        if let Some(root) = root.as_object_mut() {
+            root.retain(|_, value| !matches!(value, Value::Array(array) if array.is_empty()));
+        }
+        Ok(exporter.buffer.into_glb(root))
+    }
+
+    /// Serialize the document into a binary gltf and write it to the given
+    /// path.
+    ///
+    /// See [`GltfDocument::to_glb`].
+    pub fn save_glb(
+        &self,
+        stage: &Stage,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), StageGltfError> {
+        let glb = self.to_glb(stage)?;
+        std::fs::write(path, glb).context(WriteGlbSnafu)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec4;
+
+    use super::*;
+
+    #[test]
+    fn glb_buffer_sanity() {
+        let mut buffer = GlbBuffer::default();
+        let positions = buffer.push_f32s(
+            &[[0.0, 1.0, 0.0], [-1.0, 0.0, 2.0], [1.0, 0.0, 0.0]],
+            Some(ARRAY_BUFFER),
+            true,
+        );
+        let indices = buffer.push_accessor(
+            &[0u32, 1, 2].map(u32::to_le_bytes).concat(),
+            Some(ELEMENT_ARRAY_BUFFER),
+            UNSIGNED_INT,
+            3,
+            "SCALAR",
+        );
+        let glb = buffer.into_glb(json!({
+            "asset": {"version": "2.0"},
+            "meshes": [{
+                "primitives": [{"attributes": {"POSITION": positions}, "indices": indices}]
+            }]
+        }));
+
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let accessor = document.accessors().nth(positions).unwrap();
+        assert_eq!(
+            Some(json!([-1.0, 0.0, 0.0])),
+            accessor.min().map(|min| serde_json::to_value(min).unwrap())
+        );
+        assert_eq!(
+            Some(json!([1.0, 1.0, 2.0])),
+            accessor.max().map(|max| serde_json::to_value(max).unwrap())
+        );
+        let primitive = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|d| d.0.as_slice()));
+        assert_eq!(
+            vec![0, 1, 2],
+            reader
+                .read_indices()
+                .unwrap()
+                .into_u32()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![[0.0, 1.0, 0.0], [-1.0, 0.0, 2.0], [1.0, 0.0, 0.0]],
+            reader.read_positions().unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn tangents_are_only_exported_for_triangles() {
+        let tangent = Vec4::new(1.0, 0.0, 0.0, 1.0);
+        let vertices = [Vec3::ZERO, Vec3::X, Vec3::Y]
+            .map(|position| Vertex::default().with_position(position));
+        let mut buffer = GlbBuffer::default();
+        let points = buffer.vertex_attributes(
+            Topology::PointList,
+            &vertices.map(|v| v.with_tangent(Vec4::ZERO)),
+            &[],
+        );
+        let lines = buffer.vertex_attributes(
+            Topology::LineList,
+            &vertices.map(|v| v.with_tangent(tangent)),
+            &[],
+        );
+        let untangented_triangles = buffer.vertex_attributes(
+            Topology::TriangleList,
+            &vertices.map(|v| v.with_tangent(Vec4::ZERO)),
+            &[],
+        );
+        let triangles = buffer.vertex_attributes(
+            Topology::TriangleList,
+            &vertices.map(|v| v.with_tangent(tangent)),
+            &[],
+        );
+        assert!(points.get("TANGENT").is_none());
+        assert!(lines.get("TANGENT").is_none());
+        assert!(untangented_triangles.get("TANGENT").is_none());
+        assert!(triangles.get("TANGENT").is_some());
+
+        let glb = buffer.into_glb(json!({
+            "asset": {"version": "2.0"},
+            "meshes": [{
+                "primitives": [
+                    {"attributes": points, "mode": 0},
+                    {"attributes": lines, "mode": 1},
+                    {"attributes": untangented_triangles, "mode": 4},
+                    {"attributes": triangles, "mode": 4},
+                ]
+            }]
+        }));
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let primitives = document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .collect::<Vec<_>>();
+        let reader =
+            primitives[3].reader(|buffer| buffers.get(buffer.index()).map(|d| d.0.as_slice()));
+        assert_eq!(
+            vec![tangent.to_array(); 3],
+            reader.read_tangents().unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn projections_from_matrices() {
+        let json = projection_json(Mat4::perspective_rh(1.0, 2.0, 0.5, 100.0));
+        let perspective = &json["perspective"];
+        assert_eq!("perspective", json["type"]);
+        let get = |value: &Value| value.as_f64().unwrap() as f32;
+        assert!((get(&perspective["yfov"]) - 1.0).abs() < 1.0e-6);
+        assert!((get(&perspective["aspectRatio"]) - 2.0).abs() < 1.0e-6);
+        assert!((get(&perspective["znear"]) - 0.5).abs() < 1.0e-6);
+        assert!((get(&perspective["zfar"]) - 100.0).abs() < 1.0e-3);
+
+        let json = projection_json(Mat4::perspective_infinite_rh(1.0, 2.0, 0.5));
+        assert!((get(&json["perspective"]["znear"]) - 0.5).abs() < 1.0e-6);
+        assert!(json["perspective"].get("zfar").is_none());
+
+        let json = projection_json(Mat4::orthographic_rh(-3.0, 3.0, -2.0, 2.0, 1.0, 10.0));
+        let orthographic = &json["orthographic"];
+        assert_eq!("orthographic", json["type"]);
+        assert!((get(&orthographic["xmag"]) - 3.0).abs() < 1.0e-6);
+        assert!((get(&orthographic["ymag"]) - 2.0).abs() < 1.0e-6);
+        assert!((get(&orthographic["znear"]) - 1.0).abs() < 1.0e-6);
+        assert!((get(&orthographic["zfar"]) - 10.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    // Ensures that a document, including edits made after loading, survives
+    // being exported and loaded again.
+    fn gltf_round_trips_through_glb() {
+        let ctx = crate::Context::headless(16, 16);
+        let mut stage = ctx.new_stage();
+        let doc = stage
+            .load_gltf_document_from_path("../../gltf/gltfTutorial_019_SimpleSkin.gltf", Id::NONE)
+            .unwrap();
+        let mut moved = doc.nodes[0].transform.get_local_transform();
+        moved.translation += Vec3::new(1.0, 2.0, 3.0);
+        doc.nodes[0].transform.set_local_transform(moved);
+        let glb = doc.to_glb(&stage).unwrap();
+
+        let mut other_stage = ctx.new_stage();
+        let other = other_stage
+            .load_gltf_document_from_bytes(&glb, Id::NONE)
+            .unwrap();
+        assert_eq!(doc.nodes.len(), other.nodes.len());
+        assert_eq!(moved, other.nodes[0].transform.get_local_transform());
+        for (a, b) in doc.nodes.iter().zip(other.nodes.iter()) {
+            assert_eq!(a.children, b.children);
+            assert_eq!(a.mesh, b.mesh);
+            assert_eq!(a.skin, b.skin);
+        }
+        let (a, b) = (
+            doc.meshes[0].primitives[0].vertices.get_vec(),
+            other.meshes[0].primitives[0].vertices.get_vec(),
+        );
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.joints, b.joints);
+        }
+        assert_eq!(
+            doc.meshes[0].primitives[0].indices.get_vec(),
+            other.meshes[0].primitives[0].indices.get_vec()
+        );
+        assert_eq!(doc.skins[0].joint_nodes, other.skins[0].joint_nodes);
+        assert_eq!(
+            doc.skins[0]
+                .inverse_bind_matrices
+                .as_ref()
+                .unwrap()
+                .get_vec(),
+            other.skins[0]
+                .inverse_bind_matrices
+                .as_ref()
+                .unwrap()
+                .get_vec()
+        );
+        assert_eq!(doc.animations.len(), other.animations.len());
+        let (a, b) = (&doc.animations[0].tweens, &other.animations[0].tweens);
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.target_node_index, b.target_node_index);
+            assert_eq!(
+                a.keyframes.iter().map(|k| k.0).collect::<Vec<_>>(),
+                b.keyframes.iter().map(|k| k.0).collect::<Vec<_>>()
+            );
+        }
+
+        // textures are read back from the atlas
+        let doc = stage
+            .load_gltf_document_from_path(
+                "../../gltf/gltfTutorial_013_SimpleTexture.gltf",
+                Id::NONE,
+            )
+            .unwrap();
+        let glb = doc.to_glb(&stage).unwrap();
+        let (document, _buffers, images) = gltf::import_slice(&glb).unwrap();
+        assert_eq!(doc.textures.len(), document.textures().len());
+        let texture = doc.textures.get(0).unwrap();
+        assert_eq!(
+            (texture.size_px.x, texture.size_px.y),
+            (images[0].width, images[0].height)
+        );
+    }
+
+    #[test]
+    fn index_in_array() {
+        let array = Array::<Vec3>::new(10, 3);
+        assert_eq!(Some(0), index_in(array, Id::new(10)));
+        assert_eq!(Some(2), index_in(array, Id::new(16)));
+        assert_eq!(None, index_in(array, Id::new(11)));
+        assert_eq!(None, index_in(array, Id::new(19)));
+        assert_eq!(None, index_in(array, Id::new(7)));
+        assert_eq!(None, index_in(array, Id::NONE));
+    }
+}
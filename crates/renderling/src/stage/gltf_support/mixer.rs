@@ -0,0 +1,585 @@
+//! Blending, cross-fading and layering of gltf animations.
+use glam::{Quat, Vec3};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{slab::HybridArray, stage::NestedTransform, transform::Transform};
+
+use super::{
+    Animation, AnimationMarker, AnimationNode, Animator, InterpolationError, TweenProperty,
+};
+
+/// How an [`AnimationLayer`] combines with the layers beneath it.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerBlend {
+    /// The layer's pose replaces the pose beneath it, in proportion to the
+    /// layer's weight.
+    #[default]
+    Override,
+    /// The layer's change from the first frame of its animations is added on
+    /// top of the pose beneath it, in proportion to the layer's weight.
+    Additive,
+}
+
+/// A timed change of a [`MixerTrack`]'s weight.
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: f32,
+    to: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+/// An animation playing in an [`AnimationLayer`].
+#[derive(Debug, Clone)]
+pub struct MixerTrack {
+    /// Keeps the time of the track's animation.
+    ///
+    /// Its playback mode, speed and markers all apply, but its nodes are
+    /// ignored in favor of the mixer's.
+    pub animator: Animator,
+    /// How much this track contributes to its layer, relative to the layer's
+    /// other tracks.
+    pub weight: f32,
+    fade: Option<Fade>,
+}
+
+impl MixerTrack {
+    /// Create a new track playing the given animation at full weight.
+    pub fn new(animation: Animation) -> Self {
+        MixerTrack {
+            animator: Animator::new(std::iter::empty::<AnimationNode>(), animation),
+            weight: 1.0,
+            fade: None,
+        }
+    }
+
+    /// Set the weight of this track, cancelling any fade.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+        self.fade = None;
+    }
+
+    /// Set the weight of this track, cancelling any fade.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Change the weight of this track to `weight` over `duration` seconds.
+    pub fn fade_to(&mut self, weight: f32, duration: f32) {
+        if duration <= 0.0 {
+            self.set_weight(weight);
+        } else {
+            self.fade = Some(Fade {
+                from: self.weight,
+                to: weight,
+                duration,
+                elapsed: 0.0,
+            });
+        }
+    }
+
+    /// Returns whether this track's weight is changing.
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Returns whether this track has faded out completely, and can be
+    /// removed.
+    fn is_faded_out(&self) -> bool {
+        self.fade.is_none() && self.weight <= 0.0
+    }
+
+    /// Progress the track's fade and animation, returning the markers passed.
+    fn progress(&mut self, dt_seconds: f32) -> Vec<AnimationMarker> {
+        if let Some(fade) = self.fade.as_mut() {
+            fade.elapsed += dt_seconds;
+            let amount = (fade.elapsed / fade.duration).min(1.0);
+            self.weight = fade.from + (fade.to - fade.from) * amount;
+            if amount >= 1.0 {
+                self.fade = None;
+            }
+        }
+        self.animator.advance(dt_seconds * self.animator.speed)
+    }
+}
+
+/// A group of [`MixerTrack`]s blended together in an [`AnimationMixer`].
+#[derive(Debug, Clone)]
+pub struct AnimationLayer {
+    /// How this layer combines with the layers beneath it.
+    pub blend: LayerBlend,
+    /// How much this layer contributes to the final pose.
+    pub weight: f32,
+    /// Indices of the only nodes this layer affects, if any.
+    pub mask: Option<FxHashSet<usize>>,
+    /// The tracks of this layer.
+    pub tracks: Vec<MixerTrack>,
+}
+
+impl Default for AnimationLayer {
+    fn default() -> Self {
+        AnimationLayer {
+            blend: LayerBlend::default(),
+            weight: 1.0,
+            mask: None,
+            tracks: vec![],
+        }
+    }
+}
+
+impl AnimationLayer {
+    /// Create a new empty layer at full weight.
+    pub fn new(blend: LayerBlend) -> Self {
+        AnimationLayer {
+            blend,
+            ..Default::default()
+        }
+    }
+
+    /// Set how much this layer contributes to the final pose.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    /// Set how much this layer contributes to the final pose.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Restrict this layer to the nodes with the given indices.
+    pub fn set_mask(&mut self, nodes: impl IntoIterator<Item = usize>) {
+        self.mask = Some(nodes.into_iter().collect());
+    }
+
+    /// Restrict this layer to the nodes with the given indices.
+    pub fn with_mask(mut self, nodes: impl IntoIterator<Item = usize>) -> Self {
+        self.set_mask(nodes);
+        self
+    }
+
+    /// Add a track to this layer.
+    pub fn add_track(&mut self, track: MixerTrack) {
+        self.tracks.push(track);
+    }
+
+    /// Add a track to this layer.
+    pub fn with_track(mut self, track: MixerTrack) -> Self {
+        self.add_track(track);
+        self
+    }
+
+    /// Fade out every track of this layer while fading in the given track,
+    /// over `duration` seconds.
+    ///
+    /// Tracks are removed once they have faded out.
+    pub fn cross_fade(&mut self, mut track: MixerTrack, duration: f32) {
+        for track in self.tracks.iter_mut() {
+            track.fade_to(0.0, duration);
+        }
+        let weight = track.weight;
+        track.weight = 0.0;
+        track.fade_to(weight, duration);
+        self.tracks.push(track);
+    }
+
+    /// Returns whether this layer affects the node with the given index.
+    fn affects(&self, node_index: usize) -> bool {
+        self.mask
+            .as_ref()
+            .map(|mask| mask.contains(&node_index))
+            .unwrap_or(true)
+    }
+}
+
+/// Weighted sums of the properties of one node within a layer.
+#[derive(Debug, Default)]
+struct NodeBlend {
+    translation: Option<(Vec3, f32)>,
+    rotation: Option<(Quat, f32)>,
+    scale: Option<(Vec3, f32)>,
+    morph_weights: Option<(Vec<f32>, f32)>,
+}
+
+impl NodeBlend {
+    fn add(&mut self, property: TweenProperty, weight: f32) {
+        fn add_vec3(sum: &mut Option<(Vec3, f32)>, value: Vec3, weight: f32) {
+            let (value_sum, weight_sum) = sum.get_or_insert((Vec3::ZERO, 0.0));
+            *value_sum += value * weight;
+            *weight_sum += weight;
+        }
+        match property {
+            TweenProperty::Translation(t) => add_vec3(&mut self.translation, t, weight),
+            TweenProperty::Scale(s) => add_vec3(&mut self.scale, s, weight),
+            TweenProperty::Rotation(r) => {
+                // Rotations are averaged by slerping each into the running
+                // average by its share of the total weight so far.
+                let (rotation, weight_sum) = self.rotation.get_or_insert((r, 0.0));
+                *weight_sum += weight;
+                *rotation = rotation.slerp(r, weight / *weight_sum);
+            }
+            TweenProperty::MorphTargetWeights(ws) => {
+                let (sum, weight_sum) = self
+                    .morph_weights
+                    .get_or_insert_with(|| (vec![0.0; ws.len()], 0.0));
+                for (sum, w) in sum.iter_mut().zip(ws) {
+                    *sum += w * weight;
+                }
+                *weight_sum += weight;
+            }
+            // Pointer tweens don't target nodes, and are left to `Animator`
+            TweenProperty::Pointer(..) => {}
+        }
+    }
+
+    fn translation(&self) -> Option<Vec3> {
+        self.translation.map(|(t, w)| t / w)
+    }
+
+    fn rotation(&self) -> Option<Quat> {
+        self.rotation.map(|(r, _)| r.normalize())
+    }
+
+    fn scale(&self) -> Option<Vec3> {
+        self.scale.map(|(s, w)| s / w)
+    }
+
+    fn morph_weights(&self) -> Option<Vec<f32>> {
+        self.morph_weights
+            .as_ref()
+            .map(|(ws, weight)| ws.iter().map(|w| w / weight).collect())
+    }
+}
+
+/// Returns the change from `reference` to `property`, for additive layers.
+///
+/// Translations and morph target weights are differences, rotations are
+/// relative rotations and scales are ratios.
+fn additive_delta(property: TweenProperty, reference: &TweenProperty) -> Option<TweenProperty> {
+    Some(match (property, reference) {
+        (TweenProperty::Translation(t), TweenProperty::Translation(r)) => {
+            TweenProperty::Translation(t - *r)
+        }
+        (TweenProperty::Rotation(q), TweenProperty::Rotation(r)) => {
+            TweenProperty::Rotation(r.inverse() * q)
+        }
+        (TweenProperty::Scale(s), TweenProperty::Scale(r)) => TweenProperty::Scale(s / *r),
+        (TweenProperty::MorphTargetWeights(ws), TweenProperty::MorphTargetWeights(rs)) => {
+            TweenProperty::MorphTargetWeights(ws.iter().zip(rs).map(|(w, r)| w - r).collect())
+        }
+        _ => return None,
+    })
+}
+
+/// The pose of a node being mixed.
+#[derive(Debug, Clone)]
+struct NodePose {
+    transform: Transform,
+    morph_weights: Option<Vec<f32>>,
+}
+
+/// Blends the [`Animation`]s of several [`AnimationLayer`]s onto one set of
+/// nodes.
+///
+/// Unlike running many [`Animator`]s on the same nodes, where the last to
+/// progress wins, the mixer samples every track, blends the results and
+/// writes each node's transform once per [`AnimationMixer::progress`].
+///
+/// Layers are applied in order, starting from the nodes' poses when the
+/// mixer was created.
+#[derive(Debug, Clone, Default)]
+pub struct AnimationMixer {
+    /// All nodes under this mixer's control.
+    pub nodes: FxHashMap<usize, NestedTransform>,
+    /// Morph target weights of the nodes under this mixer's control.
+    pub morph_weights: FxHashMap<usize, HybridArray<f32>>,
+    /// The layers of animations to blend.
+    pub layers: Vec<AnimationLayer>,
+    /// Poses of the nodes when the mixer was created, which the layers are
+    /// applied on top of.
+    rest_poses: FxHashMap<usize, NodePose>,
+}
+
+impl AnimationMixer {
+    /// Create a new mixer with the given nodes, and no layers.
+    pub fn new(nodes: impl IntoIterator<Item = impl Into<AnimationNode>>) -> Self {
+        let mut mixer = AnimationMixer::default();
+        for node in nodes.into_iter().map(|n| n.into()) {
+            let morph_weights = node.morph_weights.as_ref().map(|ws| ws.get_vec());
+            mixer.rest_poses.insert(
+                node.index,
+                NodePose {
+                    transform: node.transform.get_local_transform(),
+                    morph_weights,
+                },
+            );
+            if let Some(weights) = node.morph_weights {
+                mixer.morph_weights.insert(node.index, weights);
+            }
+            mixer.nodes.insert(node.index, node.transform);
+        }
+        mixer
+    }
+
+    /// Add a layer on top of the existing layers, returning its index.
+    pub fn add_layer(&mut self, layer: AnimationLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    /// Add a layer on top of the existing layers.
+    pub fn with_layer(mut self, layer: AnimationLayer) -> Self {
+        self.add_layer(layer);
+        self
+    }
+
+    /// Progress every track of every layer, then blend their tweened
+    /// properties and apply them to the mixer's nodes.
+    ///
+    /// Returns the markers passed by all tracks.
+    pub fn progress(
+        &mut self,
+        dt_seconds: f32,
+    ) -> Result<Vec<AnimationMarker>, InterpolationError> {
+        let mut markers = vec![];
+        for layer in self.layers.iter_mut() {
+            for track in layer.tracks.iter_mut() {
+                markers.extend(track.progress(dt_seconds));
+            }
+            layer.tracks.retain(|track| !track.is_faded_out());
+        }
+
+        let mut poses = FxHashMap::<usize, NodePose>::default();
+        for layer in self.layers.iter() {
+            if layer.weight <= 0.0 {
+                continue;
+            }
+            let mut blends = FxHashMap::<usize, NodeBlend>::default();
+            for track in layer.tracks.iter() {
+                if track.weight <= 0.0 {
+                    continue;
+                }
+                let animation = &track.animator.animation;
+                let properties = animation.get_properties_at_time(track.animator.timestamp)?;
+                let references = if layer.blend == LayerBlend::Additive {
+                    Some(animation.get_properties_at_time(0.0)?)
+                } else {
+                    None
+                };
+                for (i, (node_index, property)) in properties.into_iter().enumerate() {
+                    if !layer.affects(node_index) || !self.nodes.contains_key(&node_index) {
+                        continue;
+                    }
+                    let property = match references.as_ref() {
+                        Some(references) => {
+                            let Some(delta) = additive_delta(property, &references[i].1) else {
+                                continue;
+                            };
+                            delta
+                        }
+                        None => property,
+                    };
+                    blends
+                        .entry(node_index)
+                        .or_default()
+                        .add(property, track.weight);
+                }
+            }
+
+            for (node_index, blend) in blends {
+                let pose = poses.entry(node_index).or_insert_with(|| {
+                    self.rest_poses
+                        .get(&node_index)
+                        .cloned()
+                        .unwrap_or_else(|| NodePose {
+                            transform: self.nodes[&node_index].get_local_transform(),
+                            morph_weights: None,
+                        })
+                });
+                let w = layer.weight;
+                let t = &mut pose.transform;
+                match layer.blend {
+                    LayerBlend::Override => {
+                        if let Some(translation) = blend.translation() {
+                            t.translation = t.translation.lerp(translation, w);
+                        }
+                        if let Some(rotation) = blend.rotation() {
+                            t.rotation = t.rotation.slerp(rotation, w);
+                        }
+                        if let Some(scale) = blend.scale() {
+                            t.scale = t.scale.lerp(scale, w);
+                        }
+                        if let (Some(pose_weights), Some(weights)) =
+                            (pose.morph_weights.as_mut(), blend.morph_weights())
+                        {
+                            for (p, w2) in pose_weights.iter_mut().zip(weights) {
+                                *p += (w2 - *p) * w;
+                            }
+                        }
+                    }
+                    LayerBlend::Additive => {
+                        if let Some(translation) = blend.translation() {
+                            t.translation += translation * w;
+                        }
+                        if let Some(rotation) = blend.rotation() {
+                            t.rotation *= Quat::IDENTITY.slerp(rotation, w);
+                        }
+                        if let Some(scale) = blend.scale() {
+                            t.scale *= Vec3::ONE.lerp(scale, w);
+                        }
+                        if let (Some(pose_weights), Some(weights)) =
+                            (pose.morph_weights.as_mut(), blend.morph_weights())
+                        {
+                            for (p, delta) in pose_weights.iter_mut().zip(weights) {
+                                *p += delta * w;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for (node_index, pose) in poses {
+            if let Some(transform) = self.nodes.get(&node_index) {
+                transform.set_local_transform(pose.transform);
+            }
+            if let (Some(morph_weights), Some(weights)) =
+                (self.morph_weights.get(&node_index), pose.morph_weights)
+            {
+                for (i, weight) in weights.into_iter().enumerate().take(morph_weights.len()) {
+                    morph_weights.set_item(i, weight);
+                }
+            }
+        }
+        Ok(markers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use glam::{Quat, Vec3};
+
+    use crate::{
+        slab::SlabAllocator,
+        stage::{
+            Animation, AnimationLayer, AnimationMixer, Interpolation, Keyframe, LayerBlend,
+            MixerTrack, NestedTransform, Tween, TweenProperties,
+        },
+    };
+
+    /// Returns an animation that tweens the given properties of the given
+    /// node over two seconds.
+    fn animation(node: usize, properties: TweenProperties) -> Animation {
+        Animation {
+            tweens: vec![Tween {
+                keyframes: vec![Keyframe(0.0), Keyframe(2.0)],
+                properties,
+                interpolation: Interpolation::Linear,
+                target_node_index: node,
+            }],
+            name: None,
+        }
+    }
+
+    fn translations(node: usize, from: Vec3, to: Vec3) -> Animation {
+        animation(node, TweenProperties::Translations(vec![from, to]))
+    }
+
+    #[test]
+    fn mixer_blends_tracks() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let node = NestedTransform::new(&mut slab);
+        let mut mixer = AnimationMixer::new([(0, node.clone())]).with_layer(
+            AnimationLayer::new(LayerBlend::Override)
+                .with_track(
+                    MixerTrack::new(translations(0, Vec3::ZERO, Vec3::ZERO)).with_weight(0.25),
+                )
+                .with_track(
+                    MixerTrack::new(translations(0, Vec3::X * 4.0, Vec3::X * 4.0))
+                        .with_weight(0.75),
+                ),
+        );
+        mixer.progress(0.5).unwrap();
+        assert_eq!(Vec3::X * 3.0, node.get_local_transform().translation);
+
+        let quarter_turn = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let rotations = |rotation| {
+            MixerTrack::new(animation(
+                0,
+                TweenProperties::Rotations(vec![rotation, rotation]),
+            ))
+        };
+        let mut mixer = AnimationMixer::new([(0, node.clone())]).with_layer(
+            AnimationLayer::new(LayerBlend::Override)
+                .with_track(rotations(Quat::IDENTITY))
+                .with_track(rotations(quarter_turn)),
+        );
+        mixer.progress(0.5).unwrap();
+        let expected = Quat::from_rotation_z(std::f32::consts::FRAC_PI_4);
+        assert!(node
+            .get_local_transform()
+            .rotation
+            .abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn mixer_cross_fades() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let node = NestedTransform::new(&mut slab);
+        let walk = MixerTrack::new(translations(0, Vec3::ZERO, Vec3::ZERO));
+        let run = MixerTrack::new(translations(0, Vec3::X * 4.0, Vec3::X * 4.0));
+        let mut mixer = AnimationMixer::new([(0, node.clone())])
+            .with_layer(AnimationLayer::new(LayerBlend::Override).with_track(walk));
+        mixer.progress(0.5).unwrap();
+        assert_eq!(Vec3::ZERO, node.get_local_transform().translation);
+
+        mixer.layers[0].cross_fade(run, 1.0);
+        mixer.progress(0.5).unwrap();
+        assert_eq!(Vec3::X * 2.0, node.get_local_transform().translation);
+        assert!(mixer.layers[0].tracks.iter().all(|track| track.is_fading()));
+
+        mixer.progress(0.5).unwrap();
+        assert_eq!(Vec3::X * 4.0, node.get_local_transform().translation);
+        // the walk track faded out completely, and was removed
+        assert_eq!(1, mixer.layers[0].tracks.len());
+        assert!(!mixer.layers[0].tracks[0].is_fading());
+    }
+
+    #[test]
+    fn mixer_additive_masked_layer() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let a = NestedTransform::new(&mut slab);
+        let b = NestedTransform::new(&mut slab);
+        let base = AnimationLayer::new(LayerBlend::Override)
+            .with_track(MixerTrack::new(translations(0, Vec3::X, Vec3::X)))
+            .with_track(MixerTrack::new(translations(1, Vec3::X, Vec3::X)));
+        let mut nod = translations(0, Vec3::Y, Vec3::Y * 3.0);
+        nod.tweens
+            .extend(translations(1, Vec3::Y, Vec3::Y * 3.0).tweens);
+        let additive = AnimationLayer::new(LayerBlend::Additive)
+            .with_mask([0])
+            .with_track(MixerTrack::new(nod));
+        let mut mixer = AnimationMixer::new([(0, a.clone()), (1, b.clone())])
+            .with_layer(base)
+            .with_layer(additive);
+
+        mixer.progress(1.0).unwrap();
+        // the additive layer adds its change since its first frame
+        assert_eq!(
+            Vec3::new(1.0, 1.0, 0.0),
+            a.get_local_transform().translation
+        );
+        // but is masked from the second node
+        assert_eq!(Vec3::X, b.get_local_transform().translation);
+
+        mixer.layers[1].set_weight(0.5);
+        mixer.progress(1.0).unwrap();
+        assert_eq!(
+            Vec3::new(1.0, 0.0, 0.0),
+            a.get_local_transform().translation
+        );
+    }
+}
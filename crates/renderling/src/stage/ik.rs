@@ -0,0 +1,657 @@
+//! Inverse kinematics and constraints on [`NestedTransform`] hierarchies.
+//!
+//! Constraints pose nodes by rotating their local transforms, so they can be
+//! evaluated after keyframed animation, eg after `Animator::progress`, to
+//! correct the animated pose.
+//!
+//! Constraints are solved in world space, using the global transforms of the
+//! nodes they pose. Non-uniform scale in a node's ancestors is ignored.
+use glam::{Mat4, Quat, Vec3};
+
+use crate::{math::IsVector, transform::Transform};
+
+use super::NestedTransform;
+
+/// Returns the global rotation of the node's parent.
+fn parent_rotation(node: &NestedTransform) -> Quat {
+    let global = Mat4::from(node.get_global_transform());
+    let local = Mat4::from(node.get_local_transform());
+    Transform::from(global * local.inverse()).rotation
+}
+
+/// Returns the position of the node in world space.
+fn world_position(node: &NestedTransform) -> Vec3 {
+    node.get_global_transform().translation
+}
+
+/// Rotates the node about its own origin by the world space `rotation`.
+fn rotate_global(node: &NestedTransform, rotation: Quat) {
+    let parent = parent_rotation(node);
+    node.modify_local_transform(|t| {
+        t.rotation = (parent.inverse() * rotation * parent * t.rotation).normalize();
+    });
+}
+
+/// Returns the shortest rotation from `from` to `to`, or the identity if
+/// either is zero.
+fn rotation_between(from: Vec3, to: Vec3) -> Quat {
+    let from = from.alt_norm_or_zero();
+    let to = to.alt_norm_or_zero();
+    if from == Vec3::ZERO || to == Vec3::ZERO {
+        Quat::IDENTITY
+    } else {
+        Quat::from_rotation_arc(from, to)
+    }
+}
+
+/// Blends the local rotations of `nodes` from `before` toward their current
+/// rotations by `weight`.
+fn blend_rotations(nodes: &[&NestedTransform], before: &[Quat], weight: f32) {
+    if weight >= 1.0 {
+        return;
+    }
+    for (node, before) in nodes.iter().zip(before) {
+        node.modify_local_transform(|t| {
+            t.rotation = before.slerp(t.rotation, weight.max(0.0));
+        });
+    }
+}
+
+/// Applies `f` to `nodes` and blends the result by `weight`.
+fn with_weight(nodes: &[&NestedTransform], weight: f32, f: impl FnOnce()) {
+    let before = nodes
+        .iter()
+        .map(|node| node.get_local_transform().rotation)
+        .collect::<Vec<_>>();
+    f();
+    blend_rotations(nodes, &before, weight);
+}
+
+/// Analytic inverse kinematics for a chain of two bones, like an arm or a
+/// leg.
+///
+/// Rotates `root` and `middle` so that `end` reaches `target`, or points at
+/// it when it's out of reach.
+#[derive(Clone, Debug)]
+pub struct TwoBoneIk {
+    /// The node at the start of the first bone, eg a hip.
+    pub root: NestedTransform,
+    /// The node joining the two bones, eg a knee.
+    pub middle: NestedTransform,
+    /// The node at the end of the second bone, eg an ankle.
+    pub end: NestedTransform,
+    /// Target position of `end` in world space.
+    pub target: Vec3,
+    /// Position in world space that `middle` bends toward, if any.
+    ///
+    /// Without a pole the chain bends in the plane it's already bent in.
+    pub pole: Option<Vec3>,
+    /// How much the solution replaces the current pose, from `0.0` to `1.0`.
+    pub weight: f32,
+}
+
+impl TwoBoneIk {
+    /// Creates a solver that moves `end` to `target` at full weight,
+    /// without a pole.
+    pub fn new(
+        root: &NestedTransform,
+        middle: &NestedTransform,
+        end: &NestedTransform,
+        target: Vec3,
+    ) -> Self {
+        Self {
+            root: root.clone(),
+            middle: middle.clone(),
+            end: end.clone(),
+            target,
+            pole: None,
+            weight: 1.0,
+        }
+    }
+
+    /// Set the position in world space that `middle` bends toward.
+    pub fn set_pole(&mut self, pole: Option<Vec3>) {
+        self.pole = pole;
+    }
+
+    /// Set the position in world space that `middle` bends toward.
+    pub fn with_pole(mut self, pole: Vec3) -> Self {
+        self.set_pole(Some(pole));
+        self
+    }
+
+    /// Set how much the solution replaces the current pose.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    /// Set how much the solution replaces the current pose.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Poses the root and middle nodes.
+    ///
+    /// Call this after the nodes have been animated, eg after
+    /// `Animator::progress`.
+    pub fn apply(&self) {
+        with_weight(&[&self.root, &self.middle], self.weight, || self.solve());
+    }
+
+    fn solve(&self) {
+        let a = world_position(&self.root);
+        let b = world_position(&self.middle);
+        let c = world_position(&self.end);
+        let t = self.target;
+        let ab = b - a;
+        let cb = b - c;
+        let lab = ab.length();
+        let lcb = cb.length();
+        if lab == 0.0 || lcb == 0.0 {
+            return;
+        }
+        let eps = 1.0e-4 * (lab + lcb);
+        let lat = (t - a).length().clamp(eps, lab + lcb - eps);
+
+        let acos = |x: f32| x.clamp(-1.0, 1.0).acos();
+        let ac_ab_0 = acos((c - a).alt_norm_or_zero().dot(ab / lab));
+        let ba_bc_0 = acos((-ab / lab).dot(-cb / lcb));
+        let ac_ab_1 = acos((lcb * lcb - lab * lab - lat * lat) / (-2.0 * lab * lat));
+        let ba_bc_1 = acos((lat * lat - lab * lab - lcb * lcb) / (-2.0 * lab * lcb));
+
+        let bend = self.pole.map(|pole| pole - a).unwrap_or(ab);
+        let mut axis = (c - a).cross(bend).alt_norm_or_zero();
+        if axis == Vec3::ZERO {
+            axis = (c - a).alt_norm_or_zero().any_orthonormal_vector();
+        }
+        rotate_global(&self.middle, Quat::from_axis_angle(axis, ba_bc_1 - ba_bc_0));
+        rotate_global(&self.root, Quat::from_axis_angle(axis, ac_ab_1 - ac_ab_0));
+
+        let c = world_position(&self.end);
+        rotate_global(&self.root, rotation_between(c - a, t - a));
+
+        if let Some(pole) = self.pole {
+            // twist the chain about the root-to-target axis toward the pole
+            let n = (t - a).alt_norm_or_zero();
+            let b = world_position(&self.middle) - a;
+            let p = pole - a;
+            let twist = rotation_between(b - n * b.dot(n), p - n * p.dot(n));
+            rotate_global(&self.root, twist);
+        }
+    }
+}
+
+/// The algorithm used to solve an [`IkChain`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChainSolver {
+    /// Cyclic coordinate descent, which rotates one joint at a time from the
+    /// end of the chain toward its start.
+    #[default]
+    Ccd,
+    /// Forward and backward reaching inverse kinematics, which moves joint
+    /// positions toward the target and back to the root, then rotates the
+    /// joints to match.
+    Fabrik,
+}
+
+/// Iterative inverse kinematics for a chain of any number of joints, like a
+/// spine or a tail.
+///
+/// Rotates all of the `joints` but the last so that the last reaches
+/// `target`.
+#[derive(Clone, Debug)]
+pub struct IkChain {
+    /// The joints of the chain from its root to its end, each a descendant of
+    /// the one before it.
+    pub joints: Vec<NestedTransform>,
+    /// Target position of the last joint in world space.
+    pub target: Vec3,
+    /// The algorithm used to solve the chain.
+    pub solver: ChainSolver,
+    /// The maximum number of iterations of the solver.
+    pub iterations: usize,
+    /// The distance from the target at which the chain is solved.
+    pub tolerance: f32,
+    /// How much the solution replaces the current pose, from `0.0` to `1.0`.
+    pub weight: f32,
+}
+
+impl IkChain {
+    /// Creates a chain that moves its last joint to `target` at full
+    /// weight, solved with up to 16 iterations of cyclic coordinate descent.
+    pub fn new(joints: impl IntoIterator<Item = NestedTransform>, target: Vec3) -> Self {
+        Self {
+            joints: joints.into_iter().collect(),
+            target,
+            solver: ChainSolver::default(),
+            iterations: 16,
+            tolerance: 1.0e-3,
+            weight: 1.0,
+        }
+    }
+
+    /// Set the algorithm used to solve the chain.
+    pub fn set_solver(&mut self, solver: ChainSolver) {
+        self.solver = solver;
+    }
+
+    /// Set the algorithm used to solve the chain.
+    pub fn with_solver(mut self, solver: ChainSolver) -> Self {
+        self.set_solver(solver);
+        self
+    }
+
+    /// Set the maximum number of iterations of the solver.
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.iterations = iterations;
+    }
+
+    /// Set the maximum number of iterations of the solver.
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.set_iterations(iterations);
+        self
+    }
+
+    /// Set the distance from the target at which the chain is solved.
+    pub fn set_tolerance(&mut self, tolerance: f32) {
+        self.tolerance = tolerance;
+    }
+
+    /// Set the distance from the target at which the chain is solved.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.set_tolerance(tolerance);
+        self
+    }
+
+    /// Set how much the solution replaces the current pose.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    /// Set how much the solution replaces the current pose.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Poses all of the joints but the last.
+    ///
+    /// Call this after the joints have been animated, eg after
+    /// `Animator::progress`. Chains with fewer than two joints are left as
+    /// they are.
+    pub fn apply(&self) {
+        if self.joints.len() < 2 {
+            return;
+        }
+        let rotated = self.joints[..self.joints.len() - 1]
+            .iter()
+            .collect::<Vec<_>>();
+        with_weight(&rotated, self.weight, || match self.solver {
+            ChainSolver::Ccd => self.solve_ccd(),
+            ChainSolver::Fabrik => self.solve_fabrik(),
+        });
+    }
+
+    fn end_distance(&self) -> f32 {
+        // UNWRAP: safe because chains have at least two joints
+        world_position(self.joints.last().unwrap()).distance(self.target)
+    }
+
+    fn solve_ccd(&self) {
+        // UNWRAP: safe because chains have at least two joints
+        let end = self.joints.last().unwrap();
+        for _ in 0..self.iterations {
+            if self.end_distance() <= self.tolerance {
+                return;
+            }
+            for joint in self.joints[..self.joints.len() - 1].iter().rev() {
+                let position = world_position(joint);
+                let rotation =
+                    rotation_between(world_position(end) - position, self.target - position);
+                rotate_global(joint, rotation);
+            }
+        }
+    }
+
+    fn solve_fabrik(&self) {
+        let mut positions = self.joints.iter().map(world_position).collect::<Vec<_>>();
+        let lengths = positions
+            .windows(2)
+            .map(|w| w[0].distance(w[1]))
+            .collect::<Vec<_>>();
+        let root = positions[0];
+        let last = positions.len() - 1;
+        if root.distance(self.target) >= lengths.iter().sum::<f32>() {
+            // out of reach, so straighten the chain toward the target
+            let direction = (self.target - root).alt_norm_or_zero();
+            for i in 0..last {
+                positions[i + 1] = positions[i] + direction * lengths[i];
+            }
+        } else {
+            for _ in 0..self.iterations {
+                if positions[last].distance(self.target) <= self.tolerance {
+                    break;
+                }
+                positions[last] = self.target;
+                for i in (0..last).rev() {
+                    let direction = (positions[i] - positions[i + 1]).alt_norm_or_zero();
+                    positions[i] = positions[i + 1] + direction * lengths[i];
+                }
+                positions[0] = root;
+                for i in 0..last {
+                    let direction = (positions[i + 1] - positions[i]).alt_norm_or_zero();
+                    positions[i + 1] = positions[i] + direction * lengths[i];
+                }
+            }
+        }
+        for i in 0..last {
+            let position = world_position(&self.joints[i]);
+            let child = world_position(&self.joints[i + 1]);
+            rotate_global(
+                &self.joints[i],
+                rotation_between(child - position, positions[i + 1] - position),
+            );
+        }
+    }
+}
+
+/// Rotates a node so that one of its local axes points at a target, like a
+/// head turning toward a point of interest.
+///
+/// The node is turned by the shortest rotation, so it doesn't roll about the
+/// axis.
+#[derive(Clone, Debug)]
+pub struct LookAt {
+    /// The node that is rotated.
+    pub node: NestedTransform,
+    /// Position to look at in world space.
+    pub target: Vec3,
+    /// The axis of the node that points at the target, in its local space.
+    pub forward: Vec3,
+    /// How much the rotation replaces the current pose, from `0.0` to `1.0`.
+    pub weight: f32,
+}
+
+impl LookAt {
+    /// Creates a constraint that points the node's local `+Z` axis at
+    /// `target`.
+    pub fn new(node: &NestedTransform, target: Vec3) -> Self {
+        Self {
+            node: node.clone(),
+            target,
+            forward: Vec3::Z,
+            weight: 1.0,
+        }
+    }
+
+    /// Set the axis of the node that points at the target.
+    pub fn set_forward(&mut self, forward: Vec3) {
+        self.forward = forward;
+    }
+
+    /// Set the axis of the node that points at the target.
+    pub fn with_forward(mut self, forward: Vec3) -> Self {
+        self.set_forward(forward);
+        self
+    }
+
+    /// Set how much the rotation replaces the current pose.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    /// Set how much the rotation replaces the current pose.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Poses the node.
+    ///
+    /// Call this after the node has been animated, eg after
+    /// `Animator::progress`.
+    pub fn apply(&self) {
+        with_weight(&[&self.node], self.weight, || {
+            let global = self.node.get_global_transform();
+            let forward = global.rotation * self.forward;
+            rotate_global(
+                &self.node,
+                rotation_between(forward, self.target - global.translation),
+            );
+        });
+    }
+}
+
+/// Gives a node the world space rotation of another node.
+#[derive(Clone, Debug)]
+pub struct CopyRotation {
+    /// The node whose rotation is copied.
+    pub source: NestedTransform,
+    /// The node that is rotated.
+    pub node: NestedTransform,
+    /// How much the copied rotation replaces the current pose, from `0.0`
+    /// to `1.0`.
+    pub weight: f32,
+}
+
+impl CopyRotation {
+    /// Creates a constraint that gives `node` the rotation of `source`.
+    pub fn new(source: &NestedTransform, node: &NestedTransform) -> Self {
+        Self {
+            source: source.clone(),
+            node: node.clone(),
+            weight: 1.0,
+        }
+    }
+
+    /// Set how much the copied rotation replaces the current pose.
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight;
+    }
+
+    /// Set how much the copied rotation replaces the current pose.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.set_weight(weight);
+        self
+    }
+
+    /// Poses the node.
+    ///
+    /// Call this after the nodes have been animated, eg after
+    /// `Animator::progress`.
+    pub fn apply(&self) {
+        with_weight(&[&self.node], self.weight, || {
+            let rotation = self.source.get_global_transform().rotation;
+            let current = self.node.get_global_transform().rotation;
+            rotate_global(&self.node, rotation * current.inverse());
+        });
+    }
+}
+
+/// A constraint on a [`NestedTransform`] hierarchy.
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    /// See [`TwoBoneIk`].
+    TwoBoneIk(TwoBoneIk),
+    /// See [`IkChain`].
+    Chain(IkChain),
+    /// See [`LookAt`].
+    LookAt(LookAt),
+    /// See [`CopyRotation`].
+    CopyRotation(CopyRotation),
+}
+
+impl From<TwoBoneIk> for Constraint {
+    fn from(value: TwoBoneIk) -> Self {
+        Constraint::TwoBoneIk(value)
+    }
+}
+
+impl From<IkChain> for Constraint {
+    fn from(value: IkChain) -> Self {
+        Constraint::Chain(value)
+    }
+}
+
+impl From<LookAt> for Constraint {
+    fn from(value: LookAt) -> Self {
+        Constraint::LookAt(value)
+    }
+}
+
+impl From<CopyRotation> for Constraint {
+    fn from(value: CopyRotation) -> Self {
+        Constraint::CopyRotation(value)
+    }
+}
+
+impl Constraint {
+    /// Poses the constrained nodes.
+    pub fn apply(&self) {
+        match self {
+            Constraint::TwoBoneIk(ik) => ik.apply(),
+            Constraint::Chain(chain) => chain.apply(),
+            Constraint::LookAt(look_at) => look_at.apply(),
+            Constraint::CopyRotation(copy) => copy.apply(),
+        }
+    }
+}
+
+/// Constraints applied in order, each to the pose left by the ones before
+/// it.
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    /// The constraints, in the order they are applied.
+    pub constraints: Vec<Constraint>,
+}
+
+impl Constraints {
+    /// Add a constraint, applied after those already added.
+    pub fn add_constraint(&mut self, constraint: impl Into<Constraint>) {
+        self.constraints.push(constraint.into());
+    }
+
+    /// Add a constraint, applied after those already added.
+    pub fn with_constraint(mut self, constraint: impl Into<Constraint>) -> Self {
+        self.add_constraint(constraint);
+        self
+    }
+
+    /// Poses the constrained nodes.
+    ///
+    /// Call this after the nodes have been animated, eg after
+    /// `Animator::progress`.
+    pub fn apply(&self) {
+        for constraint in self.constraints.iter() {
+            constraint.apply();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use glam::{Quat, Vec3};
+
+    use crate::{slab::SlabAllocator, stage::NestedTransform, transform::Transform};
+
+    use super::*;
+
+    /// Returns a chain of nodes, each a child of the one before it, offset
+    /// from it by `offset`.
+    fn chain(slab: &mut SlabAllocator<Mutex<Vec<u32>>>, offsets: &[Vec3]) -> Vec<NestedTransform> {
+        let mut nodes: Vec<NestedTransform> = vec![];
+        for offset in offsets {
+            let node = NestedTransform::new(slab);
+            node.set_local_transform(Transform {
+                translation: *offset,
+                ..Default::default()
+            });
+            if let Some(parent) = nodes.last() {
+                parent.add_child(&node);
+            }
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    fn position(node: &NestedTransform) -> Vec3 {
+        node.get_global_transform().translation
+    }
+
+    #[test]
+    fn two_bone_ik_reaches_target() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let nodes = chain(&mut slab, &[Vec3::ZERO, Vec3::Y, Vec3::X]);
+        let target = Vec3::new(0.5, 1.2, 0.3);
+        TwoBoneIk::new(&nodes[0], &nodes[1], &nodes[2], target).apply();
+        assert!(position(&nodes[2]).distance(target) < 1.0e-4);
+        assert!((position(&nodes[1]).distance(position(&nodes[0])) - 1.0).abs() < 1.0e-4);
+        assert!((position(&nodes[2]).distance(position(&nodes[1])) - 1.0).abs() < 1.0e-4);
+
+        // out of reach the chain straightens toward the target
+        let target = Vec3::new(0.0, 0.0, 10.0);
+        TwoBoneIk::new(&nodes[0], &nodes[1], &nodes[2], target).apply();
+        assert!(position(&nodes[2]).normalize().distance(Vec3::Z) < 1.0e-2);
+    }
+
+    #[test]
+    fn two_bone_ik_bends_toward_pole() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        // a straight leg
+        let nodes = chain(&mut slab, &[Vec3::ZERO, -Vec3::Y, -Vec3::Y]);
+        let target = Vec3::new(0.0, -1.5, 0.0);
+        TwoBoneIk::new(&nodes[0], &nodes[1], &nodes[2], target)
+            .with_pole(Vec3::new(0.0, -1.0, 5.0))
+            .apply();
+        assert!(position(&nodes[2]).distance(target) < 1.0e-4);
+        let knee = position(&nodes[1]);
+        assert!(knee.z > 0.5, "{knee}");
+        assert!(knee.x.abs() < 1.0e-4, "{knee}");
+    }
+
+    #[test]
+    fn chain_solvers_reach_target() {
+        for solver in [ChainSolver::Ccd, ChainSolver::Fabrik] {
+            let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+            let nodes = chain(&mut slab, &[Vec3::ZERO, Vec3::Y, Vec3::Y, Vec3::Y, Vec3::Y]);
+            let target = Vec3::new(2.0, 1.0, 1.0);
+            IkChain::new(nodes.clone(), target)
+                .with_solver(solver)
+                .with_iterations(64)
+                .apply();
+            let end = position(&nodes[4]);
+            assert!(end.distance(target) < 1.0e-2, "{solver:?} {end}");
+            for pair in nodes.windows(2) {
+                let length = position(&pair[0]).distance(position(&pair[1]));
+                assert!((length - 1.0).abs() < 1.0e-4, "{solver:?} {length}");
+            }
+        }
+    }
+
+    #[test]
+    fn look_at_and_copy_rotation() {
+        let mut slab = SlabAllocator::<Mutex<Vec<u32>>>::default();
+        let nodes = chain(&mut slab, &[Vec3::ZERO, Vec3::Y]);
+        nodes[0].modify_local_transform(|t| t.rotation = Quat::from_rotation_y(1.0));
+        let head = &nodes[1];
+        LookAt::new(head, Vec3::new(5.0, 1.0, 0.0)).apply();
+        let forward = head.get_global_transform().rotation * Vec3::Z;
+        assert!(forward.distance(Vec3::X) < 1.0e-4, "{forward}");
+
+        let other = NestedTransform::new(&mut slab);
+        let half = CopyRotation::new(head, &other).with_weight(0.5);
+        let mut constraints = Constraints::default().with_constraint(half);
+        constraints.apply();
+        let forward = other.get_global_transform().rotation * Vec3::Z;
+        let expected = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4) * Vec3::Z;
+        assert!(forward.distance(expected) < 1.0e-4, "{forward}");
+
+        constraints.add_constraint(CopyRotation::new(head, &other));
+        constraints.apply();
+        let forward = other.get_global_transform().rotation * Vec3::Z;
+        assert!(forward.distance(Vec3::X) < 1.0e-4, "{forward}");
+    }
+}
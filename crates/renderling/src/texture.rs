@@ -480,6 +480,41 @@ impl Texture {
         }
     }
 
+    /// Create a multisampled texture to be used as a render attachment.
+    ///
+    /// Multisampled color attachments are resolved into a single sampled
+    /// texture at the end of a render pass.
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture: Arc::new(texture),
+            view: Arc::new(view),
+            sampler: Arc::new(sampler),
+        }
+    }
+
     /// Read the texture from the GPU.
     ///
     /// To read the texture you must provide the width, height, the number of